@@ -17,21 +17,34 @@
 /// ```sh
 /// OUCH_ARTIFACTS_FOLDER=artifacts cargo build
 /// ```
+#[cfg(feature = "cli")]
 use std::{
     env,
     fs::{create_dir_all, File},
     path::Path,
 };
 
+#[cfg(feature = "cli")]
 use clap::{CommandFactory, ValueEnum};
+#[cfg(feature = "cli")]
 use clap_complete::{generate_to, Shell};
+#[cfg(feature = "cli")]
 use clap_mangen::Man;
 
+// The arguments refer to the options as `crate::options`
+#[cfg(feature = "cli")]
+mod options {
+    include!("src/options.rs");
+}
+
+#[cfg(feature = "cli")]
 include!("src/cli/args.rs");
 
 fn main() {
     println!("cargo:rerun-if-env-changed=OUCH_ARTIFACTS_FOLDER");
 
+    // Without the `cli` feature there are no arguments to generate them from
+    #[cfg(feature = "cli")]
     if let Some(dir) = env::var_os("OUCH_ARTIFACTS_FOLDER") {
         let out = &Path::new(&dir);
         create_dir_all(out).unwrap();
@@ -0,0 +1,425 @@
+//! Library API, compressing, decompressing and listing like the commands of the same name.
+//!
+//! Formats are detected like the command does, from the extensions of the paths, or from the first
+//! bytes of the files being decompressed. Nothing is asked to the user, and only warnings are
+//! printed, to stderr, unless they're given to a [`ProgressSink`].
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use fs_err as fs;
+
+use crate::{
+    archive::UnpackOptions,
+    check,
+    commands::{
+        compress::{compress_files, CodecOptions},
+        decompress::decompress_file,
+        list::archive_entries,
+    },
+    error::{Error, ErrorClass, FinalError},
+    extension::{self, flatten_compression_formats, CompressionFormat, Extension},
+    list::FileInArchive,
+    options::{ConflictPolicy, NormalizationForm, SpecialFiles},
+    progress::{CancellationToken, Progress, ProgressSink},
+    utils::{self, io::default_buffer_size, EscapedPathDisplay, FileVisibilityPolicy, PermissionsPolicy},
+    QuestionPolicy,
+};
+
+/// Settings of [`compress`]
+#[derive(Debug, Clone, Default)]
+pub struct CompressOptions {
+    level: Option<i16>,
+    overwrite: bool,
+    progress: Progress,
+}
+
+impl CompressOptions {
+    /// Compression level, clamped to the range of each format, instead of the default of each format
+    pub fn level(self, level: i16) -> Self {
+        Self {
+            level: Some(level),
+            ..self
+        }
+    }
+
+    /// Replace the output if it already exists, instead of failing
+    pub fn overwrite(self, overwrite: bool) -> Self {
+        Self { overwrite, ..self }
+    }
+
+    /// Report the stored entries, the bytes compressed and the warnings to `sink`
+    pub fn progress(self, sink: impl ProgressSink + 'static) -> Self {
+        Self {
+            progress: self.progress.with_sink(Arc::new(sink)),
+            ..self
+        }
+    }
+
+    /// Stop compressing once `token` is cancelled
+    pub fn cancellation(self, token: CancellationToken) -> Self {
+        Self {
+            progress: self.progress.with_cancellation(token),
+            ..self
+        }
+    }
+}
+
+/// Settings of [`decompress`]
+#[derive(Debug, Clone, Default)]
+pub struct DecompressOptions {
+    password: Option<Vec<u8>>,
+    overwrite: bool,
+    progress: Progress,
+}
+
+impl DecompressOptions {
+    /// Password of encrypted zip, 7z and rar archives
+    pub fn password(self, password: impl Into<Vec<u8>>) -> Self {
+        Self {
+            password: Some(password.into()),
+            ..self
+        }
+    }
+
+    /// Replace the files that already exist in the output directory, instead of keeping them
+    pub fn overwrite(self, overwrite: bool) -> Self {
+        Self { overwrite, ..self }
+    }
+
+    /// Report the unpacked entries, the bytes of the archive read and the warnings to `sink`
+    pub fn progress(self, sink: impl ProgressSink + 'static) -> Self {
+        Self {
+            progress: self.progress.with_sink(Arc::new(sink)),
+            ..self
+        }
+    }
+
+    /// Stop decompressing once `token` is cancelled
+    pub fn cancellation(self, token: CancellationToken) -> Self {
+        Self {
+            progress: self.progress.with_cancellation(token),
+            ..self
+        }
+    }
+}
+
+/// Formats of the file at `path` in the order they're applied when compressing, like
+/// `[Tar, Gzip]` for `.tar.gz`, from its extensions or from its first bytes if it has none
+pub fn detect_formats(path: &Path) -> crate::Result<Vec<CompressionFormat>> {
+    Ok(flatten_compression_formats(&formats_of(path)?))
+}
+
+/// Compress `inputs` into the file at `output`, in the formats of its extensions.
+///
+/// Fails if `output` already exists, unless [`CompressOptions::overwrite`] is set, and removes the
+/// partial output on failure, including cancellation with [`CompressOptions::cancellation`].
+pub fn compress(inputs: &[PathBuf], output: &Path, options: &CompressOptions) -> crate::Result<()> {
+    if inputs.is_empty() {
+        return Err(FinalError::with_title("No files to compress")
+            .class(ErrorClass::BadArguments)
+            .into());
+    }
+
+    // Like the paths given to the command, so entries are named after the inputs
+    let inputs = inputs.iter().map(fs::canonicalize).collect::<Result<Vec<_>, _>>()?;
+    let formats = extension::extensions_from_path(output);
+    check::check_invalid_compression_with_non_archive_format(&formats, output, &inputs, None, false)?;
+    check::check_archive_formats_position(&formats, output)?;
+    check::check_encryption_recipients(&formats, &[], output)?;
+
+    let already_exists = || Error::AlreadyExists {
+        error_title: format!("{} already exists", EscapedPathDisplay::new(output)),
+    };
+    if !options.overwrite && fs::symlink_metadata(output).is_ok() {
+        return Err(already_exists());
+    }
+    let progress = options.progress.start();
+    progress.check()?;
+
+    let output_file = utils::ask_to_create_file(output, QuestionPolicy::ALWAYS_YES)?.ok_or_else(already_exists)?;
+
+    let buffer_size = default_buffer_size(fs::metadata(&inputs[0]).ok().as_ref());
+    let codec_options = CodecOptions {
+        level: options.level,
+        ..CodecOptions::default()
+    };
+    let mut observer = progress.clone();
+    let result = progress.forward_warnings(|| {
+        compress_files(
+            inputs,
+            formats,
+            Box::new(output_file),
+            crate::commands::compress::CompressOptions {
+                output_path: output,
+                quiet: true,
+                question_policy: QuestionPolicy::ALWAYS_YES,
+                file_visibility_policy: FileVisibilityPolicy::default(),
+                codec_options: &codec_options,
+                reproducible: None,
+                comment: None,
+                name_normalization: NormalizationForm::None,
+                special_files: SpecialFiles::Skip,
+                dedup: false,
+                rate_limit: None,
+                buffer_size,
+                encryption_recipients: &[],
+                index: None,
+                observer: Some(&mut observer),
+            },
+        )
+    });
+
+    match result {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            let _ = fs::remove_file(output);
+            Err(FinalError::with_title(format!(
+                "Compression into {} was cancelled",
+                EscapedPathDisplay::new(output)
+            ))
+            .into())
+        }
+        Err(err) => {
+            let _ = fs::remove_file(output);
+            // Cancelling fails whatever was running with its own error
+            Err(if progress.is_cancelled() { Error::Cancelled } else { err })
+        }
+    }
+}
+
+/// Decompress the file at `archive` into `output_dir`, creating it if needed.
+///
+/// Archives with a single entry at their root are unpacked directly into `output_dir`, others into
+/// a directory named after the archive, like the command does. Outputs that already exist are
+/// kept, unless [`DecompressOptions::overwrite`] is set.
+pub fn decompress(archive: &Path, output_dir: &Path, options: &DecompressOptions) -> crate::Result<()> {
+    let archive = &fs::canonicalize(archive)?;
+    let formats = formats_of(archive)?;
+    let (name, _) = extension::separate_known_extensions_from_name(archive);
+    let file_name = name.file_name().unwrap_or(name.as_os_str());
+
+    utils::create_dir_if_non_existent(output_dir)?;
+
+    let (question_policy, on_conflict) = if options.overwrite {
+        (QuestionPolicy::ALWAYS_YES, ConflictPolicy::Overwrite)
+    } else {
+        (QuestionPolicy::ALWAYS_NO, ConflictPolicy::Skip)
+    };
+
+    let progress = options.progress.start();
+    progress.check()?;
+
+    let result = progress.forward_warnings(|| {
+        decompress_file(crate::commands::decompress::DecompressOptions {
+            input_file_path: archive,
+            formats,
+            output_dir,
+            output_file_path: output_dir.join(file_name),
+            question_policy,
+            temp_dir: None,
+            unpack_options: UnpackOptions {
+                password: options.password.as_deref(),
+                name_encoding: None,
+                quiet: true,
+                permissions: PermissionsPolicy::safe(),
+                normalization: NormalizationForm::None,
+                transforms: &[],
+                keep_directory_structure: true,
+                on_conflict,
+                skip_identical: None,
+                question_policy,
+                progress: Some(&progress),
+                root_entries: None,
+            },
+            remove: false,
+            verify: false,
+            rate_limit: None,
+            buffer_size: default_buffer_size(fs::metadata(archive).ok().as_ref()),
+            print0: false,
+            on_conflict: Some(on_conflict),
+            in_place: false,
+            strip_common_prefix: false,
+            filter_cmd: None,
+        })
+    });
+
+    match result {
+        // Cancelling fails whatever was running with its own error
+        Err(_) if progress.is_cancelled() => Err(Error::Cancelled),
+        result => result,
+    }
+}
+
+/// Entries of the archive at `archive`, with `password` for encrypted zip, 7z and rar archives
+pub fn list_archive(archive: &Path, password: Option<&[u8]>) -> crate::Result<Vec<FileInArchive>> {
+    let extensions = formats_of(archive)?;
+    if !extensions[0].is_archive() {
+        return Err(
+            FinalError::with_title(format!("Cannot list {}", EscapedPathDisplay::new(archive)))
+                .detail("It is not an archive")
+                .class(ErrorClass::UnsupportedFormat)
+                .into(),
+        );
+    }
+
+    let formats = flatten_compression_formats(&extensions);
+    archive_entries(archive, &formats, password, None, true)?
+        .files
+        .collect()
+}
+
+/// Formats of the file at `path`, from its extensions or from its first bytes if it has none
+fn formats_of(path: &Path) -> crate::Result<Vec<Extension>> {
+    let mut formats = extension::extensions_from_path(path);
+    if formats.is_empty() {
+        formats.extend(utils::try_infer_extension(path));
+    }
+
+    if formats.is_empty() {
+        return Err(
+            FinalError::with_title(format!("Cannot detect the format of {}", EscapedPathDisplay::new(path)))
+                .hint_all_supported_formats()
+                .class(ErrorClass::UnsupportedFormat)
+                .into(),
+        );
+    }
+
+    Ok(formats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    };
+
+    use super::*;
+    use crate::extension::CompressionFormat::*;
+
+    #[derive(Default)]
+    struct Recorder {
+        entries: Mutex<Vec<PathBuf>>,
+        bytes: AtomicU64,
+    }
+
+    impl ProgressSink for Arc<Recorder> {
+        fn bytes_processed(&self, bytes: u64) {
+            self.bytes.store(bytes, Ordering::Relaxed);
+        }
+
+        fn entry(&self, path: &Path) {
+            self.entries.lock().unwrap().push(path.to_path_buf());
+        }
+    }
+
+    struct CancelOnEntry(CancellationToken);
+
+    impl ProgressSink for CancelOnEntry {
+        fn entry(&self, _: &Path) {
+            self.0.cancel();
+        }
+    }
+
+    #[test]
+    fn test_progress_and_cancellation() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        std::fs::create_dir(&input).unwrap();
+        std::fs::write(input.join("a"), "aaaa").unwrap();
+        std::fs::write(input.join("b"), "bbbbbb").unwrap();
+
+        let recorder = Arc::new(Recorder::default());
+        let output = dir.path().join("input.tar.gz");
+        let options = CompressOptions::default().progress(Arc::clone(&recorder));
+        compress(std::slice::from_ref(&input), &output, &options).unwrap();
+        let mut entries = recorder.entries.lock().unwrap().clone();
+        entries.sort();
+        assert_eq!(entries, ["input", "input/a", "input/b"].map(PathBuf::from));
+        assert_eq!(recorder.bytes.load(Ordering::Relaxed), 10);
+
+        let recorder = Arc::new(Recorder::default());
+        let options = DecompressOptions::default().progress(Arc::clone(&recorder));
+        decompress(&output, &dir.path().join("out"), &options).unwrap();
+        assert_eq!(recorder.entries.lock().unwrap().len(), 3);
+        // The decoders can stop before the end of the archive, like the padding of tar archives
+        let bytes = recorder.bytes.load(Ordering::Relaxed);
+        assert!(bytes > 0 && bytes <= std::fs::metadata(&output).unwrap().len());
+
+        // Plain tar files are read with seeks instead
+        let tar = dir.path().join("input.tar");
+        compress(std::slice::from_ref(&input), &tar, &CompressOptions::default()).unwrap();
+        let recorder = Arc::new(Recorder::default());
+        let options = DecompressOptions::default().progress(Arc::clone(&recorder));
+        decompress(&tar, &dir.path().join("out-tar"), &options).unwrap();
+        assert_eq!(recorder.entries.lock().unwrap().len(), 3);
+        let bytes = recorder.bytes.load(Ordering::Relaxed);
+        assert!(bytes > 0 && bytes <= std::fs::metadata(&tar).unwrap().len());
+
+        // Cancelled by the sink after the first entry
+        let token = CancellationToken::new();
+        let cancelled = dir.path().join("cancelled.zip");
+        let options = CompressOptions::default()
+            .progress(CancelOnEntry(token.clone()))
+            .cancellation(token.clone());
+        let err = compress(std::slice::from_ref(&input), &cancelled, &options).unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+        assert!(!cancelled.exists());
+
+        // Cancelled before starting
+        let options = CompressOptions::default().cancellation(token.clone());
+        assert!(matches!(
+            compress(&[input], &cancelled, &options),
+            Err(Error::Cancelled)
+        ));
+
+        let token = CancellationToken::new();
+        let options = DecompressOptions::default()
+            .progress(CancelOnEntry(token.clone()))
+            .cancellation(token);
+        let out = dir.path().join("cancelled");
+        let err = decompress(&output, &out, &options).unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+        assert!(!out.join("input").exists());
+    }
+
+    #[test]
+    fn test_compress_in_single_thread_pool() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        std::fs::create_dir(&input).unwrap();
+        for name in ["a", "b", "c"] {
+            std::fs::write(input.join(name), name).unwrap();
+        }
+
+        // The only thread of the pool waits for the files to be read
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        for output in ["input.tar", "input.zip"] {
+            let output = dir.path().join(output);
+            pool.install(|| compress(std::slice::from_ref(&input), &output, &CompressOptions::default()))
+                .unwrap();
+            assert!(output.exists());
+        }
+    }
+
+    #[test]
+    fn test_detect_formats() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert_eq!(detect_formats(Path::new("a.tar.gz")).unwrap(), [Tar, Gzip]);
+        assert_eq!(detect_formats(Path::new("a.tzst")).unwrap(), [Tar, Zstd]);
+
+        // Without extensions, from the first bytes
+        let file = dir.path().join("archive");
+        std::fs::write(&file, [0x28, 0xB5, 0x2F, 0xFD, 0, 0, 0, 0]).unwrap();
+        assert_eq!(detect_formats(&file).unwrap(), [Zstd]);
+
+        let file = dir.path().join("text");
+        std::fs::write(&file, "not compressed").unwrap();
+        let err = detect_formats(&file).unwrap_err();
+        assert_eq!(err.class(), ErrorClass::UnsupportedFormat);
+    }
+}
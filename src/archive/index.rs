@@ -0,0 +1,177 @@
+//! Index side-files of tar archives, written by `--index` next to the archive, like `backup.tar.zst.idx`.
+//!
+//! The index has the offset of every entry in the uncompressed tar stream, so archives are listed
+//! without reading them, and unpacking some of their entries seeks straight to them when the stream
+//! can be read at any position: plain `.tar` files, and `.tar.zst` written with `--seekable`.
+//!
+//! All numbers are little-endian. The file starts with the magic bytes `OUCHIDX1` and the size of
+//! the archive (u64), to ignore indexes left behind by an older archive. Then, for every entry:
+//! its offset, size and modification time in seconds since the epoch (u64), 1 if it's a directory
+//! or 0 otherwise (u8), and the length (u32) and bytes of its name.
+
+use std::{
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+
+use crate::{
+    error::{Error, ErrorClass, FinalError},
+    utils::{
+        io::{joined_path, JoinedParts},
+        logger::warning,
+        EscapedPathDisplay,
+    },
+};
+
+const MAGIC: &[u8; 8] = b"OUCHIDX1";
+
+/// Entry of a tar archive, and where it starts in the uncompressed stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub path: PathBuf,
+    pub is_dir: bool,
+    /// Offset of the first header of the entry
+    pub offset: u64,
+    /// Size of the contents of the entry
+    pub size: u64,
+    /// Modification time of the entry, in seconds since the epoch
+    pub mtime: u64,
+}
+
+/// Entries of a tar archive, in the order they're stored
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ArchiveIndex {
+    pub entries: Vec<IndexEntry>,
+}
+
+/// Path of the index of the archive at `archive_path`, split files share the index of the whole file
+pub fn index_path(archive_path: &Path) -> PathBuf {
+    let mut path = joined_path(archive_path)
+        .unwrap_or_else(|| archive_path.to_path_buf())
+        .into_os_string();
+    path.push(".idx");
+    path.into()
+}
+
+/// Size of the archive at `archive_path`, adding up all the parts of split files
+fn archive_size(archive_path: &Path) -> io::Result<u64> {
+    JoinedParts::open(archive_path)?.seek(SeekFrom::End(0))
+}
+
+#[cfg(unix)]
+fn name_to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn name_to_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+fn name_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+    Some(OsString::from_vec(bytes).into())
+}
+
+#[cfg(not(unix))]
+fn name_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+/// Write `index` next to the archive at `archive_path`, which must be complete
+pub fn write_index(archive_path: &Path, index: &ArchiveIndex) -> crate::Result<()> {
+    let mut writer = BufWriter::new(fs::File::create(index_path(archive_path))?);
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&archive_size(archive_path)?.to_le_bytes())?;
+    for entry in &index.entries {
+        let name = name_to_bytes(&entry.path);
+        writer.write_all(&entry.offset.to_le_bytes())?;
+        writer.write_all(&entry.size.to_le_bytes())?;
+        writer.write_all(&entry.mtime.to_le_bytes())?;
+        writer.write_all(&[entry.is_dir as u8])?;
+        writer.write_all(&(name.len() as u32).to_le_bytes())?;
+        writer.write_all(&name)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Read the index of the archive at `archive_path`, `None` if it has none or if it belongs to
+/// another version of the archive
+pub fn read_index(archive_path: &Path) -> crate::Result<Option<ArchiveIndex>> {
+    let path = index_path(archive_path);
+    let mut reader = match fs::File::open(&path) {
+        Ok(file) => BufReader::new(file),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    let corrupted = || -> Error {
+        FinalError::with_title(format!("Corrupted index '{}'", EscapedPathDisplay::new(&path)))
+            .hint("Remove the index to read the archive without it")
+            .class(ErrorClass::CorruptArchive)
+            .into()
+    };
+
+    let mut magic = [0; MAGIC.len()];
+    reader.read_exact(&mut magic).map_err(|_| corrupted())?;
+    if &magic != MAGIC {
+        return Err(corrupted());
+    }
+
+    if read_u64(&mut reader).map_err(|_| corrupted())? != archive_size(archive_path)? {
+        warning(format!(
+            "Ignoring the index '{}', it was written for another version of the archive",
+            EscapedPathDisplay::new(&path)
+        ));
+        return Ok(None);
+    }
+
+    let mut index = ArchiveIndex::default();
+    while !reader.fill_buf()?.is_empty() {
+        let entry = (|| {
+            let offset = read_u64(&mut reader).ok()?;
+            let size = read_u64(&mut reader).ok()?;
+            let mtime = read_u64(&mut reader).ok()?;
+            let mut header = [0; 5];
+            reader.read_exact(&mut header).ok()?;
+            let mut name = vec![0; u32::from_le_bytes(header[1..].try_into().unwrap()) as usize];
+            reader.read_exact(&mut name).ok()?;
+
+            Some(IndexEntry {
+                path: name_from_bytes(name)?,
+                is_dir: header[0] != 0,
+                offset,
+                size,
+                mtime,
+            })
+        })();
+        index.entries.push(entry.ok_or_else(corrupted)?);
+    }
+
+    Ok(Some(index))
+}
+
+/// Error for an index that doesn't match the entries of its archive
+pub fn mismatch_error(path: &Path) -> Error {
+    FinalError::with_title("The index doesn't match the archive")
+        .detail(format!(
+            "Could not find '{}' at its offset",
+            EscapedPathDisplay::new(path)
+        ))
+        .hint("Remove the index to read the archive without it")
+        .class(ErrorClass::CorruptArchive)
+        .into()
+}
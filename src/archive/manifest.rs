@@ -0,0 +1,198 @@
+//! Manifests of compressed archives, written by `--manifest` as a JSON file listing every stored
+//! entry with its size, mode, modification time and the SHA-512 of its contents.
+//!
+//! The archive builders report the entries they store to an [`ArchiveObserver`], [`Manifest`] is
+//! the one collecting them for `--manifest`.
+
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use super::Reproducible;
+
+/// Told about every entry stored by an archive builder, in the order they're stored
+pub trait ArchiveObserver {
+    /// An error stops building the archive
+    fn entry_stored(&mut self, entry: StoredEntry) -> crate::Result<()>;
+
+    /// Told about `bytes` more compressed by single file formats, which have no entries, an error
+    /// stops compressing
+    fn bytes_compressed(&mut self, _bytes: u64) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether [`StoredEntry::content_hash`] is needed, computing it reads every file once more
+    fn wants_content_hash(&self) -> bool {
+        true
+    }
+}
+
+/// Writer telling `observer` about the bytes written to it
+pub struct ObservedWriter<'a, W: ?Sized> {
+    inner: &'a mut W,
+    observer: &'a mut dyn ArchiveObserver,
+}
+
+impl<'a, W: Write + ?Sized> ObservedWriter<'a, W> {
+    pub fn new(inner: &'a mut W, observer: &'a mut dyn ArchiveObserver) -> Self {
+        Self { inner, observer }
+    }
+}
+
+impl<W: Write + ?Sized> Write for ObservedWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.observer.bytes_compressed(written as u64)?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Entry as it was stored in the archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredEntry {
+    pub name: PathBuf,
+    pub is_dir: bool,
+    /// Size of the contents, zero for directories and special files
+    pub size: u64,
+    /// Unix permissions, made up from the kind of entry on other systems
+    pub mode: u32,
+    /// Modification time in seconds since the Unix epoch, the fixed one in reproducible mode
+    pub mtime: u64,
+    /// SHA-512 of the contents of files
+    pub content_hash: Option<[u8; 64]>,
+}
+
+impl StoredEntry {
+    pub fn new(
+        name: PathBuf,
+        metadata: &std::fs::Metadata,
+        reproducible: Option<Reproducible>,
+        content_hash: Option<[u8; 64]>,
+    ) -> Self {
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            metadata.permissions().mode() & 0o7777
+        };
+        #[cfg(not(unix))]
+        let mode = match (metadata.is_dir(), metadata.permissions().readonly()) {
+            (true, _) => 0o755,
+            (false, true) => 0o444,
+            (false, false) => 0o644,
+        };
+
+        let mtime = match reproducible {
+            Some(reproducible) => reproducible.mtime,
+            None => metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |mtime| mtime.as_secs()),
+        };
+
+        Self {
+            name,
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_file() { metadata.len() } else { 0 },
+            mode,
+            mtime,
+            content_hash,
+        }
+    }
+}
+
+/// Entries collected for `--manifest`
+#[derive(Debug, Default)]
+pub struct Manifest {
+    entries: Vec<StoredEntry>,
+}
+
+impl ArchiveObserver for Manifest {
+    fn entry_stored(&mut self, entry: StoredEntry) -> crate::Result<()> {
+        self.entries.push(entry);
+        Ok(())
+    }
+}
+
+impl Manifest {
+    /// The manifest as JSON, `archive_path` is the archive it describes
+    pub fn to_json(&self, archive_path: &Path) -> serde_json::Value {
+        let entries: Vec<_> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let sha512 = entry
+                    .content_hash
+                    .map(|hash| hash.iter().map(|byte| format!("{byte:02x}")).collect::<String>());
+
+                serde_json::json!({
+                    "path": entry.name.to_string_lossy(),
+                    "type": if entry.is_dir { "directory" } else { "file" },
+                    "size": entry.size,
+                    "mode": format!("{:04o}", entry.mode),
+                    "mtime": entry.mtime,
+                    "sha512": sha512,
+                })
+            })
+            .collect();
+
+        serde_json::json!({
+            "archive": archive_path.to_string_lossy(),
+            "entries": entries,
+        })
+    }
+
+    /// Write the manifest to `writer`, pretty-printed
+    pub fn write(&self, archive_path: &Path, mut writer: impl Write) -> crate::Result<()> {
+        writeln!(writer, "{:#}", self.to_json(archive_path))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fs_err as fs;
+
+    use super::*;
+
+    #[test]
+    fn test_manifest_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.txt");
+        fs::write(&file, "contents").unwrap();
+
+        let mut manifest = Manifest::default();
+        let reproducible = Some(Reproducible { mtime: 1_700_000_000 });
+        manifest
+            .entry_stored(StoredEntry::new(
+                "dir".into(),
+                &dir.path().metadata().unwrap(),
+                reproducible,
+                None,
+            ))
+            .unwrap();
+        manifest
+            .entry_stored(StoredEntry::new(
+                "dir/file.txt".into(),
+                &file.metadata().unwrap(),
+                reproducible,
+                Some([0xab; 64]),
+            ))
+            .unwrap();
+
+        let json = manifest.to_json(Path::new("archive.tar"));
+        assert_eq!(json["archive"], "archive.tar");
+        assert_eq!(json["entries"][0]["type"], "directory");
+        assert_eq!(json["entries"][0]["sha512"], serde_json::Value::Null);
+        assert_eq!(json["entries"][1]["path"], "dir/file.txt");
+        assert_eq!(json["entries"][1]["size"], 8);
+        assert_eq!(json["entries"][1]["mtime"], 1_700_000_000);
+        assert_eq!(json["entries"][1]["sha512"], "ab".repeat(64));
+    }
+}
@@ -1,9 +1,268 @@
 //! Archive compression algorithms
 
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use filetime_creation::{set_file_mtime, FileTime};
+use fs_err as fs;
+
+use crate::{
+    options::{ConflictPolicy, NormalizationForm},
+    progress::Progress,
+    utils::{self, checksum::crc32_from_reader, PermissionsPolicy, Transform},
+    QuestionPolicy,
+};
+
+pub mod index;
+pub mod manifest;
 #[cfg(feature = "unrar")]
 pub mod rar;
 #[cfg(not(feature = "unrar"))]
 pub mod rar_stub;
 pub mod sevenz;
+pub mod squashfs;
 pub mod tar;
+pub mod walk;
 pub mod zip;
+
+/// Options shared by the unpackers of every archive format
+#[derive(Debug, Clone, Copy)]
+pub struct UnpackOptions<'a> {
+    /// Password for encrypted archives
+    pub password: Option<&'a [u8]>,
+    /// Encoding of the names of zip entries not flagged as UTF-8, CP437 if `None`
+    pub name_encoding: Option<&'static encoding_rs::Encoding>,
+    /// Don't print a message for each unpacked file
+    pub quiet: bool,
+    /// How to apply the permissions stored in the archive
+    pub permissions: PermissionsPolicy,
+    /// Unicode normalization applied to entry names
+    pub normalization: NormalizationForm,
+    /// Rules given to `--transform`, applied to entry names before unpacking them
+    pub transforms: &'a [Transform],
+    /// Unpack entries in their directories, when unset every file is unpacked directly into the
+    /// output folder and directories are skipped
+    pub keep_directory_structure: bool,
+    /// What to do with entries whose path is already taken
+    pub on_conflict: ConflictPolicy,
+    /// Skip the entries whose path is taken by a file with the same contents, told apart like this
+    pub skip_identical: Option<IdenticalBy>,
+    /// Answers the questions asked by [`ConflictPolicy::Ask`]
+    pub question_policy: QuestionPolicy,
+    /// Told about every entry before it's unpacked, stopping the unpackers once cancelled
+    pub progress: Option<&'a Progress>,
+    /// Collects the metadata of the directories at the root of the archive
+    pub root_entries: Option<&'a RootEntries>,
+}
+
+/// How `--skip-identical` tells that an existing file has the contents of an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdenticalBy {
+    /// Same size and modification time, in whole seconds
+    SizeAndMtime,
+    /// Same size and CRC32, or size and modification time for the formats that don't store it
+    Checksum,
+}
+
+/// Size and CRC32 of an entry stored in the archive, compared by [`UnpackOptions::skip_identical`],
+/// unknown ones never match
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StoredContents {
+    pub size: Option<u64>,
+    pub crc32: Option<u32>,
+}
+
+/// Modification time and mode of a directory entry
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DirMetadata {
+    pub mtime: Option<SystemTime>,
+    pub mode: Option<u32>,
+}
+
+/// Metadata of the directories at the root of an archive, by their path relative to the output
+/// folder, which is empty for the root itself, like the "./" entry of `tar -C dir .`
+///
+/// Unpacking the files inside of them changes their modification time, and the archive root is
+/// unpacked into a temporary directory, so it's restored once they're moved to the output.
+#[derive(Debug, Default)]
+pub struct RootEntries(Mutex<HashMap<PathBuf, DirMetadata>>);
+
+impl RootEntries {
+    pub fn get(&self, path: &Path) -> Option<DirMetadata> {
+        self.0.lock().unwrap().get(path).copied()
+    }
+
+    fn insert(&self, path: PathBuf, metadata: DirMetadata) {
+        self.0.lock().unwrap().insert(path, metadata);
+    }
+}
+
+impl UnpackOptions<'_> {
+    /// Where the entry at `path` goes in `output_folder` after renaming it, before checking whether
+    /// the path is taken, or `None` if it should be skipped
+    pub fn renamed_path(&self, output_folder: &Path, path: &Path, is_dir: bool) -> Option<PathBuf> {
+        let path = utils::unpacked_entry_path(path, self.normalization, self.transforms)?;
+
+        match (self.keep_directory_structure, path.file_name()) {
+            (true, _) => Some(output_folder.join(path)),
+            (false, Some(file_name)) if !is_dir => Some(output_folder.join(file_name)),
+            (false, _) => None,
+        }
+    }
+
+    /// Where to unpack the entry at `path` in `output_folder`, or `None` if it should be skipped
+    ///
+    /// The name is given by [`utils::unpacked_entry_path`], when flattening files are unpacked
+    /// under their file name. If the path is taken, [`utils::resolve_conflict`] decides what to
+    /// do, comparing `mtime`, the modification time of the entry, for [`ConflictPolicy::UpdateNewer`].
+    ///
+    /// The `mtime` and `mode` of directories at the root of `output_folder` go to
+    /// [`UnpackOptions::root_entries`]. Files are skipped if [`UnpackOptions::skip_identical`]
+    /// finds that the one at their path already has their `contents`.
+    pub fn unpacked_path(
+        &self,
+        output_folder: &Path,
+        path: &Path,
+        is_dir: bool,
+        mtime: Option<SystemTime>,
+        mode: Option<u32>,
+        contents: StoredContents,
+    ) -> crate::Result<Option<PathBuf>> {
+        if let Some(progress) = self.progress {
+            progress.entry(path)?;
+        }
+
+        let Some(destination) = self.renamed_path(output_folder, path, is_dir) else {
+            return Ok(None);
+        };
+        // Links made by earlier entries could lead anywhere
+        utils::check_symlinks_stay_inside(output_folder, &destination, false)?;
+
+        if let (true, Some(root_entries)) = (is_dir, self.root_entries) {
+            // "dir/." has the same components as "dir"
+            let relative = destination.strip_prefix(output_folder).unwrap_or(&destination);
+            if relative.components().count() <= 1 {
+                root_entries.insert(relative.to_owned(), DirMetadata { mtime, mode });
+            }
+        }
+
+        // Directories are merged with the existing ones
+        if is_dir && destination.is_dir() {
+            utils::check_symlinks_stay_inside(output_folder, &destination, true)?;
+            return Ok(Some(destination));
+        }
+
+        if let Some(identical_by) = self.skip_identical.filter(|_| !is_dir) {
+            if is_identical(&destination, identical_by, mtime, contents)? {
+                return Ok(None);
+            }
+        }
+
+        utils::resolve_conflict(&destination, self.on_conflict, self.question_policy, mtime)
+    }
+}
+
+/// Whether the file at `path` has the `contents` and `mtime` of an entry, compared `identical_by`
+fn is_identical(
+    path: &Path,
+    identical_by: IdenticalBy,
+    mtime: Option<SystemTime>,
+    contents: StoredContents,
+) -> crate::Result<bool> {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return Ok(false);
+    };
+    if !metadata.is_file() || contents.size != Some(metadata.len()) {
+        return Ok(false);
+    }
+
+    let identical = match (identical_by, contents.crc32) {
+        (IdenticalBy::Checksum, Some(crc32)) => {
+            let (_, existing_crc32) = crc32_from_reader(&mut fs::File::open(path)?)?;
+            existing_crc32 == crc32
+        }
+        _ => {
+            let seconds = |time: SystemTime| time.duration_since(UNIX_EPOCH).map(|time| time.as_secs()).ok();
+            mtime
+                .zip(metadata.modified().ok())
+                .is_some_and(|(new, existing)| seconds(new).is_some() && seconds(new) == seconds(existing))
+        }
+    };
+    Ok(identical)
+}
+
+/// Modification times of the unpacked directories, set once every entry is unpacked, since
+/// unpacking the files inside of a directory changes its modification time
+#[derive(Debug, Default)]
+pub struct DirectoryMtimes(Vec<(PathBuf, SystemTime)>);
+
+impl DirectoryMtimes {
+    pub fn push(&mut self, path: PathBuf, mtime: Option<SystemTime>) {
+        if let Some(mtime) = mtime {
+            self.0.push((path, mtime));
+        }
+    }
+
+    /// Set the modification times, children before their parents
+    pub fn restore(self) -> crate::Result<()> {
+        for (path, mtime) in self.0.into_iter().rev() {
+            set_file_mtime(&path, FileTime::from_system_time(mtime))?;
+        }
+        Ok(())
+    }
+}
+
+/// Settings for building archives that are byte-identical when built from the same files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reproducible {
+    /// Modification time written to every entry, in seconds since the Unix epoch
+    pub mtime: u64,
+}
+
+impl Reproducible {
+    /// 1980-01-01 00:00:00 UTC, the earliest time that zip archives can store
+    pub const DEFAULT_MTIME: u64 = 315_532_800;
+}
+
+/// Size and CRC32 of a single archive entry, used to compare archive contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntrySummary {
+    /// The entry path inside of the archive
+    pub path: PathBuf,
+    /// Whether this entry is a directory
+    pub is_dir: bool,
+    /// Uncompressed size in bytes, always zero for directories
+    pub size: u64,
+    /// CRC32 of the uncompressed contents, always zero for directories
+    pub crc32: u32,
+}
+
+/// Metadata of a whole archive, without its entries, shown by `ouch info`
+#[derive(Debug, Default)]
+pub struct ArchiveInfo {
+    pub files: u64,
+    pub directories: u64,
+    /// Total uncompressed size of the files
+    pub unpacked_size: u64,
+    /// Number of entries with encrypted contents
+    pub encrypted_entries: u64,
+    /// Metadata specific to the format, like the zip archive comment
+    pub details: Vec<(&'static str, String)>,
+}
+
+impl ArchiveInfo {
+    /// Count an entry, the size of directories is ignored
+    pub fn add_entry(&mut self, is_dir: bool, size: u64, encrypted: bool) {
+        if is_dir {
+            self.directories += 1;
+        } else {
+            self.files += 1;
+            self.unpacked_size += size;
+        }
+        self.encrypted_entries += u64::from(encrypted);
+    }
+}
@@ -4,23 +4,19 @@ use std::path::Path;
 
 use unrar::Archive;
 
+#[cfg(unix)]
+use crate::utils::set_unpacked_permissions;
 use crate::{
+    archive::{ArchiveInfo, EntrySummary, StoredContents, UnpackOptions},
     error::{Error, Result},
     list::FileInArchive,
     utils::logger::info,
 };
 
 /// Unpacks the archive given by `archive_path` into the folder given by `output_folder`.
-/// Assumes that output_folder is empty
-pub fn unpack_archive(
-    archive_path: &Path,
-    output_folder: &Path,
-    password: Option<&[u8]>,
-    quiet: bool,
-) -> crate::Result<usize> {
-    assert!(output_folder.read_dir().expect("dir exists").count() == 0);
-
-    let archive = match password {
+/// Entries whose path is already taken are resolved with `options.on_conflict`
+pub fn unpack_archive(archive_path: &Path, output_folder: &Path, options: UnpackOptions) -> crate::Result<usize> {
+    let archive = match options.password {
         Some(password) => Archive::with_password(archive_path, password),
         None => Archive::new(archive_path),
     };
@@ -30,8 +26,21 @@ pub fn unpack_archive(
 
     while let Some(header) = archive.read_header()? {
         let entry = header.entry();
-        archive = if entry.is_file() {
-            if !quiet {
+        // The modification time isn't compared, unrar only exposes it in the DOS format
+        let contents = StoredContents {
+            size: Some(entry.unpacked_size),
+            crc32: Some(entry.file_crc),
+        };
+        let file_path = options.unpacked_path(
+            output_folder,
+            &entry.filename,
+            entry.is_directory(),
+            None,
+            None,
+            contents,
+        )?;
+        archive = if let Some(file_path) = file_path.filter(|_| entry.is_file()) {
+            if !options.quiet {
                 info(format!(
                     "{} extracted. ({})",
                     entry.filename.display(),
@@ -39,7 +48,30 @@ pub fn unpack_archive(
                 ));
             }
             unpacked += 1;
-            header.extract_with_base(output_folder)?
+            let is_renamed = file_path != output_folder.join(&entry.filename);
+            let archive = if !is_renamed {
+                header.extract_with_base(output_folder)?
+            } else {
+                if let Some(parent) = file_path.parent() {
+                    fs_err::create_dir_all(parent)?;
+                }
+                header.extract_to(&file_path)?
+            };
+
+            // unrar restores the stored permissions as they are, apply the policy on top of them
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+
+                // Links have no permissions of their own, changing them would change their target's
+                let metadata = fs_err::symlink_metadata(&file_path)?;
+                let mode = metadata.permissions().mode();
+                if !metadata.is_symlink() && mode & options.permissions.mask() != 0 {
+                    set_unpacked_permissions(&file_path, mode, options.permissions)?;
+                }
+            }
+
+            archive
         } else {
             header.skip()?
         };
@@ -61,12 +93,70 @@ pub fn list_archive(
     Ok(archive.open_for_listing()?.map(|item| {
         let item = item?;
         let is_dir = item.is_directory();
+        let encrypted = Some(item.is_encrypted());
         let path = item.filename;
 
-        Ok(FileInArchive { path, is_dir })
+        // The DOS times of the headers aren't read, incremental backups include these files
+        Ok(FileInArchive {
+            path,
+            is_dir,
+            mtime: None,
+            comment: None,
+            method: None,
+            encrypted,
+        })
     }))
 }
 
+/// List contents of `archive_path`, returning the sizes and checksums stored in each entry header
+pub fn summarize_archive(archive_path: &Path, password: Option<&[u8]>) -> Result<Vec<EntrySummary>> {
+    let archive = match password {
+        Some(password) => Archive::with_password(archive_path, password),
+        None => Archive::new(archive_path),
+    };
+
+    archive
+        .open_for_listing()?
+        .map(|item| {
+            let item = item?;
+            let is_dir = item.is_directory();
+            let (size, crc32) = if is_dir {
+                (0, 0)
+            } else {
+                (item.unpacked_size, item.file_crc)
+            };
+
+            Ok(EntrySummary {
+                path: item.filename,
+                is_dir,
+                size,
+                crc32,
+            })
+        })
+        .collect()
+}
+
+/// Counts the entries of the archive at `archive_path` from their headers
+pub fn archive_info(archive_path: &Path, password: Option<&[u8]>) -> Result<ArchiveInfo> {
+    let archive = match password {
+        Some(password) => Archive::with_password(archive_path, password),
+        None => Archive::new(archive_path),
+    };
+    let multipart = archive.is_multipart();
+
+    let mut info = ArchiveInfo::default();
+    for item in archive.open_for_listing()? {
+        let item = item?;
+        info.add_entry(item.is_directory(), item.unpacked_size, item.is_encrypted());
+    }
+
+    if multipart {
+        info.details.push(("Multipart", "yes".into()));
+    }
+
+    Ok(info)
+}
+
 pub fn no_compression() -> Error {
     Error::UnsupportedFormat {
         reason: "Creating RAR archives is not allowed due to licensing restrictions.".into(),
@@ -1,9 +1,11 @@
 //! SevenZip archive format compress function
 
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
 use std::{
     env,
     io::{self, Read, Seek, Write},
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
 
 use bstr::ByteSlice;
@@ -11,22 +13,43 @@ use fs_err as fs;
 use same_file::Handle;
 use sevenz_rust::SevenZArchiveEntry;
 
+#[cfg(unix)]
+use crate::utils::set_unpacked_permissions;
 use crate::{
+    archive::{
+        manifest::{ArchiveObserver, StoredEntry},
+        walk, ArchiveInfo, DirectoryMtimes, EntrySummary, StoredContents, UnpackOptions,
+    },
     error::{Error, FinalError, Result},
     list::FileInArchive,
+    options::{NormalizationForm, SpecialFiles},
     utils::{
         cd_into_same_dir_as,
+        checksum::{crc32_from_reader, sha512_from_reader},
+        is_path_stdin,
         logger::{info, warning},
-        Bytes, EscapedPathDisplay, FileVisibilityPolicy,
+        normalize_path, symlink_loop, Bytes, EscapedPathDisplay, FileVisibilityPolicy,
     },
 };
 
+/// Attribute set by p7zip in entries that carry unix permissions in the high 16 bits
+#[cfg(unix)]
+const FILE_ATTRIBUTE_UNIX_EXTENSION: u32 = 0x8000;
+/// Windows attribute for directories, set along with the unix permissions
+#[cfg(unix)]
+const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x10;
+
+/// Compresses `files` into `writer`, stored entries are reported to `observer`
+#[allow(clippy::too_many_arguments)]
 pub fn compress_sevenz<W>(
     files: &[PathBuf],
     output_path: &Path,
     writer: W,
     file_visibility_policy: FileVisibilityPolicy,
     quiet: bool,
+    name_normalization: NormalizationForm,
+    special_files: SpecialFiles,
+    mut observer: Option<&mut dyn ArchiveObserver>,
 ) -> crate::Result<W>
 where
     W: Write + Seek,
@@ -35,18 +58,53 @@ where
     let output_handle = Handle::from_path(output_path);
 
     for filename in files {
+        if is_path_stdin(filename) {
+            let entry_name = walk::stdin_entry_name(name_normalization)?;
+            push_temp_file(
+                &mut writer,
+                walk::spool_stdin()?,
+                &entry_name,
+                quiet,
+                observer.as_deref_mut(),
+            )?;
+            continue;
+        }
+
         let previous_location = cd_into_same_dir_as(filename)?;
 
-        // Unwrap safety:
-        //   paths should be canonicalized by now, and the root directory rejected.
-        let filename = filename.file_name().unwrap();
+        // Paths should be canonicalized by now, and the root directory rejected
+        let Some(filename) = filename.file_name() else {
+            return Err(Error::CompressingRootFolder);
+        };
 
         for entry in file_visibility_policy.build_walker(filename) {
-            let entry = entry?;
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => match symlink_loop(&err) {
+                    Some(path) => {
+                        walk::warn_symlink_loop(path);
+                        continue;
+                    }
+                    None => return Err(err.into()),
+                },
+            };
             let path = entry.path();
 
+            let metadata = match path.metadata() {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    if e.kind() == std::io::ErrorKind::NotFound && path.is_symlink() {
+                        // This path is for a broken symlink, ignore it
+                        continue;
+                    }
+                    return Err(e.into());
+                }
+            };
+
             // If the output_path is the same as the input file, warn the user and skip the input (in order to avoid compression recursion)
-            if let Ok(handle) = &output_handle {
+            // Special files aren't compared, opening a FIFO would block until something writes to it
+            let special_file_kind = walk::special_file_kind(metadata.file_type());
+            if let (Ok(handle), None) = (&output_handle, special_file_kind) {
                 if matches!(Handle::from_path(path), Ok(x) if &x == handle) {
                     warning(format!(
                         "Cannot compress `{}` into itself, skipping",
@@ -57,6 +115,11 @@ where
                 }
             }
 
+            let entry_name = normalize_path(path, name_normalization);
+            if walk::is_unchanged(&entry_name, &metadata) {
+                continue;
+            }
+
             // This is printed for every file in `input_filenames` and has
             // little importance for most users, but would generate lots of
             // spoken text for users using screen readers, braille displays
@@ -65,70 +128,166 @@ where
                 info(format!("Compressing '{}'", EscapedPathDisplay::new(path)));
             }
 
-            let metadata = match path.metadata() {
-                Ok(metadata) => metadata,
-                Err(e) => {
-                    if e.kind() == std::io::ErrorKind::NotFound && path.is_symlink() {
-                        // This path is for a broken symlink, ignore it
-                        continue;
-                    }
-                    return Err(e.into());
+            // 7z has no way to store special files, and reading a FIFO would block
+            if let Some(kind) = special_file_kind {
+                if walk::keep_special_file(path, kind, special_files)? {
+                    walk::warn_special_file_not_stored(path, kind, "7z");
                 }
-            };
+                continue;
+            }
 
-            let entry_name = path.to_str().ok_or_else(|| {
+            let entry_name = entry_name.to_str().ok_or_else(|| {
                 FinalError::with_title("7z requires that all entry names are valid UTF-8")
                     .detail(format!("File at '{path:?}' has a non-UTF-8 name"))
             })?;
 
-            let entry = sevenz_rust::SevenZArchiveEntry::from_path(path, entry_name.to_owned());
+            #[allow(unused_mut)]
+            let mut entry = sevenz_rust::SevenZArchiveEntry::from_path(path, entry_name.to_owned());
+
+            #[cfg(unix)]
+            {
+                let directory_attribute = if metadata.is_dir() { FILE_ATTRIBUTE_DIRECTORY } else { 0 };
+                entry.has_windows_attributes = true;
+                entry.windows_attributes =
+                    (metadata.permissions().mode() << 16) | FILE_ATTRIBUTE_UNIX_EXTENSION | directory_attribute;
+            }
+
             let entry_data = if metadata.is_dir() {
                 None
             } else {
                 Some(fs::File::open(path)?)
             };
 
+            if let Some(observer) = observer.as_deref_mut() {
+                let content_hash = match &entry_data {
+                    Some(_) if observer.wants_content_hash() => Some(sha512_from_reader(&mut fs::File::open(path)?)?),
+                    _ => None,
+                };
+                observer.entry_stored(StoredEntry::new(entry.name().into(), &metadata, None, content_hash))?;
+            }
+
             writer.push_archive_entry::<fs::File>(entry, entry_data)?;
         }
 
         env::set_current_dir(previous_location)?;
     }
 
+    if let Some(manifest) = walk::baseline_manifest()? {
+        push_temp_file(
+            &mut writer,
+            manifest,
+            Path::new(walk::BASELINE_MANIFEST_NAME),
+            quiet,
+            observer,
+        )?;
+    }
+
     let bytes = writer.finish()?;
     Ok(bytes)
 }
 
-pub fn decompress_sevenz<R>(reader: R, output_path: &Path, password: Option<&[u8]>, quiet: bool) -> crate::Result<usize>
+/// Store the temporary file `spooled` as a regular file named `entry_name`, reported to `observer`
+fn push_temp_file<W>(
+    writer: &mut sevenz_rust::SevenZWriter<W>,
+    spooled: tempfile::NamedTempFile,
+    entry_name: &Path,
+    quiet: bool,
+    observer: Option<&mut (dyn ArchiveObserver + '_)>,
+) -> crate::Result<()>
+where
+    W: Write + Seek,
+{
+    if !quiet {
+        info(format!("Compressing '{}'", EscapedPathDisplay::new(entry_name)));
+    }
+
+    let entry_name = entry_name.to_str().ok_or_else(|| {
+        FinalError::with_title("7z requires that all entry names are valid UTF-8")
+            .detail(format!("The entry name '{entry_name:?}' is not valid UTF-8"))
+    })?;
+    #[allow(unused_mut)]
+    let mut entry = SevenZArchiveEntry::from_path(spooled.path(), entry_name.to_owned());
+
+    #[cfg(unix)]
+    {
+        entry.has_windows_attributes = true;
+        entry.windows_attributes = (0o644 << 16) | FILE_ATTRIBUTE_UNIX_EXTENSION;
+    }
+
+    if let Some(observer) = observer {
+        let content_hash = match observer.wants_content_hash() {
+            true => Some(sha512_from_reader(&mut spooled.reopen()?)?),
+            false => None,
+        };
+        let metadata = spooled.as_file().metadata()?;
+        observer.entry_stored(StoredEntry::new(entry_name.into(), &metadata, None, content_hash))?;
+    }
+
+    writer.push_archive_entry(entry, Some(spooled.reopen()?))?;
+    Ok(())
+}
+
+pub fn decompress_sevenz<R>(reader: R, output_path: &Path, options: UnpackOptions) -> crate::Result<usize>
 where
     R: Read + Seek,
 {
     let mut count: usize = 0;
+    let mut dir_mtimes = DirectoryMtimes::default();
+    // Set once every entry is unpacked, a read-only directory would refuse the files inside of it
+    #[cfg(unix)]
+    let mut dir_modes = Vec::new();
 
-    let entry_extract_fn = |entry: &SevenZArchiveEntry, reader: &mut dyn Read, path: &PathBuf| {
-        count += 1;
+    // The path given by sevenz_rust is the name of the entry joined to `output_path` as is
+    let entry_extract_fn = |entry: &SevenZArchiveEntry, reader: &mut dyn Read, _: &PathBuf| {
         // Manually handle writing all files from 7z archive, due to library exluding empty files
         use std::io::BufWriter;
 
         use filetime_creation as ft;
 
-        let file_path = output_path.join(entry.name());
+        let mtime = entry.has_last_modified_date.then(|| entry.last_modified_date().into());
+        let contents = StoredContents {
+            size: Some(entry.size()),
+            crc32: entry.has_crc.then_some(entry.crc as u32),
+        };
+        let name = Path::new(entry.name());
+        let is_enclosed = name
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !is_enclosed {
+            warning(format!(
+                "Skipping {}, it would be unpacked outside of the output directory",
+                EscapedPathDisplay::new(name)
+            ));
+        }
+
+        let unpacked_path = match is_enclosed {
+            true => options
+                .unpacked_path(output_path, name, entry.is_directory(), mtime, None, contents)
+                // The extract function can only fail with the errors of sevenz_rust
+                .map_err(|err| io::Error::other(err.to_string()))?,
+            false => None,
+        };
+        let Some(unpacked_path) = unpacked_path else {
+            // Skipped entries still have to be read
+            io::copy(reader, &mut io::sink())?;
+            return Ok(true);
+        };
+        let path = &unpacked_path;
+        count += 1;
 
         if entry.is_directory() {
-            if !quiet {
-                info(format!(
-                    "File {} extracted to \"{}\"",
-                    entry.name(),
-                    file_path.display()
-                ));
+            if !options.quiet {
+                info(format!("File {} extracted to \"{}\"", entry.name(), path.display()));
             }
             if !path.exists() {
                 fs::create_dir_all(path)?;
             }
+            dir_mtimes.push(path.clone(), mtime);
         } else {
-            if !quiet {
+            if !options.quiet {
                 info(format!(
                     "{:?} extracted. ({})",
-                    file_path.display(),
+                    path.display(),
                     Bytes::new(entry.size())
                 ));
             }
@@ -143,69 +302,173 @@ where
             let mut writer = BufWriter::new(file);
             io::copy(reader, &mut writer)?;
 
+            // Only restore the times stored in the archive
+            let file_time = |is_set: bool, time: sevenz_rust::nt_time::FileTime| {
+                is_set.then(|| ft::FileTime::from_system_time(time.into()))
+            };
+
             ft::set_file_handle_times(
                 writer.get_ref().file(),
-                Some(ft::FileTime::from_system_time(entry.access_date().into())),
-                Some(ft::FileTime::from_system_time(entry.last_modified_date().into())),
-                Some(ft::FileTime::from_system_time(entry.creation_date().into())),
+                file_time(entry.has_access_date, entry.access_date()),
+                file_time(entry.has_last_modified_date, entry.last_modified_date()),
+                file_time(entry.has_creation_date, entry.creation_date()),
             )
             .unwrap_or_default();
         }
 
+        #[cfg(unix)]
+        if entry.has_windows_attributes && entry.windows_attributes & FILE_ATTRIBUTE_UNIX_EXTENSION != 0 {
+            let mode = (entry.windows_attributes >> 16) & 0o7777;
+            if entry.is_directory() {
+                dir_modes.push((path.clone(), mode));
+            } else {
+                set_unpacked_permissions(path, mode, options.permissions)?;
+            }
+        }
+
+        #[cfg(windows)]
+        if entry.has_windows_attributes {
+            crate::utils::set_windows_attributes(path, entry.windows_attributes)?;
+        }
+
         Ok(true)
     };
 
-    match password {
-        Some(password) => sevenz_rust::decompress_with_extract_fn_and_password(
-            reader,
-            output_path,
-            sevenz_rust::Password::from(password.to_str().map_err(|err| Error::InvalidPassword {
-                reason: err.to_string(),
-            })?),
-            entry_extract_fn,
-        )?,
-        None => sevenz_rust::decompress_with_extract_fn(reader, output_path, entry_extract_fn)?,
+    let password = sevenz_password(options.password)?;
+    sevenz_rust::decompress_with_extract_fn_and_password(reader, output_path, password, entry_extract_fn)?;
+
+    // Children before their parents
+    #[cfg(unix)]
+    for (path, mode) in dir_modes.into_iter().rev() {
+        set_unpacked_permissions(&path, mode, options.permissions)?;
     }
+    dir_mtimes.restore()?;
 
     Ok(count)
 }
 
-/// List contents of `archive_path`, returning a vector of archive entries
-pub fn list_archive(
-    archive_path: &Path,
+/// Password given to sevenz-rust, empty if there's none
+fn sevenz_password(password: Option<&[u8]>) -> Result<sevenz_rust::Password> {
+    match password {
+        Some(password) => Ok(sevenz_rust::Password::from(password.to_str().map_err(|err| {
+            Error::InvalidPassword {
+                reason: err.to_string(),
+            }
+        })?)),
+        None => Ok(sevenz_rust::Password::empty()),
+    }
+}
+
+/// List contents of the archive in `reader` from its header, without decompressing the entries
+pub fn list_archive<R>(
+    mut reader: R,
     password: Option<&[u8]>,
-) -> Result<impl Iterator<Item = crate::Result<FileInArchive>>> {
-    let reader = fs::File::open(archive_path)?;
+) -> Result<impl Iterator<Item = crate::Result<FileInArchive>>>
+where
+    R: Read + Seek,
+{
+    let password = sevenz_password(password)?;
+    let len = reader.seek(io::SeekFrom::End(0))?;
+    reader.rewind()?;
+    let archive = sevenz_rust::Archive::read(&mut reader, len, password.as_slice())?;
+    let folder_methods = folder_methods(&archive);
+
+    let files = archive.files.into_iter().zip(archive.stream_map.file_folder_index);
+    Ok(files.map(move |(entry, folder_index)| {
+        let methods = folder_index.map_or(&[][..], |index| &folder_methods[index]);
+        let (encryption, compression): (Vec<&str>, Vec<&str>) = methods
+            .iter()
+            .copied()
+            .partition(|&name| name == sevenz_rust::SevenZMethod::AES256SHA256.name());
+
+        Ok(FileInArchive {
+            path: entry.name().into(),
+            is_dir: entry.is_directory(),
+            mtime: entry.has_last_modified_date.then(|| entry.last_modified_date().into()),
+            comment: None,
+            method: (!compression.is_empty()).then(|| compression.join("+")),
+            encrypted: Some(!encryption.is_empty()),
+        })
+    }))
+}
+
+/// Decompresses every entry of the archive in `reader`, returning their sizes and checksums
+pub fn summarize_archive<R>(reader: R, password: Option<&[u8]>) -> Result<Vec<EntrySummary>>
+where
+    R: Read + Seek,
+{
+    let mut summaries = Vec::new();
 
-    let mut files = Vec::new();
+    let entry_extract_fn = |entry: &SevenZArchiveEntry, reader: &mut dyn Read, _: &PathBuf| {
+        let is_dir = entry.is_directory();
+        let (size, crc32) = if is_dir { (0, 0) } else { crc32_from_reader(reader)? };
 
-    let entry_extract_fn = |entry: &SevenZArchiveEntry, _: &mut dyn Read, _: &PathBuf| {
-        files.push(Ok(FileInArchive {
+        summaries.push(EntrySummary {
             path: entry.name().into(),
-            is_dir: entry.is_directory(),
-        }));
+            is_dir,
+            size,
+            crc32,
+        });
         Ok(true)
     };
 
-    match password {
-        Some(password) => {
-            let password = match password.to_str() {
-                Ok(p) => p,
-                Err(err) => {
-                    return Err(Error::InvalidPassword {
-                        reason: err.to_string(),
-                    })
-                }
-            };
-            sevenz_rust::decompress_with_extract_fn_and_password(
-                reader,
-                ".",
-                sevenz_rust::Password::from(password),
-                entry_extract_fn,
-            )?;
+    let password = sevenz_password(password)?;
+    sevenz_rust::decompress_with_extract_fn_and_password(reader, ".", password, entry_extract_fn)?;
+
+    Ok(summaries)
+}
+
+/// Methods of the coders of each folder of `archive`, the solid blocks of 7z
+fn folder_methods(archive: &sevenz_rust::Archive) -> Vec<Vec<&'static str>> {
+    archive
+        .folders
+        .iter()
+        .map(|folder| {
+            folder
+                .coders
+                .iter()
+                .filter_map(|coder| sevenz_rust::SevenZMethod::by_id(coder.decompression_method_id()))
+                .map(|method| method.name())
+                .collect()
+        })
+        .collect()
+}
+
+/// Counts the entries of the archive in `reader` from its header, with the compression methods and
+/// the solid blocks the entries are grouped in
+pub fn archive_info<R>(mut reader: R, password: Option<&[u8]>) -> Result<ArchiveInfo>
+where
+    R: Read + Seek,
+{
+    let password = sevenz_password(password)?;
+
+    let len = reader.seek(io::SeekFrom::End(0))?;
+    reader.rewind()?;
+    let archive = sevenz_rust::Archive::read(&mut reader, len, password.as_slice())?;
+    let folder_methods = folder_methods(&archive);
+
+    let mut info = ArchiveInfo::default();
+    for (file, folder_index) in archive.files.iter().zip(&archive.stream_map.file_folder_index) {
+        let encrypted = folder_index
+            .is_some_and(|index| folder_methods[index].contains(&sevenz_rust::SevenZMethod::AES256SHA256.name()));
+        info.add_entry(file.is_directory(), file.size(), encrypted);
+    }
+
+    let mut methods = vec![];
+    for &name in folder_methods.iter().flatten() {
+        if !methods.contains(&name) {
+            methods.push(name);
         }
-        None => sevenz_rust::decompress_with_extract_fn(reader, ".", entry_extract_fn)?,
     }
 
-    Ok(files.into_iter())
+    if !methods.is_empty() {
+        info.details.push(("Compression methods", methods.join(", ")));
+    }
+    info.details.push(("Solid blocks", archive.folders.len().to_string()));
+    if let Some(largest_block) = archive.folders.iter().map(|folder| folder.num_unpack_sub_streams).max() {
+        info.details
+            .push(("Files in the largest solid block", largest_block.to_string()));
+    }
+
+    Ok(info)
 }
@@ -0,0 +1,19 @@
+//! SquashFS images (`.squashfs`, `.sqfs`), only recognised.
+//!
+//! A SquashFS image is a whole read-only filesystem, like the ones of live CDs, snaps and
+//! AppImages, which ouch can't read, so these images are detected by extension and by their magic
+//! bytes to explain how to unpack them instead.
+
+use crate::error::{Error, ErrorClass, FinalError};
+
+/// First bytes of SquashFS images, the little-endian magic number written by `mksquashfs`
+pub const MAGIC: &[u8; 4] = b"hsqs";
+
+/// Error returned when a SquashFS image is created, listed or unpacked
+pub fn no_support() -> Error {
+    FinalError::with_title("SquashFS images are not supported")
+        .detail("ouch recognises .squashfs files, but can't create, list or unpack them")
+        .hint("Unpack it with `unsquashfs` or `7z x`, or mount it with `mount -t squashfs`")
+        .class(ErrorClass::UnsupportedFormat)
+        .into()
+}
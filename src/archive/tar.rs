@@ -1,59 +1,349 @@
 //! Contains Tar-specific building and unpacking functions
 
 use std::{
-    env,
-    io::prelude::*,
-    path::{Path, PathBuf},
-    sync::mpsc::{self, Receiver},
+    collections::{hash_map, HashMap},
+    io::{prelude::*, SeekFrom},
+    path::{Component, Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
     thread,
+    time::{Duration, UNIX_EPOCH},
 };
 
 use fs_err as fs;
-use same_file::Handle;
 
 use crate::{
-    error::FinalError,
+    archive::{
+        index::{self, ArchiveIndex, IndexEntry},
+        manifest::{ArchiveObserver, StoredEntry},
+        walk::{self, walk_input_files, EntryContents, WalkedEntry},
+        ArchiveInfo, DirectoryMtimes, EntrySummary, Reproducible, StoredContents, UnpackOptions,
+    },
+    error::{ErrorClass, FinalError},
     list::FileInArchive,
+    options::{NormalizationForm, SpecialFiles},
     utils::{
         self,
+        checksum::crc32_from_reader,
+        io::JoinedParts,
         logger::{info, warning},
         Bytes, EscapedPathDisplay, FileVisibilityPolicy,
     },
 };
 
 /// Unpacks the archive given by `archive` into the folder given by `into`.
-/// Assumes that output_folder is empty
-pub fn unpack_archive(reader: Box<dyn Read>, output_folder: &Path, quiet: bool) -> crate::Result<usize> {
-    assert!(output_folder.read_dir().expect("dir exists").count() == 0);
+/// Entries whose path is already taken are resolved with `options.on_conflict`
+pub fn unpack_archive(reader: Box<dyn Read>, output_folder: &Path, options: UnpackOptions) -> crate::Result<usize> {
     let mut archive = tar::Archive::new(reader);
+    archive.set_mask(options.permissions.mask());
+    // The mask clears the setuid, setgid and sticky bits unless they're preserved
+    archive.set_preserve_permissions(true);
+    unpack_entries(archive.entries()?, None, output_folder, options)
+}
+
+/// Like [`unpack_archive`], but seeks past the contents of the entries that are skipped
+///
+/// With the `source` file `reader` reads from, files are copied straight from it when the
+/// filesystem can, see [`utils::copy_file_range`].
+pub fn unpack_archive_with_seek(
+    reader: impl Read + Seek,
+    source: Option<&JoinedParts>,
+    output_folder: &Path,
+    options: UnpackOptions,
+) -> crate::Result<usize> {
+    let mut archive = tar::Archive::new(reader);
+    archive.set_mask(options.permissions.mask());
+    archive.set_preserve_permissions(true);
+    let source = source.map(|source| (source, 0));
+    unpack_entries(archive.entries_with_seek()?, source, output_folder, options)
+}
 
+fn unpack_entries(
+    entries: tar::Entries<impl Read>,
+    source: Option<(&JoinedParts, u64)>,
+    output_folder: &Path,
+    options: UnpackOptions,
+) -> crate::Result<usize> {
     let mut files_unpacked = 0;
-    for file in archive.entries()? {
+    let mut dir_mtimes = DirectoryMtimes::default();
+    for file in entries {
         let mut file = file?;
+        if is_global_header(&file) {
+            continue;
+        }
+        unpack_file(
+            &mut file,
+            source,
+            output_folder,
+            options,
+            &mut files_unpacked,
+            &mut dir_mtimes,
+        )?;
+    }
+    dir_mtimes.restore()?;
+
+    Ok(files_unpacked)
+}
+
+/// Like [`unpack_archive`], but only reads the entries that aren't skipped by `--transform`,
+/// seeking straight to them with the offsets in `index`, and copying files from `source` like
+/// [`unpack_archive_with_seek`]
+pub fn unpack_indexed_archive(
+    mut reader: impl Read + Seek,
+    source: Option<&JoinedParts>,
+    index: &ArchiveIndex,
+    output_folder: &Path,
+    options: UnpackOptions,
+) -> crate::Result<usize> {
+    let mut files_unpacked = 0;
+    let mut dir_mtimes = DirectoryMtimes::default();
+    for entry in &index.entries {
+        if utils::unpacked_entry_path(&entry.path, options.normalization, options.transforms).is_none() {
+            continue;
+        }
+
+        reader.seek(SeekFrom::Start(entry.offset))?;
+        let mut archive = tar::Archive::new(&mut reader);
+        archive.set_mask(options.permissions.mask());
+        archive.set_preserve_permissions(true);
+
+        let file = archive.entries()?.next().transpose()?;
+        match file {
+            Some(mut file) if file.path()? == entry.path => {
+                let source = source.map(|source| (source, entry.offset));
+                unpack_file(
+                    &mut file,
+                    source,
+                    output_folder,
+                    options,
+                    &mut files_unpacked,
+                    &mut dir_mtimes,
+                )?;
+            }
+            _ => return Err(index::mismatch_error(&entry.path)),
+        }
+    }
+    dir_mtimes.restore()?;
+
+    Ok(files_unpacked)
+}
+
+/// Unpacks a single entry, counting it in `files_unpacked`, the modification times of directories
+/// go to `dir_mtimes`
+fn unpack_file(
+    file: &mut tar::Entry<impl Read>,
+    source: Option<(&JoinedParts, u64)>,
+    output_folder: &Path,
+    options: UnpackOptions,
+    files_unpacked: &mut usize,
+    dir_mtimes: &mut DirectoryMtimes,
+) -> crate::Result<()> {
+    let Some(unpacked_path) = unpack_entry(file, source, output_folder, options)? else {
+        return Ok(());
+    };
+
+    if file.header().entry_type().is_dir() {
+        let mtime = file
+            .header()
+            .mtime()
+            .ok()
+            .map(|mtime| UNIX_EPOCH + Duration::from_secs(mtime));
+        dir_mtimes.push(unpacked_path.clone(), mtime);
+    }
+
+    #[cfg(unix)]
+    if let Ok(mode) = file.header().mode() {
+        options.permissions.warn_special_bits(&unpacked_path, mode);
+    }
+
+    #[cfg(windows)]
+    restore_windows_attributes(file, &unpacked_path)?;
+
+    // This is printed for every file in the archive and has little
+    // importance for most users, but would generate lots of
+    // spoken text for users using screen readers, braille displays
+    // and so on
+    if !options.quiet {
+        info(format!(
+            "{:?} extracted. ({})",
+            utils::strip_cur_dir(&unpacked_path),
+            Bytes::new(file.size()),
+        ));
+
+        *files_unpacked += 1;
+    }
+
+    Ok(())
+}
+
+/// Unpacks `file` like [`tar::Entry::unpack_in`], but at the path given by
+/// [`UnpackOptions::unpacked_path`], returning where it was unpacked to, or `None` if it was skipped
+///
+/// `source` is the file the archive is read from, with the position the archive starts at in it.
+fn unpack_entry(
+    file: &mut tar::Entry<impl Read>,
+    source: Option<(&JoinedParts, u64)>,
+    output_folder: &Path,
+    options: UnpackOptions,
+) -> crate::Result<Option<PathBuf>> {
+    let path = file.path()?.into_owned();
+    let is_enclosed = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
 
+    let destination = if is_enclosed {
+        let is_dir = file.header().entry_type().is_dir();
+        let mtime = file
+            .header()
+            .mtime()
+            .ok()
+            .map(|mtime| UNIX_EPOCH + Duration::from_secs(mtime));
+        let mode = file.header().mode().ok();
+        let contents = StoredContents {
+            size: Some(file.size()),
+            crc32: None,
+        };
+        match options.unpacked_path(output_folder, &path, is_dir, mtime, mode, contents)? {
+            Some(destination) => destination,
+            None => return Ok(None),
+        }
+    } else {
+        output_folder.join(&path)
+    };
+
+    if is_enclosed && copy_file_entry(file, source, &destination, options)? {
+        return Ok(Some(destination));
+    }
+
+    if destination == output_folder.join(&path) {
+        // Also rejects paths that could leave `output_folder`
         file.unpack_in(output_folder)?;
+        return Ok(Some(destination));
+    }
 
-        // This is printed for every file in the archive and has little
-        // importance for most users, but would generate lots of
-        // spoken text for users using screen readers, braille displays
-        // and so on
-        if !quiet {
-            info(format!(
-                "{:?} extracted. ({})",
-                utils::strip_cur_dir(&output_folder.join(file.path()?)),
-                Bytes::new(file.size()),
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // Hard links point to the path of another entry, renamed the same way as this one
+    if file.header().entry_type() == tar::EntryType::Link {
+        let original = file
+            .link_name()?
+            .filter(|original| {
+                original
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+            })
+            .and_then(|original| options.renamed_path(output_folder, &original, false));
+        // Like the destination, the original can't be reached through links leading outside
+        if let Some(original) = &original {
+            utils::check_symlinks_stay_inside(output_folder, original, true)?;
+        }
+        let original =
+            original.filter(|original| fs::symlink_metadata(original).is_ok_and(|metadata| metadata.is_file()));
+        let Some(original) = original else {
+            warning(format!(
+                "Skipping '{}', it is a hard link to a file that wasn't unpacked",
+                EscapedPathDisplay::new(&path)
             ));
+            return Ok(None);
+        };
 
-            files_unpacked += 1;
+        if destination.is_file() {
+            fs::remove_file(&destination)?;
         }
+        fs::hard_link(original, &destination)?;
+        return Ok(Some(destination));
     }
 
-    Ok(files_unpacked)
+    file.unpack(&destination)?;
+
+    Ok(Some(destination))
+}
+
+/// Copy the contents of `file`, a regular file, from `source` to `destination` inside the kernel,
+/// setting its modification time and permissions like [`tar::Entry::unpack`].
+///
+/// Returns `false` if it can't be done, for the contents to be read from the archive instead.
+fn copy_file_entry(
+    file: &tar::Entry<impl Read>,
+    source: Option<(&JoinedParts, u64)>,
+    destination: &Path,
+    options: UnpackOptions,
+) -> crate::Result<bool> {
+    // The contents of sparse files are split in chunks
+    if file.header().entry_type() != tar::EntryType::Regular {
+        return Ok(false);
+    }
+    let Some((part, offset)) =
+        source.and_then(|(source, start)| source.part_at(start + file.raw_file_position(), file.size()))
+    else {
+        return Ok(false);
+    };
+
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // A new file is made instead of writing over the existing one, which could be a link
+    if fs::symlink_metadata(destination).is_ok() {
+        fs::remove_file(destination)?;
+    }
+    let output_file = fs::OpenOptions::new().write(true).create_new(true).open(destination)?;
+    if !utils::copy_file_range(part, offset, output_file.file(), file.size())? {
+        return Ok(false);
+    }
+
+    // Files with no modification time don't get along with some tools, the tar crate sets it to 1
+    if let Ok(mtime) = file.header().mtime() {
+        output_file
+            .file()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(mtime.max(1)))?;
+    }
+    #[cfg(unix)]
+    if let Ok(mode) = file.header().mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        output_file.set_permissions(std::fs::Permissions::from_mode(mode & !options.permissions.mask()))?;
+    }
+    #[cfg(not(unix))]
+    let _ = options;
+
+    Ok(true)
+}
+
+/// Restore the file attributes stored by Windows archivers
+#[cfg(windows)]
+fn restore_windows_attributes(file: &mut tar::Entry<impl Read>, unpacked_path: &Path) -> crate::Result<()> {
+    let attributes = file.pax_extensions()?.and_then(|extensions| {
+        extensions
+            .filter_map(Result::ok)
+            .find(|extension| extension.key() == Ok("MSWINDOWS.rawattr"))
+            .and_then(|extension| extension.value().ok()?.parse().ok())
+    });
+
+    if let Some(attributes) = attributes {
+        utils::set_windows_attributes(unpacked_path, attributes)?;
+    }
+
+    Ok(())
 }
 
 /// List contents of `archive`, returning a vector of archive entries
 pub fn list_archive(
     mut archive: tar::Archive<impl Read + Send + 'static>,
+) -> impl Iterator<Item = crate::Result<FileInArchive>> {
+    list_entries(move |tx| send_entries(archive.entries(), tx))
+}
+
+/// Like [`list_archive`], but seeks past the contents of the entries instead of reading them
+pub fn list_archive_with_seek(
+    mut archive: tar::Archive<impl Read + Seek + Send + 'static>,
+) -> impl Iterator<Item = crate::Result<FileInArchive>> {
+    list_entries(move |tx| send_entries(archive.entries_with_seek(), tx))
+}
+
+/// Run `send` in another thread, receiving the entries it sends
+fn list_entries(
+    send: impl FnOnce(&Sender<crate::Result<FileInArchive>>) + Send + 'static,
 ) -> impl Iterator<Item = crate::Result<FileInArchive>> {
     struct Files(Receiver<crate::Result<FileInArchive>>);
     impl Iterator for Files {
@@ -65,88 +355,307 @@ pub fn list_archive(
     }
 
     let (tx, rx) = mpsc::channel();
-    thread::spawn(move || {
-        for file in archive.entries().expect("entries is only used once") {
-            let file_in_archive = (|| {
-                let file = file?;
-                let path = file.path()?.into_owned();
-                let is_dir = file.header().entry_type().is_dir();
-                Ok(FileInArchive { path, is_dir })
-            })();
-            tx.send(file_in_archive).unwrap();
-        }
-    });
+    thread::spawn(move || send(&tx));
 
     Files(rx)
 }
 
-/// Compresses the archives given by `input_filenames` into the file given previously to `writer`.
+fn send_entries<R: Read>(entries: std::io::Result<tar::Entries<R>>, tx: &Sender<crate::Result<FileInArchive>>) {
+    for file in entries.expect("entries is only used once") {
+        if file.as_ref().is_ok_and(is_global_header) {
+            continue;
+        }
+        let file_in_archive = (|| {
+            let file = file?;
+            let path = file.path()?.into_owned();
+            let is_dir = file.header().entry_type().is_dir();
+            let mtime = file
+                .header()
+                .mtime()
+                .ok()
+                .map(|mtime| UNIX_EPOCH + Duration::from_secs(mtime));
+            Ok(FileInArchive {
+                path,
+                is_dir,
+                mtime,
+                comment: None,
+                method: None,
+                encrypted: None,
+            })
+        })();
+        // Stop once the entries aren't read anymore
+        if tx.send(file_in_archive).is_err() {
+            break;
+        }
+    }
+}
+
+/// Reads every entry of `archive`, returning their sizes and checksums
+pub fn summarize_archive(mut archive: tar::Archive<impl Read>) -> crate::Result<Vec<EntrySummary>> {
+    let mut summaries = vec![];
+    // Sizes and checksums by path, for hard links
+    let mut contents: HashMap<PathBuf, (u64, u32)> = HashMap::new();
+
+    for file in archive.entries()? {
+        let mut file = file?;
+        if is_global_header(&file) {
+            continue;
+        }
+        let path = file.path()?.into_owned();
+        let is_dir = file.header().entry_type().is_dir();
+        // Hard links, like the ones stored by `--dedup`, have the contents of an earlier entry
+        let original = match file.header().entry_type() {
+            tar::EntryType::Link => file.link_name()?.and_then(|original| contents.get(&*original).copied()),
+            _ => None,
+        };
+        let (size, crc32) = match original {
+            Some(original) => original,
+            None if is_dir => (0, 0),
+            None => crc32_from_reader(&mut file)?,
+        };
+        contents.insert(path.clone(), (size, crc32));
+
+        summaries.push(EntrySummary {
+            path,
+            is_dir,
+            size,
+            crc32,
+        });
+    }
+
+    Ok(summaries)
+}
+
+/// Counts the entries of `archive` from their headers, without reading their contents
+pub fn archive_info(mut archive: tar::Archive<impl Read>) -> crate::Result<ArchiveInfo> {
+    let mut info = ArchiveInfo::default();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if is_global_header(&entry) {
+            if let Some(comment) = global_comment(&mut entry)? {
+                info.details.push(("Comment", comment));
+            }
+            continue;
+        }
+        info.add_entry(entry.header().entry_type().is_dir(), entry.size(), false);
+    }
+
+    Ok(info)
+}
+
+/// PAX global headers hold settings for the whole archive, like `--comment`, they aren't files
+fn is_global_header(entry: &tar::Entry<impl Read>) -> bool {
+    entry.header().entry_type().is_pax_global_extensions()
+}
+
+/// The "comment" record of a PAX global header
+fn global_comment(entry: &mut tar::Entry<impl Read>) -> crate::Result<Option<String>> {
+    let Some(extensions) = entry.pax_extensions()? else {
+        return Ok(None);
+    };
+    for extension in extensions {
+        let extension = extension?;
+        if extension.key() == Ok("comment") {
+            return Ok(Some(String::from_utf8_lossy(extension.value_bytes()).into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+/// Counts the bytes written to `inner`, which are the offsets of the entries in the archive
+struct CountingWriter<W> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Compresses the archives given by `input_filenames` into the file given previously to `writer`,
+/// starting with a PAX global header with `comment`, if given. The offset of each entry is added to
+/// `index`, if given. With `dedup`, files with the same contents
+/// as one already stored are stored as hard links to it. Stored entries are reported to `observer`
+#[allow(clippy::too_many_arguments)]
 pub fn build_archive_from_paths<W>(
     input_filenames: &[PathBuf],
     output_path: &Path,
     writer: W,
     file_visibility_policy: FileVisibilityPolicy,
     quiet: bool,
+    reproducible: Option<Reproducible>,
+    comment: Option<&str>,
+    name_normalization: NormalizationForm,
+    special_files: SpecialFiles,
+    dedup: bool,
+    mut index: Option<&mut ArchiveIndex>,
+    mut observer: Option<&mut dyn ArchiveObserver>,
 ) -> crate::Result<W>
 where
     W: Write,
 {
-    let mut builder = tar::Builder::new(writer);
-    let output_handle = Handle::from_path(output_path);
+    let mut builder = tar::Builder::new(CountingWriter {
+        inner: writer,
+        written: 0,
+    });
+    if let Some(comment) = comment {
+        append_comment(&mut builder, comment)?;
+    }
+    // Name of the first entry stored with each contents
+    let mut stored_contents: HashMap<[u8; 64], PathBuf> = HashMap::new();
 
-    for filename in input_filenames {
-        let previous_location = utils::cd_into_same_dir_as(filename)?;
+    for entry in walk_input_files(
+        input_filenames,
+        output_path,
+        file_visibility_policy,
+        name_normalization,
+        special_files,
+        dedup || observer.as_ref().is_some_and(|observer| observer.wants_content_hash()),
+    ) {
+        let WalkedEntry {
+            path,
+            name,
+            metadata,
+            mut contents,
+            content_hash,
+            incompressible: _,
+        } = entry?;
 
-        // Unwrap safety:
-        //   paths should be canonicalized by now, and the root directory rejected.
-        let filename = filename.file_name().unwrap();
+        // Tar has entry types for FIFOs and devices, but not for sockets
+        if matches!(contents, EntryContents::Special) {
+            if let Some(kind @ "socket") = walk::special_file_kind(metadata.file_type()) {
+                walk::warn_special_file_not_stored(&path, kind, "tar");
+                continue;
+            }
+        }
 
-        for entry in file_visibility_policy.build_walker(filename) {
-            let entry = entry?;
-            let path = entry.path();
+        // This is printed for every file in `input_filenames` and has
+        // little importance for most users, but would generate lots of
+        // spoken text for users using screen readers, braille displays
+        // and so on
+        if !quiet {
+            info(format!("Compressing '{}'", EscapedPathDisplay::new(&name)));
+        }
 
-            // If the output_path is the same as the input file, warn the user and skip the input (in order to avoid compression recursion)
-            if let Ok(handle) = &output_handle {
-                if matches!(Handle::from_path(path), Ok(x) if &x == handle) {
-                    warning(format!(
-                        "Cannot compress `{}` into itself, skipping",
-                        output_path.display()
-                    ));
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata(&metadata);
+
+        #[cfg(unix)]
+        if matches!(contents, EntryContents::Special) {
+            set_device_numbers(&mut header, &metadata)?;
+        }
+
+        if let Some(reproducible) = reproducible {
+            header.set_mtime(reproducible.mtime);
+            header.set_uid(0);
+            header.set_gid(0);
+        }
 
-                    continue;
+        // Empty files have nothing to share
+        let original = match content_hash.filter(|_| dedup && metadata.len() > 0) {
+            Some(hash) => match stored_contents.entry(hash) {
+                hash_map::Entry::Occupied(entry) => Some(entry.get().clone()),
+                hash_map::Entry::Vacant(entry) => {
+                    entry.insert(name.clone());
+                    None
                 }
-            }
+            },
+            None => None,
+        };
 
-            // This is printed for every file in `input_filenames` and has
-            // little importance for most users, but would generate lots of
-            // spoken text for users using screen readers, braille displays
-            // and so on
-            if !quiet {
-                info(format!("Compressing '{}'", EscapedPathDisplay::new(path)));
+        let offset = builder.get_ref().written;
+        let appended = match original {
+            Some(original) => {
+                header.set_entry_type(tar::EntryType::Link);
+                header.set_size(0);
+                builder.append_link(&mut header, &name, original)
             }
+            None => builder.append_data(&mut header, &name, &mut contents),
+        };
+        appended.map_err(|err| {
+            FinalError::with_title("Could not create archive")
+                .detail("Unexpected error while trying to read file")
+                .detail(format!("Error: {err}."))
+                .class(ErrorClass::Io)
+        })?;
 
-            if path.is_dir() {
-                builder.append_dir(path, path)?;
-            } else {
-                let mut file = match fs::File::open(path) {
-                    Ok(f) => f,
-                    Err(e) => {
-                        if e.kind() == std::io::ErrorKind::NotFound && path.is_symlink() {
-                            // This path is for a broken symlink, ignore it
-                            continue;
-                        }
-                        return Err(e.into());
-                    }
-                };
-                builder.append_file(path, file.file_mut()).map_err(|err| {
-                    FinalError::with_title("Could not create archive")
-                        .detail("Unexpected error while trying to read file")
-                        .detail(format!("Error: {err}."))
-                })?;
-            }
+        if let Some(index) = index.as_deref_mut() {
+            index.entries.push(IndexEntry {
+                path: name.clone(),
+                is_dir: metadata.is_dir(),
+                offset,
+                size: header.size()?,
+                mtime: header.mtime()?,
+            });
+        }
+
+        if let Some(observer) = observer.as_deref_mut() {
+            observer.entry_stored(StoredEntry::new(name, &metadata, reproducible, content_hash))?;
         }
-        env::set_current_dir(previous_location)?;
     }
 
-    Ok(builder.into_inner()?)
+    Ok(builder.into_inner()?.inner)
+}
+
+/// Store `comment` in a PAX global header, which applies to the whole archive
+fn append_comment(builder: &mut tar::Builder<impl Write>, comment: &str) -> std::io::Result<()> {
+    let record = pax_record("comment", comment);
+    let mut header = tar::Header::new_ustar();
+    header.set_entry_type(tar::EntryType::XGlobalHeader);
+    header.set_path("pax_global_header")?;
+    header.set_mode(0o644);
+    header.set_size(record.len() as u64);
+    header.set_cksum();
+    builder.append(&header, record.as_slice())
+}
+
+/// PAX record "<length> <key>=<value>\n", where the length counts its own digits
+fn pax_record(key: &str, value: &str) -> Vec<u8> {
+    // The space, '=' and newline
+    let unprefixed_len = key.len() + value.len() + 3;
+    let mut len = unprefixed_len;
+    while len != unprefixed_len + len.to_string().len() {
+        len = unprefixed_len + len.to_string().len();
+    }
+    format!("{len} {key}={value}\n").into_bytes()
+}
+
+/// Store the device numbers of character and block devices, `set_metadata` leaves them at zero
+#[cfg(unix)]
+fn set_device_numbers(header: &mut tar::Header, metadata: &std::fs::Metadata) -> std::io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    // Devices report their size as zero, or the size of the whole disk, neither is data in the archive
+    header.set_size(0);
+
+    let device = metadata.rdev();
+    // `major` and `minor` return an i32 on macOS
+    #[allow(clippy::unnecessary_cast)]
+    let (major, minor) = (libc::major(device) as u32, libc::minor(device) as u32);
+    header.set_device_major(major)?;
+    header.set_device_minor(minor)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pax_record() {
+        assert_eq!(pax_record("comment", "hi"), b"14 comment=hi\n");
+        // The length goes from 2 to 3 digits by counting itself
+        let record = pax_record("comment", &"a".repeat(88));
+        assert_eq!(record.len(), 101);
+        assert!(record.starts_with(b"101 comment="));
+    }
 }
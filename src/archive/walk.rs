@@ -0,0 +1,602 @@
+//! Walks through the files to be archived, reading them ahead of the archive builders
+//!
+//! Walking is done in a separate thread, and file contents are read by a few reader threads of
+//! their own, so the builders (and codecs behind them) don't wait on the filesystem. Entries are
+//! still yielded in walk order, so the output doesn't depend on which reads finish first.
+//!
+//! The reader threads aren't rayon's, the builders may run inside of a rayon pool with a single
+//! thread, which would be blocked waiting for the entry its own task has to read.
+
+use std::{
+    collections::HashMap,
+    io::{self, Cursor, Read, Seek, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Mutex, OnceLock, PoisonError, RwLock,
+    },
+    thread,
+    time::UNIX_EPOCH,
+};
+
+use fs_err as fs;
+use same_file::Handle;
+use tempfile::NamedTempFile;
+
+use crate::{
+    error::{ErrorClass, FinalError},
+    options::{NormalizationForm, SpecialFiles},
+    utils::{
+        checksum::sha512_from_reader, is_path_stdin, logger::warning, normalize_path, symlink_loop, Bytes,
+        EscapedPathDisplay, FileVisibilityPolicy,
+    },
+};
+
+/// Name of the entry made from the data piped to stdin, set by `--entry-name`
+static STDIN_ENTRY_NAME: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Set the name of the entry made from the data piped to stdin, when `-` is one of the input files,
+/// replacing the one of an earlier run
+pub fn set_stdin_entry_name(name: Option<PathBuf>) {
+    *STDIN_ENTRY_NAME.lock().unwrap_or_else(PoisonError::into_inner) = name;
+}
+
+/// Name of the entry made from the data piped to stdin, with the Unicode normalization `form`
+pub fn stdin_entry_name(form: NormalizationForm) -> crate::Result<PathBuf> {
+    let name = STDIN_ENTRY_NAME.lock().unwrap_or_else(PoisonError::into_inner).clone();
+    // Checked before compressing from the command line, not by library users
+    let name = name.ok_or_else(|| {
+        FinalError::with_title("Missing the name of the entry read from stdin")
+            .detail("Data piped to stdin is stored in archives as a single file")
+            .class(ErrorClass::BadArguments)
+    })?;
+    Ok(normalize_path(&name, form).into_owned())
+}
+
+/// Save the data piped to stdin into a temporary file, its size has to be known before archiving it
+pub fn spool_stdin() -> io::Result<NamedTempFile> {
+    let mut file = NamedTempFile::new()?;
+    io::copy(&mut io::stdin().lock(), &mut file)?;
+    make_archivable(&file)?;
+    Ok(file)
+}
+
+/// Temporary files are only readable by their owner, archive them like regular files
+fn make_archivable(file: &NamedTempFile) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.as_file().set_permissions(std::fs::Permissions::from_mode(0o644))?;
+    }
+
+    #[cfg(not(unix))]
+    let _ = file;
+
+    Ok(())
+}
+
+/// What incremental archives are compared to, set by `--newer-than` or `--newer-mtime`
+#[derive(Debug)]
+pub enum Baseline {
+    /// The archive at `base`, files are left out if they're in it and weren't modified after
+    /// the time stored for them, in seconds since the Unix epoch
+    Archive {
+        base: PathBuf,
+        mtimes: HashMap<PathBuf, u64>,
+    },
+    /// Files are left out if they weren't modified after this time, in seconds since the Unix epoch
+    Mtime(u64),
+}
+
+static BASELINE: RwLock<Option<Baseline>> = RwLock::new(None);
+
+/// Only archive the files that changed since `baseline`, or all of them without one, replacing
+/// the baseline of an earlier run
+pub fn set_baseline(baseline: Option<Baseline>) {
+    *BASELINE.write().unwrap_or_else(PoisonError::into_inner) = baseline;
+}
+
+/// Name of the entry added at the end of incremental archives, recording their baseline
+pub const BASELINE_MANIFEST_NAME: &str = ".ouch-incremental";
+
+/// Whether the file named `name` in the archive didn't change since the baseline, so it's left out.
+/// Directories are always kept, so the files inside of them can be unpacked where they were
+pub fn is_unchanged(name: &Path, metadata: &std::fs::Metadata) -> bool {
+    let baseline = BASELINE.read().unwrap_or_else(PoisonError::into_inner);
+    let Some(baseline) = &*baseline else {
+        return false;
+    };
+    let Ok(modified) = metadata.modified() else {
+        return false;
+    };
+    if metadata.is_dir() {
+        return false;
+    }
+
+    let mtime = modified.duration_since(UNIX_EPOCH).map_or(0, |mtime| mtime.as_secs());
+    match baseline {
+        Baseline::Archive { mtimes, .. } => mtimes.get(name).is_some_and(|&base_mtime| mtime <= base_mtime),
+        Baseline::Mtime(base_mtime) => mtime <= *base_mtime,
+    }
+}
+
+/// Temporary file with the contents of the [`BASELINE_MANIFEST_NAME`] entry, `None` unless
+/// compressing an incremental archive
+pub fn baseline_manifest() -> io::Result<Option<NamedTempFile>> {
+    let baseline = BASELINE.read().unwrap_or_else(PoisonError::into_inner);
+    let Some(baseline) = &*baseline else {
+        return Ok(None);
+    };
+
+    let mut file = NamedTempFile::new()?;
+    match baseline {
+        Baseline::Archive { base, .. } => writeln!(file, "base: {}", base.display())?,
+        Baseline::Mtime(mtime) => writeln!(file, "newer-mtime: @{mtime}")?,
+    }
+    make_archivable(&file)?;
+
+    Ok(Some(file))
+}
+
+/// Files up to this size are read into memory ahead of time
+const PREFETCH_SIZE_LIMIT: u64 = 256 * 1024;
+
+/// How many entries the walker can get ahead of the archive builder
+const WALK_AHEAD_LIMIT: usize = 64;
+
+/// Most threads reading the contents of the entries, reads are bound by the filesystem
+const MAX_READER_THREADS: usize = 8;
+
+/// Files at least this big have the start of their contents sampled, to tell whether they're
+/// already compressed
+const SAMPLED_SIZE_LIMIT: u64 = 1024 * 1024;
+
+/// Bytes sampled from the start of big files
+const SAMPLE_SIZE: u64 = 64 * 1024;
+
+/// Entropy, in bits per byte, above which a sample looks already compressed (or encrypted)
+const INCOMPRESSIBLE_ENTROPY: f64 = 7.5;
+
+/// Total size of the sampled files, and of the ones among them that looked already compressed
+static SAMPLED_BYTES: AtomicU64 = AtomicU64::new(0);
+static INCOMPRESSIBLE_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Share, from 0 to 1, of the contents of the files sampled since the last call that looked
+/// already compressed, `None` if no file was big enough to be sampled
+pub fn take_incompressible_share() -> Option<f64> {
+    let sampled = SAMPLED_BYTES.swap(0, Ordering::Relaxed);
+    let incompressible = INCOMPRESSIBLE_BYTES.swap(0, Ordering::Relaxed);
+    (sampled > 0).then(|| incompressible as f64 / sampled as f64)
+}
+
+/// Count and total size of the files walked so far, read while compressing by [`walk_status`]
+static WALKED_FILES: AtomicU64 = AtomicU64::new(0);
+static WALKED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Total size of the input files, once [`estimate_total_size`] is done walking them
+static TOTAL_SIZE: OnceLock<u64> = OnceLock::new();
+
+/// Status line with the counts of the files walked so far, like "Scanned 1200 files, 350 MiB", and
+/// the total size of the input once it's known
+pub fn walk_status() -> String {
+    let files = WALKED_FILES.load(Ordering::Relaxed);
+    let bytes = Bytes::new(WALKED_BYTES.load(Ordering::Relaxed));
+    match TOTAL_SIZE.get() {
+        Some(&total) => format!("Scanned {files} files, {bytes} of {}", Bytes::new(total)),
+        None => format!("Scanned {files} files, {bytes}"),
+    }
+}
+
+/// Sum the size of `input_filenames` in another thread, for [`walk_status`], while the builders
+/// already walk them
+pub fn estimate_total_size(input_filenames: Vec<PathBuf>, file_visibility_policy: FileVisibilityPolicy) {
+    thread::spawn(move || {
+        let _ = TOTAL_SIZE.set(input_size(&input_filenames, &file_visibility_policy));
+    });
+}
+
+/// Size of the files in `input_filenames` and inside of them, unreadable ones are left out
+pub fn input_size(input_filenames: &[PathBuf], file_visibility_policy: &FileVisibilityPolicy) -> u64 {
+    input_filenames
+        .iter()
+        .flat_map(|file| file_visibility_policy.build_walker(file))
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Whether `sample` looks already compressed, from the Shannon entropy of its bytes
+fn looks_incompressible(sample: &[u8]) -> bool {
+    let mut counts = [0u64; 256];
+    for &byte in sample {
+        counts[byte as usize] += 1;
+    }
+
+    let len = sample.len() as f64;
+    let entropy: f64 = counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let probability = count as f64 / len;
+            -probability * probability.log2()
+        })
+        .sum();
+    entropy > INCOMPRESSIBLE_ENTROPY
+}
+
+/// A file or directory found while walking the input files
+pub struct WalkedEntry {
+    /// Where the entry is in the filesystem
+    pub path: PathBuf,
+    /// Path relative to the parent of the input file it was found in, used as the name in the archive
+    pub name: PathBuf,
+    /// Metadata of the entry, with symlinks followed
+    pub metadata: std::fs::Metadata,
+    /// Contents of the entry, ready to be read
+    pub contents: EntryContents,
+    /// SHA-512 of the contents of files, when walking with `hash_contents` for `--dedup`
+    pub content_hash: Option<[u8; 64]>,
+    /// Whether the start of the contents looks already compressed, only sampled for files of at
+    /// least [`SAMPLED_SIZE_LIMIT`]
+    pub incompressible: bool,
+}
+
+/// Contents of a [`WalkedEntry`]
+pub enum EntryContents {
+    Directory,
+    /// Small files are read into memory by the workers
+    Prefetched(Cursor<Vec<u8>>),
+    /// Big files are only opened, and read while being archived
+    File(fs::File),
+    /// FIFOs, sockets and devices kept by [`SpecialFiles::Store`], they have no contents
+    Special,
+}
+
+impl Read for EntryContents {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            EntryContents::Directory | EntryContents::Special => Ok(0),
+            EntryContents::Prefetched(cursor) => cursor.read(buf),
+            EntryContents::File(file) => file.read(buf),
+        }
+    }
+}
+
+/// Walk through `input_filenames` and everything inside of them, in order.
+///
+/// The file at `output_path` is skipped (in order to avoid compression recursion), as are broken symlinks.
+/// Entry names get the Unicode normalization given by `name_normalization`. The input `-` is the data
+/// piped to stdin, archived as a single file named by [`set_stdin_entry_name`]. Special files are
+/// handled by [`keep_special_file`], the builder has to check whether it can store the ones it's given.
+/// With `hash_contents`, the workers also hash the contents of files, see [`WalkedEntry::content_hash`].
+/// With a [`Baseline`], unchanged files are skipped, and the last entry is [`BASELINE_MANIFEST_NAME`].
+pub fn walk_input_files(
+    input_filenames: &[PathBuf],
+    output_path: &Path,
+    file_visibility_policy: FileVisibilityPolicy,
+    name_normalization: NormalizationForm,
+    special_files: SpecialFiles,
+    hash_contents: bool,
+) -> impl Iterator<Item = crate::Result<WalkedEntry>> {
+    let (tx, rx) = mpsc::sync_channel::<Receiver<crate::Result<Option<WalkedEntry>>>>(WALK_AHEAD_LIMIT);
+
+    let input_filenames = input_filenames.to_vec();
+    let output_path = output_path.to_owned();
+
+    thread::spawn(move || {
+        let output_handle = Handle::from_path(&output_path);
+        // Dropped once walking ends, stopping the reader threads
+        let reads = spawn_readers(special_files, hash_contents);
+
+        for filename in &input_filenames {
+            if is_path_stdin(filename) {
+                let (entry_tx, entry_rx) = mpsc::sync_channel(1);
+                let entry = spool_stdin()
+                    .map_err(Into::into)
+                    .and_then(|spooled| temp_file_entry(spooled, "-".into(), stdin_entry_name(name_normalization)?));
+                let _ = entry_tx.send(entry);
+                if tx.send(entry_rx).is_err() {
+                    return;
+                }
+                continue;
+            }
+
+            let Some(base) = filename.parent() else {
+                let (entry_tx, entry_rx) = mpsc::sync_channel(1);
+                let _ = entry_tx.send(Err(crate::Error::CompressingRootFolder));
+                let _ = tx.send(entry_rx);
+                return;
+            };
+
+            for entry in file_visibility_policy.build_walker(filename) {
+                let (entry_tx, entry_rx) = mpsc::sync_channel(1);
+
+                match entry {
+                    Ok(entry) => {
+                        let path = entry.into_path();
+
+                        // Opening a FIFO to compare it with the output would block until something
+                        // writes to it
+                        let is_special = path
+                            .metadata()
+                            .is_ok_and(|metadata| special_file_kind(metadata.file_type()).is_some());
+                        if let (Ok(handle), false) = (&output_handle, is_special) {
+                            if matches!(Handle::from_path(&path), Ok(x) if &x == handle) {
+                                warning(format!(
+                                    "Cannot compress `{}` into itself, skipping",
+                                    output_path.display()
+                                ));
+
+                                continue;
+                            }
+                        }
+
+                        let name = path.strip_prefix(base).unwrap_or(&path);
+                        let name = normalize_path(name, name_normalization).into_owned();
+                        // Only fails if every reader thread panicked, then `entry_rx` reports it
+                        let _ = reads.send(ReadJob { path, name, entry_tx });
+                    }
+                    Err(err) => {
+                        if let Some(path) = symlink_loop(&err) {
+                            warn_symlink_loop(path);
+                            continue;
+                        }
+                        let _ = entry_tx.send(Err(err.into()));
+                    }
+                }
+
+                // The builder stopped early, probably because of an error
+                if tx.send(entry_rx).is_err() {
+                    return;
+                }
+            }
+        }
+
+        let entry = match baseline_manifest() {
+            Ok(Some(manifest)) => {
+                temp_file_entry(manifest, BASELINE_MANIFEST_NAME.into(), BASELINE_MANIFEST_NAME.into())
+            }
+            Ok(None) => Ok(None),
+            Err(err) => Err(err.into()),
+        };
+        let (entry_tx, entry_rx) = mpsc::sync_channel(1);
+        let _ = entry_tx.send(entry);
+        let _ = tx.send(entry_rx);
+    });
+
+    rx.into_iter().filter_map(|entry_rx| {
+        // The reader thread is gone without sending the entry, only when it panicked
+        entry_rx
+            .recv()
+            .unwrap_or_else(|_| {
+                Err(FinalError::with_title("Failed to read an input file")
+                    .detail("The thread reading it stopped unexpectedly")
+                    .into())
+            })
+            .transpose()
+    })
+}
+
+/// Entry for a reader thread to read, the result goes to `entry_tx`
+struct ReadJob {
+    path: PathBuf,
+    name: PathBuf,
+    entry_tx: SyncSender<crate::Result<Option<WalkedEntry>>>,
+}
+
+/// Spawn the threads reading the entries sent to the returned channel with [`read_entry`], they
+/// exit once it's dropped
+fn spawn_readers(special_files: SpecialFiles, hash_contents: bool) -> SyncSender<ReadJob> {
+    let threads = thread::available_parallelism()
+        .map_or(1, usize::from)
+        .min(MAX_READER_THREADS);
+    let (jobs_tx, jobs_rx) = mpsc::sync_channel::<ReadJob>(WALK_AHEAD_LIMIT);
+    let jobs_rx = Arc::new(Mutex::new(jobs_rx));
+
+    for _ in 0..threads {
+        let jobs_rx = Arc::clone(&jobs_rx);
+        thread::spawn(move || loop {
+            // Released before reading, so the other threads can take the next jobs
+            let job = jobs_rx.lock().unwrap_or_else(PoisonError::into_inner).recv();
+            let Ok(ReadJob { path, name, entry_tx }) = job else {
+                return;
+            };
+            let _ = entry_tx.send(read_entry(path, name, special_files, hash_contents));
+        });
+    }
+
+    jobs_tx
+}
+
+/// Archive the temporary file `spooled` as an entry named `name`, `path` is shown in errors
+fn temp_file_entry(spooled: NamedTempFile, path: PathBuf, name: PathBuf) -> crate::Result<Option<WalkedEntry>> {
+    let metadata = spooled.as_file().metadata()?;
+    let mut file = spooled.into_file();
+    file.rewind()?;
+
+    Ok(Some(WalkedEntry {
+        contents: EntryContents::File(fs::File::from_parts(file, &path)),
+        path,
+        name,
+        metadata,
+        content_hash: None,
+        incompressible: false,
+    }))
+}
+
+/// Read the metadata and contents of an entry, returns `None` for broken symlinks
+fn read_entry(
+    path: PathBuf,
+    name: PathBuf,
+    special_files: SpecialFiles,
+    hash_contents: bool,
+) -> crate::Result<Option<WalkedEntry>> {
+    let metadata = match path.metadata() {
+        Ok(metadata) => metadata,
+        // This path is for a broken symlink, ignore it
+        Err(e) if e.kind() == io::ErrorKind::NotFound && path.is_symlink() => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    if is_unchanged(&name, &metadata) {
+        return Ok(None);
+    }
+
+    if metadata.is_file() {
+        WALKED_FILES.fetch_add(1, Ordering::Relaxed);
+        WALKED_BYTES.fetch_add(metadata.len(), Ordering::Relaxed);
+    }
+
+    // Reading FIFOs would block until something writes to them, and devices may never end
+    let mut contents = if let Some(kind) = special_file_kind(metadata.file_type()) {
+        if !keep_special_file(&path, kind, special_files)? {
+            return Ok(None);
+        }
+        EntryContents::Special
+    } else if metadata.is_dir() {
+        EntryContents::Directory
+    } else if metadata.len() <= PREFETCH_SIZE_LIMIT {
+        EntryContents::Prefetched(Cursor::new(fs::read(&path)?))
+    } else {
+        EntryContents::File(fs::File::open(&path)?)
+    };
+
+    let content_hash = match &mut contents {
+        EntryContents::Prefetched(cursor) if hash_contents => {
+            Some(sha512_from_reader(&mut cursor.get_ref().as_slice())?)
+        }
+        EntryContents::File(file) if hash_contents => {
+            let hash = sha512_from_reader(file)?;
+            file.rewind()?;
+            Some(hash)
+        }
+        _ => None,
+    };
+
+    let incompressible = match &mut contents {
+        EntryContents::File(file) if metadata.len() >= SAMPLED_SIZE_LIMIT => {
+            let mut sample = vec![];
+            (&mut *file).take(SAMPLE_SIZE).read_to_end(&mut sample)?;
+            file.rewind()?;
+
+            let incompressible = looks_incompressible(&sample);
+            SAMPLED_BYTES.fetch_add(metadata.len(), Ordering::Relaxed);
+            if incompressible {
+                INCOMPRESSIBLE_BYTES.fetch_add(metadata.len(), Ordering::Relaxed);
+            }
+            incompressible
+        }
+        _ => false,
+    };
+
+    Ok(Some(WalkedEntry {
+        path,
+        name,
+        metadata,
+        contents,
+        content_hash,
+        incompressible,
+    }))
+}
+
+/// Name of the kind of special file (FIFO, socket or device) of `file_type`, `None` for regular files
+/// and directories
+pub fn special_file_kind(file_type: std::fs::FileType) -> Option<&'static str> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+
+        if file_type.is_fifo() {
+            return Some("FIFO");
+        } else if file_type.is_socket() {
+            return Some("socket");
+        } else if file_type.is_char_device() {
+            return Some("character device");
+        } else if file_type.is_block_device() {
+            return Some("block device");
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = file_type;
+
+    None
+}
+
+/// Apply the `--special-files` policy to `path`, a special file of the given `kind`, returns
+/// whether it should be stored in the archive
+pub fn keep_special_file(path: &Path, kind: &str, policy: SpecialFiles) -> crate::Result<bool> {
+    match policy {
+        SpecialFiles::Skip => {
+            warning(format!("Skipping `{}`, it is a {kind}", EscapedPathDisplay::new(path)));
+            Ok(false)
+        }
+        SpecialFiles::Store => Ok(true),
+        SpecialFiles::Fail => Err(FinalError::with_title(format!(
+            "Cannot compress `{}`",
+            EscapedPathDisplay::new(path)
+        ))
+        .detail(format!("It is a {kind}, not a regular file or directory"))
+        .hint("Use '--special-files skip' to leave special files out of the archive")
+        .hint("Use '--special-files store' to store them, in formats that support it")
+        .class(ErrorClass::BadArguments)
+        .into()),
+    }
+}
+
+/// Warn that `path` is left out because it's a symlink to one of its parent directories
+pub fn warn_symlink_loop(path: &Path) {
+    warning(format!(
+        "Skipping `{}`, it is a symlink to one of its parent directories",
+        EscapedPathDisplay::new(path)
+    ));
+}
+
+/// Warn that `path`, a special file of the given `kind`, is left out because `format` can't store it
+pub fn warn_special_file_not_stored(path: &Path, kind: &str, format: &str) {
+    warning(format!(
+        "Skipping `{}`, {format} archives can't store a {kind}",
+        EscapedPathDisplay::new(path)
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_walk_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        fs::create_dir_all(input.join("dir")).unwrap();
+        fs::write(input.join("a.txt"), "abc").unwrap();
+        fs::write(input.join("dir/b.txt"), "defgh").unwrap();
+
+        let files_before = WALKED_FILES.load(Ordering::Relaxed);
+        let bytes_before = WALKED_BYTES.load(Ordering::Relaxed);
+        let entries = walk_input_files(
+            &[input],
+            &dir.path().join("output.tar"),
+            FileVisibilityPolicy::default(),
+            NormalizationForm::None,
+            SpecialFiles::Skip,
+            false,
+        )
+        .collect::<crate::Result<Vec<_>>>()
+        .unwrap();
+        assert_eq!(entries.len(), 4);
+
+        // Other tests may be walking files at the same time, adding to the counts
+        assert!(WALKED_FILES.load(Ordering::Relaxed) - files_before >= 2);
+        assert!(WALKED_BYTES.load(Ordering::Relaxed) - bytes_before >= 8);
+
+        // Without `estimate_total_size`, the total is never known
+        assert_eq!(TOTAL_SIZE.get(), None);
+        let status = walk_status();
+        assert!(status.starts_with("Scanned "), "{status}");
+        assert!(!status.contains(" of "), "{status}");
+    }
+}
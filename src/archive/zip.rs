@@ -3,70 +3,86 @@
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 use std::{
-    env,
     io::{self, prelude::*},
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
     sync::mpsc,
     thread,
+    time::SystemTime,
 };
 
+use encoding_rs::Encoding;
 use filetime_creation::{set_file_mtime, FileTime};
 use fs_err as fs;
-use same_file::Handle;
+use ignore::overrides::{Override, OverrideBuilder};
+use rayon::prelude::{IntoParallelRefMutIterator, ParallelIterator};
+use tempfile::SpooledTempFile;
 use time::OffsetDateTime;
-use zip::{self, read::ZipFile, DateTime, ZipArchive};
+use zip::{self, read::ZipFile, write::FileOptions, CompressionMethod, DateTime, ZipArchive};
 
+#[cfg(unix)]
+use crate::utils::set_unpacked_permissions;
 use crate::{
-    error::FinalError,
+    archive::{
+        manifest::{ArchiveObserver, StoredEntry},
+        walk::{self, walk_input_files, EntryContents, WalkedEntry},
+        ArchiveInfo, DirectoryMtimes, EntrySummary, Reproducible, StoredContents, UnpackOptions,
+    },
+    error::{Error, ErrorClass, FinalError},
     list::FileInArchive,
+    options::{NormalizationForm, SpecialFiles, ZipMethod, ZipRule},
     utils::{
-        cd_into_same_dir_as, get_invalid_utf8_paths,
-        logger::{info, info_accessible, warning},
-        pretty_format_list_of_paths, strip_cur_dir, Bytes, EscapedPathDisplay, FileVisibilityPolicy,
+        self, checksum::crc32_from_reader, get_invalid_utf8_paths, io::JoinedParts, logger::info,
+        pretty_format_list_of_paths, strip_cur_dir, Bytes, EscapedPathDisplay, FileVisibilityPolicy, PermissionsPolicy,
     },
 };
 
 /// Unpacks the archive given by `archive` into the folder given by `output_folder`.
-/// Assumes that output_folder is empty
+/// Entries whose path is already taken are resolved with `options.on_conflict`.
+///
+/// `source` is the file `archive` is read from, if the contents of stored entries can be copied
+/// straight from it, see [`copy_stored_entry`]
 pub fn unpack_archive<R>(
     mut archive: ZipArchive<R>,
+    source: Option<&JoinedParts>,
     output_folder: &Path,
-    password: Option<&[u8]>,
-    quiet: bool,
+    options: UnpackOptions,
 ) -> crate::Result<usize>
 where
     R: Read + Seek,
 {
-    assert!(output_folder.read_dir().expect("dir exists").count() == 0);
-
     let mut unpacked_files = 0;
+    let mut dir_mtimes = DirectoryMtimes::default();
 
     for idx in 0..archive.len() {
-        let mut file = match password {
-            Some(password) => archive
-                .by_index_decrypt(idx, password)?
-                .map_err(|_| zip::result::ZipError::UnsupportedArchive("Password required to decrypt file"))?,
-            None => archive.by_index(idx)?,
-        };
-        let file_path = match file.enclosed_name() {
-            Some(path) => path.to_owned(),
-            None => continue,
+        let encrypted = options.password.is_some() && is_encrypted(&mut archive, idx);
+        let mut file = open_entry(&mut archive, idx, options.password)?;
+        let is_dir = is_dir(&file, options.name_encoding);
+        let mtime = file.last_modified().to_time().ok().map(SystemTime::from);
+        let Some(file_path) = enclosed_name(&file, options.name_encoding)
+            .map(|path| {
+                let contents = StoredContents {
+                    size: Some(file.size()),
+                    crc32: Some(file.crc32()),
+                };
+                options.unpacked_path(output_folder, &path, is_dir, mtime, file.unix_mode(), contents)
+            })
+            .transpose()?
+            .flatten()
+        else {
+            continue;
         };
 
-        let file_path = output_folder.join(file_path);
-
-        display_zip_comment_if_exists(&file);
-
-        match file.name().ends_with('/') {
+        match is_dir {
             _is_dir @ true => {
                 // This is printed for every file in the archive and has little
                 // importance for most users, but would generate lots of
                 // spoken text for users using screen readers, braille displays
                 // and so on
-                if !quiet {
+                if !options.quiet {
                     info(format!("File {} extracted to \"{}\"", idx, file_path.display()));
                 }
                 fs::create_dir_all(&file_path)?;
+                dir_mtimes.push(file_path.clone(), mtime);
             }
             _is_file @ false => {
                 if let Some(path) = file_path.parent() {
@@ -77,7 +93,7 @@ where
                 let file_path = strip_cur_dir(file_path.as_path());
 
                 // same reason is in _is_dir: long, often not needed text
-                if !quiet {
+                if !options.quiet {
                     info(format!(
                         "{:?} extracted. ({})",
                         file_path.display(),
@@ -86,25 +102,46 @@ where
                 }
 
                 let mut output_file = fs::File::create(file_path)?;
-                io::copy(&mut file, &mut output_file)?;
+                if !copy_stored_entry(&file, source, &output_file, file_path)? {
+                    io::copy(&mut entry_contents(&mut file)?, &mut output_file).map_err(|err| {
+                        match err.kind() {
+                            // ZipCrypto only checks a byte of the password before decrypting, and AES
+                            // checks its MAC after, so a wrong one gives corrupted contents
+                            io::ErrorKind::InvalidData if encrypted => Error::WrongPassword,
+                            _ => err.into(),
+                        }
+                    })?;
+                }
 
                 set_last_modified_time(&file, file_path)?;
             }
         }
 
         #[cfg(unix)]
-        unix_set_permissions(&file_path, &file)?;
+        unix_set_permissions(&file_path, &file, options.permissions)?;
+
+        // Zip only exposes the DOS read-only attribute, through the permissions it maps to
+        #[cfg(windows)]
+        if file.is_file() && file.unix_mode().is_some_and(|mode| mode & 0o222 == 0) {
+            let mut permissions = fs::metadata(&file_path)?.permissions();
+            permissions.set_readonly(true);
+            fs::set_permissions(&file_path, permissions)?;
+        }
 
         unpacked_files += 1;
     }
+    dir_mtimes.restore()?;
 
     Ok(unpacked_files)
 }
 
 /// List contents of `archive`, returning a vector of archive entries
+///
+/// The names not flagged as UTF-8 are decoded with `name_encoding`, or CP437 if it's `None`.
 pub fn list_archive<R>(
     mut archive: ZipArchive<R>,
     password: Option<&[u8]>,
+    name_encoding: Option<&'static Encoding>,
 ) -> impl Iterator<Item = crate::Result<FileInArchive>>
 where
     R: Read + Seek + Send + 'static,
@@ -124,46 +161,138 @@ where
     thread::spawn(move || {
         for idx in 0..archive.len() {
             let file_in_archive = (|| {
-                let zip_result = match password.clone() {
-                    Some(password) => archive
-                        .by_index_decrypt(idx, &password)?
-                        .map_err(|_| zip::result::ZipError::UnsupportedArchive("Password required to decrypt file")),
-                    None => archive.by_index(idx),
+                let encrypted = is_encrypted(&mut archive, idx);
+                // The headers aren't encrypted, the password is only checked if there's one
+                let file = match password.as_deref() {
+                    Some(password) => open_entry(&mut archive, idx, Some(password))?,
+                    None => archive.by_index_raw(idx)?,
                 };
 
-                let file = match zip_result {
-                    Ok(f) => f,
-                    Err(e) => return Err(e.into()),
-                };
-
-                let path = file.enclosed_name().unwrap_or(&*file.mangled_name()).to_owned();
-                let is_dir = file.is_dir();
-
-                Ok(FileInArchive { path, is_dir })
+                let path = enclosed_name(&file, name_encoding).unwrap_or_else(|| file.mangled_name());
+                let is_dir = is_dir(&file, name_encoding);
+                let mtime = file.last_modified().to_time().ok().map(SystemTime::from);
+                let comment = (!file.comment().is_empty()).then(|| file.comment().to_owned());
+
+                Ok(FileInArchive {
+                    path,
+                    is_dir,
+                    mtime,
+                    comment,
+                    method: Some(method_name(file.compression())),
+                    encrypted: Some(encrypted),
+                })
             })();
-            tx.send(file_in_archive).unwrap();
+            // Stop once the entries aren't read anymore
+            if tx.send(file_in_archive).is_err() {
+                break;
+            }
         }
     });
 
     Files(rx)
 }
 
-/// Compresses the archives given by `input_filenames` into the file given previously to `writer`.
+/// Reads the central directory of `archive`, returning the sizes and checksums of every entry
+///
+/// Zip stores the CRC32 of each entry, so this doesn't need to decompress (or decrypt) anything.
+pub fn summarize_archive<R>(
+    mut archive: ZipArchive<R>,
+    name_encoding: Option<&'static Encoding>,
+) -> crate::Result<Vec<EntrySummary>>
+where
+    R: Read + Seek,
+{
+    (0..archive.len())
+        .map(|idx| {
+            let file = archive.by_index_raw(idx)?;
+            let path = enclosed_name(&file, name_encoding).unwrap_or_else(|| file.mangled_name());
+            let is_dir = is_dir(&file, name_encoding);
+            let (size, crc32) = if is_dir { (0, 0) } else { (file.size(), file.crc32()) };
+
+            Ok(EntrySummary {
+                path,
+                is_dir,
+                size,
+                crc32,
+            })
+        })
+        .collect()
+}
+
+/// Comment of the whole archive, if it has one
+pub fn archive_comment<R>(archive: &ZipArchive<R>) -> Option<String>
+where
+    R: Read + Seek,
+{
+    let comment = archive.comment();
+    (!comment.is_empty()).then(|| String::from_utf8_lossy(comment).into_owned())
+}
+
+/// Counts the entries of `archive` from the central directory, with the compression methods and
+/// archive comment
+pub fn archive_info<R>(mut archive: ZipArchive<R>) -> crate::Result<ArchiveInfo>
+where
+    R: Read + Seek,
+{
+    let mut info = ArchiveInfo::default();
+    let mut methods = vec![];
+
+    for idx in 0..archive.len() {
+        let encrypted = is_encrypted(&mut archive, idx);
+        let file = archive.by_index_raw(idx)?;
+        info.add_entry(file.is_dir(), file.size(), encrypted);
+
+        let method = method_name(file.compression());
+        if !file.is_dir() && !methods.contains(&method) {
+            methods.push(method);
+        }
+    }
+
+    if !methods.is_empty() {
+        info.details.push(("Compression methods", methods.join(", ")));
+    }
+    if let Some(comment) = archive_comment(&archive) {
+        info.details.push(("Comment", comment));
+    }
+
+    Ok(info)
+}
+
+/// Compresses the archives given by `input_filenames` into the file given previously to `writer`,
+/// with the archive comment `comment`. Stored entries are reported to `observer`. With `auto_store`,
+/// files that look already compressed are stored without compressing them again, files matching
+/// one of the `rules` are compressed as the first one says instead
+#[allow(clippy::too_many_arguments)]
 pub fn build_archive_from_paths<W>(
     input_filenames: &[PathBuf],
     output_path: &Path,
     writer: W,
     file_visibility_policy: FileVisibilityPolicy,
     quiet: bool,
+    reproducible: Option<Reproducible>,
+    comment: Option<&str>,
+    name_normalization: NormalizationForm,
+    special_files: SpecialFiles,
+    auto_store: bool,
+    rules: &[ZipRule],
+    mut observer: Option<&mut dyn ArchiveObserver>,
 ) -> crate::Result<W>
 where
     W: Write + Seek,
 {
+    let rules = EntryRules::new(rules)?;
     let mut writer = zip::ZipWriter::new(writer);
+    if let Some(comment) = comment {
+        writer.set_comment(comment);
+    }
     // always use ZIP64 to allow compression of files larger than 4GB
     // the format is widely supported and the extra 20B is negligible in most cases
     let options = zip::write::FileOptions::default().large_file(true);
-    let output_handle = Handle::from_path(output_path);
+    // Directories would otherwise get the current time
+    let options = match reproducible {
+        Some(reproducible) => options.last_modified_time(reproducible_time(reproducible)),
+        None => options,
+    };
 
     #[cfg(not(unix))]
     let executable = options.unix_permissions(0o755);
@@ -182,106 +311,405 @@ where
         return Err(error.into());
     }
 
-    for filename in input_filenames {
-        let previous_location = cd_into_same_dir_as(filename)?;
-
-        // Unwrap safety:
-        //   paths should be canonicalized by now, and the root directory rejected.
-        let filename = filename.file_name().unwrap();
-
-        for entry in file_visibility_policy.build_walker(filename) {
-            let entry = entry?;
-            let path = entry.path();
-
-            // If the output_path is the same as the input file, warn the user and skip the input (in order to avoid compression recursion)
-            if let Ok(handle) = &output_handle {
-                if matches!(Handle::from_path(path), Ok(x) if &x == handle) {
-                    warning(format!(
-                        "Cannot compress `{}` into itself, skipping",
-                        output_path.display()
-                    ));
-                }
-            }
+    // Entries are compressed in parallel, a few at a time, then copied into the
+    // archive in walk order so the output doesn't depend on the number of threads
+    let chunk_size = rayon::current_num_threads() * 2;
+    let mut entries = walk_input_files(
+        input_filenames,
+        output_path,
+        file_visibility_policy,
+        name_normalization,
+        special_files,
+        observer.as_ref().is_some_and(|observer| observer.wants_content_hash()),
+    )
+    // Zip has no way to store special files
+    .filter(|entry| match entry {
+        Ok(WalkedEntry {
+            path,
+            metadata,
+            contents: EntryContents::Special,
+            ..
+        }) => {
+            let kind = walk::special_file_kind(metadata.file_type()).expect("walked as a special file");
+            walk::warn_special_file_not_stored(path, kind, "zip");
+            false
+        }
+        _ => true,
+    });
 
-            // This is printed for every file in `input_filenames` and has
-            // little importance for most users, but would generate lots of
-            // spoken text for users using screen readers, braille displays
-            // and so on
-            if !quiet {
-                info(format!("Compressing '{}'", EscapedPathDisplay::new(path)));
-            }
+    loop {
+        let chunk: Vec<WalkedEntry> = entries.by_ref().take(chunk_size).collect::<crate::Result<_>>()?;
+        if chunk.is_empty() {
+            break;
+        }
 
-            let metadata = match path.metadata() {
-                Ok(metadata) => metadata,
-                Err(e) => {
-                    if e.kind() == std::io::ErrorKind::NotFound && path.is_symlink() {
-                        // This path is for a broken symlink, ignore it
-                        continue;
-                    }
-                    return Err(e.into());
+        let mut chunk: Vec<(String, FileOptions, EntryContents)> = chunk
+            .into_iter()
+            .map(|entry| {
+                let WalkedEntry {
+                    path,
+                    name,
+                    metadata,
+                    contents,
+                    content_hash,
+                    incompressible,
+                } = entry;
+
+                // This is printed for every file in `input_filenames` and has
+                // little importance for most users, but would generate lots of
+                // spoken text for users using screen readers, braille displays
+                // and so on
+                if !quiet {
+                    info(format!("Compressing '{}'", EscapedPathDisplay::new(&name)));
                 }
-            };
 
-            #[cfg(unix)]
-            let options = options.unix_permissions(metadata.permissions().mode());
+                #[cfg(unix)]
+                let options = options.unix_permissions(metadata.permissions().mode());
 
-            let entry_name = path.to_str().ok_or_else(|| {
-                FinalError::with_title("Zip requires that all directories names are valid UTF-8")
-                    .detail(format!("File at '{path:?}' has a non-UTF-8 name"))
-            })?;
+                let entry_name = name.to_str().ok_or_else(|| {
+                    FinalError::with_title("Zip requires that all directories names are valid UTF-8")
+                        .detail(format!("File at '{path:?}' has a non-UTF-8 name"))
+                })?;
 
-            if metadata.is_dir() {
-                writer.add_directory(entry_name, options)?;
-            } else {
                 #[cfg(not(unix))]
-                let options = if is_executable::is_executable(path) {
+                let options = if !metadata.is_dir() && is_executable::is_executable(&path) {
                     executable
                 } else {
                     options
                 };
 
-                let mut file = fs::File::open(path)?;
-
                 // Updated last modified time
-                let last_modified_time = options.last_modified_time(get_last_modified_time(&file));
+                let options = match reproducible {
+                    Some(_) => options,
+                    None => options.last_modified_time(get_last_modified_time(&metadata)),
+                };
+
+                let options = match rules.matching(&name, metadata.is_dir()) {
+                    Some(rule) => options
+                        .compression_method(match rule.method {
+                            ZipMethod::Store => CompressionMethod::Stored,
+                            ZipMethod::Deflate => CompressionMethod::Deflated,
+                        })
+                        .compression_level(rule.level),
+                    None if auto_store && incompressible => options.compression_method(CompressionMethod::Stored),
+                    None => options,
+                };
+
+                if let Some(observer) = observer.as_deref_mut() {
+                    observer.entry_stored(StoredEntry::new(name.clone(), &metadata, reproducible, content_hash))?;
+                }
 
-                writer.start_file(entry_name, last_modified_time)?;
-                io::copy(&mut file, &mut writer)?;
+                Ok((entry_name.to_owned(), options, contents))
+            })
+            .collect::<crate::Result<_>>()?;
+
+        let compressed_entries: Vec<Option<ZipArchive<SpooledTempFile>>> = chunk
+            .par_iter_mut()
+            .map(|(entry_name, options, contents)| match contents {
+                EntryContents::Directory => Ok(None),
+                EntryContents::Special => unreachable!("special files are filtered out"),
+                contents => compress_entry(entry_name, *options, contents).map(Some),
+            })
+            .collect::<crate::Result<_>>()?;
+
+        for ((entry_name, options, _), compressed) in chunk.into_iter().zip(compressed_entries) {
+            match compressed {
+                Some(mut compressed) => writer.raw_copy_file(compressed.by_index_raw(0)?)?,
+                None => writer.add_directory(entry_name, options)?,
             }
         }
-
-        env::set_current_dir(previous_location)?;
     }
 
     let bytes = writer.finish()?;
     Ok(bytes)
 }
 
-fn display_zip_comment_if_exists(file: &ZipFile) {
-    let comment = file.comment();
-    if !comment.is_empty() {
-        // Zip file comments seem to be pretty rare, but if they are used,
-        // they may contain important information, so better show them
-        //
-        // "The .ZIP file format allows for a comment containing up to 65,535 (216−1) bytes
-        // of data to occur at the end of the file after the central directory."
-        //
-        // If there happen to be cases of very long and unnecessary comments in
-        // the future, maybe asking the user if he wants to display the comment
-        // (informing him of its size) would be sensible for both normal and
-        // accessibility mode..
-        info_accessible(format!("Found comment in {}: {}", file.name(), comment));
+/// Globs of the `--zip-rule` flags, matched against the names of the entries
+struct EntryRules<'a>(Vec<(Override, &'a ZipRule)>);
+
+impl<'a> EntryRules<'a> {
+    fn new(rules: &'a [ZipRule]) -> crate::Result<Self> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let glob = OverrideBuilder::new("")
+                    .add(&rule.glob)
+                    .and_then(|builder| builder.build())
+                    .map_err(|err| {
+                        FinalError::with_title(format!("Invalid glob in `--zip-rule {}`", rule.glob))
+                            .detail(err.to_string())
+                            .class(ErrorClass::BadArguments)
+                    })?;
+                Ok((glob, rule))
+            })
+            .collect::<crate::Result<_>>()?;
+        Ok(Self(rules))
+    }
+
+    /// First rule whose glob matches the entry named `name`
+    fn matching(&self, name: &Path, is_dir: bool) -> Option<&'a ZipRule> {
+        self.0
+            .iter()
+            .find(|(glob, _)| glob.matched(name, is_dir).is_whitelist())
+            .map(|(_, rule)| *rule)
+    }
+}
+
+/// Compress a single file into its own zip archive, so it can be copied into the output afterwards
+fn compress_entry(
+    entry_name: &str,
+    options: FileOptions,
+    contents: &mut EntryContents,
+) -> crate::Result<ZipArchive<SpooledTempFile>> {
+    // Compressed files bigger than this are written to a temporary file instead of memory
+    const SPOOL_SIZE_LIMIT: usize = 4 * 1024 * 1024;
+
+    let mut writer = zip::ZipWriter::new(SpooledTempFile::new(SPOOL_SIZE_LIMIT));
+    writer.start_file(entry_name, options)?;
+    io::copy(contents, &mut writer)?;
+
+    let mut spool = writer.finish()?;
+    spool.rewind()?;
+    Ok(ZipArchive::new(spool)?)
+}
+
+/// Parse the label given to `--encoding`, like "shift_jis" or "cp437", returning `None` for CP437,
+/// the encoding the zip crate decodes the names not flagged as UTF-8 with
+pub fn parse_encoding(label: &str) -> crate::Result<Option<&'static Encoding>> {
+    // encoding_rs only has the encodings of the web, which CP437 isn't part of
+    if ["cp437", "ibm437", "437"]
+        .iter()
+        .any(|cp437| label.eq_ignore_ascii_case(cp437))
+    {
+        return Ok(None);
+    }
+
+    match Encoding::for_label(label.as_bytes()) {
+        Some(encoding) => Ok(Some(encoding)),
+        None => Err(FinalError::with_title(format!("Unknown encoding '{label}'"))
+            .detail("Encodings are named by their WHATWG labels, like 'shift_jis', 'gbk' or 'windows-1252'")
+            .hint("Zip archives use 'cp437' unless their entries are flagged as UTF-8")
+            .class(ErrorClass::BadArguments)
+            .into()),
+    }
+}
+
+/// Name of `file` decoded with `encoding`, or `None` if it's flagged as UTF-8 or there's no
+/// encoding to decode it with
+fn decoded_name(file: &ZipFile, encoding: Option<&'static Encoding>) -> Option<String> {
+    let encoding = encoding?;
+    let raw = file.name_raw();
+    // The zip crate doesn't tell if the flag is set, but the names it decoded with CP437 differ
+    // from their UTF-8 decoding, unless they're plain ASCII
+    if std::str::from_utf8(raw).is_ok_and(|name| name == file.name()) {
+        return None;
+    }
+    Some(encoding.decode_without_bom_handling(raw).0.into_owned())
+}
+
+/// Path of `file` like [`ZipFile::enclosed_name`], with its name decoded with `encoding`, or `None`
+/// if it's absolute or goes up past the output folder
+fn enclosed_name(file: &ZipFile, encoding: Option<&'static Encoding>) -> Option<PathBuf> {
+    let Some(name) = decoded_name(file, encoding) else {
+        return file.enclosed_name().map(Path::to_owned);
+    };
+    if name.contains('\0') {
+        return None;
+    }
+
+    let path = PathBuf::from(name);
+    let mut depth = 0usize;
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => return None,
+            Component::ParentDir => depth = depth.checked_sub(1)?,
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+        }
+    }
+    Some(path)
+}
+
+/// Whether `file` is a directory, its name ending with a slash or a backslash, written by some
+/// Windows tools
+///
+/// Decoding the name first matters for encodings like Shift-JIS, where the second byte of some
+/// characters is a backslash in CP437.
+fn is_dir(file: &ZipFile, encoding: Option<&'static Encoding>) -> bool {
+    match decoded_name(file, encoding) {
+        Some(name) => name.ends_with(['/', '\\']),
+        None => file.is_dir(),
+    }
+}
+
+/// Opens the entry at `idx` to read its contents with [`entry_contents`], decrypting it with
+/// `password`
+///
+/// The zip crate can't decompress zstd and xz, so these entries are opened raw and decompressed by
+/// `entry_contents` instead.
+fn open_entry<'a, R>(archive: &'a mut ZipArchive<R>, idx: usize, password: Option<&[u8]>) -> crate::Result<ZipFile<'a>>
+where
+    R: Read + Seek,
+{
+    let (method, name) = {
+        let file = archive.by_index_raw(idx)?;
+        (unsupported_method(&file), file.name().to_owned())
+    };
+
+    match method {
+        Some(ZSTD_METHOD | XZ_METHOD) => {
+            if is_encrypted(archive, idx) {
+                return Err(
+                    FinalError::with_title(format!("Cannot decrypt '{name}' from zip archive"))
+                        .detail("Encrypted entries compressed with zstd or xz are not supported")
+                        .class(ErrorClass::UnsupportedFormat)
+                        .into(),
+                );
+            }
+            Ok(archive.by_index_raw(idx)?)
+        }
+        Some(method) => Err(
+            FinalError::with_title(format!("Cannot decompress '{name}' from zip archive"))
+                .detail(format!("Compression method {method} is not supported"))
+                .hint("Only the methods stored (0), deflate (8), zstd (93) and xz (95) are supported")
+                .class(ErrorClass::UnsupportedFormat)
+                .into(),
+        ),
+        None => {
+            let file = match password {
+                Some(password) => archive
+                    .by_index_decrypt(idx, password)?
+                    .map_err(|_| Error::WrongPassword)?,
+                None => archive.by_index(idx)?,
+            };
+            Ok(file)
+        }
+    }
+}
+
+/// Whether the entry at `idx` is encrypted, which only opening it tells, its contents aren't read
+///
+/// Opening fails for entries that need a password before it fails for unsupported methods.
+fn is_encrypted<R>(archive: &mut ZipArchive<R>, idx: usize) -> bool
+where
+    R: Read + Seek,
+{
+    matches!(
+        archive.by_index(idx),
+        Err(zip::result::ZipError::UnsupportedArchive(
+            zip::result::ZipError::PASSWORD_REQUIRED
+        ))
+    )
+}
+
+/// Reads the decompressed contents of `file`, opened by [`open_entry`]
+fn entry_contents<'a>(file: &'a mut ZipFile<'_>) -> io::Result<Box<dyn Read + 'a>> {
+    let crc32 = file.crc32();
+    let decoder: Box<dyn Read + 'a> = match unsupported_method(file) {
+        Some(ZSTD_METHOD) => Box::new(zstd::stream::read::Decoder::new(file)?),
+        Some(XZ_METHOD) => Box::new(xz2::read::XzDecoder::new_multi_decoder(file)),
+        _ => return Ok(Box::new(file)),
+    };
+    Ok(Box::new(Crc32Check {
+        inner: decoder,
+        hasher: crc32fast::Hasher::new(),
+        expected: crc32,
+    }))
+}
+
+/// Copy the contents of `file` from `source` into `output_file`, at `output_path`, without reading
+/// them, returning `false` if they aren't stored as is or can't be copied this way.
+///
+/// The copy is checked against the CRC32 of the entry, reading it back from `output_path`.
+fn copy_stored_entry(
+    file: &ZipFile,
+    source: Option<&JoinedParts>,
+    output_file: &fs::File,
+    output_path: &Path,
+) -> io::Result<bool> {
+    // Encrypted entries are stored with a header, so they're bigger than their contents
+    if file.compression() != CompressionMethod::Stored || file.compressed_size() != file.size() {
+        return Ok(false);
+    }
+    let Some((part, offset)) = source.and_then(|source| source.part_at(file.data_start(), file.size())) else {
+        return Ok(false);
+    };
+    if !utils::copy_file_range(part, offset, output_file.file(), file.size())? {
+        return Ok(false);
+    }
+
+    let (_, crc32) = crc32_from_reader(&mut fs::File::open(output_path)?)?;
+    if crc32 != file.crc32() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid checksum in zip entry",
+        ));
+    }
+
+    Ok(true)
+}
+
+/// Zip compression methods decompressed by ouch
+const ZSTD_METHOD: u16 = 93;
+const XZ_METHOD: u16 = 95;
+
+/// Number of the compression method of `file`, if the zip crate can't decompress it
+#[allow(deprecated)]
+fn unsupported_method(file: &ZipFile) -> Option<u16> {
+    match file.compression() {
+        CompressionMethod::Unsupported(method) => Some(method),
+        _ => None,
+    }
+}
+
+/// Name of `method`, like the ones of the methods supported by the zip crate
+#[allow(deprecated)]
+fn method_name(method: CompressionMethod) -> String {
+    match method {
+        CompressionMethod::Unsupported(ZSTD_METHOD) => "Zstd".to_string(),
+        CompressionMethod::Unsupported(XZ_METHOD) => "Xz".to_string(),
+        CompressionMethod::Unsupported(method) => format!("Unsupported method {method}"),
+        method => method.to_string(),
     }
 }
 
-fn get_last_modified_time(file: &fs::File) -> DateTime {
-    file.metadata()
-        .and_then(|metadata| metadata.modified())
+/// Checks the CRC32 of the contents decompressed by ouch, which the zip crate checks for the others
+struct Crc32Check<R> {
+    inner: R,
+    hasher: crc32fast::Hasher,
+    expected: u32,
+}
+
+impl<R: Read> Read for Crc32Check<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        if read == 0 && !buf.is_empty() && self.hasher.clone().finalize() != self.expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid checksum in zip entry",
+            ));
+        }
+        Ok(read)
+    }
+}
+
+fn get_last_modified_time(metadata: &std::fs::Metadata) -> DateTime {
+    metadata
+        .modified()
         .ok()
         .and_then(|time| DateTime::try_from(OffsetDateTime::from(time)).ok())
         .unwrap_or_default()
 }
 
+fn reproducible_time(reproducible: Reproducible) -> DateTime {
+    i64::try_from(reproducible.mtime)
+        .ok()
+        .and_then(|time| OffsetDateTime::from_unix_timestamp(time).ok())
+        .and_then(|time| DateTime::try_from(time).ok())
+        .unwrap_or_default()
+}
+
 fn set_last_modified_time(zip_file: &ZipFile, path: &Path) -> crate::Result<()> {
     let modification_time = zip_file.last_modified().to_time();
 
@@ -298,11 +726,9 @@ fn set_last_modified_time(zip_file: &ZipFile, path: &Path) -> crate::Result<()>
 }
 
 #[cfg(unix)]
-fn unix_set_permissions(file_path: &Path, file: &ZipFile) -> crate::Result<()> {
-    use std::fs::Permissions;
-
+fn unix_set_permissions(file_path: &Path, file: &ZipFile, permissions: PermissionsPolicy) -> crate::Result<()> {
     if let Some(mode) = file.unix_mode() {
-        fs::set_permissions(file_path, Permissions::from_mode(mode))?;
+        set_unpacked_permissions(file_path, mode, permissions)?;
     }
 
     Ok(())
@@ -0,0 +1,381 @@
+//! Parallel decoding of bzip2 files, used for `.bz2` when there's more than one thread.
+//!
+//! Each bzip2 block is compressed on its own and starts with a 48-bit magic number, at any bit
+//! offset since blocks aren't byte aligned. The blocks found in the input are copied into
+//! standalone streams, like `bzip2recover` does, and decoded a few at a time in parallel. Files
+//! made of several streams, like the ones written by `pbzip2` and `lbzip2`, are decoded as one.
+//!
+//! The magic number can also show up inside of the compressed data of a block. When a block fails
+//! to decode, it's retried joined with the next one, so only blocks that are really corrupted fail.
+
+use std::io::{self, Read};
+
+use rayon::prelude::*;
+
+/// Magic number at the start of every block, the BCD of pi
+const BLOCK_MAGIC: u64 = 0x3141_5926_5359;
+/// Magic number at the end of every stream, the BCD of the square root of pi
+const END_OF_STREAM_MAGIC: u64 = 0x1772_4538_5090;
+/// Bits of both magic numbers
+const MAGIC_BITS: u64 = 48;
+/// Bits of the CRC following the magic numbers
+const CRC_BITS: u64 = 32;
+
+/// Bytes read from the input at a time while looking for the end of a block
+const READ_SIZE: usize = 1024 * 1024;
+
+/// Decodes a bzip2 file, with one or more streams, decoding its blocks in parallel
+pub struct ParallelBzDecoder<R: Read> {
+    input: R,
+    input_ended: bool,
+    /// Input not decoded yet, starting at the byte of `position`
+    buffer: Vec<u8>,
+    /// Bit of `buffer` where the next block, or the end of the stream, starts
+    position: u64,
+    /// Magic numbers before this bit of `buffer` were found inside of a block, not at its end
+    skip_until: u64,
+    /// Block size of the current stream, from b'1' to b'9', `None` before the start of a stream
+    level: Option<u8>,
+    /// CRC of the blocks of the current stream decoded so far
+    stream_crc: u32,
+    /// Whether any stream was started, an input without streams is only valid if empty
+    started: bool,
+    /// Whether any block was decoded, the first batch is a single one so the start of the output
+    /// is available quickly, like to detect the format of the contents
+    decoded_any: bool,
+    output: Vec<u8>,
+    output_position: usize,
+}
+
+impl<R: Read> ParallelBzDecoder<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            input_ended: false,
+            buffer: vec![],
+            position: 0,
+            skip_until: 0,
+            level: None,
+            stream_crc: 0,
+            started: false,
+            decoded_any: false,
+            output: vec![],
+            output_position: 0,
+        }
+    }
+
+    /// Blocks are decoded in parallel, a few at a time, then output in order
+    fn batch_size(&self) -> usize {
+        if self.decoded_any {
+            rayon::current_num_threads() * 2
+        } else {
+            1
+        }
+    }
+
+    /// Append more of the input to the buffer, returns `false` at its end
+    fn read_more(&mut self) -> io::Result<bool> {
+        if self.input_ended {
+            return Ok(false);
+        }
+        let read = (&mut self.input).take(READ_SIZE as u64).read_to_end(&mut self.buffer)?;
+        self.input_ended = read == 0;
+        Ok(read > 0)
+    }
+
+    /// Make sure the buffer holds the bits up to `end`, failing if the input is shorter
+    fn fill_until(&mut self, end: u64) -> io::Result<()> {
+        while (self.buffer.len() as u64) * 8 < end {
+            if !self.read_more()? {
+                return Err(invalid_data("bzip2 stream is truncated"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop the input before the byte of `position`
+    fn discard_decoded_input(&mut self) {
+        let bytes = (self.position / 8) as usize;
+        self.buffer.drain(..bytes);
+        self.position -= bytes as u64 * 8;
+        self.skip_until = self.skip_until.saturating_sub(bytes as u64 * 8);
+    }
+
+    /// Read the header of the next stream, returns `false` if the input ended instead
+    fn start_stream(&mut self) -> io::Result<bool> {
+        debug_assert_eq!(self.position % 8, 0);
+        let start = (self.position / 8) as usize;
+        while self.buffer.len() < start + 4 && self.read_more()? {}
+
+        let header = &self.buffer[start..];
+        if header.is_empty() {
+            return Ok(false);
+        }
+        match header {
+            [b'B', b'Z', b'h', level @ b'1'..=b'9', ..] => {
+                self.level = Some(*level);
+                self.position += 32;
+                self.skip_until = self.position;
+                self.stream_crc = 0;
+                self.started = true;
+                Ok(true)
+            }
+            _ if self.started => Err(invalid_data("found unexpected data after the end of the bzip2 stream")),
+            _ => Err(invalid_data("bzip2 header is missing")),
+        }
+    }
+
+    /// Find the bit where the first magic number at or after `from` starts, reading more of the input
+    /// until there's one
+    fn find_magic(&mut self, from: u64) -> io::Result<u64> {
+        let mut search_from = from;
+        loop {
+            if let Some(found) = find_magic(&self.buffer, search_from) {
+                return Ok(found);
+            }
+            // A magic number could start in the last bits, they're searched again with more input
+            search_from = search_from.max((self.buffer.len() as u64 * 8).saturating_sub(MAGIC_BITS));
+            if !self.read_more()? {
+                return Err(invalid_data("bzip2 stream is truncated"));
+            }
+        }
+    }
+
+    /// Decode the next blocks into `output`, finishing streams and starting the next ones as needed.
+    /// Leaves `output` empty only at the end of the input.
+    fn decode_more(&mut self) -> io::Result<()> {
+        self.output.clear();
+        self.output_position = 0;
+
+        while self.output.is_empty() {
+            self.discard_decoded_input();
+
+            let Some(level) = self.level else {
+                if self.start_stream()? {
+                    continue;
+                }
+                return Ok(());
+            };
+
+            // Bounds of the blocks of this batch, and whether the end of the stream is right after them
+            let mut blocks = vec![];
+            let mut cursor = self.position;
+            let mut end_of_stream = false;
+            while blocks.len() < self.batch_size() {
+                self.fill_until(cursor + MAGIC_BITS)?;
+                match read_bits(&self.buffer, cursor, MAGIC_BITS) {
+                    BLOCK_MAGIC => {
+                        let end = self.find_magic((cursor + MAGIC_BITS).max(self.skip_until))?;
+                        blocks.push((cursor, end));
+                        cursor = end;
+                    }
+                    END_OF_STREAM_MAGIC => {
+                        end_of_stream = true;
+                        break;
+                    }
+                    _ => return Err(invalid_data("bzip2 block is corrupted")),
+                }
+            }
+
+            let buffer = &self.buffer;
+            let decoded: Vec<io::Result<Vec<u8>>> = blocks
+                .par_iter()
+                .map(|&(start, end)| decode_block(buffer, level, start, end))
+                .collect();
+
+            let mut failed = false;
+            for (&(start, end), block) in blocks.iter().zip(decoded) {
+                match block {
+                    Ok(block) => {
+                        let block_crc = read_bits(&self.buffer, start + MAGIC_BITS, CRC_BITS) as u32;
+                        self.stream_crc = self.stream_crc.rotate_left(1) ^ block_crc;
+                        self.output.extend_from_slice(&block);
+                        self.position = end;
+                        self.decoded_any = true;
+                    }
+                    // Already joined with the next block, the block itself is corrupted
+                    Err(err) if self.skip_until > start => return Err(err),
+                    // The magic number ending the block may be part of its compressed data, retry
+                    // joining both blocks
+                    Err(_) => {
+                        self.skip_until = end + 1;
+                        failed = true;
+                        break;
+                    }
+                }
+            }
+
+            if end_of_stream && !failed {
+                self.finish_stream()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check the CRC at the end of the stream at `position` and skip past it
+    fn finish_stream(&mut self) -> io::Result<()> {
+        let crc_start = self.position + MAGIC_BITS;
+        self.fill_until(crc_start + CRC_BITS)?;
+        if read_bits(&self.buffer, crc_start, CRC_BITS) as u32 != self.stream_crc {
+            return Err(invalid_data("bzip2 stream CRC doesn't match its contents"));
+        }
+        // Streams end with padding up to the next byte
+        self.position = (crc_start + CRC_BITS).div_ceil(8) * 8;
+        self.level = None;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ParallelBzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.output_position == self.output.len() {
+            self.decode_more()?;
+        }
+
+        let available = &self.output[self.output_position..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.output_position += len;
+        Ok(len)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Read `count` bits (up to 64) of `buffer` starting at bit `start`, most significant bit first
+fn read_bits(buffer: &[u8], start: u64, count: u64) -> u64 {
+    (start..start + count).fold(0, |value, bit| {
+        let byte = buffer[(bit / 8) as usize];
+        (value << 1) | u64::from((byte >> (7 - bit % 8)) & 1)
+    })
+}
+
+/// Bit of the first block or end of stream magic number of `buffer` starting at or after `from`
+fn find_magic(buffer: &[u8], from: u64) -> Option<u64> {
+    // Every window of 8 bytes holds the 48 bits starting at each of the 8 bits of its first byte
+    let last_window = buffer.len().checked_sub(8)?;
+    (from as usize / 8..=last_window).find_map(|index| {
+        let window = u64::from_be_bytes(buffer[index..index + 8].try_into().unwrap());
+        (0..8).find_map(|shift| {
+            let bit = index as u64 * 8 + shift;
+            let bits = (window << shift) >> (64 - MAGIC_BITS);
+            (bit >= from && (bits == BLOCK_MAGIC || bits == END_OF_STREAM_MAGIC)).then_some(bit)
+        })
+    })
+}
+
+/// Decode the block in the bits `start..end` of `buffer`, copied into a stream of its own
+fn decode_block(buffer: &[u8], level: u8, start: u64, end: u64) -> io::Result<Vec<u8>> {
+    let mut stream = b"BZh".to_vec();
+    stream.push(level);
+
+    let bits = end - start;
+    let first = (start / 8) as usize;
+    let shift = (start % 8) as u32;
+    // Whole bytes of the block, shifted to be byte aligned in the new stream
+    for index in first..first + (bits / 8) as usize {
+        let pair = u16::from_be_bytes([buffer[index], *buffer.get(index + 1).unwrap_or(&0)]);
+        stream.push((pair << shift >> 8) as u8);
+    }
+    let mut writer = BitWriter {
+        bytes: stream,
+        pending: 0,
+        pending_bits: 0,
+    };
+    let rest = bits % 8;
+    writer.write(read_bits(buffer, end - rest, rest), rest);
+
+    // The CRC of a stream with a single block is the CRC of the block
+    writer.write(END_OF_STREAM_MAGIC, MAGIC_BITS);
+    writer.write(read_bits(buffer, start + MAGIC_BITS, CRC_BITS), CRC_BITS);
+    let stream = writer.finish();
+
+    let mut decoded = vec![];
+    bzip2::read::BzDecoder::new(stream.as_slice()).read_to_end(&mut decoded)?;
+    Ok(decoded)
+}
+
+/// Appends bits to a buffer, most significant bit first
+struct BitWriter {
+    bytes: Vec<u8>,
+    pending: u8,
+    pending_bits: u64,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u64, count: u64) {
+        for bit in (0..count).rev() {
+            self.pending = (self.pending << 1) | ((value >> bit) & 1) as u8;
+            self.pending_bits += 1;
+            if self.pending_bits == 8 {
+                self.bytes.push(self.pending);
+                self.pending = 0;
+                self.pending_bits = 0;
+            }
+        }
+    }
+
+    /// Pad the last byte with zeros
+    fn finish(mut self) -> Vec<u8> {
+        if self.pending_bits > 0 {
+            self.bytes.push(self.pending << (8 - self.pending_bits));
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = bzip2::write::BzEncoder::new(vec![], bzip2::Compression::new(1));
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn decodes_every_block_and_stream() {
+        // Several blocks of 100k at level 1, not compressible enough to fit in a single one
+        let data: Vec<u8> = (0..1_000_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        let compressed = [compress(&data), compress(b""), compress(&data[..1000])].concat();
+
+        let mut decoded = vec![];
+        ParallelBzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, [data.as_slice(), &data[..1000]].concat());
+    }
+
+    #[test]
+    fn fails_on_corrupted_input() {
+        let data = "some repeated contents\n".repeat(10_000);
+        let compressed = compress(data.as_bytes());
+
+        let mut trailing = [compressed.as_slice(), b"garbage"].concat();
+        assert!(ParallelBzDecoder::new(trailing.as_slice())
+            .read_to_end(&mut vec![])
+            .is_err());
+
+        trailing.truncate(compressed.len() / 2);
+        assert!(ParallelBzDecoder::new(trailing.as_slice())
+            .read_to_end(&mut vec![])
+            .is_err());
+
+        let mut flipped = compressed.clone();
+        flipped[compressed.len() / 2] ^= 0xff;
+        assert!(ParallelBzDecoder::new(flipped.as_slice())
+            .read_to_end(&mut vec![])
+            .is_err());
+    }
+}
@@ -3,17 +3,20 @@
 #![warn(missing_docs)]
 
 use std::{
-    ffi::OsString,
+    ffi::{OsStr, OsString},
     ops::ControlFlow,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
 
 use crate::{
-    error::FinalError,
-    extension::{build_archive_file_suggestion, Extension},
+    error::{ErrorClass, FinalError},
+    extension::{build_archive_file_suggestion, parse_format_flag, CompressionFormat, Extension},
+    object_storage,
     utils::{
+        is_path_stdin,
         logger::{info_accessible, warning},
-        pretty_format_list_of_paths, try_infer_extension, user_wants_to_continue, EscapedPathDisplay,
+        pretty_format_list_of_paths, try_infer_extension, user_picks_format, user_wants_to_continue,
+        windows_name_problem, EscapedPathDisplay, FileVisibilityPolicy,
     },
     QuestionAction, QuestionPolicy, Result,
 };
@@ -92,7 +95,8 @@ pub fn check_for_non_archive_formats(files: &[PathBuf], formats: &[Vec<Extension
             .detail(format!(
                 "Files are not archives: {}",
                 pretty_format_list_of_paths(&not_archives)
-            ));
+            ))
+            .class(ErrorClass::BadArguments);
 
         return Err(error.into());
     }
@@ -118,13 +122,237 @@ pub fn check_archive_formats_position(formats: &[Extension], output_path: &Path)
             "Otherwise, remove the last '{}' from '{}'.",
             format,
             EscapedPathDisplay::new(output_path)
-        ));
+        ))
+        .class(ErrorClass::BadArguments);
 
         return Err(error.into());
     }
     Ok(())
 }
 
+/// Warn about the files that would be unpacked with a different name on Windows.
+///
+/// Windows doesn't accept names like `aux.txt` or `notes.`, so these would be escaped when unpacking.
+pub fn check_windows_compatible_names(files: &[PathBuf], file_visibility_policy: &FileVisibilityPolicy) -> Result<()> {
+    // The name of the data piped to stdin is checked by `check_stdin_input`
+    for file in files.iter().filter(|file| !is_path_stdin(file)) {
+        let base = file.parent().unwrap_or(file);
+
+        for entry in file_visibility_policy.build_walker(file) {
+            let path = entry?.into_path();
+
+            if let Some(problem) = path.file_name().and_then(windows_name_problem) {
+                warning(format!(
+                    "`{}` {problem}, it will be renamed when unpacked on Windows",
+                    EscapedPathDisplay::new(path.strip_prefix(base).unwrap_or(&path)),
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that stdin is read at most once, and that archives get a name for the entry made from it
+/// with `--entry-name`, a relative path that stays inside of the archive.
+pub fn check_stdin_input(formats: &[Extension], files: &[PathBuf], entry_name: Option<&Path>) -> Result<()> {
+    let stdin_inputs = files.iter().filter(|file| is_path_stdin(file)).count();
+    let is_archive = formats.first().is_some_and(Extension::is_archive);
+
+    if stdin_inputs > 1 {
+        let error = FinalError::with_title("Cannot read stdin more than once")
+            .detail("'-' was passed more than once as an input file")
+            .class(ErrorClass::BadArguments);
+        return Err(error.into());
+    }
+
+    match entry_name {
+        None if stdin_inputs == 1 && is_archive => {
+            let error = FinalError::with_title("Missing the name of the entry read from stdin")
+                .detail("Data piped to stdin is stored in archives as a single file")
+                .hint("Pass its name with '--entry-name':")
+                .hint("  some_command | ouch compress - output.tar.zst --entry-name logs/output.txt")
+                .class(ErrorClass::BadArguments);
+            Err(error.into())
+        }
+        Some(_) if stdin_inputs == 0 || !is_archive => {
+            let error = FinalError::with_title("Cannot use '--entry-name'")
+                .detail("'--entry-name' names the data piped to stdin when it's stored in an archive")
+                .hint("Pass '-' as one of the files, and an archive format like tar or zip as the output")
+                .class(ErrorClass::BadArguments);
+            Err(error.into())
+        }
+        Some(name)
+            if name.as_os_str().is_empty()
+                || !name
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) =>
+        {
+            let error = FinalError::with_title(format!("Invalid entry name '{}'", EscapedPathDisplay::new(name)))
+                .detail("The name of the entry read from stdin must be a relative path inside of the archive")
+                .class(ErrorClass::BadArguments);
+            Err(error.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check if there are recipients to encrypt to when the output is `.age`, and the other way around.
+pub fn check_encryption_recipients(formats: &[Extension], recipients: &[String], output_path: &Path) -> Result<()> {
+    let encrypts = formats
+        .iter()
+        .any(|extension| extension.compression_formats.contains(&CompressionFormat::Age));
+    let output_path = EscapedPathDisplay::new(output_path);
+
+    if encrypts && recipients.is_empty() {
+        let error = FinalError::with_title(format!("Cannot encrypt to '{output_path}'."))
+            .detail("Encrypting to '.age' requires at least one recipient")
+            .hint("Pass the public key to encrypt to with '--encrypt':")
+            .hint(format!(
+                "  ouch compress <FILES>... {output_path} --encrypt age:age1..."
+            ))
+            .class(ErrorClass::BadArguments);
+
+        return Err(error.into());
+    }
+
+    if !encrypts && !recipients.is_empty() {
+        let error = FinalError::with_title(format!("Cannot encrypt to '{output_path}'."))
+            .detail("'--encrypt' was passed, but the output doesn't have the '.age' extension")
+            .hint(format!("Try adding '.age' to the end of '{output_path}'"))
+            .class(ErrorClass::BadArguments);
+
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+/// Check that the output is a tar archive when `flag` is passed, for the flags that rely on what
+/// only tar archives can store, like the offsets of `--index` or the hard links of `--dedup`.
+pub fn check_tar_only_flag(flag: &str, formats: &[Extension], output_path: &Path) -> Result<()> {
+    let is_tar = formats
+        .first()
+        .is_some_and(|extension| extension.compression_formats[0] == CompressionFormat::Tar);
+    if is_tar {
+        return Ok(());
+    }
+
+    let error = FinalError::with_title(format!(
+        "Cannot compress to '{}'.",
+        EscapedPathDisplay::new(output_path)
+    ))
+    .detail(format!(
+        "'{flag}' only works with tar archives, like '.tar' or '.tar.zst'"
+    ))
+    .class(ErrorClass::BadArguments);
+
+    Err(error.into())
+}
+
+/// Check that the outermost format of `path` is a compression format, which `--filter-cmd` runs
+/// instead of the built-in codec
+pub fn check_filter_cmd(formats: &[Extension], path: &Path) -> Result<()> {
+    let outermost = formats
+        .last()
+        .and_then(|extension| extension.compression_formats.last());
+    if outermost.is_some_and(|format| !format.is_archive_format()) {
+        return Ok(());
+    }
+
+    let error = FinalError::with_title(format!(
+        "Cannot use '--filter-cmd' with '{}'.",
+        EscapedPathDisplay::new(path)
+    ))
+    .detail("The command takes the place of the outermost compression format, and there's none")
+    .hint(format!(
+        "Add the extension of the format it implements, like '{}.zst'",
+        EscapedPathDisplay::new(path)
+    ))
+    .class(ErrorClass::BadArguments);
+
+    Err(error.into())
+}
+
+/// Check that the output is a tar or zip archive when `flag` is passed, for the settings that the
+/// other archive formats can't store
+pub fn check_tar_or_zip_only_flag(flag: &str, formats: &[Extension], output_path: &Path) -> Result<()> {
+    let is_tar_or_zip = formats.first().is_some_and(|extension| {
+        matches!(
+            extension.compression_formats[0],
+            CompressionFormat::Tar | CompressionFormat::Zip
+        )
+    });
+    if is_tar_or_zip {
+        return Ok(());
+    }
+
+    let error = FinalError::with_title(format!(
+        "Cannot compress to '{}'.",
+        EscapedPathDisplay::new(output_path)
+    ))
+    .detail(format!(
+        "'{flag}' only works with tar and zip archives, like '.tar.zst' or '.zip'"
+    ))
+    .class(ErrorClass::BadArguments);
+
+    Err(error.into())
+}
+
+/// Check that the output is an archive when `flag` is passed, for the flags that pick which files
+/// go into it, like `--newer-than`.
+pub fn check_archive_only_flag(flag: &str, formats: &[Extension], output_path: &Path) -> Result<()> {
+    if formats.first().is_some_and(Extension::is_archive) {
+        return Ok(());
+    }
+
+    let error = FinalError::with_title(format!(
+        "Cannot compress to '{}'.",
+        EscapedPathDisplay::new(output_path)
+    ))
+    .detail(format!("'{flag}' only works with archives, like '.tar.zst' or '.zip'"))
+    .class(ErrorClass::BadArguments);
+
+    Err(error.into())
+}
+
+/// Check that the size of the inputs of `--estimate` is known, which it isn't for stdin.
+pub fn check_estimate_input(files: &[PathBuf]) -> Result<()> {
+    if !files.iter().any(|file| is_path_stdin(file)) {
+        return Ok(());
+    }
+
+    let error = FinalError::with_title("Cannot estimate the compression of stdin")
+        .detail("'--estimate' projects the output from the size of the input files")
+        .class(ErrorClass::BadArguments);
+
+    Err(error.into())
+}
+
+/// Check that the output isn't an S3 URI when other files have to be written next to it.
+pub fn check_s3_output(output_path: &Path, split: bool, sign: bool, index: bool) -> Result<()> {
+    if !object_storage::is_s3_uri(output_path) {
+        return Ok(());
+    }
+
+    let flag = match (split, sign, index) {
+        (true, _, _) => "--split-size",
+        (_, true, _) => "--sign-key",
+        (_, _, true) => "--index",
+        _ => return Ok(()),
+    };
+
+    let error = FinalError::with_title(format!(
+        "Cannot compress to '{}'.",
+        EscapedPathDisplay::new(output_path)
+    ))
+    .detail(format!("'{flag}' requires the output to be a local file"))
+    .hint("Compress to a local file, and upload it afterwards")
+    .class(ErrorClass::BadArguments);
+
+    Err(error.into())
+}
+
 /// Check if all provided files have formats to decompress.
 pub fn check_missing_formats_when_decompressing(files: &[PathBuf], formats: &[Vec<Extension>]) -> Result<()> {
     let files_with_broken_extension: Vec<&PathBuf> = files
@@ -143,7 +371,7 @@ pub fn check_missing_formats_when_decompressing(files: &[PathBuf], formats: &[Ve
             .iter()
             .partition(|path| path.extension().is_some());
 
-    let mut error = FinalError::with_title("Cannot decompress files");
+    let mut error = FinalError::with_title("Cannot decompress files").class(ErrorClass::UnsupportedFormat);
 
     if !files_with_unsupported_extensions.is_empty() {
         error = error.detail(format!(
@@ -176,35 +404,75 @@ pub fn check_missing_formats_when_decompressing(files: &[PathBuf], formats: &[Ve
     Err(error.into())
 }
 
-/// Check if there is a first format when compressing, and returns it.
-pub fn check_first_format_when_compressing<'a>(formats: &'a [Extension], output_path: &Path) -> Result<&'a Extension> {
-    formats.first().ok_or_else(|| {
-        let output_path = EscapedPathDisplay::new(output_path);
-        FinalError::with_title(format!("Cannot compress to '{output_path}'."))
-            .detail("You shall supply the compression format")
-            .hint("Try adding supported extensions (see --help):")
-            .hint(format!("  ouch compress <FILES>... {output_path}.tar.gz"))
-            .hint(format!("  ouch compress <FILES>... {output_path}.zip"))
-            .hint("")
-            .hint("Alternatively, you can overwrite this option by using the '--format' flag:")
-            .hint(format!("  ouch compress <FILES>... {output_path} --format tar.gz"))
-            .into()
-    })
+/// Formats offered when compressing to an output without extensions
+const DEFAULT_FORMAT_CHOICES: &[&str] = &["tar.gz", "tar.zst", "tar.xz", "zip", "7z"];
+
+/// Error for compressing to `output_path` without knowing the formats
+fn missing_format_error(output_path: &Path) -> FinalError {
+    let output_path = EscapedPathDisplay::new(output_path);
+    FinalError::with_title(format!("Cannot compress to '{output_path}'."))
+        .detail("You shall supply the compression format")
+        .hint("Try adding supported extensions (see --help):")
+        .hint(format!("  ouch compress <FILES>... {output_path}.tar.gz"))
+        .hint(format!("  ouch compress <FILES>... {output_path}.zip"))
+        .hint("")
+        .hint("Alternatively, you can overwrite this option by using the '--format' flag:")
+        .hint(format!("  ouch compress <FILES>... {output_path} --format tar.gz"))
+        .class(ErrorClass::BadArguments)
+}
+
+/// Check if there is a first format when compressing.
+///
+/// Without one, `formats` is filled with `default_format`, given by `--default-format`, or else
+/// with the format the user picks from a list. Questions answered by `--yes` or `--no`, or asked
+/// without a terminal, fail instead.
+pub fn check_first_format_when_compressing(
+    formats: &mut Vec<Extension>,
+    output_path: &Path,
+    default_format: Option<&OsStr>,
+    question_policy: QuestionPolicy,
+) -> Result<()> {
+    if !formats.is_empty() {
+        return Ok(());
+    }
+
+    if let Some(default_format) = default_format {
+        *formats = parse_format_flag(default_format)?;
+        return Ok(());
+    }
+
+    match user_picks_format(output_path, DEFAULT_FORMAT_CHOICES, question_policy)? {
+        Some(format) => {
+            *formats = parse_format_flag(OsStr::new(format))?;
+            Ok(())
+        }
+        None => Err(missing_format_error(output_path).into()),
+    }
 }
 
 /// Check if compression is invalid because an archive format is necessary.
 ///
-/// Non-archive formats don't support multiple file compression or folder compression.
+/// Non-archive formats don't support multiple file compression or folder compression, unless the
+/// files are joined with `concat`, which archive formats don't support.
 pub fn check_invalid_compression_with_non_archive_format(
     formats: &[Extension],
     output_path: &Path,
     files: &[PathBuf],
     formats_from_flag: Option<&OsString>,
+    concat: bool,
 ) -> Result<()> {
-    let first_format = check_first_format_when_compressing(formats, output_path)?;
+    let first_format = formats.first().ok_or_else(|| missing_format_error(output_path))?;
+
+    if concat && first_format.is_archive() {
+        let error = FinalError::with_title(format!("Cannot use '--concat' with '{first_format}'"))
+            .detail("'--concat' joins the files into a single stream, archives store them separately")
+            .hint("Remove '--concat', or compress to a format like gz or zst")
+            .class(ErrorClass::BadArguments);
+        return Err(error.into());
+    }
 
     let is_some_input_a_folder = files.iter().any(|path| path.is_dir());
-    let is_multiple_inputs = files.len() > 1;
+    let is_multiple_inputs = files.len() > 1 && !concat;
 
     // If format is archive, nothing to check
     // If there's no folder or multiple inputs, non-archive formats can handle it
@@ -239,7 +507,7 @@ pub fn check_invalid_compression_with_non_archive_format(
     };
     let output_path = EscapedPathDisplay::new(output_path);
 
-    let error = FinalError::with_title(format!("Cannot compress to '{output_path}'."))
+    let mut error = FinalError::with_title(format!("Cannot compress to '{output_path}'."))
         .detail(first_detail_message)
         .detail(format!(
             "The compression format '{first_format}' does not accept multiple files.",
@@ -247,7 +515,11 @@ pub fn check_invalid_compression_with_non_archive_format(
         .detail("Formats that bundle files into an archive are tar and zip.")
         .hint(format!("Try inserting 'tar.' or 'zip.' before '{first_format}'."))
         .hint(from_hint)
-        .hint(to_hint);
+        .hint(to_hint)
+        .class(ErrorClass::BadArguments);
+    if is_multiple_inputs && !is_some_input_a_folder {
+        error = error.hint("Or pass '--concat' to join the files into a single stream, like 'cat a b | gzip'");
+    }
 
     Err(error.into())
 }
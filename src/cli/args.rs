@@ -2,10 +2,12 @@ use std::{ffi::OsString, path::PathBuf};
 
 use clap::{Parser, ValueHint};
 
+use crate::options::{ConflictPolicy, NormalizationForm, SpecialFiles, XzCheck, ZipMethod, ZipRule};
+
 // Ouch command line options (docstrings below are part of --help)
 /// A command-line utility for easily compressing and decompressing files and directories.
 ///
-/// Supported formats: tar, zip, gz, 7z, xz/lzma, bz/bz2, bz3, lz4, sz (Snappy), zst and rar.
+/// Supported formats: tar, zip, gz, 7z, xz/lzma, bz/bz2, bz3, lz4, sz (Snappy), zst, rar and Z (only decompressed).
 ///
 /// Repository: https://github.com/ouch-org/ouch
 #[derive(Parser, Debug, PartialEq)]
@@ -21,6 +23,15 @@ pub struct CliArgs {
     #[arg(short, long, global = true)]
     pub no: bool,
 
+    /// Overwrite files without asking, still asking the other questions
+    #[arg(long, global = true)]
+    pub overwrite: bool,
+
+    /// Continue without asking after warnings, like the format detected from the contents differing
+    /// from the extension
+    #[arg(long, global = true)]
+    pub assume_format: bool,
+
     /// Activate accessibility mode, reducing visual noise
     #[arg(short = 'A', long, env = "ACCESSIBLE", global = true)]
     pub accessible: bool,
@@ -33,22 +44,95 @@ pub struct CliArgs {
     #[arg(short = 'q', long, global = true)]
     pub quiet: bool,
 
-    /// Ignores files matched by git's ignore files
+    /// Ignores files matched by git's ignore files, .ouchignore files are always read
     #[arg(short = 'g', long, global = true)]
     pub gitignore: bool,
 
-    /// Specify the format of the archive
+    /// With --gitignore, don't read the global gitignore, like "~/.config/git/ignore"
+    #[arg(long, global = true)]
+    pub no_global_gitignore: bool,
+
+    /// With --gitignore, also read .gitignore files outside of git repositories
+    #[arg(long, global = true)]
+    pub no_require_git: bool,
+
+    /// Specify the format of the archive, repeat it to use a different format for each input, in order
     #[arg(short, long, global = true)]
-    pub format: Option<OsString>,
+    pub format: Vec<OsString>,
+
+    /// Detect the formats of the files being decompressed or listed from their contents only,
+    /// ignoring their extensions
+    #[arg(long, global = true, conflicts_with = "format")]
+    pub ignore_extension: bool,
+
+    /// Detect files ending in EXT as FORMAT, like "pkg=tar.zst", can be repeated
+    #[arg(
+        long = "map",
+        value_name = "EXT=FORMAT",
+        value_parser = parse_extension_mapping,
+        env = "OUCH_EXTENSION_MAP",
+        value_delimiter = ',',
+        global = true
+    )]
+    pub extension_map: Vec<(String, String)>,
 
-    /// decompress or list with password
+    /// decompress or list with password, repeat it to use a different password for each archive, in order
     #[arg(short = 'p', long = "password", global = true)]
-    pub password: Option<OsString>,
+    pub password: Vec<OsString>,
+
+    /// Ask for the password in the terminal, instead of passing it in the command line
+    #[arg(long, global = true, conflicts_with = "password")]
+    pub password_prompt: bool,
+
+    /// Read the password from a file, ignoring a trailing newline
+    #[arg(long, value_hint = ValueHint::FilePath, global = true, conflicts_with_all = ["password", "password_prompt"])]
+    pub password_file: Option<PathBuf>,
+
+    /// Decrypt .age files with the identities in this file
+    #[arg(long, value_name = "IDENTITY_FILE", value_hint = ValueHint::FilePath, global = true)]
+    pub decrypt: Option<PathBuf>,
 
     /// cocurrent working threads
     #[arg(short = 'c', long, global = true)]
     pub threads: Option<usize>,
 
+    /// Limit the reading and writing speed, like "50MB/s" or "512KiB/s"
+    #[arg(long, value_name = "RATE", value_parser = parse_rate, global = true)]
+    pub rate_limit: Option<u64>,
+
+    /// Size of the buffers used to read and write files, like "1MiB", picked from the input by default
+    #[arg(long, value_name = "SIZE", value_parser = parse_buffer_size, global = true)]
+    pub io_buffer_size: Option<usize>,
+
+    /// Unicode normalization applied to entry names when compressing and decompressing
+    #[arg(long, value_enum, value_name = "FORM", default_value_t, global = true)]
+    pub normalize: NormalizationForm,
+
+    /// Unpack archives in a temporary directory inside of this directory, instead of the output directory
+    #[arg(long, value_hint = ValueHint::DirPath, env = "OUCH_TEMP_DIR", global = true)]
+    pub temp_dir: Option<PathBuf>,
+
+    /// Unpack archives in a temporary directory inside of the output directory, overrides --temp-dir
+    #[arg(long, global = true)]
+    pub same_dir_temp: bool,
+
+    /// Fail instead of replacing output files that are symbolic links
+    #[arg(long, global = true)]
+    pub no_follow_output_symlink: bool,
+
+    /// Print the formats, output paths and settings decided for each input before compressing or
+    /// decompressing it
+    #[arg(long, global = true)]
+    pub explain: bool,
+
+    /// Format of the error printed on failure
+    ///
+    /// The exit code tells the class of the failure: 2 for bad arguments, 3 for unsupported formats,
+    /// 4 for corrupt archives, 5 for I/O errors, 6 when only some of the files failed to
+    /// decompress and 1 otherwise.
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t, global = true)]
+    pub error_format: ErrorFormat,
+
     // Ouch and claps subcommands
     #[command(subcommand)]
     pub cmd: Subcommand,
@@ -56,17 +140,38 @@ pub struct CliArgs {
 
 #[derive(Parser, PartialEq, Eq, Debug)]
 #[allow(rustdoc::bare_urls)]
+// Parsed once, boxing the options of `compress` isn't worth it
+#[allow(clippy::large_enum_variant)]
 pub enum Subcommand {
     /// Compress one or more files into one output file
-    #[command(visible_alias = "c")]
+    // The files can be missing if they're given by `--files-from`
+    #[command(visible_alias = "c", allow_missing_positional = true)]
     Compress {
-        /// Files to be compressed
-        #[arg(required = true, value_hint = ValueHint::FilePath)]
+        /// Files to be compressed, "-" is the data piped to stdin
+        #[arg(required_unless_present_any = ["files_from", "output_dir"], value_hint = ValueHint::FilePath)]
         files: Vec<PathBuf>,
 
         /// The resulting file. Its extensions can be used to specify the compression formats
+        // Required by clap after a list of paths, with '--dir' it's the last file to compress
         #[arg(required = true, value_hint = ValueHint::FilePath)]
-        output: PathBuf,
+        output: Option<PathBuf>,
+
+        /// Write the output into this directory, named after the file being compressed with the
+        /// extension given to '--format', every path given is then a file to compress
+        #[arg(short = 'd', long = "dir", value_hint = ValueHint::DirPath)]
+        output_dir: Option<PathBuf>,
+
+        /// Also compress the files listed in FILE, one per line, or "-" to read the list from stdin
+        #[arg(short = 'T', long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        files_from: Option<PathBuf>,
+
+        /// Files in the --files-from list are separated by NUL characters instead of newlines
+        #[arg(long, requires = "files_from")]
+        null: bool,
+
+        /// Name of the entry made from the data piped to stdin when compressing "-" into an archive
+        #[arg(long, value_name = "NAME")]
+        entry_name: Option<PathBuf>,
 
         /// Compression level, applied to all formats
         #[arg(short, long, group = "compression-level")]
@@ -81,6 +186,167 @@ pub enum Subcommand {
         /// conflicts with --level and --fast
         #[arg(long, group = "compression-level")]
         slow: bool,
+
+        /// Store a checksum of the contents in zstd streams, checked when decompressing
+        #[arg(long)]
+        checksum: bool,
+
+        /// Write .zst in the seekable format, in independent frames with a seek table, so tar
+        /// archives can be listed without decompressing all of them
+        #[arg(long)]
+        seekable: bool,
+
+        /// Write the offsets of the entries of tar archives to "<OUTPUT>.idx", used to list the
+        /// archive without reading it, and to unpack some of its entries without reading the rest
+        #[arg(long)]
+        index: bool,
+
+        /// Integrity check stored in xz streams
+        #[arg(long, value_enum, value_name = "CHECK", default_value_t)]
+        xz_check: XzCheck,
+
+        /// Most memory used by the xz encoder, like "1GiB", fewer threads are used to stay under it
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        memory: Option<u64>,
+
+        /// Compress xz and lzma with the extreme variant of the level, slower for a slightly smaller output
+        #[arg(long)]
+        xz_extreme: bool,
+
+        /// Write .sz files as a single raw snappy block, without the framing, read by tools that
+        /// expect raw snappy
+        #[arg(long)]
+        snappy_raw: bool,
+
+        /// Compress gzip and zstd in a way that rsync can transfer changed files more efficiently,
+        /// for a slightly bigger output
+        #[arg(long)]
+        rsyncable: bool,
+
+        /// Store the files of zip archives that look already compressed, like images and videos,
+        /// without compressing them again
+        #[arg(long)]
+        auto_store: bool,
+
+        /// Compress the files of zip archives matching GLOB with METHOD, "store" or "deflate", at the
+        /// optional LEVEL, like "*.jpg=store" or "*.txt=deflate:9", can be repeated, the first match wins
+        #[arg(long, value_name = "GLOB=METHOD[:LEVEL]", value_parser = parse_zip_rule)]
+        zip_rule: Vec<ZipRule>,
+
+        /// Original file name stored in the gzip header
+        #[arg(long, value_name = "NAME")]
+        gzip_name: Option<String>,
+
+        /// Comment stored in the gzip header
+        #[arg(long, value_name = "COMMENT")]
+        gzip_comment: Option<String>,
+
+        /// Comment of the archive, stored as the zip comment or in a PAX global header of tar
+        #[arg(long, value_name = "COMMENT")]
+        comment: Option<String>,
+
+        /// Build byte-identical archives from the same files, with fixed timestamps and owners
+        #[arg(long)]
+        reproducible: bool,
+
+        /// Timestamp for all entries in seconds since the Unix epoch, like "@1700000000",
+        /// implies --reproducible
+        #[arg(long, value_name = "@EPOCH", value_parser = parse_epoch)]
+        mtime: Option<u64>,
+
+        /// Warn about names that can't be unpacked as they are on Windows, like "aux.txt" or "notes."
+        #[arg(long)]
+        windows_compat: bool,
+
+        /// Don't descend more than N directories below the input files, 0 compresses only the
+        /// input files themselves
+        #[arg(long, value_name = "N")]
+        max_depth: Option<usize>,
+
+        /// Don't cross into other file systems, like mount points, when walking the input files
+        #[arg(long)]
+        one_file_system: bool,
+
+        /// Compress the contents of the directories that symlinks point to, instead of an empty
+        /// directory. Symlinks to their own parent directories are skipped
+        #[arg(long)]
+        follow_dir_symlinks: bool,
+
+        /// Skip the directories of version control systems, like ".git", ".svn" and ".hg"
+        #[arg(long)]
+        exclude_vcs: bool,
+
+        /// Skip the directories tagged as caches by a "CACHEDIR.TAG" file, see https://bford.info/cachedir/
+        #[arg(long)]
+        exclude_caches: bool,
+
+        /// What to do with FIFOs, sockets and devices found in the input files
+        #[arg(long, value_enum, value_name = "POLICY", default_value_t)]
+        special_files: SpecialFiles,
+
+        /// Store files with the same contents as one already in the archive as hard links to it,
+        /// only for tar archives, zip has no entries that share the data of another one. Unpacked
+        /// duplicates are hard links to the same file
+        #[arg(long)]
+        dedup: bool,
+
+        /// Only compress the files that changed since they were stored in this archive, for
+        /// incremental backups. The archive gets a ".ouch-incremental" entry naming its base
+        #[arg(long, value_name = "ARCHIVE", value_hint = ValueHint::FilePath, conflicts_with = "newer_mtime")]
+        newer_than: Option<PathBuf>,
+
+        /// Only compress the files modified after this date, like "2024-06-01",
+        /// "2024-06-01T12:00:00+02:00", "2 weeks ago" or "@1700000000" in seconds since the Unix epoch
+        #[arg(long, value_name = "DATE", value_parser = parse_date)]
+        newer_mtime: Option<u64>,
+
+        /// Only compress the files modified before this date, in the same formats as --newer-mtime
+        #[arg(long, value_name = "DATE", value_parser = parse_date)]
+        older_mtime: Option<u64>,
+
+        /// Write a JSON manifest of the archive to FILE, with the path, size, mode, modification
+        /// time and SHA-512 of every entry
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        manifest: Option<PathBuf>,
+
+        /// Split the output into parts of this size, like "1G" or "500MiB", named "<OUTPUT>.001",
+        /// "<OUTPUT>.002" and so on. Decompressing the first part joins them back
+        #[arg(long, value_name = "SIZE", value_parser = parse_size, conflicts_with = "sign_key")]
+        split_size: Option<u64>,
+
+        /// Encrypt the output to this age public key, like "age:age1...", requires the .age extension.
+        /// Can be repeated to encrypt to multiple recipients
+        #[arg(long, value_name = "RECIPIENT")]
+        encrypt: Vec<String>,
+
+        /// Sign the output with this Ed25519 private key (PEM), writing the signature to "<OUTPUT>.sig"
+        #[arg(long, value_name = "KEY_FILE", value_hint = ValueHint::FilePath)]
+        sign_key: Option<PathBuf>,
+
+        /// Compress the outermost format with this program instead, like "zstd -T0 --ultra -22",
+        /// which reads the stream from stdin and writes to stdout
+        #[arg(long, value_name = "CMD")]
+        filter_cmd: Option<String>,
+
+        /// Format used when the output has no extension and no '--format' is given, like "tar.zst",
+        /// instead of asking which one to use
+        #[arg(long, value_name = "FORMAT", env = "OUCH_DEFAULT_FORMAT")]
+        default_format: Option<OsString>,
+
+        /// Compress a sample of the input and print the projected size of the output and how long
+        /// compressing it takes, without writing it
+        #[arg(long, conflicts_with_all = ["index", "manifest", "sign_key", "filter_cmd"])]
+        estimate: bool,
+
+        /// Compress the files one after the other into a single stream, like 'cat a b | gzip', with
+        /// formats that aren't archives
+        #[arg(long)]
+        concat: bool,
+
+        /// Don't walk the input files ahead of time to show the total size along with the counts
+        /// of the files scanned while compressing
+        #[arg(long)]
+        no_size_estimate: bool,
     },
     /// Decompresses one or more files, optionally into another folder
     #[command(visible_alias = "d")]
@@ -96,6 +362,102 @@ pub enum Subcommand {
         /// Remove the source file after successful decompression
         #[arg(short = 'r', long)]
         remove: bool,
+
+        /// Check the unpacked files against the archive after decompression
+        #[arg(long)]
+        verify: bool,
+
+        /// Apply the umask to unpacked files instead of restoring the permissions stored in the archive
+        #[arg(long)]
+        no_same_permissions: bool,
+
+        /// Restore the stored permissions without the write permission of the group and others, like
+        /// with the umask 022, '=false' restores them as they are stored
+        #[arg(
+            long,
+            value_name = "BOOL",
+            default_value_t = true,
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "true",
+            action = clap::ArgAction::Set
+        )]
+        umask_safe: bool,
+
+        /// Restore the setuid, setgid and sticky bits stored in the archive, which are cleared by
+        /// default
+        #[arg(long, overrides_with = "no_special_permissions")]
+        preserve_setuid: bool,
+
+        /// Clear the setuid, setgid and sticky bits stored in the archive, the default, overriding an
+        /// earlier '--preserve-setuid'
+        #[arg(long, overrides_with = "preserve_setuid")]
+        no_special_permissions: bool,
+
+        /// Print the unpacked paths to stdout, separated by NUL characters, for `xargs -0`
+        #[arg(long, conflicts_with = "in_place")]
+        print0: bool,
+
+        /// Rename entries with a sed-like expression, like 's/^old-name/new-name/', can be repeated
+        #[arg(long, value_name = "EXPRESSION")]
+        transform: Vec<String>,
+
+        /// Unpack entries in their directories, with '=false' every file is unpacked directly into the
+        /// output directory, numbering the names that are taken
+        #[arg(
+            long,
+            value_name = "BOOL",
+            default_value_t = true,
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "true",
+            action = clap::ArgAction::Set
+        )]
+        keep_directory_structure: bool,
+
+        /// What to do with each file that already exists, instead of asking to replace the whole output
+        #[arg(long, value_enum, value_name = "POLICY")]
+        on_conflict: Option<ConflictPolicy>,
+
+        /// Unpack directly into the output directory without a temporary directory, files that
+        /// already exist are resolved by '--on-conflict', asking by default
+        #[arg(long)]
+        in_place: bool,
+
+        /// Unpack the contents of the directory that has every entry of the archive inside of it,
+        /// like "project-1.2.3/", directly into the output directory
+        #[arg(long, conflicts_with = "print0")]
+        strip_common_prefix: bool,
+
+        /// Leave files that already exist with the size and modification time of their entry, like
+        /// rsync, unpacking in place
+        #[arg(long, requires = "in_place")]
+        skip_identical: bool,
+
+        /// Compare the CRC32 stored in the archive instead of the modification time with
+        /// '--skip-identical', tar archives don't store one
+        #[arg(long, requires = "skip_identical")]
+        checksum: bool,
+
+        /// Decompress the outermost format with this program instead, run with "-d" appended, like
+        /// "zstd -T0"
+        #[arg(long, value_name = "CMD", conflicts_with = "verify")]
+        filter_cmd: Option<String>,
+
+        /// Stop at the first file that fails, instead of decompressing the others and reporting
+        /// every failure at the end
+        #[arg(long)]
+        fail_fast: bool,
+
+        /// Restrict the process to reading the input files and writing to the output and temporary
+        /// directories while unpacking, with Landlock (Linux only)
+        #[arg(long, conflicts_with_all = ["remove", "filter_cmd"])]
+        sandbox: bool,
+
+        /// Decode the names of zip entries that aren't flagged as UTF-8 with this encoding instead of
+        /// CP437, like "shift_jis" or "gbk"
+        #[arg(long, value_name = "LABEL")]
+        encoding: Option<String>,
     },
     /// List contents of an archive
     #[command(visible_aliases = ["l", "ls"])]
@@ -107,7 +469,337 @@ pub enum Subcommand {
         /// Show archive contents as a tree
         #[arg(short, long)]
         tree: bool,
+
+        /// Print only the paths, separated by NUL characters, for `xargs -0`
+        #[arg(long, conflicts_with = "tree")]
+        print0: bool,
+
+        /// Print every archive as a line of JSON, with its comment and the ones of its entries
+        #[arg(long, conflicts_with_all = ["tree", "print0"])]
+        json: bool,
+
+        /// Show the compression method of every file and whether it's encrypted, for formats that
+        /// store them like zip and 7z
+        #[arg(short, long, conflicts_with_all = ["tree", "print0"])]
+        long: bool,
+
+        /// Decode the names of zip entries that aren't flagged as UTF-8 with this encoding instead of
+        /// CP437, like "shift_jis" or "gbk"
+        #[arg(long, value_name = "LABEL")]
+        encoding: Option<String>,
+    },
+    /// Show metadata of archives, like their formats and number of entries
+    Info {
+        /// Archives or compressed files to inspect
+        #[arg(required = true, num_args = 1.., value_hint = ValueHint::FilePath)]
+        archives: Vec<PathBuf>,
+    },
+    /// Compare the contents of two archives, or of an archive and a directory
+    Diff {
+        /// Archive or directory to compare from
+        #[arg(value_hint = ValueHint::FilePath)]
+        old: PathBuf,
+
+        /// Archive or directory to compare against
+        #[arg(value_hint = ValueHint::FilePath)]
+        new: PathBuf,
+
+        /// Print the differences as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check that an archive still matches a directory, without unpacking it
+    Compare {
+        /// Directory the archive was made from
+        #[arg(value_hint = ValueHint::DirPath)]
+        dir: PathBuf,
+
+        /// Archive to check
+        #[arg(value_hint = ValueHint::FilePath)]
+        archive: PathBuf,
+    },
+    /// Verify the signature of a file made with `compress --sign-key`
+    Verify {
+        /// File to verify
+        #[arg(value_hint = ValueHint::FilePath)]
+        file: PathBuf,
+
+        /// Signature of the file, defaults to the file path with ".sig" added
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        sig: Option<PathBuf>,
+
+        /// Ed25519 public key (PEM) to verify the signature with
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        pubkey: PathBuf,
     },
+    /// Remove the temporary directories left behind by interrupted decompressions
+    Clean {
+        /// Directory to look for leftover temporary directories in
+        #[arg(value_hint = ValueHint::DirPath)]
+        dir: PathBuf,
+    },
+    /// Compare formats and levels by compressing a sample of a file or directory
+    Bench {
+        /// File or directory to take the sample from, directories are archived with tar first
+        #[arg(value_hint = ValueHint::AnyPath)]
+        input: PathBuf,
+
+        /// Formats to compare, separated by commas
+        #[arg(long, value_delimiter = ',', default_value = "gz,zst,xz")]
+        formats: Vec<OsString>,
+
+        /// Compression levels to try with each format, separated by commas, the default level of each format if not given
+        #[arg(long, value_delimiter = ',', allow_negative_numbers = true)]
+        levels: Vec<i16>,
+
+        /// Maximum size of the sample
+        #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "100MB")]
+        sample_size: u64,
+    },
+    /// Print the shell completions to stdout
+    Completions {
+        /// Shell to generate the completions for
+        shell: clap_complete::Shell,
+    },
+    /// Print the man page to stdout
+    Manpage,
+}
+
+/// How the final error is printed
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// Human-readable text, with colors
+    #[default]
+    Text,
+    /// A JSON object with stable error codes
+    Json,
+}
+
+/// Parse a timestamp like "@1700000000", the "@" is optional
+fn parse_epoch(value: &str) -> Result<u64, String> {
+    let seconds = value.strip_prefix('@').unwrap_or(value);
+    seconds
+        .parse()
+        .map_err(|_| format!("expected seconds since the Unix epoch, like \"@1700000000\", got \"{value}\""))
+}
+
+/// Parse a date like "2024-06-01" at midnight UTC, "2024-06-01T12:00:00Z" in RFC 3339, "2 weeks ago",
+/// or "@1700000000", into seconds since the Unix epoch
+fn parse_date(value: &str) -> Result<u64, String> {
+    let invalid = || {
+        format!(
+            "expected a date like \"2024-06-01\", \"2024-06-01T12:00:00Z\", \"2 weeks ago\" or \"@1700000000\", \
+             got \"{value}\""
+        )
+    };
+
+    if let Some(ago) = value.strip_suffix(" ago") {
+        let (count, unit) = ago.trim().split_once(' ').ok_or_else(invalid)?;
+        let count: u64 = count.parse().map_err(|_| invalid())?;
+        let unit_seconds = match unit.trim().trim_end_matches('s') {
+            "second" | "sec" => 1,
+            "minute" | "min" => 60,
+            "hour" => 60 * 60,
+            "day" => 24 * 60 * 60,
+            "week" => 7 * 24 * 60 * 60,
+            // Calendar months and years vary, this is close enough for picking files
+            "month" => 30 * 24 * 60 * 60,
+            "year" => 365 * 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs());
+        return Ok(now.saturating_sub(count.saturating_mul(unit_seconds)));
+    }
+
+    let epoch = value.strip_prefix('@').unwrap_or(value);
+    if !epoch.is_empty() && epoch.bytes().all(|byte| byte.is_ascii_digit()) {
+        return epoch.parse().map_err(|_| invalid());
+    }
+
+    parse_rfc3339(value).ok_or_else(invalid)
+}
+
+/// Parse "YYYY-MM-DD", optionally followed by "THH:MM:SS", fractions of a second, and "Z" or an
+/// offset like "+02:00", into seconds since the Unix epoch
+fn parse_rfc3339(value: &str) -> Option<u64> {
+    fn number(digits: &str) -> Option<i64> {
+        digits
+            .bytes()
+            .all(|byte| byte.is_ascii_digit())
+            .then(|| digits.parse().ok())?
+    }
+
+    let (date, time) = match value.get(10..) {
+        Some("") => (value, None),
+        Some(time) if time.starts_with(['T', 't', ' ']) => (&value[..10], Some(&time[1..])),
+        _ => return None,
+    };
+
+    let [year, month, day] = <[&str; 3]>::try_from(date.split('-').collect::<Vec<_>>()).ok()?;
+    let (year, month, day) = (number(year)?, number(month)?, number(day)?);
+    let days_in_month = match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None,
+    };
+    if !(1..=days_in_month).contains(&day) {
+        return None;
+    }
+
+    // Days since the Unix epoch, with years starting in March so leap days come last
+    let year = if month <= 2 { year - 1 } else { year };
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = year.div_euclid(400) * 146_097 + day_of_era - 719_468;
+
+    let mut seconds = days * 24 * 60 * 60;
+    if let Some(time) = time {
+        let (hour, minute, second) = (
+            number(time.get(0..2)?)?,
+            number(time.get(3..5)?)?,
+            number(time.get(6..8)?)?,
+        );
+        if time.get(2..3)? != ":" || time.get(5..6)? != ":" || hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+        seconds += hour * 60 * 60 + minute * 60 + second;
+
+        let mut offset = &time[8..];
+        if let Some(fraction) = offset.strip_prefix('.') {
+            let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+            if digits == 0 {
+                return None;
+            }
+            offset = &fraction[digits..];
+        }
+        seconds -= match offset {
+            "Z" | "z" => 0,
+            _ => {
+                let sign = match offset.get(0..1)? {
+                    "+" => 1,
+                    "-" => -1,
+                    _ => return None,
+                };
+                let (hours, minutes) = offset[1..].split_once(':')?;
+                if hours.len() != 2 || minutes.len() != 2 {
+                    return None;
+                }
+                sign * (number(hours)? * 60 * 60 + number(minutes)? * 60)
+            }
+        };
+    }
+
+    u64::try_from(seconds).ok()
+}
+
+/// Parse a custom extension mapping like "pkg=tar.zst", the format is checked once all the
+/// arguments are parsed
+fn parse_extension_mapping(value: &str) -> Result<(String, String), String> {
+    let (extension, format) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected a mapping like \"pkg=tar.zst\", got \"{value}\""))?;
+    let extension = extension.strip_prefix('.').unwrap_or(extension);
+
+    if extension.is_empty() || extension.contains('.') {
+        return Err(format!("\"{extension}\" is not a single extension, like \"pkg\""));
+    }
+    if format.is_empty() {
+        return Err(format!("missing the format \"{extension}\" is mapped to"));
+    }
+
+    Ok((extension.to_string(), format.to_string()))
+}
+
+/// Parse a rule for the method of zip entries like "*.jpg=store" or "*.txt=deflate:9", the glob
+/// is checked when compressing
+fn parse_zip_rule(value: &str) -> Result<ZipRule, String> {
+    let (glob, method) = value
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected a rule like \"*.jpg=store\", got \"{value}\""))?;
+    let (method, level) = match method.split_once(':') {
+        Some((method, level)) => (method, Some(level)),
+        None => (method, None),
+    };
+
+    if glob.is_empty() {
+        return Err(format!("missing the glob of \"{value}\""));
+    }
+    let method = <ZipMethod as clap::ValueEnum>::from_str(method, true)
+        .map_err(|_| format!("unknown zip method \"{method}\", expected \"store\" or \"deflate\""))?;
+    let level = match (method, level) {
+        (_, None) => None,
+        (ZipMethod::Store, Some(_)) => return Err("stored files have no compression level".to_string()),
+        (ZipMethod::Deflate, Some(level)) => match level.parse() {
+            Ok(level @ 0..=9) => Some(level),
+            _ => return Err(format!("expected a deflate level from 0 to 9, got \"{level}\"")),
+        },
+    };
+
+    Ok(ZipRule {
+        glob: glob.to_string(),
+        method,
+        level,
+    })
+}
+
+/// Parse a transfer rate like "50MB/s" or "512KiB" into bytes per second, the "/s" is optional
+fn parse_rate(value: &str) -> Result<u64, String> {
+    let rate = value.strip_suffix("/s").unwrap_or(value);
+
+    match parse_bytes(rate, "a rate like \"50MB/s\"")? {
+        0 => Err("the rate must be at least 1 byte per second".to_string()),
+        bytes_per_second => Ok(bytes_per_second),
+    }
+}
+
+/// Parse a size like "1G" or "512KiB" into bytes
+fn parse_size(value: &str) -> Result<u64, String> {
+    match parse_bytes(value, "a size like \"1G\"")? {
+        0 => Err("the size must be at least 1 byte".to_string()),
+        size => Ok(size),
+    }
+}
+
+/// Parse the size of I/O buffers like "1MiB", up to 1GiB
+fn parse_buffer_size(value: &str) -> Result<usize, String> {
+    match parse_size(value)? {
+        size if size > 1 << 30 => Err("the buffer size can be at most 1GiB".to_string()),
+        size => Ok(size as usize),
+    }
+}
+
+/// Parse a number of bytes followed by an optional unit, `example` describes what was expected in errors
+fn parse_bytes(value: &str, example: &str) -> Result<u64, String> {
+    let unit_start = value
+        .find(|ch: char| !ch.is_ascii_digit() && ch != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_start);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("expected {example}, got \"{value}\""))?;
+
+    let unit_size: u64 = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "kib" => 1 << 10,
+        "m" | "mb" => 1000_u64.pow(2),
+        "mib" => 1 << 20,
+        "g" | "gb" => 1000_u64.pow(3),
+        "gib" => 1 << 30,
+        _ => {
+            return Err(format!(
+                "unknown unit \"{unit}\", expected one of B, KB, KiB, MB, MiB, GB or GiB"
+            ))
+        }
+    };
+
+    Ok((number * unit_size as f64) as u64)
 }
 
 #[cfg(test)]
@@ -139,19 +831,53 @@ mod tests {
         CliArgs {
             yes: false,
             no: false,
+            overwrite: false,
+            assume_format: false,
             accessible: false,
             hidden: false,
             quiet: false,
             gitignore: false,
-            format: None,
+            no_global_gitignore: false,
+            no_require_git: false,
+            format: vec![],
+            ignore_extension: false,
+            extension_map: vec![],
             // This is usually replaced in assertion tests
-            password: None,
+            password: vec![],
+            password_prompt: false,
+            password_file: None,
+            decrypt: None,
             threads: None,
+            rate_limit: None,
+            io_buffer_size: None,
+            normalize: NormalizationForm::None,
+            temp_dir: None,
+            same_dir_temp: false,
+            no_follow_output_symlink: false,
+            explain: false,
+            error_format: ErrorFormat::Text,
             cmd: Subcommand::Decompress {
                 // Put a crazy value here so no test can assert it unintentionally
                 files: vec!["\x00\x11\x22".into()],
                 output_dir: None,
                 remove: false,
+                verify: false,
+                no_same_permissions: false,
+                umask_safe: true,
+                preserve_setuid: false,
+                no_special_permissions: false,
+                print0: false,
+                transform: vec![],
+                keep_directory_structure: true,
+                on_conflict: None,
+                in_place: false,
+                strip_common_prefix: false,
+                skip_identical: false,
+                checksum: false,
+                filter_cmd: None,
+                fail_fast: false,
+                sandbox: false,
+                encoding: None,
             },
         }
     }
@@ -165,6 +891,23 @@ mod tests {
                     files: to_paths(["file.tar.gz"]),
                     output_dir: None,
                     remove: false,
+                    verify: false,
+                    no_same_permissions: false,
+                    umask_safe: true,
+                    preserve_setuid: false,
+                    no_special_permissions: false,
+                    print0: false,
+                    transform: vec![],
+                    keep_directory_structure: true,
+                    on_conflict: None,
+                    in_place: false,
+                    strip_common_prefix: false,
+                    skip_identical: false,
+                    checksum: false,
+                    filter_cmd: None,
+                    fail_fast: false,
+                    sandbox: false,
+                    encoding: None,
                 },
                 ..mock_cli_args()
             }
@@ -176,6 +919,23 @@ mod tests {
                     files: to_paths(["file.tar.gz"]),
                     output_dir: None,
                     remove: false,
+                    verify: false,
+                    no_same_permissions: false,
+                    umask_safe: true,
+                    preserve_setuid: false,
+                    no_special_permissions: false,
+                    print0: false,
+                    transform: vec![],
+                    keep_directory_structure: true,
+                    on_conflict: None,
+                    in_place: false,
+                    strip_common_prefix: false,
+                    skip_identical: false,
+                    checksum: false,
+                    filter_cmd: None,
+                    fail_fast: false,
+                    sandbox: false,
+                    encoding: None,
                 },
                 ..mock_cli_args()
             }
@@ -187,6 +947,51 @@ mod tests {
                     files: to_paths(["a", "b", "c"]),
                     output_dir: None,
                     remove: false,
+                    verify: false,
+                    no_same_permissions: false,
+                    umask_safe: true,
+                    preserve_setuid: false,
+                    no_special_permissions: false,
+                    print0: false,
+                    transform: vec![],
+                    keep_directory_structure: true,
+                    on_conflict: None,
+                    in_place: false,
+                    strip_common_prefix: false,
+                    skip_identical: false,
+                    checksum: false,
+                    filter_cmd: None,
+                    fail_fast: false,
+                    sandbox: false,
+                    encoding: None,
+                },
+                ..mock_cli_args()
+            }
+        );
+        test!(
+            "ouch d --keep-directory-structure=false --transform s/a/b/ a.zip",
+            CliArgs {
+                cmd: Subcommand::Decompress {
+                    files: to_paths(["a.zip"]),
+                    output_dir: None,
+                    remove: false,
+                    verify: false,
+                    no_same_permissions: false,
+                    umask_safe: true,
+                    preserve_setuid: false,
+                    no_special_permissions: false,
+                    print0: false,
+                    transform: vec!["s/a/b/".into()],
+                    keep_directory_structure: false,
+                    on_conflict: None,
+                    in_place: false,
+                    strip_common_prefix: false,
+                    skip_identical: false,
+                    checksum: false,
+                    filter_cmd: None,
+                    fail_fast: false,
+                    sandbox: false,
+                    encoding: None,
                 },
                 ..mock_cli_args()
             }
@@ -197,10 +1002,205 @@ mod tests {
             CliArgs {
                 cmd: Subcommand::Compress {
                     files: to_paths(["file"]),
-                    output: PathBuf::from("file.tar.gz"),
+                    output: Some(PathBuf::from("file.tar.gz")),
+                    output_dir: None,
+                    files_from: None,
+                    null: false,
+                    entry_name: None,
+                    level: None,
+                    fast: false,
+                    slow: false,
+                    checksum: false,
+                    seekable: false,
+                    index: false,
+                    xz_check: XzCheck::Crc64,
+                    memory: None,
+                    xz_extreme: false,
+                    snappy_raw: false,
+                    rsyncable: false,
+                    auto_store: false,
+                    zip_rule: vec![],
+                    gzip_name: None,
+                    gzip_comment: None,
+                    comment: None,
+                    reproducible: false,
+                    mtime: None,
+                    windows_compat: false,
+                    max_depth: None,
+                    one_file_system: false,
+                    follow_dir_symlinks: false,
+                    exclude_vcs: false,
+                    exclude_caches: false,
+                    special_files: SpecialFiles::Skip,
+                    dedup: false,
+                    newer_than: None,
+                    newer_mtime: None,
+                    older_mtime: None,
+                    manifest: None,
+                    split_size: None,
+                    encrypt: vec![],
+                    sign_key: None,
+                    filter_cmd: None,
+                    default_format: None,
+                    estimate: false,
+                    concat: false,
+                    no_size_estimate: false,
+                },
+                ..mock_cli_args()
+            }
+        );
+        test!(
+            "ouch compress project -d dist",
+            CliArgs {
+                cmd: Subcommand::Compress {
+                    files: vec![],
+                    output: Some(PathBuf::from("project")),
+                    output_dir: Some(PathBuf::from("dist")),
+                    files_from: None,
+                    null: false,
+                    entry_name: None,
+                    level: None,
+                    fast: false,
+                    slow: false,
+                    checksum: false,
+                    seekable: false,
+                    index: false,
+                    xz_check: XzCheck::Crc64,
+                    memory: None,
+                    xz_extreme: false,
+                    snappy_raw: false,
+                    rsyncable: false,
+                    auto_store: false,
+                    zip_rule: vec![],
+                    gzip_name: None,
+                    gzip_comment: None,
+                    comment: None,
+                    reproducible: false,
+                    mtime: None,
+                    windows_compat: false,
+                    max_depth: None,
+                    one_file_system: false,
+                    follow_dir_symlinks: false,
+                    exclude_vcs: false,
+                    exclude_caches: false,
+                    special_files: SpecialFiles::Skip,
+                    dedup: false,
+                    newer_than: None,
+                    newer_mtime: None,
+                    older_mtime: None,
+                    manifest: None,
+                    split_size: None,
+                    encrypt: vec![],
+                    sign_key: None,
+                    filter_cmd: None,
+                    default_format: None,
+                    estimate: false,
+                    concat: false,
+                    no_size_estimate: false,
+                },
+                ..mock_cli_args()
+            }
+        );
+        test!(
+            "ouch compress - backup.tar.zst --entry-name logs/output.txt",
+            CliArgs {
+                cmd: Subcommand::Compress {
+                    files: to_paths(["-"]),
+                    output: Some(PathBuf::from("backup.tar.zst")),
+                    output_dir: None,
+                    files_from: None,
+                    null: false,
+                    entry_name: Some(PathBuf::from("logs/output.txt")),
+                    level: None,
+                    fast: false,
+                    slow: false,
+                    checksum: false,
+                    seekable: false,
+                    index: false,
+                    xz_check: XzCheck::Crc64,
+                    memory: None,
+                    xz_extreme: false,
+                    snappy_raw: false,
+                    rsyncable: false,
+                    auto_store: false,
+                    zip_rule: vec![],
+                    gzip_name: None,
+                    gzip_comment: None,
+                    comment: None,
+                    reproducible: false,
+                    mtime: None,
+                    windows_compat: false,
+                    max_depth: None,
+                    one_file_system: false,
+                    follow_dir_symlinks: false,
+                    exclude_vcs: false,
+                    exclude_caches: false,
+                    special_files: SpecialFiles::Skip,
+                    dedup: false,
+                    newer_than: None,
+                    newer_mtime: None,
+                    older_mtime: None,
+                    manifest: None,
+                    split_size: None,
+                    encrypt: vec![],
+                    sign_key: None,
+                    filter_cmd: None,
+                    default_format: None,
+                    estimate: false,
+                    concat: false,
+                    no_size_estimate: false,
+                },
+                ..mock_cli_args()
+            }
+        );
+        test!(
+            "ouch compress -T list.txt --null archive.tar.gz",
+            CliArgs {
+                cmd: Subcommand::Compress {
+                    files: vec![],
+                    output: Some(PathBuf::from("archive.tar.gz")),
+                    output_dir: None,
+                    files_from: Some(PathBuf::from("list.txt")),
+                    null: true,
+                    entry_name: None,
                     level: None,
                     fast: false,
                     slow: false,
+                    checksum: false,
+                    seekable: false,
+                    index: false,
+                    xz_check: XzCheck::Crc64,
+                    memory: None,
+                    xz_extreme: false,
+                    snappy_raw: false,
+                    rsyncable: false,
+                    auto_store: false,
+                    zip_rule: vec![],
+                    gzip_name: None,
+                    gzip_comment: None,
+                    comment: None,
+                    reproducible: false,
+                    mtime: None,
+                    windows_compat: false,
+                    max_depth: None,
+                    one_file_system: false,
+                    follow_dir_symlinks: false,
+                    exclude_vcs: false,
+                    exclude_caches: false,
+                    special_files: SpecialFiles::Skip,
+                    dedup: false,
+                    newer_than: None,
+                    newer_mtime: None,
+                    older_mtime: None,
+                    manifest: None,
+                    split_size: None,
+                    encrypt: vec![],
+                    sign_key: None,
+                    filter_cmd: None,
+                    default_format: None,
+                    estimate: false,
+                    concat: false,
+                    no_size_estimate: false,
                 },
                 ..mock_cli_args()
             }
@@ -210,10 +1210,49 @@ mod tests {
             CliArgs {
                 cmd: Subcommand::Compress {
                     files: to_paths(["a", "b", "c"]),
-                    output: PathBuf::from("archive.tar.gz"),
+                    output: Some(PathBuf::from("archive.tar.gz")),
+                    output_dir: None,
+                    files_from: None,
+                    null: false,
+                    entry_name: None,
                     level: None,
                     fast: false,
                     slow: false,
+                    checksum: false,
+                    seekable: false,
+                    index: false,
+                    xz_check: XzCheck::Crc64,
+                    memory: None,
+                    xz_extreme: false,
+                    snappy_raw: false,
+                    rsyncable: false,
+                    auto_store: false,
+                    zip_rule: vec![],
+                    gzip_name: None,
+                    gzip_comment: None,
+                    comment: None,
+                    reproducible: false,
+                    mtime: None,
+                    windows_compat: false,
+                    max_depth: None,
+                    one_file_system: false,
+                    follow_dir_symlinks: false,
+                    exclude_vcs: false,
+                    exclude_caches: false,
+                    special_files: SpecialFiles::Skip,
+                    dedup: false,
+                    newer_than: None,
+                    newer_mtime: None,
+                    older_mtime: None,
+                    manifest: None,
+                    split_size: None,
+                    encrypt: vec![],
+                    sign_key: None,
+                    filter_cmd: None,
+                    default_format: None,
+                    estimate: false,
+                    concat: false,
+                    no_size_estimate: false,
                 },
                 ..mock_cli_args()
             }
@@ -223,10 +1262,59 @@ mod tests {
             CliArgs {
                 cmd: Subcommand::Compress {
                     files: to_paths(["a", "b", "c"]),
-                    output: PathBuf::from("archive.tar.gz"),
+                    output: Some(PathBuf::from("archive.tar.gz")),
+                    output_dir: None,
+                    files_from: None,
+                    null: false,
+                    entry_name: None,
                     level: None,
                     fast: false,
                     slow: false,
+                    checksum: false,
+                    seekable: false,
+                    index: false,
+                    xz_check: XzCheck::Crc64,
+                    memory: None,
+                    xz_extreme: false,
+                    snappy_raw: false,
+                    rsyncable: false,
+                    auto_store: false,
+                    zip_rule: vec![],
+                    gzip_name: None,
+                    gzip_comment: None,
+                    comment: None,
+                    reproducible: false,
+                    mtime: None,
+                    windows_compat: false,
+                    max_depth: None,
+                    one_file_system: false,
+                    follow_dir_symlinks: false,
+                    exclude_vcs: false,
+                    exclude_caches: false,
+                    special_files: SpecialFiles::Skip,
+                    dedup: false,
+                    newer_than: None,
+                    newer_mtime: None,
+                    older_mtime: None,
+                    manifest: None,
+                    split_size: None,
+                    encrypt: vec![],
+                    sign_key: None,
+                    filter_cmd: None,
+                    default_format: None,
+                    estimate: false,
+                    concat: false,
+                    no_size_estimate: false,
+                },
+                ..mock_cli_args()
+            }
+        );
+
+        test!(
+            "ouch clean downloads",
+            CliArgs {
+                cmd: Subcommand::Clean {
+                    dir: PathBuf::from("downloads"),
                 },
                 ..mock_cli_args()
             }
@@ -247,12 +1335,51 @@ mod tests {
                 CliArgs {
                     cmd: Subcommand::Compress {
                         files: to_paths(["a", "b", "c"]),
-                        output: PathBuf::from("output"),
+                        output: Some(PathBuf::from("output")),
+                        output_dir: None,
+                        files_from: None,
+                        null: false,
+                        entry_name: None,
                         level: None,
                         fast: false,
                         slow: false,
+                        checksum: false,
+                        seekable: false,
+                        index: false,
+                        xz_check: XzCheck::Crc64,
+                        memory: None,
+                        xz_extreme: false,
+                        snappy_raw: false,
+                        rsyncable: false,
+                        auto_store: false,
+                        zip_rule: vec![],
+                        gzip_name: None,
+                        gzip_comment: None,
+                        comment: None,
+                        reproducible: false,
+                        mtime: None,
+                        windows_compat: false,
+                        max_depth: None,
+                        one_file_system: false,
+                        follow_dir_symlinks: false,
+                        exclude_vcs: false,
+                        exclude_caches: false,
+                        special_files: SpecialFiles::Skip,
+                        dedup: false,
+                        newer_than: None,
+                        newer_mtime: None,
+                        older_mtime: None,
+                        manifest: None,
+                        split_size: None,
+                        encrypt: vec![],
+                        sign_key: None,
+                        filter_cmd: None,
+                        default_format: None,
+                        estimate: false,
+                        concat: false,
+                        no_size_estimate: false,
                     },
-                    format: Some("tar.gz".into()),
+                    format: vec!["tar.gz".into()],
                     ..mock_cli_args()
                 }
             );
@@ -265,5 +1392,76 @@ mod tests {
         assert!(CliArgs::try_parse_from(args_splitter("ouch c input")).is_err());
         assert!(CliArgs::try_parse_from(args_splitter("ouch d")).is_err());
         assert!(CliArgs::try_parse_from(args_splitter("ouch l")).is_err());
+        assert!(CliArgs::try_parse_from(args_splitter("ouch diff a.zip")).is_err());
+        assert!(CliArgs::try_parse_from(args_splitter("ouch c a b.tar --mtime yesterday")).is_err());
+        assert!(CliArgs::try_parse_from(args_splitter("ouch d a.gz --rate-limit 0MB/s")).is_err());
+        assert!(CliArgs::try_parse_from(args_splitter("ouch d a.gz --rate-limit 5parsecs")).is_err());
+        assert!(CliArgs::try_parse_from(args_splitter("ouch d a.zip --normalize nfkc")).is_err());
+        assert!(CliArgs::try_parse_from(args_splitter("ouch d --keep-directory-structure=no a.zip")).is_err());
+        assert!(CliArgs::try_parse_from(args_splitter("ouch c a b.tar --split-size 0")).is_err());
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("100"), Ok(100));
+        assert_eq!(parse_rate("50MB/s"), Ok(50_000_000));
+        assert_eq!(parse_rate("512KiB"), Ok(512 * 1024));
+        assert_eq!(parse_rate("1.5gib/s"), Ok(3 << 29));
+    }
+
+    #[test]
+    fn test_parse_buffer_size() {
+        assert_eq!(parse_buffer_size("1MiB"), Ok(1 << 20));
+        assert_eq!(parse_buffer_size("64k"), Ok(64_000));
+        assert!(parse_buffer_size("2GiB").is_err());
+        assert!(parse_buffer_size("0").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1G"), Ok(1_000_000_000));
+        assert_eq!(parse_size("500MiB"), Ok(500 << 20));
+        assert!(parse_size("1G/s").is_err());
+        assert!(parse_size("0KB").is_err());
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("@1700000000"), Ok(1_700_000_000));
+        assert_eq!(parse_date("1700000000"), Ok(1_700_000_000));
+        assert_eq!(parse_date("1970-01-01"), Ok(0));
+        assert_eq!(parse_date("2024-02-29"), Ok(1_709_164_800));
+        assert_eq!(parse_date("2023-11-14T22:13:20Z"), Ok(1_700_000_000));
+        assert_eq!(parse_date("2023-11-15T00:13:20.5+02:00"), Ok(1_700_000_000));
+        assert_eq!(parse_date("2023-11-14 19:13:20-03:00"), Ok(1_700_000_000));
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let two_weeks_ago = parse_date("2 weeks ago").unwrap();
+        assert!((now - 14 * 24 * 60 * 60).abs_diff(two_weeks_ago) <= 1);
+        assert!(parse_date("1 hour ago").is_ok());
+
+        assert!(parse_date("2023-02-29").is_err());
+        assert!(parse_date("2023-11-14T22:13:20").is_err());
+        assert!(parse_date("1969-12-31").is_err());
+        assert!(parse_date("2 fortnights ago").is_err());
+        assert!(parse_date("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_parse_extension_mapping() {
+        assert_eq!(
+            parse_extension_mapping("pkg=tar.zst"),
+            Ok(("pkg".to_string(), "tar.zst".to_string()))
+        );
+        assert_eq!(
+            parse_extension_mapping(".pkg=tgz"),
+            Ok(("pkg".to_string(), "tgz".to_string()))
+        );
+        assert!(parse_extension_mapping("pkg").is_err());
+        assert!(parse_extension_mapping("pkg=").is_err());
+        assert!(parse_extension_mapping("a.pkg=tar").is_err());
     }
 }
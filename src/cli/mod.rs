@@ -3,58 +3,225 @@
 mod args;
 
 use std::{
-    io,
+    ffi::OsString,
+    io::{self, Read},
     path::{Path, PathBuf},
 };
 
+use bstr::ByteSlice;
 use clap::Parser;
 use fs_err as fs;
 
-pub use self::args::{CliArgs, Subcommand};
+pub use self::args::{CliArgs, ErrorFormat, Subcommand};
 use crate::{
     accessible::set_accessible,
-    utils::{is_path_stdin, FileVisibilityPolicy},
+    commands, encryption,
+    error::{ErrorClass, FinalError},
+    extension, http,
+    utils::{
+        ask_password, is_path_stdin,
+        logger::{shutdown_logger_and_wait, spawn_logger_thread},
+        set_refuse_symlinked_outputs, Answer, EscapedPathDisplay, FileVisibilityPolicy,
+    },
     QuestionPolicy,
 };
 
+/// Entry point of the `ouch` binary, exits with the code of the class of the error on failure
+pub fn main() {
+    spawn_logger_thread();
+    let args = CliArgs::parse();
+    let error_format = args.error_format;
+    let result = run(args);
+    shutdown_logger_and_wait();
+
+    if let Err(err) = result {
+        match error_format {
+            ErrorFormat::Text => eprintln!("{err}"),
+            ErrorFormat::Json => eprintln!("{}", err.to_json()),
+        }
+        std::process::exit(err.class().exit_code());
+    }
+}
+
+/// Run the command given by `args`, the settings of an earlier run in the same process don't carry
+/// over to it
+pub fn run(args: CliArgs) -> crate::Result<()> {
+    let (args, question_policy, file_visibility_policy) = args.validate()?;
+    commands::run(args, question_policy, file_visibility_policy)
+}
+
 impl CliArgs {
-    /// A helper method that calls `clap::Parser::parse`.
+    /// A helper method to call after `clap::Parser::parse`.
     ///
-    /// And:
-    ///   1. Make paths absolute.
-    ///   2. Checks the QuestionPolicy.
-    pub fn parse_and_validate_args() -> crate::Result<(Self, QuestionPolicy, FileVisibilityPolicy)> {
-        let mut args = Self::parse();
+    /// It:
+    ///   1. Adds the files listed in `--files-from`, makes paths absolute and names the output
+    ///      given by `--dir`.
+    ///   2. Sets the identity file used to decrypt and the extensions given to `--map`.
+    ///   3. Reads the password from `--password-file` or `--password-prompt`.
+    ///   4. Checks the QuestionPolicy.
+    pub fn validate(self) -> crate::Result<(Self, QuestionPolicy, FileVisibilityPolicy)> {
+        let mut args = self;
 
         set_accessible(args.accessible);
+        set_refuse_symlinked_outputs(args.no_follow_output_symlink);
 
-        let (Subcommand::Compress { files, .. }
-        | Subcommand::Decompress { files, .. }
-        | Subcommand::List { archives: files, .. }) = &mut args.cmd;
-        *files = canonicalize_files(files)?;
+        match &mut args.cmd {
+            Subcommand::Compress {
+                files,
+                output,
+                output_dir,
+                files_from,
+                null,
+                default_format,
+                ..
+            } => {
+                if output_dir.is_some() {
+                    // Every path is a file to compress, clap gives the last one to `output`
+                    files.extend(output.take());
+                }
+                if let Some(list) = files_from {
+                    files.extend(read_file_list(list, *null)?);
+                }
+                *files = canonicalize_files(files)?;
 
-        let skip_questions_positively = match (args.yes, args.no) {
-            (false, false) => QuestionPolicy::Ask,
-            (true, false) => QuestionPolicy::AlwaysYes,
-            (false, true) => QuestionPolicy::AlwaysNo,
-            (true, true) => unreachable!(),
+                // Without files to name it after, compressing fails anyway
+                if let (Some(dir), false) = (output_dir, files.is_empty()) {
+                    // Extensions name the formats, so the default one is used without '--format'
+                    let formats = match args.format.is_empty() {
+                        true => default_format.as_slice(),
+                        false => args.format.as_slice(),
+                    };
+                    *output = Some(dir.join(output_name_in_dir(files, formats)?));
+                }
+            }
+            Subcommand::Decompress { files, .. }
+            | Subcommand::List { archives: files, .. }
+            | Subcommand::Info { archives: files } => *files = canonicalize_files(files)?,
+            Subcommand::Diff { old, new, .. } | Subcommand::Compare { dir: old, archive: new } => {
+                *old = fs::canonicalize(&old)?;
+                *new = fs::canonicalize(&new)?;
+            }
+            Subcommand::Clean { dir } => *dir = fs::canonicalize(&dir)?,
+            Subcommand::Bench { input, .. } => *input = fs::canonicalize(&input)?,
+            Subcommand::Verify { .. } | Subcommand::Completions { .. } | Subcommand::Manpage => {}
+        }
+
+        extension::set_custom_extensions(&args.extension_map)?;
+        encryption::set_identity_file(args.decrypt.as_deref().map(fs::canonicalize).transpose()?);
+
+        if let Some(path) = &args.password_file {
+            args.password = vec![read_password_file(path)?.into()];
+        } else if args.password_prompt {
+            args.password = vec![ask_password()?.into()];
+        }
+
+        // `--overwrite` and `--assume-format` answer their questions even with `--no`
+        let answer = |yes: bool| match (yes || args.yes, args.no) {
+            (false, false) => Answer::Ask,
+            (true, _) => Answer::Yes,
+            (false, true) => Answer::No,
+        };
+        let skip_questions_positively = QuestionPolicy {
+            overwrite: answer(args.overwrite),
+            proceed: answer(args.assume_format),
         };
 
         let file_visibility_policy = FileVisibilityPolicy::new()
             .read_git_exclude(args.gitignore)
             .read_ignore(args.gitignore)
             .read_git_ignore(args.gitignore)
+            .read_global_git_ignore(!args.no_global_gitignore)
+            .require_git(!args.no_require_git)
             .read_hidden(args.hidden);
 
         Ok((args, skip_questions_positively, file_visibility_policy))
     }
 }
 
+/// Name of the output of compressing `files` into the directory given to `--dir`, the name of the
+/// file with the extension given to `--format` or `--default-format`, if any
+fn output_name_in_dir(files: &[PathBuf], formats: &[OsString]) -> crate::Result<PathBuf> {
+    let cannot_name = |title: String, detail: &'static str| -> crate::Error {
+        FinalError::with_title(title)
+            .detail(detail)
+            .hint("Pass the path of the output instead of '--dir'")
+            .class(ErrorClass::BadArguments)
+            .into()
+    };
+
+    let file = match files {
+        [file] => file,
+        _ => {
+            return Err(cannot_name(
+                "Cannot name the output after multiple files".to_string(),
+                "'--dir' names the output after the file being compressed",
+            ))
+        }
+    };
+    let Some(name) = file.file_name().filter(|_| !is_path_stdin(file)) else {
+        return Err(cannot_name(
+            format!("Cannot name the output after {}", EscapedPathDisplay::new(file)),
+            "It has no file name",
+        ));
+    };
+
+    let mut name = name.to_owned();
+    if let [format] = formats {
+        name.push(".");
+        name.push(format.to_string_lossy().trim_start_matches('.'));
+    }
+    Ok(name.into())
+}
+
+/// Read the password in `path`, without the newline editors usually add at the end
+fn read_password_file(path: &Path) -> io::Result<String> {
+    let mut password = fs::read_to_string(path)?;
+
+    if password.ends_with('\n') {
+        password.pop();
+        if password.ends_with('\r') {
+            password.pop();
+        }
+    }
+
+    Ok(password)
+}
+
+/// Read the paths listed in the file at `path`, or in stdin if it's "-", separated by newlines or
+/// by NUL characters if `null`, empty entries are skipped
+fn read_file_list(path: &Path, null: bool) -> io::Result<Vec<PathBuf>> {
+    let list = if is_path_stdin(path) {
+        let mut list = vec![];
+        io::stdin().lock().read_to_end(&mut list)?;
+        list
+    } else {
+        fs::read(path)?
+    };
+
+    let separator = if null { b'\0' } else { b'\n' };
+    list.split(|&byte| byte == separator)
+        .map(|entry| {
+            if null {
+                entry
+            } else {
+                entry.strip_suffix(b"\r").unwrap_or(entry)
+            }
+        })
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .to_path()
+                .map(Path::to_path_buf)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        })
+        .collect()
+}
+
 fn canonicalize_files(files: &[impl AsRef<Path>]) -> io::Result<Vec<PathBuf>> {
     files
         .iter()
         .map(|f| {
-            if is_path_stdin(f.as_ref()) {
+            if is_path_stdin(f.as_ref()) || http::is_url(f.as_ref()) {
                 Ok(f.as_ref().to_path_buf())
             } else {
                 fs::canonicalize(f)
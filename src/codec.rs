@@ -0,0 +1,247 @@
+//! Registry of compression formats implemented outside of `ouch`.
+//!
+//! Registered formats are detected from their extensions and their first bytes like the built-in
+//! ones, and can be chained with them, like `.tar.br` for a codec registered with the `br`
+//! extension. They're single file formats, archives are always built in a built-in format.
+
+use std::{
+    io::{self, Read, Write},
+    sync::{PoisonError, RwLock},
+};
+
+use crate::{
+    error::{ErrorClass, FinalError},
+    extension::{self, CompressionFormat, Extension},
+};
+
+/// Encoder and decoder of a compression format, registered with [`register_codec`]
+pub trait Codec: Send + Sync {
+    /// Name of the format, like "brotli", shown by `ouch info`
+    fn name(&self) -> &str;
+
+    /// Extensions of the format without the leading dot, like `["br"]`
+    fn extensions(&self) -> &[&str];
+
+    /// Bytes every stream of the format starts with, to detect files without extensions, empty if
+    /// there's no such signature
+    fn magic(&self) -> &[u8] {
+        &[]
+    }
+
+    /// Wrap `writer` in an encoder, at `level` or at the default level of the format if `None`.
+    ///
+    /// The stream has to be finished when the encoder is dropped, like the built-in encoders.
+    fn encoder(&self, writer: Box<dyn Write + Send>, level: Option<i16>) -> io::Result<Box<dyn Write + Send>>;
+
+    /// Wrap `reader` in a decoder
+    fn decoder(&self, reader: Box<dyn Read + Send>) -> io::Result<Box<dyn Read + Send>>;
+}
+
+/// Identifier of a registered codec, in [`CompressionFormat::External`]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct CodecId(usize);
+
+struct Registered {
+    codec: &'static dyn Codec,
+    /// Formats of the extensions of the codec, `[External(id)]`
+    formats: &'static [CompressionFormat],
+}
+
+/// Codecs are leaked on registration, so they can be used without holding the lock
+static CODECS: RwLock<Vec<Registered>> = RwLock::new(Vec::new());
+
+/// Register `codec`, returning the format its extensions are detected as.
+///
+/// Fails if an extension of `codec` is empty, has a dot, or is already taken by a built-in format
+/// or another registered codec.
+pub fn register_codec(codec: impl Codec + 'static) -> crate::Result<CompressionFormat> {
+    let error = |detail: String| {
+        FinalError::with_title(format!("Cannot register the codec '{}'", codec.name()))
+            .detail(detail)
+            .class(ErrorClass::BadArguments)
+    };
+
+    let mut codecs = CODECS.write().unwrap_or_else(PoisonError::into_inner);
+
+    if codec.extensions().is_empty() {
+        return Err(error("It has no extensions".to_string()).into());
+    }
+    for extension in codec.extensions() {
+        if extension.is_empty() || extension.contains('.') {
+            return Err(error(format!("The extension '{extension}' is invalid")).into());
+        }
+        let taken = extension::builtin_formats(extension.as_bytes()).is_some()
+            || codecs
+                .iter()
+                .any(|registered| registered.codec.extensions().contains(extension));
+        if taken {
+            return Err(error(format!("The extension '{extension}' is already taken")).into());
+        }
+    }
+
+    let format = CompressionFormat::External(CodecId(codecs.len()));
+    codecs.push(Registered {
+        codec: Box::leak(Box::new(codec)),
+        formats: Box::leak(Box::new([format])),
+    });
+    Ok(format)
+}
+
+/// The codec registered as `id`
+pub(crate) fn get(id: CodecId) -> &'static dyn Codec {
+    let codecs = CODECS.read().unwrap_or_else(PoisonError::into_inner);
+    // Ids are only made by `register_codec`, and codecs are never removed
+    codecs[id.0].codec
+}
+
+/// Formats of the registered codec with the extension `extension`
+pub(crate) fn formats_from_extension(extension: &[u8]) -> Option<&'static [CompressionFormat]> {
+    let codecs = CODECS.read().unwrap_or_else(PoisonError::into_inner);
+    codecs
+        .iter()
+        .find(|registered| {
+            registered
+                .codec
+                .extensions()
+                .iter()
+                .any(|ext| ext.as_bytes() == extension)
+        })
+        .map(|registered| registered.formats)
+}
+
+/// Extension of the registered codec whose signature `buf` starts with
+pub(crate) fn extension_from_magic(buf: &[u8]) -> Option<Extension> {
+    let codecs = CODECS.read().unwrap_or_else(PoisonError::into_inner);
+    codecs
+        .iter()
+        .find(|registered| {
+            let magic = registered.codec.magic();
+            !magic.is_empty() && buf.starts_with(magic)
+        })
+        .map(|registered| Extension::new(registered.formats, registered.codec.extensions()[0]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+    use crate::{
+        extension::{extensions_from_path, flatten_compression_formats, CompressionFormat::*},
+        utils::try_infer_extension,
+        CompressOptions, DecompressOptions,
+    };
+
+    /// Flips the bits of every byte after a signature
+    struct Flip;
+
+    const FLIP_MAGIC: &[u8] = b"FLIP";
+
+    impl Codec for Flip {
+        fn name(&self) -> &str {
+            "flip"
+        }
+
+        fn extensions(&self) -> &[&str] {
+            &["flip", "flp"]
+        }
+
+        fn magic(&self) -> &[u8] {
+            FLIP_MAGIC
+        }
+
+        fn encoder(&self, mut writer: Box<dyn Write + Send>, _: Option<i16>) -> io::Result<Box<dyn Write + Send>> {
+            writer.write_all(FLIP_MAGIC)?;
+            Ok(Box::new(FlipWriter(writer)))
+        }
+
+        fn decoder(&self, mut reader: Box<dyn Read + Send>) -> io::Result<Box<dyn Read + Send>> {
+            let mut magic = [0; FLIP_MAGIC.len()];
+            reader.read_exact(&mut magic)?;
+            if magic != FLIP_MAGIC {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "not a flip stream"));
+            }
+            Ok(Box::new(FlipReader(reader)))
+        }
+    }
+
+    struct FlipWriter(Box<dyn Write + Send>);
+
+    impl Write for FlipWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let flipped: Vec<u8> = buf.iter().map(|byte| !byte).collect();
+            self.0.write_all(&flipped)?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    struct FlipReader(Box<dyn Read + Send>);
+
+    impl Read for FlipReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let read = self.0.read(buf)?;
+            buf[..read].iter_mut().for_each(|byte| *byte = !*byte);
+            Ok(read)
+        }
+    }
+
+    #[test]
+    fn test_register_codec() {
+        let format = register_codec(Flip).unwrap();
+        let formats = |path: &str| flatten_compression_formats(&extensions_from_path(Path::new(path)));
+
+        assert_eq!(formats("file.flip"), [format]);
+        assert_eq!(formats("file.tar.flp"), [Tar, format]);
+        assert_eq!(formats("file.flip.gz"), [format, Gzip]);
+        assert!(register_codec(Flip).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        std::fs::write(&input, "some contents").unwrap();
+
+        let output = dir.path().join("input.tar.flip");
+        crate::compress(std::slice::from_ref(&input), &output, &CompressOptions::default()).unwrap();
+        assert!(std::fs::read(&output).unwrap().starts_with(FLIP_MAGIC));
+
+        // Detected from the signature without the extension
+        let renamed = dir.path().join("renamed");
+        std::fs::rename(&output, &renamed).unwrap();
+        let extension = try_infer_extension(&renamed).unwrap();
+        assert_eq!(extension.compression_formats, [format]);
+        std::fs::rename(&renamed, &output).unwrap();
+
+        let out_dir = dir.path().join("out");
+        crate::decompress(&output, &out_dir, &DecompressOptions::default()).unwrap();
+        let unpacked: PathBuf = out_dir.join("input");
+        assert_eq!(std::fs::read(unpacked).unwrap(), b"some contents");
+    }
+
+    #[test]
+    fn test_register_codec_with_builtin_extension() {
+        struct Gz;
+
+        impl Codec for Gz {
+            fn name(&self) -> &str {
+                "fake gzip"
+            }
+
+            fn extensions(&self) -> &[&str] {
+                &["gz"]
+            }
+
+            fn encoder(&self, writer: Box<dyn Write + Send>, _: Option<i16>) -> io::Result<Box<dyn Write + Send>> {
+                Ok(writer)
+            }
+
+            fn decoder(&self, reader: Box<dyn Read + Send>) -> io::Result<Box<dyn Read + Send>> {
+                Ok(reader)
+            }
+        }
+
+        assert!(register_codec(Gz).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! Comparison of compression formats and levels on a sample of the user's data.
+//!
+//! Directories are archived in a tar file first, like `ouch compress dir dir.tar.<format>` does,
+//! and only the compression layer on top of it is measured.
+
+use std::{
+    ffi::{OsStr, OsString},
+    io::{self, stdout, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use fs_err as fs;
+
+use crate::{
+    commands::{
+        compress::{compress_files, CodecOptions, CompressOptions},
+        decompress::chain_reader_decoder,
+    },
+    error::{ErrorClass, FinalError},
+    extension::{parse_format_flag, CompressionFormat::*, Extension},
+    options::{NormalizationForm, SpecialFiles},
+    utils::{Bytes, EscapedPathDisplay, FileVisibilityPolicy},
+    QuestionPolicy,
+};
+
+/// Measurements of one format and level
+struct Row {
+    format: String,
+    level: Option<i16>,
+    compressed_size: u64,
+    compress_time: Duration,
+    decompress_time: Duration,
+}
+
+/// Compress a sample of at most `sample_size` bytes of `input` with each of `formats`, at each of
+/// `levels` or at the default level of each format if there's none, and print a table of the
+/// compression ratio and the compression and decompression speeds
+pub fn benchmark(
+    input: &Path,
+    formats: &[OsString],
+    levels: &[i16],
+    sample_size: u64,
+    file_visibility_policy: FileVisibilityPolicy,
+    buffer_size: usize,
+) -> crate::Result<()> {
+    let extensions = formats
+        .iter()
+        .map(|format| stream_format(format))
+        .collect::<crate::Result<Vec<_>>>()?;
+    let levels: Vec<Option<i16>> = if levels.is_empty() {
+        vec![None]
+    } else {
+        levels.iter().copied().map(Some).collect()
+    };
+
+    let temp_dir = tempfile::tempdir()?;
+    let sample = take_sample(input, temp_dir.path(), sample_size, file_visibility_policy, buffer_size)?;
+    let sample_size = fs::metadata(&sample)?.len();
+
+    let mut rows = vec![];
+    for extension in &extensions {
+        for &level in &levels {
+            let compressed = temp_dir.path().join(format!("sample.{extension}"));
+            let codec_options = CodecOptions {
+                level,
+                ..CodecOptions::default()
+            };
+
+            let start = Instant::now();
+            compress_files(
+                vec![sample.clone()],
+                vec![extension.clone()],
+                Box::new(fs::File::create(&compressed)?),
+                CompressOptions {
+                    output_path: &compressed,
+                    quiet: true,
+                    question_policy: QuestionPolicy::ALWAYS_YES,
+                    // The sample is a single file, nothing is walked
+                    file_visibility_policy: FileVisibilityPolicy::default(),
+                    codec_options: &codec_options,
+                    reproducible: None,
+                    comment: None,
+                    name_normalization: NormalizationForm::None,
+                    special_files: SpecialFiles::Skip,
+                    dedup: false,
+                    rate_limit: None,
+                    buffer_size,
+                    encryption_recipients: &[],
+                    index: None,
+                    observer: None,
+                },
+            )?;
+            let compress_time = start.elapsed();
+            let compressed_size = fs::metadata(&compressed)?.len();
+
+            let start = Instant::now();
+            let file = BufReader::with_capacity(buffer_size, fs::File::open(&compressed)?);
+            let mut reader = chain_reader_decoder(&extension.compression_formats[0], Box::new(file))?;
+            io::copy(&mut reader, &mut io::sink())?;
+            let decompress_time = start.elapsed();
+
+            rows.push(Row {
+                format: extension.to_string(),
+                level,
+                compressed_size,
+                compress_time,
+                decompress_time,
+            });
+        }
+    }
+
+    print_table(input, sample_size, &rows);
+    Ok(())
+}
+
+/// Parse `format` as a single compression format, archives and chains of formats can't be compared
+fn stream_format(format: &OsStr) -> crate::Result<Extension> {
+    let mut extensions = parse_format_flag(format)?;
+
+    match extensions.as_slice() {
+        [extension]
+            if matches!(
+                extension.compression_formats,
+                [Gzip | Bzip | Bzip3 | Lz4 | Lzma | LzmaAlone | Snappy | Zstd | External(_)]
+            ) =>
+        {
+            Ok(extensions.remove(0))
+        }
+        _ => Err(
+            FinalError::with_title(format!("Cannot benchmark the format '{}'", format.to_string_lossy()))
+                .detail("Only single compression formats can be compared, like gz, xz or zst")
+                .class(ErrorClass::BadArguments)
+                .into(),
+        ),
+    }
+}
+
+/// Copy the first `sample_size` bytes of `input` into `temp_dir`, directories are archived with tar
+/// first, returning the path of the sample
+fn take_sample(
+    input: &Path,
+    temp_dir: &Path,
+    sample_size: u64,
+    file_visibility_policy: FileVisibilityPolicy,
+    buffer_size: usize,
+) -> crate::Result<PathBuf> {
+    let sample = temp_dir.join("sample");
+
+    if input.is_dir() {
+        compress_files(
+            vec![input.to_path_buf()],
+            vec![Extension::new(&[Tar], "tar")],
+            Box::new(fs::File::create(&sample)?),
+            CompressOptions {
+                output_path: &sample,
+                quiet: true,
+                question_policy: QuestionPolicy::ALWAYS_YES,
+                file_visibility_policy,
+                codec_options: &CodecOptions::default(),
+                reproducible: None,
+                comment: None,
+                name_normalization: NormalizationForm::None,
+                special_files: SpecialFiles::Skip,
+                dedup: false,
+                rate_limit: None,
+                buffer_size,
+                encryption_recipients: &[],
+                index: None,
+                observer: None,
+            },
+        )?;
+
+        let file = fs::OpenOptions::new().write(true).open(&sample)?;
+        if file.metadata()?.len() > sample_size {
+            file.set_len(sample_size)?;
+        }
+    } else {
+        let mut file = fs::File::open(input)?.take(sample_size);
+        io::copy(&mut file, &mut fs::File::create(&sample)?)?;
+    }
+
+    Ok(sample)
+}
+
+fn print_table(input: &Path, sample_size: u64, rows: &[Row]) {
+    let header = ["format", "level", "ratio", "compress", "decompress"];
+    let lines: Vec<[String; 5]> = rows
+        .iter()
+        .map(|row| {
+            let level = row
+                .level
+                .map_or_else(|| "default".to_string(), |level| level.to_string());
+            let ratio = if sample_size == 0 {
+                "-".to_string()
+            } else {
+                format!("{:.1}%", row.compressed_size as f64 * 100.0 / sample_size as f64)
+            };
+
+            [
+                row.format.clone(),
+                level,
+                ratio,
+                speed(sample_size, row.compress_time),
+                speed(sample_size, row.decompress_time),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.map(str::len);
+    for line in &lines {
+        for (width, cell) in widths.iter_mut().zip(line) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let out = &mut stdout().lock();
+    let _ = writeln!(
+        out,
+        "Sample of {} from {}",
+        Bytes::new(sample_size),
+        EscapedPathDisplay::new(input)
+    );
+    let header = header.map(str::to_string);
+    for line in std::iter::once(&header).chain(&lines) {
+        let cells: Vec<String> = line
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        let _ = writeln!(out, "{}", cells.join("  ").trim_end());
+    }
+}
+
+/// Speed of processing `size` bytes in `time`, per second
+fn speed(size: u64, time: Duration) -> String {
+    let seconds = time.as_secs_f64().max(f64::EPSILON);
+    format!("{}/s", Bytes::new((size as f64 / seconds) as u64))
+}
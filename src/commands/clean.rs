@@ -0,0 +1,61 @@
+//! Removal of the temporary directories left behind by interrupted decompressions.
+
+use std::path::{Path, PathBuf};
+
+use fs_err as fs;
+
+use crate::{
+    utils::{
+        find_stale_temp_dirs,
+        logger::{self, info_accessible, warning},
+        nice_directory_display, user_wants_to_remove_temp_dirs, EscapedPathDisplay,
+    },
+    QuestionPolicy,
+};
+
+/// Remove the temporary directories in `dir` that belong to ouch processes that are no longer running
+pub fn clean_temp_dirs(dir: &Path) -> crate::Result<()> {
+    let stale_dirs = find_stale_temp_dirs(dir)?;
+
+    if stale_dirs.is_empty() {
+        info_accessible(format!(
+            "No leftover temporary directories found in {}",
+            nice_directory_display(dir)
+        ));
+        return Ok(());
+    }
+
+    remove_temp_dirs(&stale_dirs)
+}
+
+/// Before decompressing into `dir`, offer to remove the temporary directories left there
+pub fn offer_to_clean_temp_dirs(dir: &Path, question_policy: QuestionPolicy) -> crate::Result<()> {
+    let stale_dirs = find_stale_temp_dirs(dir)?;
+
+    if stale_dirs.is_empty() {
+        return Ok(());
+    }
+
+    warning(format!(
+        "Found {} temporary directories left behind by interrupted decompressions in {}",
+        stale_dirs.len(),
+        nice_directory_display(dir)
+    ));
+    // Print the warning before the question
+    logger::flush_messages();
+
+    if user_wants_to_remove_temp_dirs(question_policy)? {
+        remove_temp_dirs(&stale_dirs)?;
+    }
+
+    Ok(())
+}
+
+fn remove_temp_dirs(dirs: &[PathBuf]) -> crate::Result<()> {
+    for dir in dirs {
+        fs::remove_dir_all(dir)?;
+        info_accessible(format!("Removed {}", EscapedPathDisplay::new(dir)));
+    }
+
+    Ok(())
+}
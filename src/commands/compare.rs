@@ -0,0 +1,132 @@
+//! Check that an archive still matches a directory, without unpacking it.
+
+use std::{
+    collections::BTreeSet,
+    io::{stdout, Write},
+    path::Path,
+};
+
+use crate::{
+    archive::EntrySummary,
+    commands::diff::{index_by_path, summarize_archive, summarize_directory},
+    error::{ErrorClass, FinalError},
+    extension::CompressionFormat,
+    utils::{colors::*, logger::info_accessible, Bytes, EscapedPathDisplay, FileVisibilityPolicy},
+};
+
+/// Why an entry of the directory doesn't match the archive
+enum Difference {
+    OnlyInDirectory,
+    OnlyInArchive,
+    /// A directory on one side and a file on the other, `true` if it's the directory in the archive
+    Type {
+        dir_in_archive: bool,
+    },
+    Size {
+        directory: u64,
+        archive: u64,
+    },
+    Contents,
+}
+
+impl Difference {
+    fn between(directory: Option<&EntrySummary>, archive: Option<&EntrySummary>) -> Option<Self> {
+        let difference = match (directory, archive) {
+            (Some(_), None) => Self::OnlyInDirectory,
+            (None, Some(_)) => Self::OnlyInArchive,
+            (Some(directory), Some(archive)) if directory.is_dir != archive.is_dir => Self::Type {
+                dir_in_archive: archive.is_dir,
+            },
+            (Some(directory), Some(archive)) if directory.size != archive.size => Self::Size {
+                directory: directory.size,
+                archive: archive.size,
+            },
+            (Some(directory), Some(archive)) if directory.crc32 != archive.crc32 => Self::Contents,
+            _ => return None,
+        };
+        Some(difference)
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::OnlyInDirectory => "only in the directory".to_string(),
+            Self::OnlyInArchive => "only in the archive".to_string(),
+            Self::Type { dir_in_archive: true } => "directory in the archive, file in the directory".to_string(),
+            Self::Type { dir_in_archive: false } => "file in the archive, directory in the directory".to_string(),
+            Self::Size { directory, archive } => format!(
+                "size differs, {} in the directory and {} in the archive",
+                Bytes::new(*directory),
+                Bytes::new(*archive)
+            ),
+            Self::Contents => "contents differ".to_string(),
+        }
+    }
+}
+
+/// Compare the files under `dir` with the entries of the archive at `archive_path`, printing every
+/// difference, and fail if there's any.
+///
+/// Entries are named like in archives built by `ouch` from `dir`, and compared by type, size and
+/// CRC32 of their contents.
+pub fn compare_directory(
+    dir: &Path,
+    archive_path: &Path,
+    formats: &[CompressionFormat],
+    file_visibility_policy: &FileVisibilityPolicy,
+    password: Option<&[u8]>,
+) -> crate::Result<()> {
+    let directory_entries = summarize_directory(dir, file_visibility_policy)?;
+    let archive_entries = summarize_archive(archive_path, formats, password, None)?;
+
+    let directory_entries = index_by_path(&directory_entries);
+    let archive_entries = index_by_path(&archive_entries);
+
+    let all_paths: BTreeSet<&Path> = directory_entries
+        .keys()
+        .chain(archive_entries.keys())
+        .copied()
+        .collect();
+
+    let out = &mut stdout().lock();
+    let mut difference_count = 0;
+    for path in all_paths {
+        let Some(difference) =
+            Difference::between(directory_entries.get(path).copied(), archive_entries.get(path).copied())
+        else {
+            continue;
+        };
+
+        let (color, symbol) = match difference {
+            Difference::OnlyInDirectory => (*RED, '-'),
+            Difference::OnlyInArchive => (*GREEN, '+'),
+            _ => (*YELLOW, '~'),
+        };
+        let _ = writeln!(
+            out,
+            "{color}{symbol}{} {} ({})",
+            *RESET,
+            EscapedPathDisplay::new(path),
+            difference.describe()
+        );
+        difference_count += 1;
+    }
+
+    if difference_count > 0 {
+        let error = FinalError::with_title(format!(
+            "'{}' doesn't match '{}'",
+            EscapedPathDisplay::new(archive_path),
+            EscapedPathDisplay::new(dir)
+        ))
+        .detail(format!("{difference_count} entries differ"))
+        .class(ErrorClass::Other);
+
+        return Err(error.into());
+    }
+
+    info_accessible(format!(
+        "'{}' matches '{}'",
+        EscapedPathDisplay::new(archive_path),
+        EscapedPathDisplay::new(dir)
+    ));
+    Ok(())
+}
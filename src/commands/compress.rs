@@ -1,48 +1,231 @@
 use std::{
-    io::{self, BufWriter, Cursor, Seek, Write},
+    collections::HashMap,
+    io::{self, BufWriter, Cursor, Read, Seek, Write},
     path::{Path, PathBuf},
+    time::UNIX_EPOCH,
 };
 
+use ed25519_dalek::SigningKey;
 use fs_err as fs;
 
 use super::warn_user_about_loading_sevenz_in_memory;
 use crate::{
-    archive,
-    commands::warn_user_about_loading_zip_in_memory,
-    extension::{split_first_compression_format, CompressionFormat::*, Extension},
-    utils::{io::lock_and_flush_output_stdio, user_wants_to_continue, FileVisibilityPolicy},
-    QuestionAction, QuestionPolicy, BUFFER_CAPACITY,
+    archive::{
+        self,
+        index::ArchiveIndex,
+        manifest::{ArchiveObserver, ObservedWriter},
+        walk::Baseline,
+        Reproducible,
+    },
+    codec,
+    commands::{list::archive_entries, warn_user_about_loading_zip_in_memory},
+    encryption,
+    error::{ErrorClass, FinalError},
+    extension::{self, split_first_compression_format, CompressionFormat::*, Extension},
+    filter::{self, FilterCommand},
+    lrzip, lz4, lzop, lzw,
+    options::{NormalizationForm, SpecialFiles, XzCheck, ZipRule},
+    signature, snappy,
+    utils::{
+        self,
+        io::{lock_and_flush_output_stdio, Rsyncable, Throttled},
+        is_path_stdin,
+        logger::info_accessible,
+        path_to_str, pipeline, user_wants_to_continue, EscapedPathDisplay, FileVisibilityPolicy,
+    },
+    zstd_seekable, QuestionAction, QuestionPolicy,
 };
 
+/// Settings of the encoders in the chain, each one is ignored by the formats it doesn't apply to
+#[derive(Debug, Default)]
+pub struct CodecOptions {
+    /// Compression level, clamped to the range of each format
+    pub level: Option<i16>,
+    /// Store a checksum of the contents in zstd frames
+    pub zstd_checksum: bool,
+    /// Write zstd in the seekable format, see [`zstd_seekable`]
+    pub zstd_seekable: bool,
+    /// Integrity check of xz streams
+    pub xz_check: XzCheck,
+    /// Most memory used by the xz encoder, lowering the number of threads to stay under it
+    pub xz_memory_limit: Option<u64>,
+    /// Use the extreme variant of the xz and lzma presets
+    pub xz_extreme: bool,
+    /// Write snappy as a single raw block instead of the framed format
+    pub snappy_raw: bool,
+    /// Flush gzip and zstd where their output can be synced with rsync more efficiently
+    pub rsyncable: bool,
+    /// Store the files of zip archives that look already compressed without compressing them again
+    pub auto_store: bool,
+    /// Methods of the files of zip archives matching each glob, set by `--zip-rule`
+    pub zip_rules: Vec<ZipRule>,
+    /// File name stored in the gzip header
+    pub gzip_name: Option<String>,
+    /// Comment stored in the gzip header
+    pub gzip_comment: Option<String>,
+    /// Program run instead of the encoder of the outermost format
+    pub filter_cmd: Option<FilterCommand>,
+}
+
+impl From<XzCheck> for xz2::stream::Check {
+    fn from(check: XzCheck) -> Self {
+        match check {
+            XzCheck::None => Self::None,
+            XzCheck::Crc32 => Self::Crc32,
+            XzCheck::Crc64 => Self::Crc64,
+            XzCheck::Sha256 => Self::Sha256,
+        }
+    }
+}
+
+/// `LZMA_PRESET_EXTREME` of liblzma, or'ed with the level
+const XZ_PRESET_EXTREME: u32 = 1 << 31;
+
+/// Preset of the xz and lzma encoders, from the level and `--xz-extreme`
+fn xz_preset(level: Option<i16>, codec_options: &CodecOptions) -> u32 {
+    let preset = level.map_or(6, |l| (l as u32).clamp(0, 9));
+    if codec_options.xz_extreme {
+        preset | XZ_PRESET_EXTREME
+    } else {
+        preset
+    }
+}
+
+/// Encoder of LZMA_Alone streams, liblzma fails to flush them before their end (and xz2 panics), so
+/// flushing only flushes what was already written to the inner writer
+struct LzmaAloneEncoder<W: Write>(xz2::write::XzEncoder<W>);
+
+impl<W: Write> Write for LzmaAloneEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.get_mut().flush()
+    }
+}
+
+/// Encoder of xz streams using every thread of the pool, as many as fit in
+/// [`CodecOptions::xz_memory_limit`], or a single threaded one when there's a single thread left
+fn xz_encoder_stream(preset: u32, codec_options: &CodecOptions) -> io::Result<xz2::stream::Stream> {
+    let mut builder = xz2::stream::MtStreamBuilder::new();
+    builder.preset(preset).check(codec_options.xz_check.into());
+
+    let mut threads = rayon::current_num_threads() as u32;
+    builder.threads(threads);
+    if let Some(limit) = codec_options.xz_memory_limit {
+        while threads > 1 && builder.memusage() > limit {
+            threads -= 1;
+            builder.threads(threads);
+        }
+    }
+
+    // A single thread would still split the output into blocks, the regular encoder writes the
+    // same streams as before for `--threads 1`
+    let stream = if threads > 1 {
+        builder.encoder()
+    } else {
+        xz2::stream::Stream::new_easy_encoder(preset, codec_options.xz_check.into())
+    };
+    stream.map_err(io::Error::from)
+}
+
+/// Settings of [`compress_files`]
+pub struct CompressOptions<'a> {
+    /// The resulting compressed file name, example: "archive.tar.gz"
+    pub output_path: &'a Path,
+    pub quiet: bool,
+    pub question_policy: QuestionPolicy,
+    pub file_visibility_policy: FileVisibilityPolicy,
+    /// Compression level and per-format settings of the encoders
+    pub codec_options: &'a CodecOptions,
+    /// If set, fixes the metadata that would otherwise change between runs
+    pub reproducible: Option<Reproducible>,
+    /// If set, the comment of zip archives, or stored in a PAX global header of tar archives
+    pub comment: Option<&'a str>,
+    /// Unicode normalization applied to entry names
+    pub name_normalization: NormalizationForm,
+    /// What to do with FIFOs, sockets and devices found in the input files
+    pub special_files: SpecialFiles,
+    /// Store files with the same contents as one already in the archive as hard links to it
+    pub dedup: bool,
+    /// If set, the maximum speed for writing the output, in bytes per second
+    pub rate_limit: Option<u64>,
+    /// Size of the buffers used to read the input files and write the output
+    pub buffer_size: usize,
+    /// Age public keys to encrypt to, used by `.age`
+    pub encryption_recipients: &'a [String],
+    /// If set, filled with the offsets of the entries of tar archives, for `--index`
+    pub index: Option<&'a mut ArchiveIndex>,
+    /// If set, told about every entry stored in archives, for `--manifest`, and about the bytes
+    /// compressed by single file formats
+    pub observer: Option<&'a mut dyn ArchiveObserver>,
+}
+
 /// Compress files into `output_file`.
 ///
 /// # Arguments:
 /// - `files`: is the list of paths to be compressed: ["dir/file1.txt", "dir/file2.txt"]
 /// - `extensions`: is a list of compression formats for compressing, example: [Tar, Gz] (in compression order)
-/// - `output_file` is the resulting compressed file name, example: "archive.tar.gz"
+/// - `output_file`: where the compressed data is written, the file at `options.output_path` or its parts
+/// - `options`: the name of the output and how the files are compressed into it
 ///
 /// # Return value
 /// - Returns `Ok(true)` if compressed all files normally.
 /// - Returns `Ok(false)` if user opted to abort compression mid-way.
-#[allow(clippy::too_many_arguments)]
 pub fn compress_files(
     files: Vec<PathBuf>,
     extensions: Vec<Extension>,
-    output_file: fs::File,
-    output_path: &Path,
-    quiet: bool,
-    question_policy: QuestionPolicy,
-    file_visibility_policy: FileVisibilityPolicy,
-    level: Option<i16>,
+    output_file: Box<dyn Send + Write>,
+    options: CompressOptions,
 ) -> crate::Result<bool> {
-    // If the input files contain a directory, then the total size will be underestimated
-    let file_writer = BufWriter::with_capacity(BUFFER_CAPACITY, output_file);
+    let CompressOptions {
+        output_path,
+        quiet,
+        question_policy,
+        file_visibility_policy,
+        codec_options,
+        reproducible,
+        comment,
+        name_normalization,
+        special_files,
+        dedup,
+        rate_limit,
+        buffer_size,
+        encryption_recipients,
+        index,
+        observer,
+    } = options;
+    let file_writer = BufWriter::with_capacity(buffer_size, output_file);
 
-    let mut writer: Box<dyn Send + Write> = Box::new(file_writer);
+    let mut writer: Box<dyn Send + Write> = match rate_limit {
+        Some(bytes_per_second) => Box::new(Throttled::new(file_writer, bytes_per_second)),
+        None => Box::new(file_writer),
+    };
+
+    let level = codec_options.level;
 
-    // Grab previous encoder and wrap it inside of a new one
-    let chain_writer_encoder = |format: &_, encoder| -> crate::Result<_> {
+    // Grab previous encoder and wrap it inside of a new one, `source` is the file compressed by
+    // single file formats
+    let chain_writer_encoder = |format: &_, encoder, source: Option<&Path>| -> crate::Result<_> {
         let encoder: Box<dyn Send + Write> = match format {
+            // ParCompress can't write the optional header fields, or flush at the points of
+            // `--rsyncable`, so fall back to flate2 when they're needed
+            Gzip if codec_options.gzip_name.is_some()
+                || codec_options.gzip_comment.is_some()
+                || source.is_some()
+                || codec_options.rsyncable =>
+            {
+                let gzip_encoder = gzip_header(codec_options, source, reproducible)?.write(
+                    encoder,
+                    level.map_or_else(Default::default, |l| flate2::Compression::new((l as u32).clamp(0, 9))),
+                );
+                if codec_options.rsyncable {
+                    Box::new(Rsyncable::new(gzip_encoder))
+                } else {
+                    Box::new(gzip_encoder)
+                }
+            }
             Gzip => Box::new(
                 // by default, ParCompress uses a default compression level of 3
                 // instead of the regular default that flate2 uses
@@ -60,11 +243,21 @@ pub fn compress_files(
                 // Use block size of 16 MiB
                 bzip3::write::Bz3Encoder::new(encoder, 16 * 2_usize.pow(20))?,
             ),
-            Lz4 => Box::new(lz4_flex::frame::FrameEncoder::new(encoder).auto_finish()),
-            Lzma => Box::new(xz2::write::XzEncoder::new(
+            Lz4 => lz4::encoder(
                 encoder,
-                level.map_or(6, |l| (l as u32).clamp(0, 9)),
-            )),
+                source.map(fs::metadata).transpose()?.map(|metadata| metadata.len()),
+            ),
+            Lzma => {
+                let stream = xz_encoder_stream(xz_preset(level, codec_options), codec_options)?;
+                Box::new(xz2::write::XzEncoder::new_stream(encoder, stream))
+            }
+            LzmaAlone => {
+                let options =
+                    xz2::stream::LzmaOptions::new_preset(xz_preset(level, codec_options)).map_err(io::Error::from)?;
+                let stream = xz2::stream::Stream::new_lzma_encoder(&options).map_err(io::Error::from)?;
+                Box::new(LzmaAloneEncoder(xz2::write::XzEncoder::new_stream(encoder, stream)))
+            }
+            Snappy if codec_options.snappy_raw => Box::new(snappy::RawEncoder::new(encoder)),
             Snappy => Box::new(
                 gzp::par::compress::ParCompress::<gzp::snap::Snap>::builder()
                     .compression_level(gzp::par::compress::Compression::new(
@@ -73,36 +266,92 @@ pub fn compress_files(
                     .from_writer(encoder),
             ),
             Zstd => {
-                let mut zstd_encoder = zstd::stream::write::Encoder::new(
-                    encoder,
-                    level.map_or(zstd::DEFAULT_COMPRESSION_LEVEL, |l| {
-                        (l as i32).clamp(zstd::zstd_safe::min_c_level(), zstd::zstd_safe::max_c_level())
-                    }),
-                )?;
-                // Use all available PHYSICAL cores for compression
-                zstd_encoder.multithread(num_cpus::get_physical() as u32)?;
-                Box::new(zstd_encoder.auto_finish())
+                let zstd_level = level.map_or(zstd::DEFAULT_COMPRESSION_LEVEL, |l| {
+                    (l as i32).clamp(zstd::zstd_safe::min_c_level(), zstd::zstd_safe::max_c_level())
+                });
+                if codec_options.zstd_seekable {
+                    Box::new(zstd_seekable::SeekableEncoder::new(
+                        encoder,
+                        zstd_level,
+                        codec_options.zstd_checksum,
+                    ))
+                } else {
+                    let mut zstd_encoder = zstd::stream::write::Encoder::new(encoder, zstd_level)?;
+                    // Use all available PHYSICAL cores for compression
+                    zstd_encoder.multithread(num_cpus::get_physical() as u32)?;
+                    zstd_encoder.include_checksum(codec_options.zstd_checksum)?;
+                    // Only applies with workers, which are always used above
+                    zstd_encoder.set_parameter(zstd::stream::raw::CParameter::RSyncable(codec_options.rsyncable))?;
+                    Box::new(zstd_encoder.auto_finish())
+                }
             }
-            Tar | Zip | Rar | SevenZip => unreachable!(),
+            Age => encryption::encrypt(encoder, encryption_recipients)?,
+            Lzw => return Err(lzw::no_compression()),
+            Lzop => lzop::encoder(encoder)?,
+            Lrzip => return Err(lrzip::no_support()),
+            External(id) => codec::get(*id).encoder(encoder, level)?,
+            Tar | Zip | Rar | SevenZip | Squashfs => unreachable!(),
         };
         Ok(encoder)
     };
 
     let (first_format, formats) = split_first_compression_format(&extensions);
 
-    for format in formats.iter().rev() {
-        writer = chain_writer_encoder(format, writer)?;
+    // The filter command takes the place of the outermost format, checked to be a compression format
+    let mut outer_formats = formats.iter().rev();
+    let mut filter = None;
+    if let Some(command) = &codec_options.filter_cmd {
+        let (input, encoder) = filter::encoder(command, writer)?;
+        writer = Box::new(input);
+        filter = Some(encoder);
+        outer_formats.next();
+    }
+    let first_format_filtered = filter.is_some() && formats.is_empty();
+
+    for format in outer_formats {
+        writer = chain_writer_encoder(format, writer, None)?;
     }
 
     match first_format {
-        Gzip | Bzip | Bzip3 | Lz4 | Lzma | Snappy | Zstd => {
-            writer = chain_writer_encoder(&first_format, writer)?;
-            let mut reader = fs::File::open(&files[0])?;
+        Gzip | Bzip | Bzip3 | Lz4 | Lzma | LzmaAlone | Snappy | Zstd | Age | Lzw | Lzop | Lrzip | External(_) => {
+            // Data piped to stdin has no name or modification time to store, and neither do
+            // concatenated files
+            let source = Some(files[0].as_path()).filter(|path| files.len() == 1 && !is_path_stdin(path));
+            if !first_format_filtered {
+                writer = chain_writer_encoder(&first_format, writer, source)?;
+            }
 
-            io::copy(&mut reader, &mut writer)?;
+            let mut observed;
+            let writer: &mut dyn Write = match observer {
+                Some(observer) => {
+                    observed = ObservedWriter::new(&mut writer, observer);
+                    &mut observed
+                }
+                None => &mut writer,
+            };
+            if files.len() > 1 {
+                pipeline::copy(&mut ConcatReader::new(&files), writer, buffer_size)?;
+            } else if source.is_some() {
+                pipeline::copy(&mut fs::File::open(&files[0])?, writer, buffer_size)?;
+            } else {
+                pipeline::copy(&mut io::stdin(), writer, buffer_size)?;
+            }
         }
         Tar => {
-            archive::tar::build_archive_from_paths(&files, output_path, &mut writer, file_visibility_policy, quiet)?;
+            archive::tar::build_archive_from_paths(
+                &files,
+                output_path,
+                &mut writer,
+                file_visibility_policy,
+                quiet,
+                reproducible,
+                comment,
+                name_normalization,
+                special_files,
+                dedup,
+                index,
+                observer,
+            )?;
             writer.flush()?;
         }
         Zip => {
@@ -125,6 +374,13 @@ pub fn compress_files(
                 &mut vec_buffer,
                 file_visibility_policy,
                 quiet,
+                reproducible,
+                comment,
+                name_normalization,
+                special_files,
+                codec_options.auto_store,
+                &codec_options.zip_rules,
+                observer,
             )?;
             vec_buffer.rewind()?;
             io::copy(&mut vec_buffer, &mut writer)?;
@@ -136,7 +392,15 @@ pub fn compress_files(
             #[cfg(not(feature = "unrar"))]
             return Err(archive::rar_stub::no_support());
         }
+        Squashfs => return Err(archive::squashfs::no_support()),
         SevenZip => {
+            if reproducible.is_some() {
+                return Err(FinalError::with_title("Cannot build a reproducible .7z archive")
+                    .detail("Reproducible mode is only supported for .tar and .zip archives")
+                    .class(ErrorClass::BadArguments)
+                    .into());
+            }
+
             if !formats.is_empty() {
                 // Locking necessary to guarantee that warning and question
                 // messages stay adjacent
@@ -149,11 +413,147 @@ pub fn compress_files(
             }
 
             let mut vec_buffer = Cursor::new(vec![]);
-            archive::sevenz::compress_sevenz(&files, output_path, &mut vec_buffer, file_visibility_policy, quiet)?;
+            archive::sevenz::compress_sevenz(
+                &files,
+                output_path,
+                &mut vec_buffer,
+                file_visibility_policy,
+                quiet,
+                name_normalization,
+                special_files,
+                observer,
+            )?;
             vec_buffer.rewind()?;
             io::copy(&mut vec_buffer, &mut writer)?;
         }
     }
 
+    // Dropping the encoders finishes their streams, closing the input of the filter command
+    drop(writer);
+    if let Some(filter) = filter {
+        filter.finish()?;
+    }
+
     Ok(true)
 }
+
+/// Header of gzip streams with the optional fields set, like the `gzip` tool, the name and
+/// modification time of `source` are stored unless `--gzip-name` or reproducible mode override them
+fn gzip_header(
+    codec_options: &CodecOptions,
+    source: Option<&Path>,
+    reproducible: Option<Reproducible>,
+) -> crate::Result<flate2::GzBuilder> {
+    let mut builder = flate2::GzBuilder::new();
+
+    let name = match (&codec_options.gzip_name, source.and_then(Path::file_name)) {
+        (Some(name), _) => Some(name.as_bytes()),
+        (None, Some(file_name)) => Some(file_name.as_encoded_bytes()),
+        (None, None) => None,
+    };
+    if let Some(name) = name {
+        builder = builder.filename(name);
+    }
+    if let Some(comment) = &codec_options.gzip_comment {
+        builder = builder.comment(comment.as_bytes());
+    }
+
+    let mtime = match (reproducible, source) {
+        (Some(reproducible), _) => Some(reproducible.mtime),
+        (None, Some(source)) => fs::metadata(source)?
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs()),
+        (None, None) => None,
+    };
+    // Zero means the modification time isn't available, as do times that don't fit in 32 bits
+    builder = builder.mtime(mtime.and_then(|mtime| u32::try_from(mtime).ok()).unwrap_or(0));
+
+    Ok(builder)
+}
+
+/// Reads the files one after the other for `--concat`, stdin in place of "-", opening each one
+/// once the previous one ends
+struct ConcatReader<'a> {
+    files: std::slice::Iter<'a, PathBuf>,
+    current: Option<Box<dyn Read + Send>>,
+}
+
+impl<'a> ConcatReader<'a> {
+    fn new(files: &'a [PathBuf]) -> Self {
+        Self {
+            files: files.iter(),
+            current: None,
+        }
+    }
+}
+
+impl Read for ConcatReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(reader) = &mut self.current {
+                match reader.read(buf)? {
+                    0 if !buf.is_empty() => {}
+                    read => return Ok(read),
+                }
+            }
+
+            let Some(path) = self.files.next() else {
+                return Ok(0);
+            };
+            self.current = Some(if is_path_stdin(path) {
+                Box::new(io::stdin())
+            } else {
+                Box::new(fs::File::open(path)?)
+            });
+        }
+    }
+}
+
+/// Sign the file at `output_path` with `signing_key`, writing the signature next to it
+pub fn sign_compressed_file(
+    output_path: &Path,
+    signing_key: &SigningKey,
+    question_policy: QuestionPolicy,
+) -> crate::Result<()> {
+    let signature_path = signature::signature_path(output_path);
+
+    let Some(mut signature_file) = utils::ask_to_create_file(&signature_path, question_policy)? else {
+        return Ok(());
+    };
+    writeln!(signature_file, "{}", signature::sign_file(output_path, signing_key)?)?;
+
+    info_accessible(format!("Signature written to '{}'", path_to_str(&signature_path)));
+    Ok(())
+}
+
+/// Modification times of the entries of the archive at `base`, the reference of `--newer-than`.
+/// Entries without one, like in rar archives, are compressed again
+pub fn read_baseline_archive(base: &Path, quiet: bool) -> crate::Result<Baseline> {
+    let extensions = extension::extensions_from_path(base);
+    if !extensions.first().is_some_and(Extension::is_archive) {
+        return Err(FinalError::with_title(format!(
+            "Cannot compare the files to '{}'",
+            EscapedPathDisplay::new(base)
+        ))
+        .detail("The reference of '--newer-than' has to be an archive, like '.tar.zst' or '.zip'")
+        .class(ErrorClass::BadArguments)
+        .into());
+    }
+
+    let formats = extension::flatten_compression_formats(&extensions);
+    let mut mtimes = HashMap::new();
+    for entry in archive_entries(base, &formats, None, None, quiet)?.files {
+        let entry = entry?;
+        if let Some(mtime) = entry.mtime {
+            let mtime = mtime.duration_since(UNIX_EPOCH).map_or(0, |mtime| mtime.as_secs());
+            mtimes.insert(entry.path, mtime);
+        }
+    }
+
+    Ok(Baseline::Archive {
+        base: base.to_path_buf(),
+        mtimes,
+    })
+}
@@ -1,51 +1,349 @@
 use std::{
-    io::{self, BufReader, Read},
+    ffi::{OsStr, OsString},
+    io::{self, BufRead, BufReader, IsTerminal, Read, Write},
+    iter,
     ops::ControlFlow,
     path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
 };
 
+use filetime_creation::{set_file_mtime, FileTime};
 use fs_err as fs;
 
 use crate::{
-    commands::{warn_user_about_loading_sevenz_in_memory, warn_user_about_loading_zip_in_memory},
+    archive::{index, DirMetadata, EntrySummary, RootEntries, UnpackOptions},
+    bzip2_parallel::ParallelBzDecoder,
+    codec,
+    commands::{
+        diff::summarize_archive, list::archive_entries, warn_user_about_loading_sevenz_in_memory,
+        warn_user_about_loading_zip_in_memory,
+    },
+    encryption,
+    error::{Error, ErrorClass, FinalError},
     extension::{
-        split_first_compression_format,
+        flatten_compression_formats, split_first_compression_format,
         CompressionFormat::{self, *},
         Extension,
     },
+    filter::{self, FilterCommand},
+    http, lrzip, lz4, lzop, lzw,
+    options::ConflictPolicy,
+    snappy,
     utils::{
-        self,
-        io::lock_and_flush_output_stdio,
+        self, ask_password,
+        checksum::crc32_from_reader,
+        common_prefix,
+        io::{joined_path, lock_and_flush_output_stdio, split_part_paths, JoinedParts, Throttled},
         is_path_stdin,
-        logger::{info, info_accessible},
-        nice_directory_display, user_wants_to_continue,
+        logger::{info, info_accessible, warning},
+        nice_directory_display, normalize_path, transform_path, user_wants_to_continue, Answer, EscapedPathDisplay,
+        PermissionsPolicy, Transform,
     },
+    zstd_seekable::SeekableDecoder,
     QuestionAction, QuestionPolicy, BUFFER_CAPACITY,
 };
 
+/// Times the password is asked again after a wrong one
+const PASSWORD_RETRIES: usize = 3;
+
 trait ReadSeek: Read + io::Seek {}
 impl<T: Read + io::Seek> ReadSeek for T {}
 
+#[derive(Clone)]
 pub struct DecompressOptions<'a> {
     pub input_file_path: &'a Path,
     pub formats: Vec<Extension>,
     pub output_dir: &'a Path,
     pub output_file_path: PathBuf,
     pub question_policy: QuestionPolicy,
-    pub quiet: bool,
-    pub password: Option<&'a [u8]>,
+    /// Where to create the temporary directory to unpack archives into, instead of `output_dir`
+    pub temp_dir: Option<&'a Path>,
+    pub unpack_options: UnpackOptions<'a>,
     pub remove: bool,
+    pub verify: bool,
+    /// Maximum speed for reading the input file, in bytes per second
+    pub rate_limit: Option<u64>,
+    /// Size of the buffers used to read the input file and write single file outputs
+    pub buffer_size: usize,
+    /// Print the unpacked paths to stdout, each followed by a NUL character
+    pub print0: bool,
+    /// Set by `--on-conflict`, existing outputs are merged with the unpacked files one by one
+    /// instead of being replaced as a whole
+    pub on_conflict: Option<ConflictPolicy>,
+    /// Unpack archives directly into `output_dir`, without a temporary directory
+    pub in_place: bool,
+    /// Strip the directory that has every entry of archives inside of it, unpacking its contents
+    /// into `output_dir`, cleared for archives that have none
+    pub strip_common_prefix: bool,
+    /// Program run instead of the decoder of the outermost format
+    pub filter_cmd: Option<&'a FilterCommand>,
+}
+
+type DecoderInput = BufReader<Box<dyn Read + Send>>;
+
+/// Grab previous decoder and wrap it inside of a new one
+///
+/// Panics if `format` is an archive format, these can't be chained as a stream decoder.
+pub fn chain_reader_decoder(
+    format: &CompressionFormat,
+    decoder: Box<dyn Read + Send>,
+) -> crate::Result<Box<dyn Read + Send>> {
+    let input: DecoderInput = BufReader::with_capacity(BUFFER_CAPACITY, decoder);
+
+    let decoder: Box<dyn Read + Send> = match format {
+        // Blocks are decoded in parallel unless `--threads 1` is given, this also decodes every stream
+        // of files written by `pbzip2` and `lbzip2`, and fails on trailing data
+        Bzip if rayon::current_num_threads() > 1 => Box::new(ParallelBzDecoder::new(input)),
+        Bzip3 => Box::new(bzip3::read::Bz3Decoder::new(input)?),
+        Lz4 => lz4::decoder(input)?,
+        Lzma => Box::new(TrailingDataCheck::new(xz2::bufread::XzDecoder::new(input), "xz")),
+        // Also reads xz, older versions wrote it to .lzma files
+        LzmaAlone => {
+            let stream = xz2::stream::Stream::new_auto_decoder(u64::MAX, 0).map_err(io::Error::from)?;
+            Box::new(TrailingDataCheck::new(
+                xz2::bufread::XzDecoder::new_stream(input, stream),
+                "lzma",
+            ))
+        }
+        // These decoders keep reading members (or frames) until the end of the input, like
+        // files joined with `cat a.gz b.gz > c.gz`, and fail on trailing data
+        Bzip => Box::new(bzip2::bufread::MultiBzDecoder::new(input)),
+        Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(input)),
+        Snappy => snappy::decoder(input)?,
+        Zstd => Box::new(zstd::stream::Decoder::with_buffer(input)?),
+        Age => encryption::decrypt(input)?,
+        Lzw => Box::new(lzw::LzwDecoder::new(input)?),
+        Lzop => lzop::decoder(input)?,
+        Lrzip => return Err(lrzip::no_support()),
+        External(id) => codec::get(*id).decoder(Box::new(input))?,
+        Tar | Zip | Rar | SevenZip | Squashfs => unreachable!(),
+    };
+    Ok(decoder)
+}
+
+/// Bytes decoded from every layer to detect the format of the next one, enough for tar headers
+const SNIFF_LEN: u64 = 270;
+
+/// Most formats detected in a chain, past them the contents are left as a single file
+const MAX_SNIFFED_FORMATS: usize = 8;
+
+/// Formats of the file at `path` detected from its contents alone, ignoring its name, in the order
+/// of [`extension::extensions_from_path`](crate::extension::extensions_from_path).
+///
+/// The start of every compressed layer is decoded to detect the format of the next one, until an
+/// archive or contents of an unknown format are found.
+pub fn sniff_formats(path: &Path) -> crate::Result<Vec<Extension>> {
+    let mut formats = vec![];
+    let mut reader: Box<dyn Read + Send> = Box::new(fs::File::open(path)?);
+
+    while formats.len() < MAX_SNIFFED_FORMATS {
+        let mut buf = vec![];
+        // Decoding can fail past the outer layers, like without the identity of an .age file,
+        // the formats detected so far are still used
+        if (&mut reader).take(SNIFF_LEN).read_to_end(&mut buf).is_err() {
+            break;
+        }
+        // The end of the file is only reachable for the outermost layer, where disk images are signed
+        let extension = match utils::infer_extension(&buf) {
+            None if formats.is_empty() => utils::try_infer_extension(path),
+            extension => extension,
+        };
+        let Some(extension) = extension else {
+            break;
+        };
+
+        let is_archive = extension.is_archive();
+        let format = extension.compression_formats[0];
+        formats.push(extension);
+        if is_archive {
+            break;
+        }
+
+        match chain_reader_decoder(&format, Box::new(io::Cursor::new(buf).chain(reader))) {
+            Ok(decoder) => reader = decoder,
+            Err(_) => break,
+        }
+    }
+
+    formats.reverse();
+    Ok(formats)
+}
+
+/// Decoders that stop at the end of their stream, giving access to the input left after it
+trait StreamDecoder: Read {
+    fn input(&mut self) -> &mut DecoderInput;
+}
+
+impl StreamDecoder for xz2::bufread::XzDecoder<DecoderInput> {
+    fn input(&mut self) -> &mut DecoderInput {
+        self.get_mut()
+    }
+}
+
+/// Fails reading if there's data left in the input after the end of the decoded stream,
+/// instead of silently ignoring it
+struct TrailingDataCheck<D> {
+    decoder: D,
+    format_name: &'static str,
+}
+
+impl<D: StreamDecoder> TrailingDataCheck<D> {
+    fn new(decoder: D, format_name: &'static str) -> Self {
+        Self { decoder, format_name }
+    }
+}
+
+impl<D: StreamDecoder> Read for TrailingDataCheck<D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.decoder.read(buf)?;
+
+        if read == 0 && !buf.is_empty() && !self.decoder.input().fill_buf()?.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("found unexpected data after the end of the {} stream", self.format_name),
+            ));
+        }
+
+        Ok(read)
+    }
+}
+
+/// Copy everything decoded by `reader` into `writer` in chunks of `buffer_size` bytes, naming
+/// `input_path` in decoding errors
+fn copy_decoded(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    input_path: &Path,
+    buffer_size: usize,
+) -> crate::Result<()> {
+    let mut buffer = vec![0; buffer_size];
+
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                return Err(FinalError::with_title(format!(
+                    "Failed to decompress {}",
+                    EscapedPathDisplay::new(input_path)
+                ))
+                .detail(format!("Error: {err}."))
+                .hint("The file is probably truncated or corrupted.")
+                .class(ErrorClass::CorruptArchive)
+                .into())
+            }
+        };
+
+        writer.write_all(&buffer[..read])?;
+    }
+}
+
+/// Optional fields of a gzip header restored when decompressing
+#[derive(Default)]
+struct GzipHeader {
+    /// Name of the original file, only kept if it's a plain file name
+    name: Option<PathBuf>,
+    mtime: Option<FileTime>,
+}
+
+impl GzipHeader {
+    fn from_decoder<R: BufRead>(decoder: &flate2::bufread::MultiGzDecoder<R>) -> Self {
+        let Some(header) = decoder.header() else {
+            return Self::default();
+        };
+
+        // Names with directories could point anywhere, like "../../.bashrc"
+        let name = header
+            .filename()
+            .and_then(|name| std::str::from_utf8(name).ok())
+            .map(PathBuf::from)
+            .filter(|name| name.file_name() == Some(name.as_os_str()));
+        // Zero means the modification time isn't available
+        let mtime = (header.mtime() != 0).then(|| FileTime::from_unix_time(header.mtime().into(), 0));
+
+        Self { name, mtime }
+    }
 }
 
 /// Decompress a file
 ///
-/// File at input_file_path is opened for reading, example: "archive.tar.gz"
+/// File at input_file_path is opened for reading, example: "archive.tar.gz", or downloaded if it's a URL
 /// formats contains each format necessary for decompression, example: [Gz, Tar] (in decompression order)
 /// output_dir it's where the file will be decompressed to, this function assumes that the directory exists
 /// output_file_path is only used when extracting single file formats, not archive formats like .tar or .zip
+///
+/// When the password is wrong, it's asked again up to [`PASSWORD_RETRIES`] times if questions
+/// can be asked in a terminal and the input can be read again.
 pub fn decompress_file(options: DecompressOptions) -> crate::Result<()> {
+    let path = options.input_file_path;
+    let can_retry = options.question_policy.proceed == Answer::Ask
+        && io::stdin().is_terminal()
+        && !is_path_stdin(path)
+        && !http::is_url(path);
+
+    let mut result = decompress_file_with_password(options.clone());
+    let mut password;
+    for _ in 0..PASSWORD_RETRIES {
+        if !can_retry || !matches!(result, Err(Error::WrongPassword)) {
+            break;
+        }
+
+        warning(format!(
+            "Wrong password for {}, try again",
+            EscapedPathDisplay::new(path)
+        ));
+        password = ask_password()?;
+        result = decompress_file_with_password(DecompressOptions {
+            unpack_options: UnpackOptions {
+                password: Some(password.as_bytes()),
+                ..options.unpack_options
+            },
+            ..options.clone()
+        });
+    }
+    result
+}
+
+/// Decompress like [`decompress_file`], failing right away if the password is wrong
+fn decompress_file_with_password(options: DecompressOptions) -> crate::Result<()> {
     assert!(options.output_dir.exists());
+    let root_entries = RootEntries::default();
+    let options = DecompressOptions {
+        unpack_options: UnpackOptions {
+            root_entries: Some(&root_entries),
+            ..options.unpack_options
+        },
+        ..options
+    };
     let input_is_stdin = is_path_stdin(options.input_file_path);
+    let input_is_url = http::is_url(options.input_file_path);
+    // Only files can be read again to be verified, or removed
+    let input_is_file = !input_is_stdin && !input_is_url;
+
+    if input_is_stdin && options.verify {
+        warning("Skipping --verify, the archive can't be read twice from stdin".to_string());
+    } else if input_is_url && options.verify {
+        warning("Skipping --verify, the archive would have to be downloaded twice".to_string());
+    }
+
+    let strip_transforms;
+    let options = match common_prefix_transforms(&options, input_is_file)? {
+        Some(transforms) => {
+            strip_transforms = transforms;
+            DecompressOptions {
+                unpack_options: UnpackOptions {
+                    transforms: &strip_transforms,
+                    ..options.unpack_options
+                },
+                ..options
+            }
+        }
+        None => DecompressOptions {
+            strip_common_prefix: false,
+            ..options
+        },
+    };
 
     // Zip archives are special, because they require io::Seek, so it requires it's logic separated
     // from decoder chaining.
@@ -64,21 +362,46 @@ pub fn decompress_file(options: DecompressOptions) -> crate::Result<()> {
             warn_user_about_loading_zip_in_memory();
             io::copy(&mut io::stdin(), &mut vec)?;
             Box::new(io::Cursor::new(vec))
+        } else if input_is_url {
+            warn_user_about_loading_zip_in_memory();
+            io::copy(
+                &mut http::open_url(options.input_file_path, options.unpack_options.quiet)?,
+                &mut vec,
+            )?;
+            Box::new(io::Cursor::new(vec))
         } else {
-            Box::new(fs::File::open(options.input_file_path)?)
+            Box::new(JoinedParts::open(options.input_file_path)?)
+        };
+        let reader: Box<dyn ReadSeek> = match options.rate_limit {
+            Some(bytes_per_second) => Box::new(Throttled::new(reader, bytes_per_second)),
+            None => reader,
+        };
+        let reader: Box<dyn ReadSeek> = match options.unpack_options.progress {
+            Some(progress) => Box::new(progress.reader(reader)),
+            None => reader,
         };
         let zip_archive = zip::ZipArchive::new(reader)?;
-        let files_unpacked = if let ControlFlow::Continue(files) = smart_unpack(
-            |output_dir| crate::archive::zip::unpack_archive(zip_archive, output_dir, options.password, options.quiet),
-            options.output_dir,
-            &options.output_file_path,
-            options.question_policy,
+        // Stored entries are copied straight from the file, which would get around --rate-limit,
+        // and the count of bytes read
+        let source = match (options.rate_limit, options.unpack_options.progress) {
+            (None, None) if input_is_file => Some(JoinedParts::open(options.input_file_path)?),
+            _ => None,
+        };
+        let (files_unpacked, unpacked_root) = if let ControlFlow::Continue(unpacked) = smart_unpack(
+            |output_dir| {
+                crate::archive::zip::unpack_archive(zip_archive, source.as_ref(), output_dir, options.unpack_options)
+            },
+            &options,
         )? {
-            files
+            unpacked
         } else {
             return Ok(());
         };
 
+        if input_is_file && options.verify {
+            verify_unpacked_files(&options, &unpacked_root)?;
+        }
+
         // this is only printed once, so it doesn't result in much text. On the other hand,
         // having a final status message is important especially in an accessibility context
         // as screen readers may not read a commands exit code, making it hard to reason
@@ -89,68 +412,149 @@ pub fn decompress_file(options: DecompressOptions) -> crate::Result<()> {
             files_unpacked
         ));
 
-        if !input_is_stdin && options.remove {
-            fs::remove_file(options.input_file_path)?;
-            info(format!(
-                "Removed input file {}",
-                nice_directory_display(options.input_file_path)
-            ));
+        if options.print0 {
+            print_unpacked_paths(&unpacked_root.path)?;
+        }
+
+        if input_is_file && options.remove {
+            remove_input_file(options.input_file_path)?;
         }
 
         return Ok(());
     }
 
     // Will be used in decoder chaining
-    let reader: Box<dyn Read> = if input_is_stdin {
+    let reader: Box<dyn Read + Send> = if input_is_stdin {
         Box::new(io::stdin())
+    } else if input_is_url {
+        http::open_url(options.input_file_path, options.unpack_options.quiet)?
     } else {
-        Box::new(fs::File::open(options.input_file_path)?)
+        Box::new(JoinedParts::open(options.input_file_path)?)
     };
-    let reader = BufReader::with_capacity(BUFFER_CAPACITY, reader);
-    let mut reader: Box<dyn Read> = Box::new(reader);
-
-    // Grab previous decoder and wrap it inside of a new one
-    let chain_reader_decoder = |format: &CompressionFormat, decoder: Box<dyn Read>| -> crate::Result<Box<dyn Read>> {
-        let decoder: Box<dyn Read> = match format {
-            Gzip => Box::new(flate2::read::GzDecoder::new(decoder)),
-            Bzip => Box::new(bzip2::read::BzDecoder::new(decoder)),
-            Bzip3 => Box::new(bzip3::read::Bz3Decoder::new(decoder)?),
-            Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(decoder)),
-            Lzma => Box::new(xz2::read::XzDecoder::new(decoder)),
-            Snappy => Box::new(snap::read::FrameDecoder::new(decoder)),
-            Zstd => Box::new(zstd::stream::Decoder::new(decoder)?),
-            Tar | Zip | Rar | SevenZip => unreachable!(),
-        };
-        Ok(decoder)
+    let reader: Box<dyn Read + Send> = match options.rate_limit {
+        Some(bytes_per_second) => Box::new(Throttled::new(reader, bytes_per_second)),
+        None => reader,
     };
+    let reader: Box<dyn Read + Send> = match options.unpack_options.progress {
+        Some(progress) => Box::new(progress.reader(reader)),
+        None => reader,
+    };
+    let reader = BufReader::with_capacity(options.buffer_size, reader);
+    let mut reader: Box<dyn Read + Send> = Box::new(reader);
 
     let (first_extension, extensions) = split_first_compression_format(&options.formats);
 
-    for format in extensions.iter().rev() {
+    // The filter command takes the place of the outermost format, checked to be a compression format
+    let mut outer_formats = extensions.iter().rev();
+    if let Some(command) = options.filter_cmd {
+        reader = Box::new(filter::decoder(command, reader)?);
+        outer_formats.next();
+    }
+    let first_format_filtered = options.filter_cmd.is_some() && extensions.is_empty();
+
+    for format in outer_formats {
         reader = chain_reader_decoder(format, reader)?;
     }
 
-    let files_unpacked = match first_extension {
-        Gzip | Bzip | Bzip3 | Lz4 | Lzma | Snappy | Zstd => {
-            reader = chain_reader_decoder(&first_extension, reader)?;
+    // Where the entries of the archive were unpacked to, `None` for single file formats
+    let (files_unpacked, output_path, unpacked_root) = match first_extension {
+        Gzip | Bzip | Bzip3 | Lz4 | Lzma | LzmaAlone | Snappy | Zstd | Age | Lzw | Lzop | Lrzip | External(_) => {
+            let mut header = GzipHeader::default();
+            if first_format_filtered {
+                // Already decoded by the filter command
+            } else if first_extension == Gzip {
+                let decoder =
+                    flate2::bufread::MultiGzDecoder::new(BufReader::with_capacity(options.buffer_size, reader));
+                header = GzipHeader::from_decoder(&decoder);
+                reader = Box::new(decoder);
+            } else {
+                reader = chain_reader_decoder(&first_extension, reader)?;
+            }
+
+            // Stdin has no name to derive the output name from, so use the one of the original file
+            let output_file_path = match header.name.filter(|_| input_is_stdin) {
+                Some(name) => options.output_dir.join(name),
+                None => options.output_file_path.clone(),
+            };
 
-            let mut writer = match utils::ask_to_create_file(&options.output_file_path, options.question_policy)? {
+            let output_file_path = match options.on_conflict {
+                Some(on_conflict) => {
+                    let mtime = header
+                        .mtime
+                        .map(|mtime| UNIX_EPOCH + Duration::from_secs(mtime.unix_seconds().unsigned_abs()));
+                    match utils::resolve_conflict(&output_file_path, on_conflict, options.question_policy, mtime)? {
+                        Some(output_file_path) => output_file_path,
+                        None => return Ok(()),
+                    }
+                }
+                None => output_file_path,
+            };
+
+            let mut writer = match utils::ask_to_create_file(&output_file_path, options.question_policy)? {
                 Some(file) => file,
                 None => return Ok(()),
             };
 
-            io::copy(&mut reader, &mut writer)?;
+            copy_decoded(&mut reader, &mut writer, options.input_file_path, options.buffer_size)?;
+            drop(writer);
+
+            if let Some(mtime) = header.mtime {
+                set_file_mtime(&output_file_path, mtime)?;
+            }
 
-            1
+            (1, output_file_path, None)
         }
         Tar => {
-            if let ControlFlow::Continue(files) = smart_unpack(
-                |output_dir| crate::archive::tar::unpack_archive(reader, output_dir, options.quiet),
-                options.output_dir,
-                &options.output_file_path,
-                options.question_policy,
+            // Plain tar files and seekable zstd can be read at any position, so entries skipped by
+            // --transform or on conflicts aren't read, and aren't even looked at with an index.
+            // Progress counts the bytes taken out of the buffer of plain tar files, it's filled again
+            // after seeks, and the compressed bytes of seekable zstd
+            let with_progress = |reader: Box<dyn ReadSeek>| -> Box<dyn ReadSeek> {
+                match options.unpack_options.progress {
+                    Some(progress) => Box::new(progress.reader(reader)),
+                    None => reader,
+                }
+            };
+            let seekable: Option<Box<dyn ReadSeek>> = match extensions.as_slice() {
+                _ if !input_is_file || options.rate_limit.is_some() => None,
+                [] => Some(with_progress(Box::new(BufReader::with_capacity(
+                    options.buffer_size,
+                    JoinedParts::open(options.input_file_path)?,
+                )))),
+                [Zstd] => SeekableDecoder::new(with_progress(Box::new(JoinedParts::open(options.input_file_path)?)))?
+                    .map(|decoder| Box::new(decoder) as Box<dyn ReadSeek>),
+                _ => None,
+            };
+            // Files of plain tar files are copied straight from them, like the stored entries of zip
+            let source = match (extensions.as_slice(), options.unpack_options.progress) {
+                ([], None) if seekable.is_some() => Some(JoinedParts::open(options.input_file_path)?),
+                _ => None,
+            };
+            let archive_index = match seekable {
+                Some(_) if !options.unpack_options.transforms.is_empty() => index::read_index(options.input_file_path)?,
+                _ => None,
+            };
+
+            if let ControlFlow::Continue((files, unpacked_root)) = smart_unpack(
+                |output_dir| match (seekable, archive_index) {
+                    (Some(reader), Some(archive_index)) => crate::archive::tar::unpack_indexed_archive(
+                        reader,
+                        source.as_ref(),
+                        &archive_index,
+                        output_dir,
+                        options.unpack_options,
+                    ),
+                    (Some(reader), None) => crate::archive::tar::unpack_archive_with_seek(
+                        reader,
+                        source.as_ref(),
+                        output_dir,
+                        options.unpack_options,
+                    ),
+                    (None, _) => crate::archive::tar::unpack_archive(reader, output_dir, options.unpack_options),
+                },
+                &options,
             )? {
-                files
+                (files, unpacked_root.path.clone(), Some(unpacked_root))
             } else {
                 return Ok(());
             }
@@ -175,15 +579,11 @@ pub fn decompress_file(options: DecompressOptions) -> crate::Result<()> {
             io::copy(&mut reader, &mut vec)?;
             let zip_archive = zip::ZipArchive::new(io::Cursor::new(vec))?;
 
-            if let ControlFlow::Continue(files) = smart_unpack(
-                |output_dir| {
-                    crate::archive::zip::unpack_archive(zip_archive, output_dir, options.password, options.quiet)
-                },
-                options.output_dir,
-                &options.output_file_path,
-                options.question_policy,
+            if let ControlFlow::Continue((files, unpacked_root)) = smart_unpack(
+                |output_dir| crate::archive::zip::unpack_archive(zip_archive, None, output_dir, options.unpack_options),
+                &options,
             )? {
-                files
+                (files, unpacked_root.path.clone(), Some(unpacked_root))
             } else {
                 return Ok(());
             }
@@ -191,30 +591,25 @@ pub fn decompress_file(options: DecompressOptions) -> crate::Result<()> {
         #[cfg(feature = "unrar")]
         Rar => {
             type UnpackResult = crate::Result<usize>;
-            let unpack_fn: Box<dyn FnOnce(&Path) -> UnpackResult> = if options.formats.len() > 1 || input_is_stdin {
+            // The rar library reads a single file from the disk
+            let needs_temp_file = options.formats.len() > 1
+                || !input_is_file
+                || options.rate_limit.is_some()
+                || joined_path(options.input_file_path).is_some();
+            let unpack_fn: Box<dyn FnOnce(&Path) -> UnpackResult> = if needs_temp_file {
                 let mut temp_file = tempfile::NamedTempFile::new()?;
                 io::copy(&mut reader, &mut temp_file)?;
                 Box::new(move |output_dir| {
-                    crate::archive::rar::unpack_archive(temp_file.path(), output_dir, options.password, options.quiet)
+                    crate::archive::rar::unpack_archive(temp_file.path(), output_dir, options.unpack_options)
                 })
             } else {
                 Box::new(|output_dir| {
-                    crate::archive::rar::unpack_archive(
-                        options.input_file_path,
-                        output_dir,
-                        options.password,
-                        options.quiet,
-                    )
+                    crate::archive::rar::unpack_archive(options.input_file_path, output_dir, options.unpack_options)
                 })
             };
 
-            if let ControlFlow::Continue(files) = smart_unpack(
-                unpack_fn,
-                options.output_dir,
-                &options.output_file_path,
-                options.question_policy,
-            )? {
-                files
+            if let ControlFlow::Continue((files, unpacked_root)) = smart_unpack(unpack_fn, &options)? {
+                (files, unpacked_root.path.clone(), Some(unpacked_root))
             } else {
                 return Ok(());
             }
@@ -223,6 +618,7 @@ pub fn decompress_file(options: DecompressOptions) -> crate::Result<()> {
         Rar => {
             return Err(crate::archive::rar_stub::no_support());
         }
+        Squashfs => return Err(crate::archive::squashfs::no_support()),
         SevenZip => {
             if options.formats.len() > 1 {
                 // Locking necessary to guarantee that warning and question
@@ -242,20 +638,13 @@ pub fn decompress_file(options: DecompressOptions) -> crate::Result<()> {
             let mut vec = vec![];
             io::copy(&mut reader, &mut vec)?;
 
-            if let ControlFlow::Continue(files) = smart_unpack(
+            if let ControlFlow::Continue((files, unpacked_root)) = smart_unpack(
                 |output_dir| {
-                    crate::archive::sevenz::decompress_sevenz(
-                        io::Cursor::new(vec),
-                        output_dir,
-                        options.password,
-                        options.quiet,
-                    )
+                    crate::archive::sevenz::decompress_sevenz(io::Cursor::new(vec), output_dir, options.unpack_options)
                 },
-                options.output_dir,
-                &options.output_file_path,
-                options.question_policy,
+                &options,
             )? {
-                files
+                (files, unpacked_root.path.clone(), Some(unpacked_root))
             } else {
                 return Ok(());
             }
@@ -272,12 +661,67 @@ pub fn decompress_file(options: DecompressOptions) -> crate::Result<()> {
     ));
     info_accessible(format!("Files unpacked: {}", files_unpacked));
 
-    if !input_is_stdin && options.remove {
-        fs::remove_file(options.input_file_path)?;
-        info(format!(
-            "Removed input file {}",
-            nice_directory_display(options.input_file_path)
-        ));
+    if options.print0 {
+        print_unpacked_paths(&output_path)?;
+    }
+
+    if input_is_file && options.verify {
+        match &unpacked_root {
+            Some(unpacked_root) => verify_unpacked_files(&options, unpacked_root)?,
+            None => verify_unpacked_file(&options, &output_path)?,
+        }
+    }
+
+    if input_is_file && options.remove {
+        remove_input_file(options.input_file_path)?;
+    }
+
+    Ok(())
+}
+
+/// The `--transform` rules of `options` after the one stripping the common prefix of the entries,
+/// for `--strip-common-prefix`, or `None` if there's nothing to strip. The archive is listed to
+/// find the prefix, so it has to be a file
+fn common_prefix_transforms(options: &DecompressOptions, input_is_file: bool) -> crate::Result<Option<Vec<Transform>>> {
+    let is_archive = options.formats.first().is_some_and(Extension::is_archive);
+    if !options.strip_common_prefix || !is_archive {
+        return Ok(None);
+    }
+    if !input_is_file {
+        warning("Skipping --strip-common-prefix, the archive would have to be read twice".to_string());
+        return Ok(None);
+    }
+
+    let formats = flatten_compression_formats(&options.formats);
+    let entries = archive_entries(
+        options.input_file_path,
+        &formats,
+        options.unpack_options.password,
+        options.unpack_options.name_encoding,
+        true,
+    )?
+    .files
+    .collect::<crate::Result<Vec<_>>>()?;
+
+    let strip = common_prefix(entries.iter().map(|entry| (entry.path.as_path(), entry.is_dir)))
+        .and_then(|prefix| Transform::strip_prefix(&prefix));
+    Ok(strip.map(|strip| {
+        iter::once(strip)
+            .chain(options.unpack_options.transforms.iter().cloned())
+            .collect()
+    }))
+}
+
+/// Remove the input file after unpacking it, along with the parts that follow it if it was split
+fn remove_input_file(input_path: &Path) -> crate::Result<()> {
+    let paths = match joined_path(input_path) {
+        Some(joined) => split_part_paths(&joined),
+        None => vec![input_path.to_path_buf()],
+    };
+
+    for path in paths {
+        fs::remove_file(&path)?;
+        info(format!("Removed input file {}", nice_directory_display(&path)));
     }
 
     Ok(())
@@ -288,15 +732,38 @@ pub fn decompress_file(options: DecompressOptions) -> crate::Result<()> {
 /// - If the archive contains multiple files, it will be extracted to a subdirectory of the
 ///   output_dir named after the archive (given by `output_file_path`)
 ///
+/// The archive is unpacked into a temporary directory created inside of `temp_dir`, or of
+/// `output_dir` if not set. If the output already exists, it's replaced after asking, or with
+/// `on_conflict` merged with the unpacked files one by one.
+///
+/// With `in_place`, the archive is unpacked directly into `output_dir` instead, the unpackers
+/// resolve the files that already exist.
+///
+/// Returns the number of unpacked files and where the entries of the archive now are.
+///
 /// Note: This functions assumes that `output_dir` exists
 fn smart_unpack(
     unpack_fn: impl FnOnce(&Path) -> crate::Result<usize>,
-    output_dir: &Path,
-    output_file_path: &Path,
-    question_policy: QuestionPolicy,
-) -> crate::Result<ControlFlow<(), usize>> {
+    options: &DecompressOptions,
+) -> crate::Result<ControlFlow<(), (usize, UnpackedRoot)>> {
+    let output_dir = options.output_dir;
+    let question_policy = options.question_policy;
+    let on_conflict = options.on_conflict;
     assert!(output_dir.exists());
-    let temp_dir = tempfile::Builder::new().prefix(".tmp-ouch-").tempdir_in(output_dir)?;
+
+    if options.in_place {
+        let files = unpack_fn(output_dir)?;
+        return Ok(ControlFlow::Continue((
+            files,
+            UnpackedRoot {
+                path: output_dir.to_owned(),
+                single_entry: None,
+            },
+        )));
+    }
+    let temp_dir = tempfile::Builder::new()
+        .prefix(&utils::temp_dir_prefix())
+        .tempdir_in(options.temp_dir.unwrap_or(output_dir))?;
     let temp_dir_path = temp_dir.path();
 
     info_accessible(format!(
@@ -306,6 +773,28 @@ fn smart_unpack(
 
     let files = unpack_fn(temp_dir_path)?;
 
+    if options.strip_common_prefix {
+        // The directory wrapping the entries was stripped, so they go straight into `output_dir`
+        utils::merge_into(
+            temp_dir_path,
+            output_dir,
+            on_conflict.unwrap_or(ConflictPolicy::Ask),
+            question_policy,
+        )?;
+        info_accessible(format!(
+            "Successfully merged \"{}\" into \"{}\"",
+            nice_directory_display(temp_dir_path),
+            nice_directory_display(output_dir),
+        ));
+        return Ok(ControlFlow::Continue((
+            files,
+            UnpackedRoot {
+                path: output_dir.to_owned(),
+                single_entry: None,
+            },
+        )));
+    }
+
     let root_contains_only_one_element = fs::read_dir(temp_dir_path)?.count() == 1;
 
     let (previous_path, new_path) = if root_contains_only_one_element {
@@ -319,21 +808,262 @@ fn smart_unpack(
 
         (file_path, correct_path)
     } else {
-        (temp_dir_path.to_owned(), output_file_path.to_owned())
+        (temp_dir_path.to_owned(), options.output_file_path.clone())
     };
 
-    // Before moving, need to check if a file with the same name already exists
-    if !utils::clear_path(&new_path, question_policy)? {
-        return Ok(ControlFlow::Break(()));
+    let merge_policy = on_conflict.filter(|_| previous_path.is_dir() && new_path.is_dir());
+    let new_path = if let Some(on_conflict) = merge_policy {
+        // Existing directories keep the files that aren't in the archive
+        utils::merge_into(&previous_path, &new_path, on_conflict, question_policy)?;
+        info_accessible(format!(
+            "Successfully merged \"{}\" into \"{}\"",
+            nice_directory_display(&previous_path),
+            nice_directory_display(&new_path),
+        ));
+        new_path
+    } else {
+        let new_path = match on_conflict {
+            Some(on_conflict) => {
+                let mtime = fs::symlink_metadata(&previous_path)?.modified().ok();
+                match utils::resolve_conflict(&new_path, on_conflict, question_policy, mtime)? {
+                    Some(new_path) => new_path,
+                    None => return Ok(ControlFlow::Break(())),
+                }
+            }
+            // Before moving, need to check if a file with the same name already exists
+            None => {
+                if !utils::clear_path(&new_path, question_policy)? {
+                    return Ok(ControlFlow::Break(()));
+                }
+                new_path
+            }
+        };
+
+        // Rename the temporary directory to the archive name, which is output_file_path
+        utils::move_path(&previous_path, &new_path)?;
+        info_accessible(format!(
+            "Successfully moved \"{}\" to \"{}\"",
+            nice_directory_display(&previous_path),
+            nice_directory_display(&new_path),
+        ));
+
+        // What was moved is either the single directory at the root, or the root itself
+        let root_entry = match root_contains_only_one_element {
+            true => previous_path.file_name().map(Path::new),
+            false => Some(Path::new("")),
+        };
+        let root_metadata = root_entry.and_then(|path| options.unpack_options.root_entries?.get(path));
+        if let Some(metadata) = root_metadata.filter(|_| new_path.is_dir()) {
+            restore_dir_metadata(&new_path, metadata, options.unpack_options.permissions)?;
+        }
+        new_path
+    };
+
+    // The single entry keeps its contents, but may have been renamed because of a conflict
+    let single_entry = previous_path
+        .file_name()
+        .filter(|_| root_contains_only_one_element)
+        .map(OsStr::to_owned);
+    Ok(ControlFlow::Continue((
+        files,
+        UnpackedRoot {
+            path: new_path,
+            single_entry,
+        },
+    )))
+}
+
+/// Where the entries of an archive are once it's unpacked
+struct UnpackedRoot {
+    /// The file or directory that was moved into the output directory, or the output directory
+    /// itself when unpacking straight into it
+    path: PathBuf,
+    /// Name of the single entry at the root of the archive, which was unpacked as `path`, `None`
+    /// if `path` holds the root of the archive
+    single_entry: Option<OsString>,
+}
+
+impl UnpackedRoot {
+    /// Where the entry with the given path inside of the archive was unpacked to
+    fn entry_path(&self, entry: &Path) -> PathBuf {
+        let entry = entry.strip_prefix(".").unwrap_or(entry);
+        let Some(single_entry) = &self.single_entry else {
+            return self.path.join(entry);
+        };
+
+        match entry.strip_prefix(single_entry) {
+            // Joining an empty path would add a trailing separator, which fails for files
+            Ok(rest) if rest.as_os_str().is_empty() => self.path.clone(),
+            Ok(rest) => self.path.join(rest),
+            Err(_) => self.path.join(entry),
+        }
     }
+}
 
-    // Rename the temporary directory to the archive name, which is output_file_path
-    fs::rename(&previous_path, &new_path)?;
-    info_accessible(format!(
-        "Successfully moved \"{}\" to \"{}\"",
-        nice_directory_display(&previous_path),
-        nice_directory_display(&new_path),
+/// Set the modification time and mode stored in the archive on the directory at `path`, following
+/// the `permissions` policy
+fn restore_dir_metadata(path: &Path, metadata: DirMetadata, permissions: PermissionsPolicy) -> crate::Result<()> {
+    if let Some(mtime) = metadata.mtime {
+        set_file_mtime(path, FileTime::from_system_time(mtime))?;
+    }
+
+    #[cfg(unix)]
+    if let Some(mode) = metadata.mode {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Zip archives store the file type in the upper bits
+        let mode = mode & 0o7777 & !permissions.mask();
+        fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    #[cfg(not(unix))]
+    let _ = permissions;
+
+    Ok(())
+}
+
+/// Print `path` and, if it's a directory, every path under it, each followed by a NUL character
+/// instead of a newline, so they can be read by `xargs -0` whatever characters they contain
+fn print_unpacked_paths(path: &Path) -> crate::Result<()> {
+    let out = &mut io::stdout().lock();
+
+    for entry in ignore::WalkBuilder::new(path).standard_filters(false).build() {
+        let entry = entry.map_err(io::Error::other)?;
+        out.write_all(entry.path().as_os_str().as_encoded_bytes())?;
+        out.write_all(b"\0")?;
+    }
+
+    Ok(())
+}
+
+/// Re-read the archive and check that the files unpacked into `unpacked_root` match its entries
+///
+/// Catches files that were truncated or changed after being written, like when the disk fills up
+/// or another program interferes with the output.
+fn verify_unpacked_files(options: &DecompressOptions, unpacked_root: &UnpackedRoot) -> crate::Result<()> {
+    let formats = flatten_compression_formats(&options.formats);
+    let entries = summarize_archive(
+        options.input_file_path,
+        &formats,
+        options.unpack_options.password,
+        options.unpack_options.name_encoding,
+    )?;
+
+    let mismatches: Vec<String> = entries
+        .iter()
+        .filter_map(|entry| {
+            // Entries skipped by `--transform` weren't unpacked
+            let path = transform_path(&entry.path, options.unpack_options.transforms)?;
+            let path = normalize_path(&path, options.unpack_options.normalization);
+            #[cfg(windows)]
+            let path = utils::windows_compatible_path(&path).into_owned();
+
+            let problem = match check_unpacked_entry(&unpacked_root.entry_path(&path), entry) {
+                Ok(problem) => problem?,
+                Err(err) => err.to_string(),
+            };
+            Some(format!("{}: {problem}", EscapedPathDisplay::new(&entry.path)))
+        })
+        .collect();
+
+    if !mismatches.is_empty() {
+        return Err(verification_error(options, mismatches));
+    }
+
+    info_accessible(format!("Verified {} entries against the archive", entries.len()));
+
+    Ok(())
+}
+
+/// Decode the input of a single file format again and check that the file decompressed to `path`
+/// matches it
+fn verify_unpacked_file(options: &DecompressOptions, path: &Path) -> crate::Result<()> {
+    let mut reader: Box<dyn Read + Send> = Box::new(BufReader::with_capacity(
+        options.buffer_size,
+        JoinedParts::open(options.input_file_path)?,
+    ));
+
+    // Same order as when decompressing, outermost first
+    let formats = flatten_compression_formats(&options.formats);
+    let mut formats = formats.iter().rev();
+    if let Some(command) = options.filter_cmd {
+        reader = Box::new(filter::decoder(command, reader)?);
+        formats.next();
+    }
+    for format in formats {
+        reader = chain_reader_decoder(format, reader)?;
+    }
+
+    let (size, crc32) = crc32_from_reader(&mut reader)?;
+    let entry = EntrySummary {
+        path: path.to_owned(),
+        is_dir: false,
+        size,
+        crc32,
+    };
+    let problem = match check_unpacked_entry(path, &entry) {
+        Ok(problem) => problem,
+        Err(err) => Some(err.to_string()),
+    };
+    if let Some(problem) = problem {
+        return Err(verification_error(
+            options,
+            vec![format!("{}: {problem}", EscapedPathDisplay::new(path))],
+        ));
+    }
+
+    info_accessible(format!("Verified {} against the input", nice_directory_display(path)));
+
+    Ok(())
+}
+
+/// Error for the unpacked files that don't match the input, each mismatch being a detail
+fn verification_error(options: &DecompressOptions, mismatches: Vec<String>) -> Error {
+    let error = FinalError::with_title(format!(
+        "Verification of {} failed",
+        EscapedPathDisplay::new(options.input_file_path)
     ));
+    let error = mismatches
+        .into_iter()
+        .fold(error, |error, mismatch| error.detail(mismatch));
+
+    error
+        .hint("The unpacked files don't match the archive.")
+        .hint("Check if the disk is full, or if another program (like an antivirus) changed them.")
+        .class(ErrorClass::Io)
+        .into()
+}
+
+/// Compare a single unpacked entry against the archive, returning a description of the mismatch
+fn check_unpacked_entry(path: &Path, entry: &EntrySummary) -> io::Result<Option<String>> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Some("missing".to_string())),
+        Err(err) => return Err(err),
+    };
+
+    // Symlinks don't carry contents to check
+    if metadata.is_symlink() {
+        return Ok(None);
+    }
+
+    if entry.is_dir != metadata.is_dir() {
+        let expected = if entry.is_dir { "directory" } else { "file" };
+        return Ok(Some(format!("expected a {expected}")));
+    }
+
+    if entry.is_dir {
+        return Ok(None);
+    }
+
+    let (size, crc32) = crc32_from_reader(&mut fs::File::open(path)?)?;
+
+    let problem = if size != entry.size {
+        Some(format!("size is {size} bytes, expected {}", entry.size))
+    } else if crc32 != entry.crc32 {
+        Some(format!("CRC32 is {crc32:08x}, expected {:08x}", entry.crc32))
+    } else {
+        None
+    };
 
-    Ok(ControlFlow::Continue(files))
+    Ok(problem)
 }
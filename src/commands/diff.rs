@@ -0,0 +1,209 @@
+//! Compare the contents of two archives, or of an archive and a directory.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, stdout, BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use encoding_rs::Encoding;
+use fs_err as fs;
+
+use crate::{
+    archive::{self, EntrySummary},
+    commands::decompress::chain_reader_decoder,
+    extension::CompressionFormat::{self, *},
+    utils::{checksum::crc32_from_reader, colors::*, io::JoinedParts, EscapedPathDisplay, FileVisibilityPolicy},
+    BUFFER_CAPACITY,
+};
+
+/// One side of the comparison
+pub enum DiffSource {
+    /// An archive and its formats, example: [Tar, Gzip] (in decompression order)
+    Archive(PathBuf, Vec<CompressionFormat>),
+    /// A directory in the filesystem, compared as if it was compressed by `ouch`
+    Directory(PathBuf),
+}
+
+/// How an entry changed from the old source to the new one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// Compare `old` and `new` entry by entry and print every path that was added, removed or modified.
+///
+/// Entries are considered modified if their type, size or CRC32 differ.
+pub fn diff_sources(
+    old: &DiffSource,
+    new: &DiffSource,
+    json: bool,
+    file_visibility_policy: &FileVisibilityPolicy,
+    [old_password, new_password]: [Option<&[u8]>; 2],
+) -> crate::Result<()> {
+    let old_entries = summarize_source(old, file_visibility_policy, old_password)?;
+    let new_entries = summarize_source(new, file_visibility_policy, new_password)?;
+
+    let old_entries = index_by_path(&old_entries);
+    let new_entries = index_by_path(&new_entries);
+
+    let all_paths: BTreeSet<&Path> = old_entries.keys().chain(new_entries.keys()).copied().collect();
+
+    let changes: Vec<(Change, &Path)> = all_paths
+        .into_iter()
+        .filter_map(|path| {
+            let change = match (old_entries.get(path), new_entries.get(path)) {
+                (Some(_), None) => Change::Removed,
+                (None, Some(_)) => Change::Added,
+                (Some(old), Some(new)) if old != new => Change::Modified,
+                _ => return None,
+            };
+            Some((change, path))
+        })
+        .collect();
+
+    if json {
+        let paths_with = |change: Change| -> Vec<String> {
+            changes
+                .iter()
+                .filter(|(c, _)| *c == change)
+                .map(|(_, path)| path.to_string_lossy().into_owned())
+                .collect()
+        };
+
+        let output = serde_json::json!({
+            "added": paths_with(Change::Added),
+            "removed": paths_with(Change::Removed),
+            "modified": paths_with(Change::Modified),
+        });
+        println!("{output}");
+
+        return Ok(());
+    }
+
+    let out = &mut stdout().lock();
+    for (change, path) in &changes {
+        let (color, symbol) = match change {
+            Change::Added => (*GREEN, '+'),
+            Change::Removed => (*RED, '-'),
+            Change::Modified => (*YELLOW, '~'),
+        };
+        let _ = writeln!(out, "{color}{symbol}{} {}", *RESET, EscapedPathDisplay::new(path));
+    }
+
+    let count = |change: Change| changes.iter().filter(|(c, _)| *c == change).count();
+    let _ = writeln!(
+        out,
+        "{} added, {} removed, {} modified",
+        count(Change::Added),
+        count(Change::Removed),
+        count(Change::Modified),
+    );
+
+    Ok(())
+}
+
+pub fn index_by_path(entries: &[EntrySummary]) -> BTreeMap<&Path, &EntrySummary> {
+    entries.iter().map(|entry| (entry.path.as_path(), entry)).collect()
+}
+
+fn summarize_source(
+    source: &DiffSource,
+    file_visibility_policy: &FileVisibilityPolicy,
+    password: Option<&[u8]>,
+) -> crate::Result<Vec<EntrySummary>> {
+    match source {
+        DiffSource::Archive(path, formats) => summarize_archive(path, formats, password, None),
+        DiffSource::Directory(path) => summarize_directory(path, file_visibility_policy),
+    }
+}
+
+/// Summarize the files under `dir`, named relative to its parent like in archives built by `ouch`
+pub fn summarize_directory(
+    dir: &Path,
+    file_visibility_policy: &FileVisibilityPolicy,
+) -> crate::Result<Vec<EntrySummary>> {
+    let base = dir.parent().unwrap_or(dir);
+    let mut summaries = vec![];
+
+    for entry in file_visibility_policy.build_walker(dir) {
+        let entry = entry?;
+        let path = entry.path();
+        let is_dir = path.is_dir();
+
+        let (size, crc32) = if is_dir {
+            (0, 0)
+        } else {
+            match fs::File::open(path) {
+                Ok(mut file) => crc32_from_reader(&mut file)?,
+                // This path is for a broken symlink, ignore it
+                Err(e) if e.kind() == io::ErrorKind::NotFound && path.is_symlink() => continue,
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        summaries.push(EntrySummary {
+            path: path.strip_prefix(base).unwrap_or(path).to_owned(),
+            is_dir,
+            size,
+            crc32,
+        });
+    }
+
+    Ok(summaries)
+}
+
+pub fn summarize_archive(
+    archive_path: &Path,
+    formats: &[CompressionFormat],
+    password: Option<&[u8]>,
+    name_encoding: Option<&'static Encoding>,
+) -> crate::Result<Vec<EntrySummary>> {
+    let reader = JoinedParts::open(archive_path)?;
+
+    // Zip and 7z need io::Seek, we can only read them directly if they're not chained
+    match formats {
+        [Zip] => return archive::zip::summarize_archive(zip::ZipArchive::new(reader)?, name_encoding),
+        [SevenZip] => return archive::sevenz::summarize_archive(reader, password),
+        _ => {}
+    }
+
+    let reader = BufReader::with_capacity(BUFFER_CAPACITY, reader);
+    let mut reader: Box<dyn Read + Send> = Box::new(reader);
+
+    for format in formats.iter().skip(1).rev() {
+        reader = chain_reader_decoder(format, reader)?;
+    }
+
+    match formats[0] {
+        Tar => archive::tar::summarize_archive(tar::Archive::new(reader)),
+        Zip => {
+            let mut vec = vec![];
+            io::copy(&mut reader, &mut vec)?;
+            archive::zip::summarize_archive(zip::ZipArchive::new(io::Cursor::new(vec))?, name_encoding)
+        }
+        SevenZip => {
+            let mut vec = vec![];
+            io::copy(&mut reader, &mut vec)?;
+            archive::sevenz::summarize_archive(io::Cursor::new(vec), password)
+        }
+        #[cfg(feature = "unrar")]
+        Rar => {
+            if formats.len() > 1 {
+                let mut temp_file = tempfile::NamedTempFile::new()?;
+                io::copy(&mut reader, &mut temp_file)?;
+                archive::rar::summarize_archive(temp_file.path(), password)
+            } else {
+                archive::rar::summarize_archive(archive_path, password)
+            }
+        }
+        #[cfg(not(feature = "unrar"))]
+        Rar => Err(archive::rar_stub::no_support()),
+        Squashfs => Err(archive::squashfs::no_support()),
+        Gzip | Bzip | Bzip3 | Lz4 | Lzma | LzmaAlone | Snappy | Zstd | Age | Lzw | Lzop | Lrzip | External(_) => {
+            unreachable!("non-archive formats are rejected by `check_for_non_archive_formats`")
+        }
+    }
+}
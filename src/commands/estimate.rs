@@ -0,0 +1,191 @@
+//! Projection of `--estimate`, compressing a sample of the input with the same encoders into a
+//! sink that only counts the bytes, then scaling its size and duration to the whole input.
+//!
+//! The sample ends at the first entry of archives past [`SAMPLE_SIZE`], or right there in the input
+//! of single file formats. Zip and 7z archives are built in memory and only written at the end, so
+//! all of their input is compressed.
+
+use std::{
+    io::{self, stdout, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    archive::{
+        manifest::{ArchiveObserver, StoredEntry},
+        walk::input_size,
+    },
+    commands::compress::{compress_files, CodecOptions, CompressOptions},
+    error::FinalError,
+    extension::{CompressionFormat::*, Extension},
+    options::{NormalizationForm, SpecialFiles},
+    utils::{Bytes, EscapedPathDisplay, FileVisibilityPolicy},
+    QuestionPolicy,
+};
+
+/// Bytes of input compressed to make the projection
+const SAMPLE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Compress a sample of `files` like [`compress_files`] would into `output_path`, and print the
+/// projected size of the output and how long compressing all of `files` takes
+#[allow(clippy::too_many_arguments)]
+pub fn estimate_compression(
+    files: Vec<PathBuf>,
+    formats: Vec<Extension>,
+    output_path: &Path,
+    file_visibility_policy: FileVisibilityPolicy,
+    codec_options: &CodecOptions,
+    name_normalization: NormalizationForm,
+    special_files: SpecialFiles,
+    buffer_size: usize,
+    encryption_recipients: &[String],
+) -> crate::Result<()> {
+    let input_size = input_size(&files, &file_visibility_policy);
+
+    let in_memory = matches!(formats[0].compression_formats[0], Zip | SevenZip);
+    let mut sampler = Sampler {
+        limit: if in_memory { u64::MAX } else { SAMPLE_SIZE },
+        sampled: 0,
+        done: false,
+    };
+    let compressed = Arc::new(AtomicU64::new(0));
+
+    let start = Instant::now();
+    let result = compress_files(
+        files,
+        formats,
+        Box::new(CountingSink(compressed.clone())),
+        CompressOptions {
+            output_path,
+            quiet: true,
+            question_policy: QuestionPolicy::ALWAYS_YES,
+            file_visibility_policy,
+            codec_options,
+            reproducible: None,
+            comment: None,
+            name_normalization,
+            special_files,
+            dedup: false,
+            rate_limit: None,
+            buffer_size,
+            encryption_recipients,
+            index: None,
+            observer: Some(&mut sampler),
+        },
+    );
+    // Stopping at the end of the sample fails on purpose, the encoders were finished when dropped
+    if let Err(err) = result {
+        if !sampler.done {
+            return Err(err);
+        }
+    }
+    let elapsed = start.elapsed();
+
+    let compressed = compressed.load(Ordering::Relaxed);
+    let scale = if sampler.sampled == 0 {
+        1.0
+    } else {
+        input_size.max(sampler.sampled) as f64 / sampler.sampled as f64
+    };
+    let projected_size = (compressed as f64 * scale) as u64;
+    let projected_time = elapsed.mul_f64(scale);
+
+    let out = &mut stdout().lock();
+    let _ = writeln!(out, "{}", EscapedPathDisplay::new(output_path));
+    let _ = writeln!(
+        out,
+        "  Input: {}, sampled {}",
+        Bytes::new(input_size),
+        Bytes::new(sampler.sampled.min(input_size))
+    );
+    let ratio = if input_size == 0 {
+        String::new()
+    } else {
+        format!(
+            ", {:.1}% of the input",
+            projected_size as f64 * 100.0 / input_size as f64
+        )
+    };
+    let _ = writeln!(out, "  Estimated size: {}{ratio}", Bytes::new(projected_size));
+    let _ = writeln!(out, "  Estimated time: {}", format_duration(projected_time));
+    Ok(())
+}
+
+/// Duration like "1h 02m 03s", or "1.5s" under a minute
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..=59 => format!("{:.1}s", duration.as_secs_f64()),
+        60..=3599 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {:02}m {:02}s", seconds / 3600, seconds / 60 % 60, seconds % 60),
+    }
+}
+
+/// Counts the bytes of input compressed, and stops compressing once there are `limit` of them
+struct Sampler {
+    limit: u64,
+    sampled: u64,
+    /// The sample was taken, the error that stopped compressing is expected
+    done: bool,
+}
+
+impl Sampler {
+    fn add(&mut self, bytes: u64) -> bool {
+        self.sampled += bytes;
+        self.done = self.sampled >= self.limit;
+        !self.done
+    }
+}
+
+impl ArchiveObserver for Sampler {
+    fn entry_stored(&mut self, entry: StoredEntry) -> crate::Result<()> {
+        if self.add(entry.size) {
+            Ok(())
+        } else {
+            Err(FinalError::with_title("The sample was taken").into())
+        }
+    }
+
+    fn bytes_compressed(&mut self, bytes: u64) -> io::Result<()> {
+        if self.add(bytes) {
+            Ok(())
+        } else {
+            Err(io::Error::other("the sample was taken"))
+        }
+    }
+
+    fn wants_content_hash(&self) -> bool {
+        false
+    }
+}
+
+/// Discards what is written to it, counting the bytes
+struct CountingSink(Arc<AtomicU64>);
+
+impl Write for CountingSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.fetch_add(buf.len() as u64, Ordering::Relaxed);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1.5s");
+        assert_eq!(format_duration(Duration::from_secs(62)), "1m 02s");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1h 02m 03s");
+    }
+}
@@ -0,0 +1,128 @@
+//! Report of `--explain`, printing what was decided for each input before compressing or
+//! decompressing it, like the formats, where the output goes and the compression levels.
+//!
+//! It's printed to stderr, so it doesn't mix with the paths printed by `--print0`.
+
+use std::{
+    io::{stderr, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    commands::info::{display_formats, format_name},
+    extension::{
+        flatten_compression_formats,
+        CompressionFormat::{self, *},
+        Extension,
+    },
+    utils::{nice_directory_display, pretty_format_list_of_paths, EscapedPathDisplay},
+};
+
+/// Print the plan of compressing `files` into `output_path`, with `level` given by `--level`,
+/// `--fast` or `--slow`
+pub fn explain_compression(files: &[PathBuf], output_path: &Path, formats: &[Extension], level: Option<i16>) {
+    let levels: Vec<String> = flatten_compression_formats(formats)
+        .into_iter()
+        .filter_map(|format| Some(format!("{} {}", format_name(format), level_of(format, level)?)))
+        .collect();
+
+    let mut lines = vec![
+        ("Inputs", pretty_format_list_of_paths(files)),
+        ("Formats", display_formats(formats)),
+    ];
+    if !levels.is_empty() {
+        lines.push(("Levels", levels.join(", ")));
+    }
+    lines.push(("Threads", rayon::current_num_threads().to_string()));
+
+    print_lines(output_path, &lines);
+}
+
+/// Print the plan of decompressing `input` with `formats` into `output_dir`
+///
+/// Archives are unpacked into a temporary directory in `temp_dir`, or `output_dir` if it's not
+/// set, then moved to `output_path` unless there's a single entry at their root, which is moved
+/// into `output_dir` instead. Other files are decompressed to `output_path`.
+pub fn explain_decompression(
+    input: &Path,
+    formats: &[Extension],
+    output_dir: &Path,
+    output_path: &Path,
+    temp_dir: Option<&Path>,
+    in_place: bool,
+) {
+    let mut lines = vec![("Formats", display_formats(formats))];
+
+    let is_archive = formats.first().is_some_and(Extension::is_archive);
+    match (is_archive, in_place) {
+        (true, true) => lines.push((
+            "Output",
+            format!("unpacked directly into {}", nice_directory_display(output_dir)),
+        )),
+        (true, false) => {
+            lines.push((
+                "Output",
+                format!(
+                    "'{}', or {} if the archive has a single entry at its root",
+                    EscapedPathDisplay::new(output_path),
+                    nice_directory_display(output_dir),
+                ),
+            ));
+            lines.push((
+                "Temporary directory",
+                format!("inside of {}", nice_directory_display(temp_dir.unwrap_or(output_dir))),
+            ));
+        }
+        (false, _) => lines.push(("Output", format!("'{}'", EscapedPathDisplay::new(output_path)))),
+    }
+    lines.push(("Threads", rayon::current_num_threads().to_string()));
+
+    print_lines(input, &lines);
+}
+
+/// Level `format` compresses at, with `level` clamped to its range like the encoders of
+/// [`compress_files`](super::compress::compress_files) do, or `None` if it has no levels
+fn level_of(format: CompressionFormat, level: Option<i16>) -> Option<String> {
+    let Some(level) = level else {
+        let has_levels = matches!(format, Gzip | Bzip | Lzma | LzmaAlone | Snappy | Zstd | External(_));
+        return has_levels.then(|| "default".to_string());
+    };
+
+    let level = match format {
+        Gzip | Lzma | LzmaAlone | Snappy => (level as u32).clamp(0, 9).to_string(),
+        Bzip => (level as u32).clamp(1, 9).to_string(),
+        Zstd => (level as i32)
+            .clamp(zstd::zstd_safe::min_c_level(), zstd::zstd_safe::max_c_level())
+            .to_string(),
+        // Registered codecs handle the level themselves
+        External(_) => level.to_string(),
+        Bzip3 | Lz4 | Age | Lzw | Lzop | Lrzip | Tar | Zip | Rar | SevenZip | Squashfs => return None,
+    };
+    Some(level)
+}
+
+fn print_lines(path: &Path, lines: &[(&str, String)]) {
+    let err = &mut stderr().lock();
+    let _ = writeln!(err, "{}", EscapedPathDisplay::new(path));
+    for (label, value) in lines {
+        let _ = writeln!(err, "  {label}: {value}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_of() {
+        assert_eq!(level_of(Zstd, None).as_deref(), Some("default"));
+        assert_eq!(
+            level_of(Zstd, Some(i16::MAX)),
+            Some(zstd::zstd_safe::max_c_level().to_string())
+        );
+        assert_eq!(level_of(Gzip, Some(1)).as_deref(), Some("1"));
+        assert_eq!(level_of(Bzip, Some(0)).as_deref(), Some("1"));
+        assert_eq!(level_of(Tar, Some(5)), None);
+        assert_eq!(level_of(Lz4, None), None);
+    }
+}
@@ -0,0 +1,24 @@
+//! Generation of shell completions and the man page, for packagers and users to install.
+
+use std::io::{self, Write};
+
+use clap::CommandFactory;
+use clap_complete::Shell;
+use clap_mangen::Man;
+
+use crate::cli::CliArgs;
+
+/// Print the completions for `shell` to stdout
+pub fn print_completions(shell: Shell) -> crate::Result<()> {
+    // Generated in memory first, clap_complete panics if writing fails (like when the pipe is closed)
+    let mut completions = Vec::new();
+    clap_complete::generate(shell, &mut CliArgs::command(), "ouch", &mut completions);
+    io::stdout().write_all(&completions)?;
+    Ok(())
+}
+
+/// Print the man page to stdout
+pub fn print_manpage() -> crate::Result<()> {
+    Man::new(CliArgs::command()).render(&mut io::stdout())?;
+    Ok(())
+}
@@ -0,0 +1,413 @@
+//! Metadata of archives and compressed files, without listing their entries.
+//!
+//! The headers of each compression layer are read as the file is decoded, while the entries
+//! of archives are counted by the `archive_info` functions of each archive format.
+
+use std::{
+    io::{self, stdout, BufRead, BufReader, Read, Write},
+    path::Path,
+};
+
+use fs_err as fs;
+
+use crate::{
+    archive::{self, ArchiveInfo},
+    codec,
+    commands::decompress::chain_reader_decoder,
+    encryption,
+    extension::{
+        flatten_compression_formats,
+        CompressionFormat::{self, *},
+        Extension,
+    },
+    lrzip, lz4, lzop, lzw,
+    utils::{Bytes, EscapedPathDisplay},
+    BUFFER_CAPACITY,
+};
+
+/// Print the formats of the file at `path`, the parameters found in the headers of its
+/// compression layers and, for archives, a summary of the entries
+pub fn print_info(path: &Path, formats: &[Extension], password: Option<&[u8]>) -> crate::Result<()> {
+    let file = fs::File::open(path)?;
+    let mut lines = vec![
+        ("Formats", display_formats(formats)),
+        ("Size", Bytes::new(file.metadata()?.len()).to_string()),
+    ];
+
+    let formats = flatten_compression_formats(formats);
+    let (archive_format, layers) = match formats[0] {
+        Tar | Zip | Rar | SevenZip | Squashfs => (Some(formats[0]), &formats[1..]),
+        _ => (None, &formats[..]),
+    };
+
+    let mut reader: Box<dyn Read + Send> = Box::new(file);
+    let mut encrypted_layer = false;
+
+    for format in layers.iter().rev() {
+        let mut input = BufReader::with_capacity(BUFFER_CAPACITY, reader);
+        let details = describe_stream(*format, input.fill_buf()?);
+        if !details.is_empty() {
+            lines.push((format_name(*format), details.join(", ")));
+        }
+
+        if *format == Age {
+            encrypted_layer = true;
+            if !encryption::has_identity() {
+                lines.push(("Contents", "encrypted, pass '--decrypt' to inspect them".into()));
+                print_lines(path, &lines);
+                return Ok(());
+            }
+        }
+
+        if *format == Lrzip {
+            lines.push(("Contents", "unknown, lrzip files can't be decompressed".into()));
+            print_lines(path, &lines);
+            return Ok(());
+        }
+
+        reader = chain_reader_decoder(format, Box::new(input))?;
+    }
+
+    let Some(archive_format) = archive_format else {
+        let unpacked_size = io::copy(&mut reader, &mut io::sink())?;
+        lines.push(("Unpacked size", Bytes::new(unpacked_size).to_string()));
+        lines.push(("Encrypted", yes_or_no(encrypted_layer).into()));
+        print_lines(path, &lines);
+        return Ok(());
+    };
+
+    let info = archive_info(path, archive_format, reader, layers.is_empty(), password)?;
+
+    lines.push(("Files", info.files.to_string()));
+    lines.push(("Directories", info.directories.to_string()));
+    lines.push(("Unpacked size", Bytes::new(info.unpacked_size).to_string()));
+    lines.push((
+        "Encrypted",
+        yes_or_no(encrypted_layer || info.encrypted_entries > 0).into(),
+    ));
+    if info.encrypted_entries > 0 {
+        lines.push(("Encrypted entries", info.encrypted_entries.to_string()));
+    }
+    lines.extend(info.details);
+
+    print_lines(path, &lines);
+    Ok(())
+}
+
+/// Summarize the entries of the archive in `reader`, which is the file at `path` itself if `is_file`
+fn archive_info(
+    path: &Path,
+    format: CompressionFormat,
+    mut reader: Box<dyn Read + Send>,
+    is_file: bool,
+    password: Option<&[u8]>,
+) -> crate::Result<ArchiveInfo> {
+    // Zip and 7z need io::Seek, so they're read again from the file when they aren't chained
+    match format {
+        Tar => archive::tar::archive_info(tar::Archive::new(reader)),
+        Zip if is_file => archive::zip::archive_info(zip::ZipArchive::new(fs::File::open(path)?)?),
+        Zip => {
+            let mut vec = vec![];
+            io::copy(&mut reader, &mut vec)?;
+            archive::zip::archive_info(zip::ZipArchive::new(io::Cursor::new(vec))?)
+        }
+        SevenZip if is_file => archive::sevenz::archive_info(fs::File::open(path)?, password),
+        SevenZip => {
+            let mut vec = vec![];
+            io::copy(&mut reader, &mut vec)?;
+            archive::sevenz::archive_info(io::Cursor::new(vec), password)
+        }
+        #[cfg(feature = "unrar")]
+        Rar if is_file => archive::rar::archive_info(path, password),
+        #[cfg(feature = "unrar")]
+        Rar => {
+            let mut temp_file = tempfile::NamedTempFile::new()?;
+            io::copy(&mut reader, &mut temp_file)?;
+            archive::rar::archive_info(temp_file.path(), password)
+        }
+        #[cfg(not(feature = "unrar"))]
+        Rar => Err(archive::rar_stub::no_support()),
+        Squashfs => Err(archive::squashfs::no_support()),
+        Gzip | Bzip | Bzip3 | Lz4 | Lzma | LzmaAlone | Snappy | Zstd | Age | Lzw | Lzop | Lrzip | External(_) => {
+            unreachable!("not an archive format")
+        }
+    }
+}
+
+fn print_lines(path: &Path, lines: &[(&str, String)]) {
+    let out = &mut stdout().lock();
+    let _ = writeln!(out, "{}", EscapedPathDisplay::new(path));
+    for (label, value) in lines {
+        let _ = writeln!(out, "  {label}: {value}");
+    }
+}
+
+pub fn display_formats(formats: &[Extension]) -> String {
+    formats.iter().map(ToString::to_string).collect::<Vec<_>>().join(".")
+}
+
+fn yes_or_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+pub fn format_name(format: CompressionFormat) -> &'static str {
+    match format {
+        Gzip => "gzip",
+        Bzip => "bzip2",
+        Bzip3 => "bzip3",
+        Lz4 => "lz4",
+        Lzma => "xz",
+        LzmaAlone => "lzma",
+        Snappy => "snappy",
+        Zstd => "zstd",
+        Age => "age",
+        Tar => "tar",
+        Zip => "zip",
+        Rar => "rar",
+        SevenZip => "7z",
+        Squashfs => "squashfs",
+        Lzw => "compress",
+        Lzop => "lzop",
+        Lrzip => "lrzip",
+        External(id) => codec::get(id).name(),
+    }
+}
+
+/// Parameters found in `header`, the start of a `format` stream, it may be too short to have all of them
+fn describe_stream(format: CompressionFormat, header: &[u8]) -> Vec<String> {
+    let details = match format {
+        Gzip => describe_gzip(header),
+        Bzip => describe_bzip2(header),
+        Bzip3 => describe_bzip3(header),
+        Lz4 => describe_lz4(header),
+        Lzma => describe_xz(header),
+        Zstd => describe_zstd(header),
+        Age => describe_age(header),
+        Lzw => describe_lzw(header),
+        Lzop => describe_lzop(header),
+        Lrzip => describe_lrzip(header),
+        LzmaAlone | Snappy | Tar | Zip | Rar | SevenZip | Squashfs | External(_) => Some(vec![]),
+    };
+
+    details.unwrap_or_else(|| vec!["unrecognized header".to_string()])
+}
+
+fn describe_gzip(header: &[u8]) -> Option<Vec<String>> {
+    let extra_flags = *header.get(8)?;
+    let decoder = flate2::bufread::GzDecoder::new(header);
+    let header = decoder.header()?;
+    let mut details = vec![];
+
+    match extra_flags {
+        2 => details.push("maximum compression".to_string()),
+        4 => details.push("fastest compression".to_string()),
+        _ => {}
+    }
+    if let Some(name) = header.filename() {
+        details.push(format!("original name \"{}\"", String::from_utf8_lossy(name)));
+    }
+    if header.mtime() != 0 {
+        details.push(format!("modified at {} (Unix time)", header.mtime()));
+    }
+    if let Some(comment) = header.comment() {
+        details.push(format!("comment \"{}\"", String::from_utf8_lossy(comment)));
+    }
+
+    Some(details)
+}
+
+fn describe_bzip2(header: &[u8]) -> Option<Vec<String>> {
+    let level = match header {
+        [b'B', b'Z', b'h', level @ b'1'..=b'9', ..] => u64::from(level - b'0'),
+        _ => return None,
+    };
+    Some(vec![format!("block size {}", Bytes::new(level * 100_000))])
+}
+
+fn describe_bzip3(header: &[u8]) -> Option<Vec<String>> {
+    let block_size = header.strip_prefix(b"BZ3v1")?.get(..4)?;
+    let block_size = u32::from_le_bytes(block_size.try_into().ok()?);
+    Some(vec![format!("block size {}", Bytes::new(block_size.into()))])
+}
+
+fn describe_lz4(header: &[u8]) -> Option<Vec<String>> {
+    if header.starts_with(&lz4::LEGACY_MAGIC) {
+        return Some(vec!["legacy format".to_string()]);
+    }
+    let descriptor = header.strip_prefix(&lz4::MAGIC)?;
+    let [flags, block_descriptor] = *descriptor.get(..2)? else {
+        return None;
+    };
+    let block_size = match (block_descriptor >> 4) & 0b111 {
+        4 => 64 * 1024,
+        5 => 256 * 1024,
+        6 => 1024 * 1024,
+        7 => 4 * 1024 * 1024,
+        _ => return None,
+    };
+
+    let mut details = vec![format!("block size {}", Bytes::new(block_size))];
+    if flags & 0b100 != 0 {
+        details.push("content checksum".to_string());
+    }
+    if flags & 0b1_0000 != 0 {
+        details.push("block checksums".to_string());
+    }
+    if flags & 0b1000 != 0 {
+        let content_size = u64::from_le_bytes(descriptor.get(2..10)?.try_into().unwrap());
+        details.push(format!("content size {}", Bytes::new(content_size)));
+    }
+    Some(details)
+}
+
+fn describe_xz(header: &[u8]) -> Option<Vec<String>> {
+    let check = match *header.strip_prefix(b"\xFD7zXZ\0")?.get(1)? {
+        0x00 => "no integrity check",
+        0x01 => "CRC32 check",
+        0x04 => "CRC64 check",
+        0x0A => "SHA-256 check",
+        _ => "unknown integrity check",
+    };
+    let mut details = vec![check.to_string()];
+
+    // The filters of the first block follow the stream header, LZMA2 stores its dictionary size
+    if let Some(dictionary_size) = header.get(12..).and_then(xz_dictionary_size) {
+        details.push(format!("dictionary size {}", Bytes::new(dictionary_size)));
+    }
+    Some(details)
+}
+
+/// Dictionary size of the LZMA2 filter in the xz `block_header`, if it's the last of its filters
+fn xz_dictionary_size(block_header: &[u8]) -> Option<u64> {
+    let [size, flags, ..] = *block_header else {
+        return None;
+    };
+    if size == 0 || flags & 0b11 != 0 {
+        // No block (an empty stream), or more than one filter
+        return None;
+    }
+
+    // Skip the compressed and uncompressed sizes, stored as variable length integers
+    let mut fields = &block_header[2..];
+    for present in [flags & 0x40 != 0, flags & 0x80 != 0] {
+        if present {
+            let length = fields.iter().position(|byte| byte & 0x80 == 0)? + 1;
+            fields = &fields[length..];
+        }
+    }
+
+    // LZMA2 filter ID, size of its properties and the properties
+    let [0x21, 0x01, properties, ..] = *fields else {
+        return None;
+    };
+    match properties {
+        40 => Some(u64::from(u32::MAX)),
+        0..=39 => Some((2 | u64::from(properties & 1)) << (properties / 2 + 11)),
+        _ => None,
+    }
+}
+
+fn describe_zstd(header: &[u8]) -> Option<Vec<String>> {
+    let descriptor = *header.strip_prefix(&[0x28, 0xB5, 0x2F, 0xFD])?.first()?;
+    let fields = &header[5..];
+    let single_segment = descriptor & 0b10_0000 != 0;
+    let mut details = vec![];
+
+    // The compression level isn't stored, only the window size it chose
+    let content_size_offset = if single_segment {
+        0
+    } else {
+        let window_descriptor = *fields.first()?;
+        let window_base = 1u64 << (10 + (window_descriptor >> 3));
+        let window_size = window_base + window_base / 8 * u64::from(window_descriptor & 0b111);
+        details.push(format!("window size {}", Bytes::new(window_size)));
+        1
+    } + [0, 1, 2, 4][usize::from(descriptor & 0b11)];
+
+    let content_size_length = match descriptor >> 6 {
+        0 if single_segment => 1,
+        0 => 0,
+        flag => 1 << flag,
+    };
+    if content_size_length > 0 {
+        let field = fields.get(content_size_offset..content_size_offset + content_size_length)?;
+        let mut bytes = [0; 8];
+        bytes[..content_size_length].copy_from_slice(field);
+        // Two byte sizes are stored with an offset of 256
+        let content_size = u64::from_le_bytes(bytes) + if content_size_length == 2 { 256 } else { 0 };
+        details.push(format!("content size {}", Bytes::new(content_size)));
+    }
+    if descriptor & 0b100 != 0 {
+        details.push("content checksum".to_string());
+    }
+    Some(details)
+}
+
+fn describe_lzw(header: &[u8]) -> Option<Vec<String>> {
+    let flags = *header.strip_prefix(&lzw::MAGIC)?.first()?;
+    let (max_bits, block_mode) = lzw::parse_flags(flags).ok()?;
+
+    let mut details = vec![format!("codes of up to {max_bits} bits")];
+    if block_mode {
+        details.push("block mode".to_string());
+    }
+    Some(details)
+}
+
+fn describe_lzop(header: &[u8]) -> Option<Vec<String>> {
+    let header = header.strip_prefix(&lzop::MAGIC)?;
+    let version = u16::from_be_bytes(header.get(..2)?.try_into().ok()?);
+
+    // Newer versions store the version needed to extract before the method, and the level after it
+    let (method, level) = if version >= 0x0940 {
+        (*header.get(6)?, Some(*header.get(7)?))
+    } else {
+        (*header.get(4)?, None)
+    };
+
+    let mut details = vec![format!("method {}", lzop::method_name(method)?)];
+    if let Some(level) = level {
+        details.push(format!("level {level}"));
+    }
+    Some(details)
+}
+
+fn describe_lrzip(header: &[u8]) -> Option<Vec<String>> {
+    let [major, minor, ..] = header.strip_prefix(lrzip::MAGIC)? else {
+        return None;
+    };
+    Some(vec![format!("format version {major}.{minor}")])
+}
+
+fn describe_age(header: &[u8]) -> Option<Vec<String>> {
+    let header = header.strip_prefix(b"age-encryption.org/v1\n")?;
+
+    // Each recipient has a stanza starting with "-> TYPE", the header ends with "---"
+    let mut recipient_types: Vec<&[u8]> = vec![];
+    for line in header.split(|&byte| byte == b'\n') {
+        if line.starts_with(b"---") {
+            break;
+        }
+        if let Some(stanza) = line.strip_prefix(b"-> ") {
+            recipient_types.push(stanza.split(|&byte| byte == b' ').next().unwrap_or_default());
+        }
+    }
+
+    let mut types: Vec<String> = vec![];
+    for recipient_type in &recipient_types {
+        let recipient_type = String::from_utf8_lossy(recipient_type).into_owned();
+        if !types.contains(&recipient_type) {
+            types.push(recipient_type);
+        }
+    }
+
+    Some(vec![format!(
+        "recipients: {} ({})",
+        recipient_types.len(),
+        types.join(", ")
+    )])
+}
@@ -1,94 +1,115 @@
 use std::{
-    io::{self, BufReader, Read},
+    io::{BufReader, Read},
     path::Path,
+    time::{Duration, UNIX_EPOCH},
 };
 
+use encoding_rs::Encoding;
 use fs_err as fs;
 
 use crate::{
-    archive::sevenz,
-    commands::warn_user_about_loading_zip_in_memory,
+    archive::{index, sevenz},
+    commands::decompress::chain_reader_decoder,
     extension::CompressionFormat::{self, *},
-    list::{self, FileInArchive, ListOptions},
-    utils::{io::lock_and_flush_output_stdio, user_wants_to_continue},
-    QuestionAction, QuestionPolicy, BUFFER_CAPACITY,
+    http,
+    list::{self, ArchiveEntries, FileInArchive, ListOptions},
+    utils::io::spool,
+    zstd_seekable::SeekableDecoder,
+    BUFFER_CAPACITY,
 };
 
-/// File at input_file_path is opened for reading, example: "archive.tar.gz"
+/// File at input_file_path is opened for reading, example: "archive.tar.gz", or downloaded if it's a URL
 /// formats contains each format necessary for decompression, example: [Gz, Tar] (in decompression order)
 pub fn list_archive_contents(
     archive_path: &Path,
     formats: Vec<CompressionFormat>,
     list_options: ListOptions,
-    question_policy: QuestionPolicy,
     password: Option<&[u8]>,
+    name_encoding: Option<&'static Encoding>,
+    quiet: bool,
 ) -> crate::Result<()> {
-    let reader = fs::File::open(archive_path)?;
+    let entries = archive_entries(archive_path, &formats, password, name_encoding, quiet)?;
+    list::list_files(archive_path, entries, list_options)
+}
+
+/// Entries of the archive at `archive_path`, read like [`list_archive_contents`] does
+///
+/// The names of zip entries not flagged as UTF-8 are decoded with `name_encoding`, or CP437.
+pub fn archive_entries(
+    archive_path: &Path,
+    formats: &[CompressionFormat],
+    password: Option<&[u8]>,
+    name_encoding: Option<&'static Encoding>,
+    quiet: bool,
+) -> crate::Result<ArchiveEntries> {
+    let is_url = http::is_url(archive_path);
 
     // Zip archives are special, because they require io::Seek, so it requires it's logic separated
     // from decoder chaining.
     //
-    // This is the only case where we can read and unpack it directly, without having to do
-    // in-memory decompression/copying first.
-    //
-    // Any other Zip decompression done can take up the whole RAM and freeze ouch.
-    if let &[Zip] = formats.as_slice() {
-        let zip_archive = zip::ZipArchive::new(reader)?;
-        let files = crate::archive::zip::list_archive(zip_archive, password);
-        list::list_files(archive_path, files, list_options)?;
+    // This is the only case where we can read it directly, without having to spool it first.
+    if let (&[Zip], false) = (formats, is_url) {
+        let zip_archive = zip::ZipArchive::new(fs::File::open(archive_path)?)?;
+        return Ok(ArchiveEntries {
+            comment: crate::archive::zip::archive_comment(&zip_archive),
+            files: Box::new(crate::archive::zip::list_archive(zip_archive, password, name_encoding)),
+        });
+    }
 
-        return Ok(());
+    // Tar archives compressed with `--index` are listed without reading them
+    if formats[0] == Tar && !is_url {
+        if let Some(index) = index::read_index(archive_path)? {
+            let files = index.entries.into_iter().map(|entry| {
+                Ok(FileInArchive {
+                    path: entry.path,
+                    is_dir: entry.is_dir,
+                    mtime: Some(UNIX_EPOCH + Duration::from_secs(entry.mtime)),
+                    comment: None,
+                    method: None,
+                    encrypted: None,
+                })
+            });
+            return Ok(ArchiveEntries::without_comment(files));
+        }
+    }
+
+    // Seekable zstd has a table of its frames, so the contents of the entries can be skipped
+    if let (&[Tar, Zstd], false) = (formats, is_url) {
+        if let Some(decoder) = SeekableDecoder::new(fs::File::open(archive_path)?)? {
+            let files = crate::archive::tar::list_archive_with_seek(tar::Archive::new(decoder));
+            return Ok(ArchiveEntries::without_comment(files));
+        }
     }
 
+    let reader: Box<dyn Read + Send> = if is_url {
+        http::open_url(archive_path, quiet)?
+    } else {
+        Box::new(fs::File::open(archive_path)?)
+    };
+
     // Will be used in decoder chaining
     let reader = BufReader::with_capacity(BUFFER_CAPACITY, reader);
     let mut reader: Box<dyn Read + Send> = Box::new(reader);
 
-    // Grab previous decoder and wrap it inside of a new one
-    let chain_reader_decoder =
-        |format: &CompressionFormat, decoder: Box<dyn Read + Send>| -> crate::Result<Box<dyn Read + Send>> {
-            let decoder: Box<dyn Read + Send> = match format {
-                Gzip => Box::new(flate2::read::GzDecoder::new(decoder)),
-                Bzip => Box::new(bzip2::read::BzDecoder::new(decoder)),
-                Bzip3 => Box::new(bzip3::read::Bz3Decoder::new(decoder).unwrap()),
-                Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(decoder)),
-                Lzma => Box::new(xz2::read::XzDecoder::new(decoder)),
-                Snappy => Box::new(snap::read::FrameDecoder::new(decoder)),
-                Zstd => Box::new(zstd::stream::Decoder::new(decoder)?),
-                Tar | Zip | Rar | SevenZip => unreachable!(),
-            };
-            Ok(decoder)
-        };
-
     for format in formats.iter().skip(1).rev() {
         reader = chain_reader_decoder(format, reader)?;
     }
 
+    // Zip and 7z have their table of entries at the end, so they're read from a spooled copy of
+    // the stream, which only stays in memory while it's small
+    let mut comment = None;
     let files: Box<dyn Iterator<Item = crate::Result<FileInArchive>>> = match formats[0] {
         Tar => Box::new(crate::archive::tar::list_archive(tar::Archive::new(reader))),
         Zip => {
-            if formats.len() > 1 {
-                // Locking necessary to guarantee that warning and question
-                // messages stay adjacent
-                let _locks = lock_and_flush_output_stdio();
-
-                warn_user_about_loading_zip_in_memory();
-                if !user_wants_to_continue(archive_path, question_policy, QuestionAction::Decompression)? {
-                    return Ok(());
-                }
-            }
-
-            let mut vec = vec![];
-            io::copy(&mut reader, &mut vec)?;
-            let zip_archive = zip::ZipArchive::new(io::Cursor::new(vec))?;
-
-            Box::new(crate::archive::zip::list_archive(zip_archive, password))
+            let zip_archive = zip::ZipArchive::new(spool(&mut reader)?)?;
+            comment = crate::archive::zip::archive_comment(&zip_archive);
+            Box::new(crate::archive::zip::list_archive(zip_archive, password, name_encoding))
         }
         #[cfg(feature = "unrar")]
         Rar => {
-            if formats.len() > 1 {
+            if formats.len() > 1 || is_url {
                 let mut temp_file = tempfile::NamedTempFile::new()?;
-                io::copy(&mut reader, &mut temp_file)?;
+                std::io::copy(&mut reader, &mut temp_file)?;
                 Box::new(crate::archive::rar::list_archive(temp_file.path(), password)?)
             } else {
                 Box::new(crate::archive::rar::list_archive(archive_path, password)?)
@@ -98,24 +119,18 @@ pub fn list_archive_contents(
         Rar => {
             return Err(crate::archive::rar_stub::no_support());
         }
+        Squashfs => return Err(crate::archive::squashfs::no_support()),
         SevenZip => {
-            if formats.len() > 1 {
-                // Locking necessary to guarantee that warning and question
-                // messages stay adjacent
-                let _locks = lock_and_flush_output_stdio();
-
-                warn_user_about_loading_zip_in_memory();
-                if !user_wants_to_continue(archive_path, question_policy, QuestionAction::Decompression)? {
-                    return Ok(());
-                }
+            if formats.len() > 1 || is_url {
+                Box::new(sevenz::list_archive(spool(&mut reader)?, password)?)
+            } else {
+                Box::new(sevenz::list_archive(fs::File::open(archive_path)?, password)?)
             }
-
-            Box::new(sevenz::list_archive(archive_path, password)?)
         }
-        Gzip | Bzip | Bzip3 | Lz4 | Lzma | Snappy | Zstd => {
+        Gzip | Bzip | Bzip3 | Lz4 | Lzma | LzmaAlone | Snappy | Zstd | Age | Lzw | Lzop | Lrzip | External(_) => {
             panic!("Not an archive! This should never happen, if it does, something is wrong with `CompressionFormat::is_archive()`. Please report this error!");
         }
     };
 
-    list::list_files(archive_path, files, list_options)
+    Ok(ArchiveEntries { comment, files })
 }
@@ -0,0 +1,954 @@
+//! Run the subcommand parsed from the command line.
+
+use std::{
+    env,
+    ffi::OsString,
+    io::Write,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    slice,
+};
+
+use bstr::ByteSlice;
+use fs_err as fs;
+use rayon::prelude::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    archive::{
+        index::ArchiveIndex,
+        manifest::{ArchiveObserver, Manifest},
+        walk::{self, Baseline},
+        zip::parse_encoding,
+        IdenticalBy, Reproducible, UnpackOptions,
+    },
+    check,
+    cli::{CliArgs, Subcommand},
+    commands::{
+        bench::benchmark,
+        clean::{clean_temp_dirs, offer_to_clean_temp_dirs},
+        compare::compare_directory,
+        compress::{compress_files, read_baseline_archive, sign_compressed_file, CodecOptions, CompressOptions},
+        decompress::{self, decompress_file, DecompressOptions},
+        diff::{diff_sources, DiffSource},
+        estimate::estimate_compression,
+        explain::{explain_compression, explain_decompression},
+        generate::{print_completions, print_manpage},
+        info::print_info,
+        list::list_archive_contents,
+        verify::verify_signature,
+    },
+    error::{Error, ErrorClass, FinalError},
+    extension::{self, flatten_compression_formats, parse_format_flag, CompressionFormat},
+    filter::FilterCommand,
+    http,
+    list::ListOptions,
+    object_storage,
+    options::ConflictPolicy,
+    signature,
+    utils::{
+        self, colors,
+        io::{joined_path, split_part_path, split_part_paths, SplitWriter},
+        is_path_stdin,
+        logger::{self, info_accessible, warning},
+        path_to_str,
+        sandbox::{self, SandboxRules},
+        EscapedPathDisplay, FileVisibilityPolicy, PermissionsPolicy, Transform,
+    },
+    QuestionPolicy,
+};
+
+/// Password to use for each of the `input_count` inputs, a single password is used for all of them
+fn passwords_for_inputs(passwords: &[OsString], input_count: usize) -> crate::Result<Vec<Option<&[u8]>>> {
+    let passwords: Vec<&[u8]> = passwords
+        .iter()
+        .map(|str| <[u8] as ByteSlice>::from_os_str(str).expect("convert password to bytes failed"))
+        .collect();
+
+    match passwords.as_slice() {
+        [] => Ok(vec![None; input_count]),
+        [password] => Ok(vec![Some(*password); input_count]),
+        _ if passwords.len() == input_count => Ok(passwords.into_iter().map(Some).collect()),
+        _ => Err(FinalError::with_title("Cannot match the passwords to the input files")
+            .detail(format!("Got {} passwords for {input_count} files", passwords.len()))
+            .hint("Pass '--password' once to use it for every file,")
+            .hint("or once for each file, in the same order as the files.")
+            .class(ErrorClass::BadArguments)
+            .into()),
+    }
+}
+
+/// Format to use for each of the `input_count` inputs, a single format is used for all of them and
+/// `None` means the format is detected from the input
+fn formats_for_inputs(formats: &[OsString], input_count: usize) -> crate::Result<Vec<Option<&OsString>>> {
+    match formats {
+        [] => Ok(vec![None; input_count]),
+        [format] => Ok(vec![Some(format); input_count]),
+        _ if formats.len() == input_count => Ok(formats.iter().map(Some).collect()),
+        _ => Err(FinalError::with_title("Cannot match the formats to the input files")
+            .detail(format!("Got {} formats for {input_count} files", formats.len()))
+            .hint("Pass '--format' once to use it for every file,")
+            .hint("or once for each file, in the same order as the files.")
+            .class(ErrorClass::BadArguments)
+            .into()),
+    }
+}
+
+/// Decompress every input with `process`, in parallel, `paths` being the paths of the inputs in order.
+///
+/// Unless `fail_fast` is set, every input is processed even if some fail, and the failures are
+/// summed up in a single error at the end, of class [`ErrorClass::PartialFailure`] if some inputs
+/// succeeded. With `fail_fast`, the first failure is returned as soon as it happens.
+fn decompress_batch<I, F>(paths: &[PathBuf], inputs: I, fail_fast: bool, process: F) -> crate::Result<()>
+where
+    I: IndexedParallelIterator,
+    F: Fn(I::Item) -> crate::Result<()> + Sync + Send,
+{
+    if fail_fast {
+        return inputs.try_for_each(process);
+    }
+
+    let results: Vec<crate::Result<()>> = inputs.map(process).collect();
+    let mut failures: Vec<(&PathBuf, Error)> = paths
+        .iter()
+        .zip(results)
+        .filter_map(|(path, result)| Some((path, result.err()?)))
+        .collect();
+
+    match failures.len() {
+        0 => Ok(()),
+        // A single input keeps its own error, like with `--fail-fast`
+        _ if paths.len() == 1 => Err(failures.remove(0).1),
+        failed => {
+            let class = if failed < paths.len() {
+                ErrorClass::PartialFailure
+            } else {
+                failures[0].1.class()
+            };
+            let mut error = FinalError::with_title(format!("Failed to decompress {failed} of {} files", paths.len()));
+            for (path, err) in failures {
+                let err = FinalError::from(err);
+                error = error.detail(format!("{}: {}", EscapedPathDisplay::new(path), err.title()));
+            }
+            Err(error.class(class).into())
+        }
+    }
+}
+
+/// Restrict the process to reading `files` and the identity file given to `--decrypt`, and to
+/// unpacking into `output_dir` and the temporary directories
+fn enter_sandbox(
+    files: &[PathBuf],
+    output_dir: &Path,
+    temp_dir: Option<&Path>,
+    identity_file: Option<&Path>,
+) -> crate::Result<()> {
+    if let Some(url) = files.iter().find(|path| http::is_url(path)) {
+        return Err(FinalError::with_title("Cannot download files in a sandbox")
+            .detail(format!("{} is a URL", EscapedPathDisplay::new(url)))
+            .hint("Download the file first, or remove '--sandbox'")
+            .class(ErrorClass::BadArguments)
+            .into());
+    }
+
+    // The other parts of split files are opened as the first one is read
+    let parts: Vec<PathBuf> = files
+        .iter()
+        .filter_map(|path| joined_path(path))
+        .flat_map(|joined| split_part_paths(&joined))
+        .collect();
+    // Where compressed archives are spooled to be unpacked
+    let system_temp_dir = env::temp_dir();
+
+    let rules = SandboxRules {
+        readable: files
+            .iter()
+            .filter(|path| !is_path_stdin(path))
+            .chain(&parts)
+            .map(PathBuf::as_path)
+            .chain(identity_file)
+            .collect(),
+        writable: [output_dir]
+            .into_iter()
+            .chain(temp_dir)
+            .chain(system_temp_dir.is_dir().then_some(system_temp_dir.as_path()))
+            .collect(),
+    };
+    sandbox::enter(&rules)
+}
+
+/// Formats of the file at `path` detected from its contents, for `--ignore-extension`
+fn sniff_formats_ignoring_extension(path: &Path) -> crate::Result<Vec<extension::Extension>> {
+    if http::is_url(path) || is_path_stdin(path) {
+        // Their contents can't be read twice
+        return Err(FinalError::with_title(format!(
+            "Cannot detect the formats of {} from its contents",
+            EscapedPathDisplay::new(path)
+        ))
+        .detail("'--ignore-extension' only works with files")
+        .hint("Remove '--ignore-extension', or pass the formats with '--format'")
+        .class(ErrorClass::BadArguments)
+        .into());
+    }
+
+    decompress::sniff_formats(path)
+}
+
+/// Path of the file decompressed from the input named `file_name`, without its extensions, also
+/// the directory archives with multiple entries at their root are unpacked into
+fn output_file_path(output_dir: &Path, file_name: &Path) -> PathBuf {
+    if is_path_stdin(file_name) {
+        output_dir.join("stdin-output")
+    } else {
+        output_dir.join(file_name)
+    }
+}
+
+/// Warn that `share` of the archived contents look already compressed, suggesting how to spend less
+/// time compressing them again for archives of `formats`
+fn warn_about_incompressible_contents(share: f64, formats: &[CompressionFormat], auto_store: bool) {
+    use CompressionFormat::*;
+
+    let suggestion = match formats {
+        [Zip] if !auto_store => "use `--auto-store` to store them as they are",
+        [Tar, _, ..] => "use `--fast`, or a plain .tar archive, to spend less time on them",
+        _ => return,
+    };
+    warning(format!(
+        "{:.0}% of the contents look already compressed, like images or videos, compressing them again \
+         takes long for little gain, {suggestion}",
+        share * 100.0
+    ));
+}
+
+/// Use `threads` threads to (de)compress in parallel, if given
+///
+/// The pool is only built once, by the first run in the process, later ones keep using it.
+fn build_thread_pool(threads: Option<usize>) {
+    if let Some(threads) = threads {
+        let built = rayon::ThreadPoolBuilder::new().num_threads(threads).build_global();
+        if built.is_err() && rayon::current_num_threads() != threads {
+            warning(format!(
+                "Using {} threads instead of {threads}, the thread pool was already started",
+                rayon::current_num_threads()
+            ));
+        }
+    }
+}
+
+/// This function checks what command needs to be run and performs A LOT of ahead-of-time checks
+/// to assume everything is OK.
+///
+/// There are a lot of custom errors to give enough error description and explanation.
+pub fn run(
+    args: CliArgs,
+    question_policy: QuestionPolicy,
+    file_visibility_policy: FileVisibilityPolicy,
+) -> crate::Result<()> {
+    // The sandbox only restricts the threads started after it, so the pool waits for it
+    if !matches!(args.cmd, Subcommand::Decompress { sandbox: true, .. }) {
+        build_thread_pool(args.threads);
+    }
+
+    match args.cmd {
+        Subcommand::Compress {
+            files,
+            output: output_path,
+            output_dir,
+            // Already added to `files` by `CliArgs::validate`
+            files_from: _,
+            null: _,
+            entry_name,
+            level,
+            fast,
+            slow,
+            checksum,
+            seekable,
+            index,
+            xz_check,
+            memory,
+            xz_extreme,
+            snappy_raw,
+            rsyncable,
+            auto_store,
+            zip_rule,
+            gzip_name,
+            gzip_comment,
+            comment,
+            reproducible,
+            mtime,
+            windows_compat,
+            max_depth,
+            one_file_system,
+            follow_dir_symlinks,
+            exclude_vcs,
+            exclude_caches,
+            special_files,
+            dedup,
+            newer_than,
+            newer_mtime,
+            older_mtime,
+            manifest,
+            split_size,
+            encrypt,
+            sign_key,
+            filter_cmd,
+            default_format,
+            estimate,
+            concat,
+            no_size_estimate,
+        } => {
+            // After cleaning, if there are no input files left, exit
+            if files.is_empty() {
+                return Err(FinalError::with_title("No files to compress")
+                    .class(ErrorClass::BadArguments)
+                    .into());
+            }
+            let output_path = output_path.expect("clap requires it, or it's named by `CliArgs::validate` for `--dir`");
+
+            // Formats from path extension, like "file.tar.gz.xz" -> vec![Tar, Gzip, Lzma]
+            let formats_from_flag = formats_for_inputs(&args.format, 1)?[0];
+            let mut formats = match formats_from_flag {
+                Some(formats) => parse_format_flag(formats)?,
+                None => extension::extensions_from_path(&output_path),
+            };
+            check::check_first_format_when_compressing(
+                &mut formats,
+                &output_path,
+                default_format.as_deref(),
+                question_policy,
+            )?;
+
+            check::check_invalid_compression_with_non_archive_format(
+                &formats,
+                &output_path,
+                &files,
+                formats_from_flag,
+                concat,
+            )?;
+            check::check_archive_formats_position(&formats, &output_path)?;
+            check::check_encryption_recipients(&formats, &encrypt, &output_path)?;
+            check::check_stdin_input(&formats, &files, entry_name.as_deref())?;
+            check::check_s3_output(&output_path, split_size.is_some(), sign_key.is_some(), index)?;
+            if index {
+                check::check_tar_only_flag("--index", &formats, &output_path)?;
+            }
+            if dedup {
+                check::check_tar_only_flag("--dedup", &formats, &output_path)?;
+            }
+            if comment.is_some() {
+                check::check_tar_or_zip_only_flag("--comment", &formats, &output_path)?;
+            }
+            if newer_than.is_some() {
+                check::check_archive_only_flag("--newer-than", &formats, &output_path)?;
+            }
+            if newer_mtime.is_some() {
+                check::check_archive_only_flag("--newer-mtime", &formats, &output_path)?;
+            }
+            if older_mtime.is_some() {
+                check::check_archive_only_flag("--older-mtime", &formats, &output_path)?;
+            }
+            if manifest.is_some() {
+                check::check_archive_only_flag("--manifest", &formats, &output_path)?;
+            }
+            let filter_cmd = filter_cmd.as_deref().map(FilterCommand::parse).transpose()?;
+            if filter_cmd.is_some() {
+                check::check_filter_cmd(&formats, &output_path)?;
+            }
+
+            let file_visibility_policy = file_visibility_policy
+                .max_depth(max_depth)
+                .same_file_system(one_file_system)
+                .follow_dir_symlinks(follow_dir_symlinks)
+                .exclude_vcs(exclude_vcs)
+                .exclude_caches(exclude_caches)
+                .modified_before(older_mtime);
+
+            if windows_compat {
+                check::check_windows_compatible_names(&files, &file_visibility_policy)?;
+            }
+
+            crate::archive::walk::set_stdin_entry_name(entry_name);
+
+            // Read before compressing, so an invalid key doesn't waste the work
+            let signing_key = sign_key.as_deref().map(signature::read_signing_key).transpose()?;
+
+            // The reference can be the output itself, so it's read before replacing it
+            walk::set_baseline(match (newer_than, newer_mtime) {
+                (Some(base), _) => Some(read_baseline_archive(&base, args.quiet)?),
+                (None, Some(mtime)) => Some(Baseline::Mtime(mtime)),
+                (None, None) => None,
+            });
+
+            let level = if fast {
+                Some(1) // Lowest level of compression
+            } else if slow {
+                Some(i16::MAX) // Highest level of compression
+            } else {
+                level
+            };
+
+            if args.explain {
+                explain_compression(&files, &output_path, &formats, level);
+            }
+
+            let codec_options = CodecOptions {
+                level,
+                zstd_checksum: checksum,
+                zstd_seekable: seekable,
+                xz_check,
+                xz_memory_limit: memory,
+                xz_extreme,
+                snappy_raw,
+                rsyncable,
+                auto_store,
+                zip_rules: zip_rule,
+                gzip_name,
+                gzip_comment,
+                filter_cmd,
+            };
+
+            let buffer_size = args
+                .io_buffer_size
+                .unwrap_or_else(|| utils::io::default_buffer_size(fs::metadata(&files[0]).ok().as_ref()));
+
+            if estimate {
+                check::check_estimate_input(&files)?;
+                return estimate_compression(
+                    files,
+                    formats,
+                    &output_path,
+                    file_visibility_policy,
+                    &codec_options,
+                    args.normalize,
+                    special_files,
+                    buffer_size,
+                    &encrypt,
+                );
+            }
+
+            if let Some(dir) = &output_dir {
+                utils::create_dir_if_non_existent(dir)?;
+            }
+
+            let output_is_s3 = object_storage::is_s3_uri(&output_path);
+            let mut upload = None;
+
+            let output_file: Box<dyn Send + Write> = match split_size {
+                _ if output_is_s3 => {
+                    let (writer, object_upload) = object_storage::create_object(&output_path)?;
+                    upload = Some(object_upload);
+                    writer
+                }
+                Some(part_size) => {
+                    let first_part = split_part_path(&output_path, 1);
+                    if fs::symlink_metadata(&first_part).is_ok() {
+                        utils::check_symlinked_output(&first_part)?;
+                        if !utils::user_wants_to_overwrite(&first_part, question_policy)? {
+                            return Ok(());
+                        }
+                    }
+                    // Parts left by a previous split would be joined with the new ones
+                    for part in split_part_paths(&output_path) {
+                        utils::remove_file_or_dir(&part)?;
+                    }
+                    Box::new(SplitWriter::new(output_path.clone(), part_size)?)
+                }
+                None => match utils::ask_to_create_file(&output_path, question_policy)? {
+                    Some(writer) => Box::new(writer),
+                    None => return Ok(()),
+                },
+            };
+
+            let reproducible = (reproducible || mtime.is_some()).then(|| Reproducible {
+                mtime: mtime.unwrap_or(Reproducible::DEFAULT_MTIME),
+            });
+            // Directory listing order depends on the filesystem
+            let file_visibility_policy = file_visibility_policy.sort_by_name(reproducible.is_some());
+
+            let compression_formats = flatten_compression_formats(&formats);
+            // Walking millions of files takes a while, their counts are shown meanwhile
+            if !args.quiet && formats[0].is_archive() && logger::set_status_line(walk::walk_status) && !no_size_estimate
+            {
+                walk::estimate_total_size(files.clone(), file_visibility_policy.clone());
+            }
+            let mut archive_index = index.then(ArchiveIndex::default);
+            let mut archive_manifest = manifest.is_some().then(Manifest::default);
+            let compress_result = compress_files(
+                files,
+                formats,
+                output_file,
+                CompressOptions {
+                    output_path: &output_path,
+                    quiet: args.quiet,
+                    question_policy,
+                    file_visibility_policy,
+                    codec_options: &codec_options,
+                    reproducible,
+                    comment: comment.as_deref(),
+                    name_normalization: args.normalize,
+                    special_files,
+                    dedup,
+                    rate_limit: args.rate_limit,
+                    buffer_size,
+                    encryption_recipients: &encrypt,
+                    index: archive_index.as_mut(),
+                    observer: archive_manifest
+                        .as_mut()
+                        .map(|manifest| manifest as &mut dyn ArchiveObserver),
+                },
+            );
+            logger::clear_status_line();
+            // The object is only replaced once everything was uploaded
+            let compress_result = match (compress_result, upload) {
+                (Ok(true), Some(upload)) => upload.finish(&output_path).map(|_| true),
+                (compress_result, Some(upload)) => {
+                    let _ = upload.abort(&output_path);
+                    compress_result
+                }
+                (compress_result, None) => compress_result,
+            };
+
+            if let Ok(true) = compress_result {
+                // this is only printed once, so it doesn't result in much text. On the other hand,
+                // having a final status message is important especially in an accessibility context
+                // as screen readers may not read a commands exit code, making it hard to reason
+                // about whether the command succeeded without such a message
+                if split_size.is_some() {
+                    info_accessible(format!(
+                        "Successfully compressed '{}' into {} parts",
+                        path_to_str(&output_path),
+                        split_part_paths(&output_path).len()
+                    ));
+                } else {
+                    info_accessible(format!("Successfully compressed '{}'", path_to_str(&output_path)));
+                }
+
+                if let Some(share) = walk::take_incompressible_share().filter(|&share| share > 0.5) {
+                    warn_about_incompressible_contents(share, &compression_formats, auto_store);
+                }
+
+                if let Some(archive_index) = archive_index {
+                    let archive_path = match split_size {
+                        Some(_) => split_part_path(&output_path, 1),
+                        None => output_path.clone(),
+                    };
+                    crate::archive::index::write_index(&archive_path, &archive_index)?;
+                }
+
+                if let (Some(manifest_path), Some(archive_manifest)) = (manifest, archive_manifest) {
+                    if let Some(manifest_file) = utils::ask_to_create_file(&manifest_path, question_policy)? {
+                        archive_manifest.write(&output_path, manifest_file)?;
+                        info_accessible(format!("Manifest written to '{}'", path_to_str(&manifest_path)));
+                    }
+                }
+
+                if let Some(signing_key) = signing_key {
+                    sign_compressed_file(&output_path, &signing_key, question_policy)?;
+                }
+            } else {
+                // If Ok(false) or Err() occurred, delete incomplete file at `output_path`
+                //
+                // if deleting fails, print an extra alert message pointing
+                // out that we left a possibly CORRUPTED file at `output_path`
+                let written_paths = match split_size {
+                    // Failed uploads were already aborted
+                    _ if output_is_s3 => vec![],
+                    Some(_) => split_part_paths(&output_path),
+                    None => vec![output_path.clone()],
+                };
+                let failed_removals = written_paths
+                    .iter()
+                    .filter(|path| utils::remove_file_or_dir(path).is_err())
+                    .count();
+                if failed_removals > 0 {
+                    eprintln!("{red}FATAL ERROR:\n", red = *colors::RED);
+                    eprintln!(
+                        "  Ouch failed to delete the file '{}'.",
+                        EscapedPathDisplay::new(&output_path)
+                    );
+                    eprintln!("  Please delete it manually.");
+                    eprintln!("  This file is corrupted if compression didn't finished.");
+
+                    if compress_result.is_err() {
+                        eprintln!("  Compression failed for reasons below.");
+                    }
+                }
+            }
+
+            compress_result.map(|_| ())
+        }
+        Subcommand::Decompress {
+            files,
+            output_dir,
+            remove,
+            verify,
+            no_same_permissions,
+            umask_safe,
+            preserve_setuid,
+            // Only clears `preserve_setuid`, which it overrides
+            no_special_permissions: _,
+            print0,
+            transform,
+            keep_directory_structure,
+            on_conflict,
+            in_place,
+            strip_common_prefix,
+            skip_identical,
+            checksum,
+            filter_cmd,
+            fail_fast,
+            sandbox,
+            encoding,
+        } => {
+            if verify && !keep_directory_structure {
+                // Renamed files can't be matched to the entries they came from
+                return Err(FinalError::with_title("Cannot verify flattened files")
+                    .detail("'--verify' requires the unpacked files to keep their paths in the archive")
+                    .hint("Remove '--verify' or '--keep-directory-structure=false'")
+                    .class(ErrorClass::BadArguments)
+                    .into());
+            }
+
+            let transforms = transform
+                .iter()
+                .map(|expression| Transform::parse(expression))
+                .collect::<crate::Result<Vec<_>>>()?;
+            let name_encoding = encoding.as_deref().map(parse_encoding).transpose()?.flatten();
+
+            // The first parts of split files, like "archive.tar.gz.001", are named after the whole file,
+            // and URLs after the last segment of their path
+            let names: Vec<PathBuf> = files
+                .iter()
+                .map(|path| {
+                    if http::is_url(path) {
+                        http::url_file_name(path)
+                    } else {
+                        joined_path(path).unwrap_or_else(|| path.clone())
+                    }
+                })
+                .collect();
+
+            let mut output_paths = vec![];
+            let mut formats = vec![];
+
+            let input_formats = formats_for_inputs(&args.format, files.len())?;
+            for ((path, name), format) in files.iter().zip(&names).zip(input_formats) {
+                if let Some(format) = format {
+                    let file_name = name.file_name().ok_or_else(|| Error::NotFound {
+                        error_title: format!("{} does not have a file name", EscapedPathDisplay::new(name)),
+                    })?;
+                    output_paths.push(file_name.as_ref());
+                    formats.push(parse_format_flag(format)?);
+                    continue;
+                }
+
+                let (pathbase, mut file_formats) = extension::separate_known_extensions_from_name(name);
+
+                // Downloads can't be sniffed without starting them, `--ignore-extension` rejects them
+                if args.ignore_extension {
+                    file_formats = sniff_formats_ignoring_extension(path)?;
+                } else if !http::is_url(path)
+                    && check::check_mime_type(path, &mut file_formats, question_policy)?.is_break()
+                {
+                    return Ok(());
+                }
+
+                output_paths.push(pathbase);
+                formats.push(file_formats);
+            }
+
+            check::check_missing_formats_when_decompressing(&files, &formats)?;
+            let filter_cmd = filter_cmd.as_deref().map(FilterCommand::parse).transpose()?;
+            if filter_cmd.is_some() {
+                for (path, formats) in files.iter().zip(&formats) {
+                    check::check_filter_cmd(formats, path)?;
+                }
+            }
+            let passwords = passwords_for_inputs(&args.password, files.len())?;
+
+            // The directory that will contain the output files
+            // We default to the current directory if the user didn't specify an output directory with --dir
+            let output_dir = if let Some(dir) = output_dir {
+                utils::create_dir_if_non_existent(&dir)?;
+                dir
+            } else {
+                PathBuf::from(".")
+            };
+
+            // Where archives are unpacked before being moved into `output_dir`
+            let temp_dir = match args.temp_dir {
+                Some(temp_dir) if !args.same_dir_temp => {
+                    utils::create_dir_if_non_existent(&temp_dir)?;
+                    Some(temp_dir)
+                }
+                _ => None,
+            };
+
+            offer_to_clean_temp_dirs(temp_dir.as_ref().unwrap_or(&output_dir), question_policy)?;
+
+            // Flattened files used to be renamed and other repeated entries replaced, files that
+            // existed before unpacking in place are asked about
+            let entry_conflict_policy = on_conflict.unwrap_or(match (keep_directory_structure, in_place) {
+                (false, _) => ConflictPolicy::Rename,
+                (true, false) => ConflictPolicy::Overwrite,
+                (true, true) => ConflictPolicy::Ask,
+            });
+
+            // Read before unpacking in parallel, reading the umask changes it for a moment
+            let permissions = if no_same_permissions {
+                PermissionsPolicy::apply_umask()
+            } else if umask_safe {
+                PermissionsPolicy::safe()
+            } else {
+                PermissionsPolicy::preserve()
+            }
+            .with_special_bits(preserve_setuid);
+
+            if sandbox {
+                enter_sandbox(&files, &output_dir, temp_dir.as_deref(), args.decrypt.as_deref())?;
+                build_thread_pool(args.threads);
+            }
+
+            if args.explain {
+                for ((path, formats), file_name) in files.iter().zip(&formats).zip(&output_paths) {
+                    explain_decompression(
+                        path,
+                        formats,
+                        &output_dir,
+                        &output_file_path(&output_dir, file_name),
+                        temp_dir.as_deref(),
+                        in_place,
+                    );
+                }
+            }
+
+            let inputs = files.par_iter().zip(formats).zip(output_paths).zip(passwords);
+            decompress_batch(
+                &files,
+                inputs,
+                fail_fast,
+                |(((input_path, formats), file_name), password)| {
+                    decompress_file(DecompressOptions {
+                        input_file_path: input_path,
+                        formats,
+                        output_dir: &output_dir,
+                        output_file_path: output_file_path(&output_dir, file_name),
+                        question_policy,
+                        temp_dir: temp_dir.as_deref(),
+                        unpack_options: UnpackOptions {
+                            password,
+                            name_encoding,
+                            quiet: args.quiet,
+                            permissions,
+                            normalization: args.normalize,
+                            transforms: &transforms,
+                            keep_directory_structure,
+                            on_conflict: entry_conflict_policy,
+                            skip_identical: skip_identical.then_some(if checksum {
+                                IdenticalBy::Checksum
+                            } else {
+                                IdenticalBy::SizeAndMtime
+                            }),
+                            question_policy,
+                            progress: None,
+                            root_entries: None,
+                        },
+                        remove,
+                        verify,
+                        rate_limit: args.rate_limit,
+                        buffer_size: args
+                            .io_buffer_size
+                            .unwrap_or_else(|| utils::io::default_buffer_size(fs::metadata(input_path).ok().as_ref())),
+                        print0,
+                        on_conflict,
+                        in_place,
+                        strip_common_prefix,
+                        filter_cmd: filter_cmd.as_ref(),
+                    })
+                },
+            )
+        }
+        Subcommand::List {
+            archives: files,
+            tree,
+            print0,
+            json,
+            long,
+            encoding,
+        } => {
+            let name_encoding = encoding.as_deref().map(parse_encoding).transpose()?.flatten();
+            let mut formats = vec![];
+
+            let input_formats = formats_for_inputs(&args.format, files.len())?;
+            for (path, format) in files.iter().zip(input_formats) {
+                if let Some(format) = format {
+                    formats.push(parse_format_flag(format)?);
+                    continue;
+                }
+
+                if args.ignore_extension {
+                    formats.push(sniff_formats_ignoring_extension(path)?);
+                    continue;
+                }
+
+                if http::is_url(path) {
+                    // Downloads can't be sniffed without starting them
+                    formats.push(extension::extensions_from_path(&http::url_file_name(path)));
+                    continue;
+                }
+
+                let mut file_formats = extension::extensions_from_path(path);
+
+                if let ControlFlow::Break(_) = check::check_mime_type(path, &mut file_formats, question_policy)? {
+                    return Ok(());
+                }
+
+                formats.push(file_formats);
+            }
+
+            // Ensure we were not told to list the content of a non-archive compressed file
+            check::check_for_non_archive_formats(&files, &formats)?;
+            let passwords = passwords_for_inputs(&args.password, files.len())?;
+
+            let list_options = ListOptions {
+                tree,
+                print0,
+                json,
+                long,
+            };
+
+            for (i, ((archive_path, formats), password)) in files.iter().zip(formats).zip(passwords).enumerate() {
+                if i > 0 && !print0 && !json {
+                    println!();
+                }
+                let formats = extension::flatten_compression_formats(&formats);
+                list_archive_contents(archive_path, formats, list_options, password, name_encoding, args.quiet)?;
+            }
+
+            Ok(())
+        }
+        Subcommand::Info { archives: files } => {
+            let mut formats = vec![];
+
+            let input_formats = formats_for_inputs(&args.format, files.len())?;
+            for (path, format) in files.iter().zip(input_formats) {
+                let file_formats = if let Some(format) = format {
+                    parse_format_flag(format)?
+                } else {
+                    let mut file_formats = extension::extensions_from_path(path);
+
+                    if let ControlFlow::Break(_) = check::check_mime_type(path, &mut file_formats, question_policy)? {
+                        return Ok(());
+                    }
+
+                    file_formats
+                };
+                formats.push(file_formats);
+            }
+
+            check::check_missing_formats_when_decompressing(&files, &formats)?;
+            let passwords = passwords_for_inputs(&args.password, files.len())?;
+
+            for (i, ((path, formats), password)) in files.iter().zip(formats).zip(passwords).enumerate() {
+                if i > 0 {
+                    println!();
+                }
+                print_info(path, &formats, password)?;
+            }
+
+            Ok(())
+        }
+        Subcommand::Diff { old, new, json } => {
+            let passwords = passwords_for_inputs(&args.password, 2)?;
+            let input_formats = formats_for_inputs(&args.format, 2)?;
+            let mut sources = Vec::with_capacity(2);
+
+            for (path, format) in [old, new].into_iter().zip(input_formats) {
+                if path.is_dir() {
+                    sources.push(DiffSource::Directory(path));
+                    continue;
+                }
+
+                let formats = if let Some(format) = format {
+                    parse_format_flag(format)?
+                } else {
+                    let mut file_formats = extension::extensions_from_path(&path);
+
+                    if let ControlFlow::Break(_) = check::check_mime_type(&path, &mut file_formats, question_policy)? {
+                        return Ok(());
+                    }
+
+                    file_formats
+                };
+
+                // Ensure we were not told to compare the content of a non-archive compressed file
+                check::check_for_non_archive_formats(slice::from_ref(&path), slice::from_ref(&formats))?;
+
+                let formats = extension::flatten_compression_formats(&formats);
+                sources.push(DiffSource::Archive(path, formats));
+            }
+
+            diff_sources(
+                &sources[0],
+                &sources[1],
+                json,
+                &file_visibility_policy,
+                [passwords[0], passwords[1]],
+            )
+        }
+        Subcommand::Compare { dir, archive } => {
+            if !dir.is_dir() {
+                return Err(FinalError::with_title(format!(
+                    "Cannot compare '{}' to an archive",
+                    EscapedPathDisplay::new(&dir)
+                ))
+                .detail("It is not a directory")
+                .hint("Use 'ouch diff' to compare two archives")
+                .class(ErrorClass::BadArguments)
+                .into());
+            }
+
+            let formats = if let Some(format) = formats_for_inputs(&args.format, 1)?[0] {
+                parse_format_flag(format)?
+            } else {
+                let mut file_formats = extension::extensions_from_path(&archive);
+
+                if let ControlFlow::Break(_) = check::check_mime_type(&archive, &mut file_formats, question_policy)? {
+                    return Ok(());
+                }
+
+                file_formats
+            };
+            check::check_for_non_archive_formats(slice::from_ref(&archive), slice::from_ref(&formats))?;
+
+            let formats = extension::flatten_compression_formats(&formats);
+            let password = passwords_for_inputs(&args.password, 1)?[0];
+            compare_directory(&dir, &archive, &formats, &file_visibility_policy, password)
+        }
+        Subcommand::Verify { file, sig, pubkey } => {
+            let sig = sig.unwrap_or_else(|| signature::signature_path(&file));
+            verify_signature(&file, &sig, &pubkey)
+        }
+        Subcommand::Clean { dir } => clean_temp_dirs(&dir),
+        Subcommand::Bench {
+            input,
+            formats,
+            levels,
+            sample_size,
+        } => {
+            let buffer_size = args
+                .io_buffer_size
+                .unwrap_or_else(|| utils::io::default_buffer_size(fs::metadata(&input).ok().as_ref()));
+            benchmark(
+                &input,
+                &formats,
+                &levels,
+                sample_size,
+                file_visibility_policy,
+                buffer_size,
+            )
+        }
+        Subcommand::Completions { shell } => print_completions(shell),
+        Subcommand::Manpage => print_manpage(),
+    }
+}
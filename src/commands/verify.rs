@@ -0,0 +1,32 @@
+//! Verification of the signatures made with `ouch compress --sign-key`.
+
+use std::path::Path;
+
+use fs_err as fs;
+
+use crate::{
+    error::{ErrorClass, FinalError},
+    signature::{read_verifying_key, verify_file},
+    utils::{logger::info_accessible, EscapedPathDisplay},
+};
+
+/// Check the signature at `signature_path` of `file` against the public key at `public_key_path`
+pub fn verify_signature(file: &Path, signature_path: &Path, public_key_path: &Path) -> crate::Result<()> {
+    let key = read_verifying_key(public_key_path)?;
+    let signature = fs::read_to_string(signature_path)?;
+
+    if !verify_file(file, &signature, &key)? {
+        let error = FinalError::with_title(format!("Invalid signature for {}", EscapedPathDisplay::new(file)))
+            .detail(format!(
+                "The signature in {} wasn't made for this file with this key",
+                EscapedPathDisplay::new(signature_path)
+            ))
+            .hint("The file was modified or corrupted, or signed with another key.")
+            .class(ErrorClass::CorruptArchive);
+
+        return Err(error.into());
+    }
+
+    info_accessible(format!("Valid signature for {}", EscapedPathDisplay::new(file)));
+    Ok(())
+}
@@ -0,0 +1,151 @@
+//! Encryption of streams with [age](https://age-encryption.org), used by the `.age` extension.
+//!
+//! Like compression formats, it's a layer in the chain of encoders and decoders, so
+//! `backup.tar.zst.age` is a `.tar.zst` encrypted as a whole.
+
+use std::{
+    io::{Read, Write},
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+};
+
+use crate::error::Error;
+#[cfg(feature = "age")]
+use crate::error::{ErrorClass, FinalError};
+
+/// File with the identities used to decrypt, set by `--decrypt`.
+static IDENTITY_FILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Set the identity file used to decrypt `.age` inputs, replacing the one of an earlier run.
+pub fn set_identity_file(path: Option<PathBuf>) {
+    *IDENTITY_FILE.lock().unwrap_or_else(PoisonError::into_inner) = path;
+}
+
+/// Whether `--decrypt` was given, so `.age` layers can be decrypted
+pub fn has_identity() -> bool {
+    IDENTITY_FILE.lock().unwrap_or_else(PoisonError::into_inner).is_some()
+}
+
+#[cfg(feature = "age")]
+fn identity_file() -> crate::Result<PathBuf> {
+    let identity_file = IDENTITY_FILE.lock().unwrap_or_else(PoisonError::into_inner).clone();
+    identity_file.ok_or_else(|| {
+        FinalError::with_title("Cannot decrypt without an identity")
+            .detail("Decrypting '.age' files requires the identity they were encrypted to")
+            .hint("Pass the file with the identity to '--decrypt':")
+            .hint("  ouch decompress backup.tar.gz.age --decrypt key.txt")
+            .class(ErrorClass::BadArguments)
+            .into()
+    })
+}
+
+/// Recipient in the format accepted by `--encrypt`, like `age:age1...`, the `age:` prefix is optional
+#[cfg(feature = "age")]
+fn strip_recipient_prefix(recipient: &str) -> &str {
+    recipient.strip_prefix("age:").unwrap_or(recipient)
+}
+
+/// Writes the age encrypted stream to the inner writer, finishing it when dropped like the
+/// other encoders in the chain
+#[cfg(feature = "age")]
+struct AutoFinishEncryptor<W: Write>(Option<age::stream::StreamWriter<W>>);
+
+#[cfg(feature = "age")]
+impl<W: Write> Write for AutoFinishEncryptor<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .as_mut()
+            .expect("encryptor is only taken when dropped")
+            .write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.as_mut().expect("encryptor is only taken when dropped").flush()
+    }
+}
+
+#[cfg(feature = "age")]
+impl<W: Write> Drop for AutoFinishEncryptor<W> {
+    fn drop(&mut self) {
+        if let Some(encryptor) = self.0.take() {
+            let _ = encryptor.finish();
+        }
+    }
+}
+
+/// Wrap `writer` with an encryptor to each of the `recipients`, there should be at least one
+#[cfg(feature = "age")]
+pub fn encrypt<W: Write + Send + 'static>(writer: W, recipients: &[String]) -> crate::Result<Box<dyn Write + Send>> {
+    let recipients = recipients
+        .iter()
+        .map(|recipient| {
+            strip_recipient_prefix(recipient)
+                .parse::<age::x25519::Recipient>()
+                .map_err(|err| {
+                    FinalError::with_title(format!("Invalid recipient '{recipient}'"))
+                        .detail(err)
+                        .hint("Recipients are age public keys, like 'age:age1...'")
+                        .class(ErrorClass::BadArguments)
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let encryptor =
+        age::Encryptor::with_recipients(recipients.iter().map(|recipient| recipient as &dyn age::Recipient))
+            .map_err(|err| FinalError::with_title("Could not encrypt").detail(err.to_string()))?;
+
+    Ok(Box::new(AutoFinishEncryptor(Some(encryptor.wrap_output(writer)?))))
+}
+
+/// Wrap `reader` with a decryptor, using the identities in the file given to `--decrypt`
+#[cfg(feature = "age")]
+pub fn decrypt<R: Read + Send + 'static>(reader: R) -> crate::Result<Box<dyn Read + Send>> {
+    let identity_file = identity_file()?;
+    let decryption_error = |err: age::DecryptError| -> Error {
+        FinalError::with_title("Could not decrypt")
+            .detail(err.to_string())
+            .hint("Check if the identity passed to '--decrypt' is one the file was encrypted to")
+            .into()
+    };
+
+    let identities = age::IdentityFile::from_file(identity_file.to_string_lossy().into_owned())
+        .map_err(|err| {
+            FinalError::with_title(format!(
+                "Could not read the identity file {}",
+                crate::utils::EscapedPathDisplay::new(&identity_file)
+            ))
+            .detail(err.to_string())
+            .class(ErrorClass::Io)
+        })?
+        .into_identities()
+        .map_err(decryption_error)?;
+
+    let decryptor = age::Decryptor::new(reader).map_err(decryption_error)?;
+    let reader = decryptor
+        .decrypt(
+            identities
+                .iter()
+                .map(|identity| identity.as_ref() as &dyn age::Identity),
+        )
+        .map_err(decryption_error)?;
+
+    Ok(Box::new(reader))
+}
+
+/// Error returned when `.age` files are used in builds without the `age` feature
+#[cfg(not(feature = "age"))]
+fn no_support() -> Error {
+    Error::UnsupportedFormat {
+        reason: "Encryption with age is disabled for this build, it requires the `age` feature.".into(),
+    }
+}
+
+#[cfg(not(feature = "age"))]
+pub fn encrypt<W: Write + Send + 'static>(_: W, _: &[String]) -> crate::Result<Box<dyn Write + Send>> {
+    Err(no_support())
+}
+
+#[cfg(not(feature = "age"))]
+pub fn decrypt<R: Read + Send + 'static>(_: R) -> crate::Result<Box<dyn Read + Send>> {
+    Err(no_support())
+}
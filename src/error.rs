@@ -47,6 +47,56 @@ pub enum Error {
     UnsupportedFormat { reason: String },
     /// Invalid password provided
     InvalidPassword { reason: String },
+    /// The password doesn't decrypt the archive, reported by its format as corrupted data
+    WrongPassword,
+    /// Stopped by the cancellation token given to the library API
+    Cancelled,
+}
+
+/// Classes of failures, each with its own exit code
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Failures that don't fit the other classes
+    #[default]
+    Other,
+    /// Invalid command line arguments, or arguments that don't make sense together
+    BadArguments,
+    /// The format is recognised but isn't supported, or couldn't be detected
+    UnsupportedFormat,
+    /// The archive or compressed stream is truncated or corrupted
+    CorruptArchive,
+    /// Errors from the file system
+    Io,
+    /// Some of the inputs of a command failed, and the others succeeded
+    PartialFailure,
+}
+
+impl ErrorClass {
+    /// Stable name of the class, for scripts
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Other => "other",
+            Self::BadArguments => "bad-arguments",
+            Self::UnsupportedFormat => "unsupported-format",
+            Self::CorruptArchive => "corrupt-archive",
+            Self::Io => "io",
+            Self::PartialFailure => "partial-failure",
+        }
+    }
+
+    /// Exit code of the process when failing with an error of this class
+    ///
+    /// Invalid arguments use the same code as the argument parser does.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Other => 1,
+            Self::BadArguments => 2,
+            Self::UnsupportedFormat => 3,
+            Self::CorruptArchive => 4,
+            Self::Io => 5,
+            Self::PartialFailure => 6,
+        }
+    }
 }
 
 /// Alias to std's Result with ouch's Error
@@ -64,6 +114,8 @@ pub struct FinalError {
     details: Vec<CowStr>,
     /// Shown as green at the end to give hints on how to work around this error, if it's fixable
     hints: Vec<CowStr>,
+    /// Decides the exit code
+    class: ErrorClass,
 }
 
 impl Display for FinalError {
@@ -114,6 +166,7 @@ impl FinalError {
             title: title.into(),
             details: vec![],
             hints: vec![],
+            class: ErrorClass::Other,
         }
     }
 
@@ -131,10 +184,33 @@ impl FinalError {
         self
     }
 
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// Set the class of the error, which decides the exit code
+    #[must_use]
+    pub fn class(mut self, class: ErrorClass) -> Self {
+        self.class = class;
+        self
+    }
+
+    /// Format the error as a JSON object, without colors
+    pub fn to_json(&self, code: &str) -> serde_json::Value {
+        serde_json::json!({
+            "code": code,
+            "class": self.class.name(),
+            "exit_code": self.class.exit_code(),
+            "title": self.title,
+            "details": self.details,
+            "hints": self.hints,
+        })
+    }
+
     /// Adds all supported formats as hints.
     ///
     /// This is what it looks like:
-    /// ```
+    /// ```text
     /// hint: Supported extensions are: tar, zip, bz, bz2, gz, lz4, xz, lzma, sz, zst
     /// hint: Supported aliases are: tgz, tbz, tlz4, txz, tzlma, tsz, tzst
     /// ```
@@ -176,10 +252,73 @@ impl From<Error> for FinalError {
                 FinalError::with_title("Recognised but unsupported format").detail(reason.clone())
             }
             Error::InvalidPassword { reason } => FinalError::with_title("Invalid password").detail(reason.clone()),
+            Error::WrongPassword => FinalError::with_title("Wrong password")
+                .detail("The password doesn't decrypt the archive")
+                .hint("Try again with the right password, given to '--password', '--password-file' or")
+                .hint("'--password-prompt'"),
+            Error::Cancelled => FinalError::with_title("Operation cancelled"),
         }
     }
 }
 
+impl Error {
+    /// The archive is encrypted, and no password was given
+    pub fn password_required() -> Self {
+        FinalError::with_title("Cannot read an encrypted archive without its password")
+            .hint("Pass it with '--password', '--password-file' or '--password-prompt'")
+            .class(ErrorClass::BadArguments)
+            .into()
+    }
+
+    /// Stable identifier of the error, for scripts
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::IoError { .. } => "io-error",
+            Error::Lz4Error { .. } => "lz4-error",
+            Error::NotFound { .. } => "not-found",
+            Error::AlreadyExists { .. } => "already-exists",
+            Error::InvalidZipArchive(_) => "invalid-zip-archive",
+            Error::PermissionDenied { .. } => "permission-denied",
+            Error::UnsupportedZipArchive(_) => "unsupported-zip-archive",
+            Error::CompressingRootFolder => "compressing-root-folder",
+            Error::WalkdirError { .. } => "walkdir-error",
+            Error::Custom { .. } => "custom",
+            Error::InvalidFormatFlag { .. } => "invalid-format-flag",
+            Error::SevenzipError { .. } => "sevenzip-error",
+            Error::UnsupportedFormat { .. } => "unsupported-format",
+            Error::InvalidPassword { .. } => "invalid-password",
+            Error::WrongPassword => "wrong-password",
+            Error::Cancelled => "cancelled",
+        }
+    }
+
+    /// Class of the failure, which decides the exit code
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Error::IoError { .. }
+            | Error::NotFound { .. }
+            | Error::AlreadyExists { .. }
+            | Error::PermissionDenied { .. }
+            | Error::WalkdirError { .. } => ErrorClass::Io,
+            Error::Lz4Error { .. } | Error::InvalidZipArchive(_) | Error::SevenzipError { .. } => {
+                ErrorClass::CorruptArchive
+            }
+            Error::UnsupportedZipArchive(_) | Error::UnsupportedFormat { .. } => ErrorClass::UnsupportedFormat,
+            Error::CompressingRootFolder
+            | Error::InvalidFormatFlag { .. }
+            | Error::InvalidPassword { .. }
+            | Error::WrongPassword => ErrorClass::BadArguments,
+            Error::Custom { reason } => reason.class,
+            Error::Cancelled => ErrorClass::Other,
+        }
+    }
+
+    /// Format the error as a JSON object, see [`FinalError::to_json`]
+    pub fn to_json(&self) -> serde_json::Value {
+        FinalError::from(self.clone()).class(self.class()).to_json(self.code())
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let err = FinalError::from(self.clone());
@@ -206,7 +345,10 @@ impl From<bzip3::Error> for Error {
         match err {
             Bz3Error::Io(inner) => inner.into(),
             Bz3Error::BlockSize | Bz3Error::ProcessBlock(_) | Bz3Error::InvalidSignature => {
-                FinalError::with_title("bzip3 error").detail(err.to_string()).into()
+                FinalError::with_title("bzip3 error")
+                    .detail(err.to_string())
+                    .class(ErrorClass::CorruptArchive)
+                    .into()
             }
         }
     }
@@ -219,8 +361,11 @@ impl From<zip::result::ZipError> for Error {
             ZipError::Io(io_err) => Self::from(io_err),
             ZipError::InvalidArchive(filename) => Self::InvalidZipArchive(filename),
             ZipError::FileNotFound => Self::Custom {
-                reason: FinalError::with_title("Unexpected error in zip archive").detail("File not found"),
+                reason: FinalError::with_title("Unexpected error in zip archive")
+                    .detail("File not found")
+                    .class(ErrorClass::CorruptArchive),
             },
+            ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED) => Self::password_required(),
             ZipError::UnsupportedArchive(filename) => Self::UnsupportedZipArchive(filename),
         }
     }
@@ -229,16 +374,27 @@ impl From<zip::result::ZipError> for Error {
 #[cfg(feature = "unrar")]
 impl From<unrar::error::UnrarError> for Error {
     fn from(err: unrar::error::UnrarError) -> Self {
-        Self::Custom {
-            reason: FinalError::with_title("Unexpected error in rar archive").detail(format!("{:?}", err.code)),
+        use unrar::error::Code;
+        match err.code {
+            Code::MissingPassword => Self::password_required(),
+            Code::BadPassword => Self::WrongPassword,
+            code => Self::Custom {
+                reason: FinalError::with_title("Unexpected error in rar archive")
+                    .detail(format!("{code:?}"))
+                    .class(ErrorClass::CorruptArchive),
+            },
         }
     }
 }
 
 impl From<sevenz_rust::Error> for Error {
     fn from(err: sevenz_rust::Error) -> Self {
-        Self::SevenzipError {
-            reason: err.to_string(),
+        match err {
+            sevenz_rust::Error::PasswordRequired => Self::password_required(),
+            sevenz_rust::Error::MaybeBadPassword(_) => Self::WrongPassword,
+            err => Self::SevenzipError {
+                reason: err.to_string(),
+            },
         }
     }
 }
@@ -1,11 +1,20 @@
 //! Our representation of all the supported compression formats.
 
-use std::{ffi::OsStr, fmt, path::Path};
+use std::{
+    ffi::OsStr,
+    fmt,
+    path::Path,
+    sync::{PoisonError, RwLock},
+};
 
 use bstr::ByteSlice;
 use CompressionFormat::*;
 
-use crate::{error::Error, utils::logger::warning};
+use crate::{
+    codec::{self, CodecId},
+    error::{Error, ErrorClass, FinalError},
+    utils::logger::warning,
+};
 
 pub const SUPPORTED_EXTENSIONS: &[&str] = &[
     "tar",
@@ -21,16 +30,21 @@ pub const SUPPORTED_EXTENSIONS: &[&str] = &[
     #[cfg(feature = "unrar")]
     "rar",
     "7z",
+    "Z",
+    #[cfg(feature = "lzo")]
+    "lzo",
+    #[cfg(feature = "age")]
+    "age",
 ];
 
-pub const SUPPORTED_ALIASES: &[&str] = &["tgz", "tbz", "tlz4", "txz", "tzlma", "tsz", "tzst"];
+pub const SUPPORTED_ALIASES: &[&str] = &["tgz", "tbz", "tlz4", "txz", "tzlma", "tsz", "tzst", "taz"];
 
 #[cfg(not(feature = "unrar"))]
-pub const PRETTY_SUPPORTED_EXTENSIONS: &str = "tar, zip, bz, bz2, bz3, gz, lz4, xz, lzma, sz, zst, 7z";
+pub const PRETTY_SUPPORTED_EXTENSIONS: &str = "tar, zip, bz, bz2, bz3, gz, lz4, xz, lzma, sz, zst, 7z, Z";
 #[cfg(feature = "unrar")]
-pub const PRETTY_SUPPORTED_EXTENSIONS: &str = "tar, zip, bz, bz2, bz3, gz, lz4, xz, lzma, sz, zst, rar, 7z";
+pub const PRETTY_SUPPORTED_EXTENSIONS: &str = "tar, zip, bz, bz2, bz3, gz, lz4, xz, lzma, sz, zst, rar, 7z, Z";
 
-pub const PRETTY_SUPPORTED_ALIASES: &str = "tgz, tbz, tlz4, txz, tzlma, tsz, tzst";
+pub const PRETTY_SUPPORTED_ALIASES: &str = "tgz, tbz, tlz4, txz, tzlma, tsz, tzst, taz";
 
 /// A wrapper around `CompressionFormat` that allows combinations like `tgz`
 #[derive(Debug, Clone)]
@@ -81,11 +95,13 @@ pub enum CompressionFormat {
     Bzip3,
     /// .lz4
     Lz4,
-    /// .xz .lzma
+    /// .xz
     Lzma,
+    /// .lzma, the legacy format of LZMA Utils
+    LzmaAlone,
     /// .sz
     Snappy,
-    /// tar, tgz, tbz, tbz2, tbz3, txz, tlz4, tlzma, tsz, tzst
+    /// tar, tgz, tbz, tbz2, tbz3, txz, tlz4, tlzma, tsz, tzst, taz, tzo, tlrz
     Tar,
     /// .zst
     Zstd,
@@ -96,50 +112,120 @@ pub enum CompressionFormat {
     Rar,
     /// .7z
     SevenZip,
+    /// .Z, made by the Unix `compress`, only decompressed
+    Lzw,
+    // like age, recognised even if built without LZO support
+    /// .lzo
+    Lzop,
+    // recognised to explain that it isn't supported
+    /// .lrz
+    Lrzip,
+    // recognised to explain that it isn't supported
+    /// .squashfs .sqfs
+    Squashfs,
+    // like RAR, recognised even if built without age support
+    /// .age, encryption instead of compression
+    Age,
+    /// Format of a codec registered with [`crate::register_codec`]
+    External(CodecId),
 }
 
 impl CompressionFormat {
     /// Currently supported archive formats are .tar (and aliases to it) and .zip
-    fn is_archive_format(&self) -> bool {
+    pub fn is_archive_format(&self) -> bool {
         // Keep this match like that without a wildcard `_` so we don't forget to update it
         match self {
-            Tar | Zip | Rar | SevenZip => true,
+            Tar | Zip | Rar | SevenZip | Squashfs => true,
             Gzip => false,
             Bzip => false,
             Bzip3 => false,
             Lz4 => false,
             Lzma => false,
+            LzmaAlone => false,
             Snappy => false,
             Zstd => false,
+            Age => false,
+            Lzw => false,
+            Lzop => false,
+            Lrzip => false,
+            External(_) => false,
         }
     }
 }
 
+/// Extensions given to `--map`, and the formats each one is made of
+static CUSTOM_EXTENSIONS: RwLock<Vec<(String, &'static [CompressionFormat])>> = RwLock::new(Vec::new());
+
+/// Register the extensions given to `--map`, like "pkg" for "tar.zst", detected like the
+/// built-in ones and taking precedence over them. The ones of an earlier run are replaced
+pub fn set_custom_extensions(mappings: &[(String, String)]) -> crate::Result<()> {
+    let custom_extensions = mappings
+        .iter()
+        .map(|(extension, format)| match parse_format_flag(OsStr::new(format)) {
+            // Leaked like the built-in formats are static, only a few bytes for each run
+            Ok(extensions) => Ok((extension.clone(), &*flatten_compression_formats(&extensions).leak())),
+            Err(Error::InvalidFormatFlag { reason, .. }) => Err(FinalError::with_title(format!(
+                "Failed to parse `--map {extension}={format}`"
+            ))
+            .detail(reason)
+            .hint_all_supported_formats()
+            .class(ErrorClass::BadArguments)
+            .into()),
+            Err(err) => Err(err),
+        })
+        .collect::<crate::Result<_>>()?;
+
+    *CUSTOM_EXTENSIONS.write().unwrap_or_else(PoisonError::into_inner) = custom_extensions;
+    Ok(())
+}
+
 fn to_extension(ext: &[u8]) -> Option<Extension> {
-    Some(Extension::new(
-        match ext {
-            b"tar" => &[Tar],
-            b"tgz" => &[Tar, Gzip],
-            b"tbz" | b"tbz2" => &[Tar, Bzip],
-            b"tbz3" => &[Tar, Bzip3],
-            b"tlz4" => &[Tar, Lz4],
-            b"txz" | b"tlzma" => &[Tar, Lzma],
-            b"tsz" => &[Tar, Snappy],
-            b"tzst" => &[Tar, Zstd],
-            b"zip" => &[Zip],
-            b"bz" | b"bz2" => &[Bzip],
-            b"bz3" => &[Bzip3],
-            b"gz" => &[Gzip],
-            b"lz4" => &[Lz4],
-            b"xz" | b"lzma" => &[Lzma],
-            b"sz" => &[Snappy],
-            b"zst" => &[Zstd],
-            b"rar" => &[Rar],
-            b"7z" => &[SevenZip],
-            _ => return None,
-        },
-        ext.to_str_lossy(),
-    ))
+    let custom_extensions = CUSTOM_EXTENSIONS.read().unwrap_or_else(PoisonError::into_inner);
+    let custom = custom_extensions
+        .iter()
+        .find(|(extension, _)| extension.as_bytes() == ext);
+    if let Some((extension, formats)) = custom {
+        return Some(Extension::new(formats, extension));
+    }
+
+    let formats = builtin_formats(ext).or_else(|| codec::formats_from_extension(ext))?;
+    Some(Extension::new(formats, ext.to_str_lossy()))
+}
+
+/// Formats of the built-in extension `ext`
+pub fn builtin_formats(ext: &[u8]) -> Option<&'static [CompressionFormat]> {
+    let formats: &'static [CompressionFormat] = match ext {
+        b"tar" => &[Tar],
+        b"tgz" => &[Tar, Gzip],
+        b"tbz" | b"tbz2" => &[Tar, Bzip],
+        b"tbz3" => &[Tar, Bzip3],
+        b"tlz4" => &[Tar, Lz4],
+        b"txz" => &[Tar, Lzma],
+        b"tlzma" => &[Tar, LzmaAlone],
+        b"tsz" => &[Tar, Snappy],
+        b"tzst" => &[Tar, Zstd],
+        b"taz" => &[Tar, Lzw],
+        b"tzo" => &[Tar, Lzop],
+        b"tlrz" => &[Tar, Lrzip],
+        b"zip" => &[Zip],
+        b"bz" | b"bz2" => &[Bzip],
+        b"bz3" => &[Bzip3],
+        b"gz" => &[Gzip],
+        b"lz4" => &[Lz4],
+        b"xz" => &[Lzma],
+        b"lzma" => &[LzmaAlone],
+        b"sz" => &[Snappy],
+        b"zst" => &[Zstd],
+        b"rar" => &[Rar],
+        b"7z" => &[SevenZip],
+        b"Z" => &[Lzw],
+        b"lzo" => &[Lzop],
+        b"lrz" => &[Lrzip],
+        b"squashfs" | b"sqfs" => &[Squashfs],
+        b"age" => &[Age],
+        _ => return None,
+    };
+    Some(formats)
 }
 
 fn split_extension(name: &mut &[u8]) -> Option<Extension> {
@@ -183,7 +269,9 @@ pub fn parse_format_flag(input: &OsStr) -> crate::Result<Vec<Extension>> {
 
 /// Extracts extensions from a path.
 ///
-/// Returns both the remaining path and the list of extension objects
+/// Returns both the remaining path and the list of extension objects. Extensions are taken from
+/// the right and the first unknown one stops the search, so the dotted parts of names like
+/// "ouch-0.5.1.tar.gz" stay in the name.
 pub fn separate_known_extensions_from_name(path: &Path) -> (&Path, Vec<Extension>) {
     let mut extensions = vec![];
 
@@ -232,35 +320,24 @@ pub fn flatten_compression_formats(extensions: &[Extension]) -> Vec<CompressionF
 /// Builds a suggested output file in scenarios where the user tried to compress
 /// a folder into a non-archive compression format, for error message purposes
 ///
+/// The suggestion goes before the chain of known extensions at the end of the file name, like
+/// [`separate_known_extensions_from_name`] finds it.
+///
 /// E.g.: `build_suggestion("file.bz.xz", ".tar")` results in `Some("file.tar.bz.xz")`
 pub fn build_archive_file_suggestion(path: &Path, suggested_extension: &str) -> Option<String> {
-    let path = path.to_string_lossy();
-    let mut rest = &*path;
-    let mut position_to_insert = 0;
-
-    // Walk through the path to find the first supported compression extension
-    while let Some(pos) = rest.find('.') {
-        // Use just the text located after the dot we found
-        rest = &rest[pos + 1..];
-        position_to_insert += pos + 1;
-
-        // If the string contains more chained extensions, clip to the immediate one
-        let maybe_extension = {
-            let idx = rest.find('.').unwrap_or(rest.len());
-            &rest[..idx]
-        };
-
-        // If the extension we got is a supported extension, generate the suggestion
-        // at the position we found
-        if SUPPORTED_EXTENSIONS.contains(&maybe_extension) || SUPPORTED_ALIASES.contains(&maybe_extension) {
-            let mut path = path.to_string();
-            path.insert_str(position_to_insert - 1, suggested_extension);
-
-            return Some(path);
-        }
+    let (name, extensions) = separate_known_extensions_from_name(path);
+    if extensions.is_empty() {
+        return None;
     }
 
-    None
+    let path_bytes = path.as_os_str().as_encoded_bytes();
+    let file_name = path.file_name()?.as_encoded_bytes();
+    // The file name starts with the name left without the extensions
+    let position_to_insert = path_bytes.rfind(file_name)? + name.as_os_str().len();
+
+    let mut suggestion = path_bytes.to_vec();
+    suggestion.splice(position_to_insert..position_to_insert, suggested_extension.bytes());
+    Some(String::from_utf8_lossy(&suggestion).into_owned())
 }
 
 #[cfg(test)]
@@ -311,6 +388,50 @@ mod tests {
         );
     }
 
+    #[test]
+    /// Dotted version numbers and other unknown extensions stay in the name
+    fn test_separate_known_extensions_from_dotted_name() {
+        let _handler = spawn_logger_thread();
+        let tar_gz = || vec![Extension::new(&[Tar], "tar"), Extension::new(&[Gzip], "gz")];
+
+        assert_eq!(
+            separate_known_extensions_from_name("name.1.2.tar.gz".as_ref()),
+            ("name.1.2".as_ref(), tar_gz())
+        );
+        assert_eq!(
+            separate_known_extensions_from_name("ouch-0.5.1.tar.gz".as_ref()),
+            ("ouch-0.5.1".as_ref(), tar_gz())
+        );
+        assert_eq!(
+            separate_known_extensions_from_name("name.1.2".as_ref()),
+            ("name.1.2".as_ref(), vec![])
+        );
+        assert_eq!(
+            separate_known_extensions_from_name("name.tar.gz.1".as_ref()),
+            ("name.tar.gz.1".as_ref(), vec![])
+        );
+        assert_eq!(
+            separate_known_extensions_from_name("name.tar.1.gz".as_ref()),
+            ("name.tar.1".as_ref(), vec![Extension::new(&[Gzip], "gz")])
+        );
+        assert_eq!(
+            separate_known_extensions_from_name("name.zip.v2.tar.gz".as_ref()),
+            ("name.zip.v2".as_ref(), tar_gz())
+        );
+        assert_eq!(
+            separate_known_extensions_from_name("1.2.tar.gz".as_ref()),
+            ("1.2".as_ref(), tar_gz())
+        );
+        assert_eq!(
+            separate_known_extensions_from_name("v1.2/name.1.2.tar.gz".as_ref()),
+            ("name.1.2".as_ref(), tar_gz())
+        );
+        assert_eq!(
+            separate_known_extensions_from_name("name.1.7z".as_ref()),
+            ("name.1".as_ref(), vec![Extension::new(&[SevenZip], "7z")])
+        );
+    }
+
     #[test]
     /// Test extension parsing of `--format FORMAT`
     fn test_parse_of_format_flag() {
@@ -362,4 +483,29 @@ mod tests {
             "linux.pkg.info.tar.zst"
         );
     }
+
+    #[test]
+    fn builds_suggestion_after_unknown_extensions() {
+        let _handler = spawn_logger_thread();
+        assert_eq!(build_archive_file_suggestion(Path::new("name.1.2"), ".tar"), None);
+        assert_eq!(
+            build_archive_file_suggestion(Path::new("name.1.2.gz"), ".tar").unwrap(),
+            "name.1.2.tar.gz"
+        );
+        assert_eq!(
+            build_archive_file_suggestion(Path::new("name.1.2.xz.zst"), ".tar").unwrap(),
+            "name.1.2.tar.xz.zst"
+        );
+        // Known extensions before an unknown one are part of the name
+        assert_eq!(
+            build_archive_file_suggestion(Path::new("name.gz.1.2.zst"), ".tar").unwrap(),
+            "name.gz.1.2.tar.zst"
+        );
+        assert_eq!(build_archive_file_suggestion(Path::new("name.gz.1"), ".tar"), None);
+        // Only the file name is looked at, not its directories
+        assert_eq!(
+            build_archive_file_suggestion(Path::new("dir.gz/v1.2/name.3.zst"), ".tar").unwrap(),
+            "dir.gz/v1.2/name.3.tar.zst"
+        );
+    }
 }
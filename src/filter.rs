@@ -0,0 +1,260 @@
+//! External programs given to `--filter-cmd`, run instead of the built-in codec of the outermost
+//! format, like `--use-compress-program` of GNU tar.
+//!
+//! The stream is piped through the program, which compresses its stdin to its stdout, and
+//! decompresses it when `-d` is appended to its arguments.
+
+use std::{
+    io::{self, Read, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    thread::{self, JoinHandle},
+};
+
+use crate::error::{ErrorClass, FinalError};
+
+/// Program and arguments given to `--filter-cmd`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterCommand {
+    program: String,
+    args: Vec<String>,
+}
+
+impl FilterCommand {
+    /// Split `command` into the program and its arguments at whitespace, it isn't run by a shell
+    pub fn parse(command: &str) -> crate::Result<Self> {
+        let mut words = command.split_whitespace().map(str::to_string);
+        let Some(program) = words.next() else {
+            return Err(FinalError::with_title("Invalid '--filter-cmd'")
+                .detail("The command is empty")
+                .class(ErrorClass::BadArguments)
+                .into());
+        };
+
+        Ok(Self {
+            program,
+            args: words.collect(),
+        })
+    }
+
+    fn spawn(&self, decompress: bool) -> crate::Result<Child> {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        if decompress {
+            command.arg("-d");
+        }
+
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| {
+                FinalError::with_title(format!("Failed to run '{}'", self.program))
+                    .detail(err.to_string())
+                    .hint("Check that '--filter-cmd' names a program in your PATH")
+                    .class(ErrorClass::BadArguments)
+                    .into()
+            })
+    }
+
+    /// Error for the program exiting with `status`, or stopping before reading all of its input
+    fn failed(&self, status: Option<std::process::ExitStatus>) -> io::Error {
+        match status {
+            Some(status) => io::Error::other(format!("'{}' failed with {status}", self.program)),
+            None => io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                format!("'{}' exited before reading all of its input", self.program),
+            ),
+        }
+    }
+}
+
+/// Run `command` to compress what's written to the returned writer into `writer`.
+///
+/// The program is only done once the writer is dropped and [`FilterEncoder::finish`] returns,
+/// which reports its failures.
+pub fn encoder(
+    command: &FilterCommand,
+    mut writer: Box<dyn Write + Send>,
+) -> crate::Result<(FilterInput, FilterEncoder)> {
+    let mut child = command.spawn(false)?;
+    let stdin = child.stdin.take().expect("stdin is piped");
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+
+    let copier = thread::spawn(move || {
+        io::copy(&mut stdout, &mut writer)?;
+        writer.flush()
+    });
+
+    let input = FilterInput {
+        stdin,
+        command: command.clone(),
+    };
+    let encoder = FilterEncoder {
+        child,
+        copier: Some(copier),
+        command: command.clone(),
+    };
+    Ok((input, encoder))
+}
+
+/// Stdin of the program run by [`encoder`]
+pub struct FilterInput {
+    stdin: ChildStdin,
+    command: FilterCommand,
+}
+
+impl Write for FilterInput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdin.write(buf).map_err(|err| match err.kind() {
+            io::ErrorKind::BrokenPipe => self.command.failed(None),
+            _ => err,
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin.flush()
+    }
+}
+
+/// Program run by [`encoder`], killed if dropped before finishing
+pub struct FilterEncoder {
+    child: Child,
+    copier: Option<JoinHandle<io::Result<()>>>,
+    command: FilterCommand,
+}
+
+impl FilterEncoder {
+    /// Wait for the program to write all of its output, once its [`FilterInput`] is dropped
+    pub fn finish(mut self) -> crate::Result<()> {
+        let status = self.child.wait()?;
+        // Its stdout is closed once it exits, so copying ends too
+        let copied = self.copier.take().map_or(Ok(()), |copier| {
+            copier
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("copying thread panicked")))
+        });
+
+        if !status.success() {
+            return Err(self.command.failed(Some(status)).into());
+        }
+        Ok(copied?)
+    }
+}
+
+impl Drop for FilterEncoder {
+    fn drop(&mut self) {
+        // Does nothing after `finish`, the program already exited
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Run `command` with `-d` to decompress `reader`
+pub fn decoder(command: &FilterCommand, mut reader: Box<dyn Read + Send>) -> crate::Result<FilterDecoder> {
+    let mut child = command.spawn(true)?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let stdout = child.stdout.take().expect("stdout is piped");
+
+    let feeder = thread::spawn(move || match io::copy(&mut reader, &mut stdin) {
+        // The program can stop reading once it's done, its exit status tells if that's a failure
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result.map(|_| ()),
+    });
+
+    Ok(FilterDecoder {
+        child,
+        stdout,
+        feeder: Some(feeder),
+        command: command.clone(),
+    })
+}
+
+/// Output of the program run by [`decoder`], failing at the end if the program failed
+pub struct FilterDecoder {
+    child: Child,
+    stdout: ChildStdout,
+    feeder: Option<JoinHandle<io::Result<()>>>,
+    command: FilterCommand,
+}
+
+impl Read for FilterDecoder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+        if read > 0 || buf.is_empty() {
+            return Ok(read);
+        }
+
+        // The end of the output, checked once
+        if let Some(feeder) = self.feeder.take() {
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(self.command.failed(Some(status)));
+            }
+            feeder
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("feeding thread panicked")))?;
+        }
+        Ok(0)
+    }
+}
+
+impl Drop for FilterDecoder {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// Runs `cat`, ignoring the `-d` appended when decompressing
+    fn cat() -> FilterCommand {
+        FilterCommand::parse("sh -c cat").unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let command = FilterCommand::parse("  zstd -T0   --ultra -22 ").unwrap();
+        assert_eq!(command.program, "zstd");
+        assert_eq!(command.args, ["-T0", "--ultra", "-22"]);
+
+        assert!(FilterCommand::parse(" ").is_err());
+    }
+
+    #[test]
+    fn test_encoder_and_decoder() {
+        let data: Vec<u8> = (0..300_000).map(|i| (i % 251) as u8).collect();
+        let output = tempfile::NamedTempFile::new().unwrap();
+
+        let writer = Box::new(output.reopen().unwrap());
+        let (mut input, encoder) = super::encoder(&cat(), writer).unwrap();
+        input.write_all(&data).unwrap();
+        drop(input);
+        encoder.finish().unwrap();
+        assert_eq!(std::fs::read(output.path()).unwrap(), data);
+
+        let mut decoder = super::decoder(&cat(), Box::new(io::Cursor::new(data.clone()))).unwrap();
+        let mut decoded = vec![];
+        decoder.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn test_failing_command() {
+        let failing = FilterCommand::parse("sh -c false").unwrap();
+
+        let (input, encoder) = super::encoder(&failing, Box::new(io::sink())).unwrap();
+        drop(input);
+        assert!(encoder.finish().is_err());
+
+        let mut decoder = super::decoder(&failing, Box::new(io::repeat(0).take(1_000_000))).unwrap();
+        assert!(decoder.read_to_end(&mut vec![]).is_err());
+
+        assert!(FilterCommand::parse("ouch-missing-program")
+            .unwrap()
+            .spawn(false)
+            .is_err());
+    }
+}
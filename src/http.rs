@@ -0,0 +1,135 @@
+//! Reading inputs straight from HTTP(S) URLs, like `ouch decompress https://example.com/release.tar.gz`.
+//!
+//! The download is streamed into the chain of decoders, like stdin, instead of being saved first.
+//! S3 URIs are downloaded the same way, from a presigned URL, see [`crate::object_storage`].
+
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use crate::{error::Error, object_storage};
+#[cfg(feature = "http")]
+use crate::{
+    error::{ErrorClass, FinalError},
+    utils::{logger::info, Bytes},
+};
+
+/// Whether the input at `path` is an HTTP(S) URL or an S3 URI instead of a file
+pub fn is_url(path: &Path) -> bool {
+    let is_http_url = path
+        .to_str()
+        .is_some_and(|path| path.starts_with("http://") || path.starts_with("https://"));
+    is_http_url || object_storage::is_s3_uri(path)
+}
+
+/// Name of the file at `url`, the last segment of its path without the query, used to detect its
+/// formats and to name the output, empty if the URL has no path
+pub fn url_file_name(url: &Path) -> PathBuf {
+    let url = url.to_str().unwrap_or_default();
+    let url = url.split(['?', '#']).next().unwrap_or_default();
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+
+    // The first segment is the host
+    let name = match url.split_once('/') {
+        Some((_, path)) => path.rsplit('/').next().unwrap_or_default(),
+        None => "",
+    };
+    PathBuf::from(name)
+}
+
+/// Reports how much of a download was read every 10%, when its size is known
+#[cfg(feature = "http")]
+struct DownloadProgress<R> {
+    inner: R,
+    downloaded: u64,
+    size: Option<u64>,
+    reported_tenths: u64,
+    quiet: bool,
+}
+
+#[cfg(feature = "http")]
+impl<R: Read> Read for DownloadProgress<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.downloaded += read as u64;
+
+        if let Some(size) = self.size.filter(|&size| size > 0 && !self.quiet) {
+            let tenths = (self.downloaded * 10 / size).min(10);
+            if tenths > self.reported_tenths {
+                self.reported_tenths = tenths;
+                info(format!("Downloaded {}% of {}", tenths * 10, Bytes::new(size)));
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+/// Start downloading `url`, returning a reader of the response body
+#[cfg(feature = "http")]
+pub fn open_url(url: &Path, quiet: bool) -> crate::Result<Box<dyn Read + Send>> {
+    let download_error = |err: reqwest::Error| -> Error {
+        // Presigned URLs carry a signature that shouldn't be printed
+        FinalError::with_title(format!("Could not download '{}'", url.display()))
+            .detail(err.without_url().to_string())
+            .class(ErrorClass::Io)
+            .into()
+    };
+
+    let presigned_url;
+    let url = if object_storage::is_s3_uri(url) {
+        presigned_url = object_storage::presigned_url(url)?;
+        presigned_url.as_str()
+    } else {
+        url.to_str().expect("URLs are valid UTF-8, checked by `is_url`")
+    };
+
+    // The default timeout of 30 seconds would cut big downloads short
+    let client = reqwest::blocking::Client::builder()
+        .user_agent(concat!("ouch/", env!("CARGO_PKG_VERSION")))
+        .timeout(None)
+        .build()
+        .map_err(download_error)?;
+    let response = client
+        .get(url)
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(download_error)?;
+
+    Ok(Box::new(DownloadProgress {
+        size: response.content_length(),
+        inner: response,
+        downloaded: 0,
+        reported_tenths: 0,
+        quiet,
+    }))
+}
+
+/// Error returned when URLs are used in builds without the `http` feature
+#[cfg(not(feature = "http"))]
+pub fn open_url(_: &Path, _: bool) -> crate::Result<Box<dyn Read + Send>> {
+    Err(Error::UnsupportedFormat {
+        reason: "Reading from URLs is disabled for this build, it requires the `http` feature, or `s3` for S3 URIs."
+            .into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_file_name() {
+        let name = |url: &str| url_file_name(Path::new(url));
+
+        assert_eq!(name("https://example.com/release.tar.gz"), Path::new("release.tar.gz"));
+        assert_eq!(name("http://example.com/a/b/c.zip?token=1#top"), Path::new("c.zip"));
+        assert_eq!(name("https://example.com"), Path::new(""));
+        assert_eq!(name("https://example.com/dir/"), Path::new(""));
+        assert!(is_url(Path::new("https://example.com/release.tar.gz")));
+        assert!(is_url(Path::new("s3://bucket/backups/release.tar.gz")));
+        assert_eq!(name("s3://bucket/backups/release.tar.gz"), Path::new("release.tar.gz"));
+        assert!(!is_url(Path::new("release.tar.gz")));
+    }
+}
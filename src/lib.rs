@@ -0,0 +1,59 @@
+//! Compress and decompress files like the `ouch` command, in the formats given by their extensions.
+//!
+//! The API of the library is made of the items at the root of the crate, like [`compress`],
+//! [`decompress`], [`list_archive`] and [`detect_formats`], and [`register_codec`] adds formats to
+//! them.
+//!
+//! The `cli` feature, enabled by default, builds the `ouch` binary and its argument parsing, it can
+//! be disabled to use the library without `clap`.
+
+// Without the binary, what only its subcommands use is left unused
+#![cfg_attr(not(feature = "cli"), allow(dead_code, unused_imports))]
+
+mod accessible;
+mod api;
+mod archive;
+mod bzip2_parallel;
+mod check;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod cli;
+mod codec;
+mod commands;
+mod encryption;
+mod error;
+mod extension;
+mod filter;
+mod http;
+mod list;
+mod lrzip;
+mod lz4;
+mod lzop;
+mod lzw;
+mod object_storage;
+mod options;
+mod progress;
+mod signature;
+mod snappy;
+mod utils;
+mod zstd_seekable;
+
+use std::{env, path::PathBuf};
+
+use once_cell::sync::Lazy;
+
+use self::utils::{QuestionAction, QuestionPolicy};
+pub use self::{
+    api::{compress, decompress, detect_formats, list_archive, CompressOptions, DecompressOptions},
+    codec::{register_codec, Codec, CodecId},
+    error::{Error, ErrorClass, Result},
+    extension::CompressionFormat,
+    list::FileInArchive,
+    progress::{CancellationToken, ProgressSink},
+};
+
+// Used in BufReader and BufWriter to perform less syscalls
+const BUFFER_CAPACITY: usize = 1024 * 32;
+
+/// Current directory or empty directory
+static CURRENT_DIRECTORY: Lazy<PathBuf> = Lazy::new(|| env::current_dir().unwrap_or_default());
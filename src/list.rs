@@ -3,6 +3,7 @@
 use std::{
     io::{stdout, Write},
     path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use self::tree::Tree;
@@ -13,6 +14,12 @@ use crate::{accessible::is_running_in_accessible_mode, utils::EscapedPathDisplay
 pub struct ListOptions {
     /// Whether to show a tree view
     pub tree: bool,
+    /// Print only the paths, each followed by a NUL character instead of a newline
+    pub print0: bool,
+    /// Print the archive as a line of JSON
+    pub json: bool,
+    /// Print the compression method of each entry and whether it's encrypted
+    pub long: bool,
 }
 
 /// Represents a single file in an archive, used in `list::list_files()`
@@ -23,25 +30,115 @@ pub struct FileInArchive {
 
     /// Whether this file is a directory
     pub is_dir: bool,
+
+    /// Modification time stored in the archive, if any
+    pub mtime: Option<SystemTime>,
+
+    /// Comment stored with the entry, only zip archives have them
+    pub comment: Option<String>,
+
+    /// Compression method of the entry, like "Deflated", for formats that compress each entry on
+    /// its own
+    pub method: Option<String>,
+
+    /// Whether the contents of the entry are encrypted, if the format tells
+    pub encrypted: Option<bool>,
+}
+
+impl FileInArchive {
+    /// Method and encryption of the file shown by `--long`, like "Deflated, encrypted", empty for
+    /// directories
+    fn details(&self) -> String {
+        if self.is_dir {
+            return String::new();
+        }
+        let encrypted = (self.encrypted == Some(true)).then_some("encrypted");
+        self.method
+            .as_deref()
+            .into_iter()
+            .chain(encrypted)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Entries of an archive, with the comment of the archive if its format stores one
+pub struct ArchiveEntries {
+    pub comment: Option<String>,
+    pub files: Box<dyn Iterator<Item = crate::Result<FileInArchive>>>,
+}
+
+impl ArchiveEntries {
+    pub fn without_comment(files: impl Iterator<Item = crate::Result<FileInArchive>> + 'static) -> Self {
+        Self {
+            comment: None,
+            files: Box::new(files),
+        }
+    }
 }
 
 /// Actually print the files
 /// Returns an Error, if one of the files can't be read
-pub fn list_files(
-    archive: &Path,
-    files: impl IntoIterator<Item = crate::Result<FileInArchive>>,
-    list_options: ListOptions,
-) -> crate::Result<()> {
+pub fn list_files(archive: &Path, entries: ArchiveEntries, list_options: ListOptions) -> crate::Result<()> {
+    let ArchiveEntries { comment, files } = entries;
     let out = &mut stdout().lock();
+
+    // Paths are printed as they are, without escaping, so scripts can use them
+    if list_options.print0 {
+        for file in files {
+            let _ = out.write_all(file?.path.as_os_str().as_encoded_bytes());
+            let _ = out.write_all(b"\0");
+        }
+        return Ok(());
+    }
+
+    if list_options.json {
+        let entries = files
+            .map(|file| {
+                let file = file?;
+                let mtime = file
+                    .mtime
+                    .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+                    .map(|mtime| mtime.as_secs());
+
+                Ok(serde_json::json!({
+                    "path": file.path.to_string_lossy(),
+                    "type": if file.is_dir { "directory" } else { "file" },
+                    "mtime": mtime,
+                    "comment": file.comment,
+                    "method": file.method,
+                    "encrypted": file.encrypted,
+                }))
+            })
+            .collect::<crate::Result<Vec<_>>>()?;
+
+        let output = serde_json::json!({
+            "archive": archive.to_string_lossy(),
+            "comment": comment,
+            "entries": entries,
+        });
+        let _ = writeln!(out, "{output}");
+        return Ok(());
+    }
+
     let _ = writeln!(out, "Archive: {}", EscapedPathDisplay::new(archive));
+    if let Some(comment) = comment {
+        let _ = writeln!(out, "Comment: {comment}");
+    }
 
     if list_options.tree {
-        let tree = files.into_iter().collect::<crate::Result<Tree>>()?;
+        let tree = files.collect::<crate::Result<Tree>>()?;
         tree.print(out);
     } else {
         for file in files {
-            let FileInArchive { path, is_dir } = file?;
-            print_entry(out, EscapedPathDisplay::new(&path), is_dir);
+            let file = file?;
+            let details = file.details();
+            let name = EscapedPathDisplay::new(&file.path);
+            if list_options.long && !details.is_empty() {
+                print_entry(out, format_args!("{name} ({details})"), file.is_dir);
+            } else {
+                print_entry(out, name, file.is_dir);
+            }
         }
     }
     Ok(())
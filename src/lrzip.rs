@@ -0,0 +1,19 @@
+//! Files of [lrzip](https://github.com/ckolivas/lrzip), used by the `.lrz` extension.
+//!
+//! lrzip finds long-range repetitions with rzip and compresses what's left with one of several
+//! backends, which ouch has no decoders for. The format is still recognised, by extension and by
+//! the `LRZI` magic bytes, to explain how to unpack these files instead of failing to detect them.
+
+use crate::error::{Error, ErrorClass, FinalError};
+
+/// First bytes of `.lrz` files, followed by the major and minor versions of the format
+pub const MAGIC: &[u8; 4] = b"LRZI";
+
+/// Error returned when a `.lrz` file is compressed or decompressed
+pub fn no_support() -> Error {
+    FinalError::with_title("lrzip files are not supported")
+        .detail("ouch recognises .lrz files, but can't compress or decompress them")
+        .hint("Unpack it with `lrzip -d FILE` first, then use ouch on the result")
+        .class(ErrorClass::UnsupportedFormat)
+        .into()
+}
@@ -0,0 +1,135 @@
+//! The frames of `.lz4` files, and the legacy format written by `lz4 -l` and old versions of the
+//! lz4 tools.
+//!
+//! Legacy files start with their own magic number, followed by blocks of up to 8MiB of data
+//! compressed with the lz4 block format, each one after its compressed size. Another legacy frame
+//! may follow, starting with the magic number again where a block size would be.
+
+use std::io::{self, BufRead, Read, Write};
+
+/// First bytes of `.lz4` files
+pub const MAGIC: [u8; 4] = [0x04, 0x22, 0x4D, 0x18];
+/// First bytes of legacy `.lz4` files
+pub const LEGACY_MAGIC: [u8; 4] = [0x02, 0x21, 0x4C, 0x18];
+
+/// Most data of a block of a legacy file
+const LEGACY_BLOCK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Encoder of lz4 frames, storing `content_size` in the header when it's known, which other
+/// tools use to show their progress
+pub fn encoder<W: Write + Send + 'static>(writer: W, content_size: Option<u64>) -> Box<dyn Write + Send> {
+    let frame_info = lz4_flex::frame::FrameInfo::new().content_size(content_size);
+    Box::new(lz4_flex::frame::FrameEncoder::with_frame_info(frame_info, writer).auto_finish())
+}
+
+/// Decoder of lz4 frames, or of the legacy format if `reader` starts with its magic number
+pub fn decoder<R: BufRead + Send + 'static>(mut reader: R) -> io::Result<Box<dyn Read + Send>> {
+    if reader.fill_buf()?.starts_with(&LEGACY_MAGIC) {
+        Ok(Box::new(LegacyDecoder::new(reader)))
+    } else {
+        Ok(Box::new(lz4_flex::frame::FrameDecoder::new(reader)))
+    }
+}
+
+/// Decodes legacy `.lz4` files, one block at a time
+pub struct LegacyDecoder<R> {
+    inner: R,
+    compressed: Vec<u8>,
+    block: Vec<u8>,
+    /// Bytes of `block` holding the data of the current block
+    len: usize,
+    position: usize,
+}
+
+impl<R: Read> LegacyDecoder<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            compressed: vec![],
+            block: vec![],
+            len: 0,
+            position: 0,
+        }
+    }
+
+    /// Decode the next block, returns `false` at the end of the input
+    fn next_block(&mut self) -> io::Result<bool> {
+        loop {
+            let Some(size) = self.read_block_size()? else {
+                return Ok(false);
+            };
+            if size == LEGACY_MAGIC {
+                continue;
+            }
+
+            let size = u32::from_le_bytes(size) as usize;
+            if size > lz4_flex::block::get_maximum_output_size(LEGACY_BLOCK_SIZE) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "lz4 block is too big"));
+            }
+            self.compressed.resize(size, 0);
+            self.inner.read_exact(&mut self.compressed)?;
+
+            self.block.resize(LEGACY_BLOCK_SIZE, 0);
+            self.len = lz4_flex::block::decompress_into(&self.compressed, &mut self.block)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            self.position = 0;
+            return Ok(true);
+        }
+    }
+
+    /// Read the size of the next block, or the magic number of the next frame, `None` at the end
+    /// of the input
+    fn read_block_size(&mut self) -> io::Result<Option<[u8; 4]>> {
+        let mut size = [0; 4];
+        let mut read = 0;
+        while read < size.len() {
+            match self.inner.read(&mut size[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(len) => read += len,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(Some(size))
+    }
+}
+
+impl<R: Read> Read for LegacyDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.len && !self.next_block()? {
+            return Ok(0);
+        }
+
+        let available = &self.block[self.position..self.len];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_legacy_frames() {
+        let data = "some repeated contents\n".repeat(1000);
+        let block = lz4_flex::block::compress(data.as_bytes());
+
+        let mut legacy = vec![];
+        for _ in 0..2 {
+            legacy.extend_from_slice(&LEGACY_MAGIC);
+            legacy.extend_from_slice(&(block.len() as u32).to_le_bytes());
+            legacy.extend_from_slice(&block);
+        }
+
+        let mut decoded = String::new();
+        decoder(io::Cursor::new(legacy))
+            .unwrap()
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data.repeat(2));
+    }
+}
@@ -0,0 +1,402 @@
+//! The [lzop](https://www.lzop.org) file format, used by the `.lzo` extension.
+//!
+//! Files start with a header with the method and the flags of the file, followed by blocks of
+//! data compressed with LZO1X, each one with its sizes and the checksums chosen by the flags, and
+//! end with an empty block. Blocks that don't shrink are stored as they are.
+//!
+//! LZO is licensed under the GPL, so it's only built with the `lzo` feature.
+
+use std::io::{Read, Write};
+
+#[cfg(not(feature = "lzo"))]
+use crate::error::Error;
+
+/// First bytes of `.lzo` files
+pub const MAGIC: [u8; 9] = [0x89, b'L', b'Z', b'O', 0x00, 0x0d, 0x0a, 0x1a, 0x0a];
+
+/// Name of the method in the header of a `.lzo` file
+pub fn method_name(method: u8) -> Option<&'static str> {
+    match method {
+        1 => Some("LZO1X-1"),
+        2 => Some("LZO1X-1(15)"),
+        3 => Some("LZO1X-999"),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "lzo")]
+pub use self::codec::{LzopDecoder, LzopEncoder};
+
+#[cfg(feature = "lzo")]
+pub fn encoder<W: Write + Send + 'static>(writer: W) -> crate::Result<Box<dyn Write + Send>> {
+    Ok(Box::new(LzopEncoder::new(writer)?))
+}
+
+#[cfg(feature = "lzo")]
+pub fn decoder<R: Read + Send + 'static>(reader: R) -> crate::Result<Box<dyn Read + Send>> {
+    Ok(Box::new(LzopDecoder::new(reader)?))
+}
+
+/// Error returned when `.lzo` files are used in builds without the `lzo` feature
+#[cfg(not(feature = "lzo"))]
+fn no_support() -> Error {
+    Error::UnsupportedFormat {
+        reason: "LZO support is disabled for this build, it requires the `lzo` feature.".into(),
+    }
+}
+
+#[cfg(not(feature = "lzo"))]
+pub fn encoder<W: Write + Send + 'static>(_: W) -> crate::Result<Box<dyn Write + Send>> {
+    Err(no_support())
+}
+
+#[cfg(not(feature = "lzo"))]
+pub fn decoder<R: Read + Send + 'static>(_: R) -> crate::Result<Box<dyn Read + Send>> {
+    Err(no_support())
+}
+
+#[cfg(feature = "lzo")]
+mod codec {
+    use std::io::{self, Read, Write};
+
+    use minilzo_rs::LZO;
+
+    use super::MAGIC;
+
+    /// Version of lzop written in the header, and the oldest one that can read the files
+    const VERSION: u16 = 0x1030;
+    const LIB_VERSION: u16 = 0x2080;
+    const VERSION_NEEDED: u16 = 0x0940;
+    /// Versions older than this one have a shorter header
+    const NEW_HEADER_VERSION: u16 = 0x0940;
+    const METHOD_LZO1X_1: u8 = 1;
+    const LEVEL: u8 = 5;
+
+    const F_ADLER32_D: u32 = 0x1;
+    const F_ADLER32_C: u32 = 0x2;
+    const F_H_EXTRA_FIELD: u32 = 0x40;
+    const F_CRC32_D: u32 = 0x100;
+    const F_CRC32_C: u32 = 0x200;
+    const F_H_FILTER: u32 = 0x800;
+    const F_H_CRC32: u32 = 0x1000;
+    const F_OS_UNIX: u32 = 0x0300_0000;
+
+    /// Uncompressed size of the blocks written, the one used by lzop
+    const BLOCK_SIZE: usize = 256 * 1024;
+    /// Largest block lzop writes, bigger sizes come from corrupted files
+    const MAX_BLOCK_SIZE: usize = 64 * 1024 * 1024;
+
+    /// Adler-32 checksum, used by lzop for the header and the blocks
+    fn adler32(data: &[u8]) -> u32 {
+        const MOD: u32 = 65521;
+        // Largest amount of bytes added up before `b` could overflow
+        const CHUNK_SIZE: usize = 5552;
+
+        let (mut a, mut b) = (1u32, 0u32);
+        for chunk in data.chunks(CHUNK_SIZE) {
+            for &byte in chunk {
+                a += u32::from(byte);
+                b += a;
+            }
+            a %= MOD;
+            b %= MOD;
+        }
+        (b << 16) | a
+    }
+
+    fn corrupted(reason: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, format!("corrupted .lzo file: {reason}"))
+    }
+
+    fn lzo_error(err: minilzo_rs::Error) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, format!("LZO error: {err:?}"))
+    }
+
+    fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        reader.read_exact(&mut bytes)?;
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    /// Read `N` bytes of the header, keeping them in `header` to check its checksum
+    fn read_header_field<const N: usize>(reader: &mut impl Read, header: &mut Vec<u8>) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        reader.read_exact(&mut bytes)?;
+        header.extend_from_slice(&bytes);
+        Ok(bytes)
+    }
+
+    /// Compresses what is written to it in blocks, writing the end of the file when dropped, like the
+    /// other encoders in the chain
+    pub struct LzopEncoder<W: Write> {
+        inner: W,
+        lzo: LZO,
+        buffer: Vec<u8>,
+    }
+
+    impl<W: Write> LzopEncoder<W> {
+        pub fn new(mut inner: W) -> io::Result<Self> {
+            let lzo = LZO::init().map_err(lzo_error)?;
+
+            let mut header = vec![];
+            header.extend_from_slice(&VERSION.to_be_bytes());
+            header.extend_from_slice(&LIB_VERSION.to_be_bytes());
+            header.extend_from_slice(&VERSION_NEEDED.to_be_bytes());
+            header.push(METHOD_LZO1X_1);
+            header.push(LEVEL);
+            header.extend_from_slice(&(F_ADLER32_D | F_OS_UNIX).to_be_bytes());
+            // The mode, modification time (low and high parts) and name of the original file aren't stored
+            header.extend_from_slice(&[0; 12]);
+            header.push(0);
+
+            inner.write_all(&MAGIC)?;
+            inner.write_all(&header)?;
+            inner.write_all(&adler32(&header).to_be_bytes())?;
+
+            Ok(Self {
+                inner,
+                lzo,
+                buffer: vec![],
+            })
+        }
+
+        fn write_block(&mut self, len: usize) -> io::Result<()> {
+            let data = &self.buffer[..len];
+            let compressed = self.lzo.compress(data).map_err(lzo_error)?;
+            let stored = if compressed.len() < data.len() {
+                &compressed
+            } else {
+                data
+            };
+
+            self.inner.write_all(&(data.len() as u32).to_be_bytes())?;
+            self.inner.write_all(&(stored.len() as u32).to_be_bytes())?;
+            self.inner.write_all(&adler32(data).to_be_bytes())?;
+            self.inner.write_all(stored)?;
+
+            self.buffer.drain(..len);
+            Ok(())
+        }
+
+        fn finish(&mut self) -> io::Result<()> {
+            if !self.buffer.is_empty() {
+                self.write_block(self.buffer.len())?;
+            }
+            self.inner.write_all(&0u32.to_be_bytes())?;
+            self.inner.flush()
+        }
+    }
+
+    impl<W: Write> Write for LzopEncoder<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.buffer.extend_from_slice(buf);
+            while self.buffer.len() >= BLOCK_SIZE {
+                self.write_block(BLOCK_SIZE)?;
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            // Blocks are only cut at their size, so only what was already compressed is flushed
+            self.inner.flush()
+        }
+    }
+
+    impl<W: Write> Drop for LzopEncoder<W> {
+        fn drop(&mut self) {
+            let _ = self.finish();
+        }
+    }
+
+    /// Reads the decompressed data of a `.lzo` file, checking the checksums of its blocks
+    pub struct LzopDecoder<R> {
+        inner: R,
+        lzo: LZO,
+        flags: u32,
+        /// Decompressed data of the current block, and how much of it was already read
+        block: Vec<u8>,
+        position: usize,
+        finished: bool,
+    }
+
+    impl<R: Read> LzopDecoder<R> {
+        pub fn new(mut inner: R) -> io::Result<Self> {
+            let mut magic = [0; MAGIC.len()];
+            inner.read_exact(&mut magic)?;
+            if magic != MAGIC {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "not a .lzo file"));
+            }
+
+            let mut header = vec![];
+            let version = u16::from_be_bytes(read_header_field(&mut inner, &mut header)?);
+            let _lib_version: [u8; 2] = read_header_field(&mut inner, &mut header)?;
+            if version >= NEW_HEADER_VERSION {
+                let _version_needed: [u8; 2] = read_header_field(&mut inner, &mut header)?;
+            }
+            let [method] = read_header_field(&mut inner, &mut header)?;
+            if super::method_name(method).is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsupported .lzo file compressed with method {method}"),
+                ));
+            }
+            if version >= NEW_HEADER_VERSION {
+                let _level: [u8; 1] = read_header_field(&mut inner, &mut header)?;
+            }
+            let flags = u32::from_be_bytes(read_header_field(&mut inner, &mut header)?);
+            if flags & F_H_FILTER != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unsupported .lzo file written with a filter",
+                ));
+            }
+            let _mode: [u8; 4] = read_header_field(&mut inner, &mut header)?;
+            let _mtime: [u8; 4] = read_header_field(&mut inner, &mut header)?;
+            if version >= NEW_HEADER_VERSION {
+                let _mtime_high: [u8; 4] = read_header_field(&mut inner, &mut header)?;
+            }
+            let [name_len] = read_header_field(&mut inner, &mut header)?;
+            let mut name = vec![0; name_len.into()];
+            inner.read_exact(&mut name)?;
+            header.extend_from_slice(&name);
+
+            let checksum = if flags & F_H_CRC32 != 0 {
+                crc32fast::hash(&header)
+            } else {
+                adler32(&header)
+            };
+            if read_u32(&mut inner)? != checksum {
+                return Err(corrupted("the checksum of the header doesn't match"));
+            }
+
+            if flags & F_H_EXTRA_FIELD != 0 {
+                // Skip the extra field and its checksum
+                let len = read_u32(&mut inner)?;
+                io::copy(&mut (&mut inner).take(u64::from(len) + 4), &mut io::sink())?;
+            }
+
+            Ok(Self {
+                inner,
+                lzo: LZO::init().map_err(lzo_error)?,
+                flags,
+                block: vec![],
+                position: 0,
+                finished: false,
+            })
+        }
+
+        /// Read the checksum of the flag `adler32_flag` and the one of `crc32_flag`, if they're stored
+        fn read_checksums(&mut self, adler32_flag: u32, crc32_flag: u32) -> io::Result<(Option<u32>, Option<u32>)> {
+            let adler = (self.flags & adler32_flag != 0)
+                .then(|| read_u32(&mut self.inner))
+                .transpose()?;
+            let crc = (self.flags & crc32_flag != 0)
+                .then(|| read_u32(&mut self.inner))
+                .transpose()?;
+            Ok((adler, crc))
+        }
+
+        /// Read the next block, `false` after the last one
+        fn read_block(&mut self) -> io::Result<bool> {
+            let len = read_u32(&mut self.inner)? as usize;
+            if len == 0 {
+                if self.inner.read(&mut [0])? != 0 {
+                    return Err(corrupted("unexpected data after the end of the file"));
+                }
+                return Ok(false);
+            }
+            let compressed_len = read_u32(&mut self.inner)? as usize;
+            if len > MAX_BLOCK_SIZE || compressed_len > len {
+                return Err(corrupted("invalid block size"));
+            }
+
+            let data_checksums = self.read_checksums(F_ADLER32_D, F_CRC32_D)?;
+            // Checksums of the compressed data are only stored for blocks that were compressed
+            let is_compressed = compressed_len < len;
+            let compressed_checksums = if is_compressed {
+                self.read_checksums(F_ADLER32_C, F_CRC32_C)?
+            } else {
+                (None, None)
+            };
+
+            let mut data = vec![0; compressed_len];
+            self.inner.read_exact(&mut data)?;
+
+            let check = |data: &[u8], (adler, crc): (Option<u32>, Option<u32>)| {
+                if adler.is_some_and(|adler| adler != adler32(data))
+                    || crc.is_some_and(|crc| crc != crc32fast::hash(data))
+                {
+                    return Err(corrupted("the checksum of a block doesn't match"));
+                }
+                Ok(())
+            };
+
+            if is_compressed {
+                check(&data, compressed_checksums)?;
+                data = self.lzo.decompress_safe(&data, len).map_err(lzo_error)?;
+                if data.len() != len {
+                    return Err(corrupted("a block is smaller than its size"));
+                }
+            }
+            check(&data, data_checksums)?;
+
+            self.block = data;
+            self.position = 0;
+            Ok(true)
+        }
+    }
+
+    impl<R: Read> Read for LzopDecoder<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            while self.position == self.block.len() {
+                if self.finished || !self.read_block()? {
+                    self.finished = true;
+                    return Ok(0);
+                }
+            }
+
+            let data = &self.block[self.position..];
+            let read = data.len().min(buf.len());
+            buf[..read].copy_from_slice(&data[..read]);
+            self.position += read;
+            Ok(read)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_adler32() {
+            assert_eq!(adler32(b""), 1);
+            assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+            // Long enough to take the modulo of both sums
+            assert_eq!(adler32(&[0xff; 100_000]), 0x149A_302C);
+        }
+
+        #[test]
+        fn test_lzop_round_trip() {
+            let data: Vec<u8> = (0..600_000).map(|i| (i % 251) as u8).collect();
+
+            let mut compressed = vec![];
+            LzopEncoder::new(&mut compressed).unwrap().write_all(&data).unwrap();
+            assert!(compressed.starts_with(&MAGIC));
+            assert!(compressed.len() < data.len());
+
+            let mut decompressed = vec![];
+            LzopDecoder::new(compressed.as_slice())
+                .unwrap()
+                .read_to_end(&mut decompressed)
+                .unwrap();
+            assert_eq!(decompressed, data);
+
+            // Trailing garbage
+            compressed.push(0);
+            assert!(LzopDecoder::new(compressed.as_slice())
+                .unwrap()
+                .read_to_end(&mut vec![])
+                .is_err());
+        }
+    }
+}
@@ -0,0 +1,249 @@
+//! Decoder of `.Z` files, made by the old Unix `compress` with adaptive LZW.
+//!
+//! The header is the magic bytes `1f 9d` and a byte with the maximum code size (9 to 16 bits) and,
+//! in its highest bit, whether the table is reset by CLEAR codes ("block mode"). Codes are packed
+//! LSB first and start at 9 bits, growing by one bit each time the table is full for their size.
+//!
+//! `compress` reads and writes codes in groups of 8, so when the code size changes the rest of
+//! the current group is padding and skipped.
+//!
+//! Only decompression is supported, `.Z` files are only found in old archives nowadays.
+
+use std::io::{self, Read};
+
+use crate::error::Error;
+
+/// First bytes of `.Z` files
+pub const MAGIC: [u8; 2] = [0x1f, 0x9d];
+/// Set in the third byte when CLEAR codes are used
+const BLOCK_MODE: u8 = 0x80;
+/// Mask of the maximum code size in the third byte
+const MAX_BITS_MASK: u8 = 0x1f;
+
+const INIT_BITS: u8 = 9;
+const MAX_BITS: u8 = 16;
+/// Code that resets the table in block mode
+const CLEAR: usize = 256;
+
+pub fn no_compression() -> Error {
+    Error::UnsupportedFormat {
+        reason: "Creating .Z files is not supported, they can only be decompressed.".into(),
+    }
+}
+
+fn corrupted(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("corrupted .Z file: {reason}"))
+}
+
+/// Maximum code size and block mode, read from the third byte of the header
+pub fn parse_flags(flags: u8) -> io::Result<(u8, bool)> {
+    let max_bits = flags & MAX_BITS_MASK;
+    if !(INIT_BITS..=MAX_BITS).contains(&max_bits) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported .Z file with codes of up to {max_bits} bits"),
+        ));
+    }
+    Ok((max_bits, flags & BLOCK_MODE != 0))
+}
+
+/// Reads the decompressed data of a `.Z` file
+pub struct LzwDecoder<R> {
+    inner: R,
+    max_bits: u8,
+    block_mode: bool,
+    /// Size of the codes being read
+    bits: u8,
+    bit_buffer: u32,
+    bit_count: u8,
+    /// Codes read since the code size last changed, to skip the padding of their group
+    group_codes: usize,
+    /// Entries of the table, each one is the code of its prefix followed by a byte
+    prefixes: Vec<u16>,
+    suffixes: Vec<u8>,
+    /// Code of the next entry added to the table
+    next_code: usize,
+    previous: Option<u16>,
+    /// First byte of the last decoded string
+    first_byte: u8,
+    /// Decoded string of the last code, and how much of it was already read
+    output: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> LzwDecoder<R> {
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut header = [0; 3];
+        inner.read_exact(&mut header)?;
+        if header[..2] != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a .Z file"));
+        }
+        let (max_bits, block_mode) = parse_flags(header[2])?;
+
+        Ok(Self {
+            inner,
+            max_bits,
+            block_mode,
+            bits: INIT_BITS,
+            bit_buffer: 0,
+            bit_count: 0,
+            group_codes: 0,
+            prefixes: vec![0; 1 << max_bits],
+            suffixes: (0..1usize << max_bits).map(|code| code as u8).collect(),
+            next_code: if block_mode { CLEAR + 1 } else { CLEAR },
+            previous: None,
+            first_byte: 0,
+            output: vec![],
+            position: 0,
+        })
+    }
+
+    /// Largest code of the current size, the table can't grow past `1 << max_bits` entries.
+    ///
+    /// Like in `compress`, codes still grow to 10 bits when the maximum is 9.
+    fn max_code(&self) -> usize {
+        if self.bits == self.max_bits && self.bits > INIT_BITS {
+            1 << self.max_bits
+        } else {
+            (1 << self.bits) - 1
+        }
+    }
+
+    /// Read the next code, `None` at the end of the input, which may end with a partial code
+    fn read_code(&mut self) -> io::Result<Option<usize>> {
+        while self.bit_count < self.bits {
+            let mut byte = [0];
+            match self.inner.read_exact(&mut byte) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(err) => return Err(err),
+            }
+            self.bit_buffer |= u32::from(byte[0]) << self.bit_count;
+            self.bit_count += 8;
+        }
+
+        let code = self.bit_buffer & ((1 << self.bits) - 1);
+        self.bit_buffer >>= self.bits;
+        self.bit_count -= self.bits;
+        self.group_codes += 1;
+        Ok(Some(code as usize))
+    }
+
+    /// Skip the padding codes left in the current group of 8
+    fn skip_to_group_end(&mut self) -> io::Result<()> {
+        for _ in 0..(8 - self.group_codes % 8) % 8 {
+            if self.read_code()?.is_none() {
+                break;
+            }
+        }
+        self.group_codes = 0;
+        Ok(())
+    }
+
+    /// Decode the next code into `output`, which must be empty, `false` at the end of the input
+    fn decode_next(&mut self) -> io::Result<bool> {
+        if self.next_code > self.max_code() {
+            self.skip_to_group_end()?;
+            self.bits += 1;
+        }
+
+        let Some(code) = self.read_code()? else {
+            return Ok(false);
+        };
+
+        let Some(previous) = self.previous else {
+            if code >= CLEAR {
+                return Err(corrupted("the first code is not a byte"));
+            }
+            self.previous = Some(code as u16);
+            self.first_byte = code as u8;
+            self.output.push(self.first_byte);
+            return Ok(true);
+        };
+
+        if code == CLEAR && self.block_mode {
+            // Like `compress`, the entry after CLEAR is a placeholder that's never used, so the
+            // table grows in step with the one of the encoder
+            self.next_code = CLEAR;
+            self.skip_to_group_end()?;
+            self.bits = INIT_BITS;
+            return Ok(true);
+        }
+
+        // The string is decoded from its last byte, then reversed
+        let mut current = code;
+        if current >= self.next_code {
+            // The code being added to the table, the previous string followed by its own first byte
+            if current > self.next_code {
+                return Err(corrupted("a code is not in the table"));
+            }
+            self.output.push(self.first_byte);
+            current = previous.into();
+        }
+        while current >= CLEAR {
+            if self.output.len() >= self.prefixes.len() {
+                return Err(corrupted("the table has a loop"));
+            }
+            self.output.push(self.suffixes[current]);
+            current = self.prefixes[current].into();
+        }
+        self.first_byte = current as u8;
+        self.output.push(self.first_byte);
+        self.output.reverse();
+
+        if self.next_code < self.prefixes.len() {
+            self.prefixes[self.next_code] = previous;
+            self.suffixes[self.next_code] = self.first_byte;
+            self.next_code += 1;
+        }
+        self.previous = Some(code as u16);
+
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for LzwDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.output.len() {
+            self.output.clear();
+            self.position = 0;
+            if !self.decode_next()? {
+                return Ok(0);
+            }
+        }
+
+        let data = &self.output[self.position..];
+        let read = data.len().min(buf.len());
+        buf[..read].copy_from_slice(&data[..read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output of `printf 'TOBEORNOTTOBEORTOBEORNOT' | compress -c`
+    const TOBEORNOT: &[u8] = &[
+        0x1f, 0x9d, 0x90, 0x54, 0x9e, 0x08, 0x29, 0xf2, 0x44, 0x8a, 0x93, 0x27, 0x54, 0x02, 0x0e, 0x2c, 0xa8, 0x90,
+        0xa0, 0x41, 0x84,
+    ];
+
+    #[test]
+    fn test_decompress() {
+        let mut decompressed = vec![];
+        LzwDecoder::new(TOBEORNOT)
+            .unwrap()
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, b"TOBEORNOTTOBEORTOBEORNOT");
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        assert!(LzwDecoder::new(&b"\x1f\x8b\x08"[..]).is_err());
+        // Codes of up to 20 bits
+        assert!(LzwDecoder::new(&b"\x1f\x9d\x94"[..]).is_err());
+    }
+}
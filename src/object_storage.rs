@@ -0,0 +1,270 @@
+//! Reading and writing objects in S3 compatible storage, with URIs like `s3://bucket/backup.tar.zst`.
+//!
+//! Credentials and the region come from the usual `AWS_*` environment variables and config files,
+//! and `AWS_ENDPOINT_URL` points to other S3 compatible services, like MinIO or Google Cloud Storage.
+//! Objects are read by downloading a presigned URL, see [`crate::http`], and written with a
+//! multipart upload, so neither needs a local copy of the whole file.
+
+#[cfg(feature = "s3")]
+use std::sync::{Arc, Mutex};
+use std::{io::Write, path::Path};
+
+use crate::error::Error;
+#[cfg(feature = "s3")]
+use crate::error::{ErrorClass, FinalError};
+
+/// Whether `path` is an S3 URI, like `s3://bucket/backup.tar.zst`
+pub fn is_s3_uri(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.starts_with("s3://"))
+}
+
+/// Size of the parts of uploads, S3 requires at least 5 MiB for every part but the last one, and
+/// allows up to 10000 parts, so objects can take up to 640 GiB
+#[cfg(feature = "s3")]
+const PART_SIZE: usize = 64 * 1024 * 1024;
+
+/// How long presigned URLs are valid, only the start of the download has to happen before that
+#[cfg(feature = "s3")]
+const PRESIGN_EXPIRY_SECONDS: u32 = 60 * 60;
+
+#[cfg(feature = "s3")]
+fn s3_error(title: String) -> impl FnOnce(s3::error::S3Error) -> Error {
+    move |err| {
+        FinalError::with_title(title)
+            .detail(err.to_string())
+            .hint("Check the AWS_* environment variables, or the AWS config and credentials files")
+            .class(ErrorClass::Io)
+            .into()
+    }
+}
+
+/// Bucket and key of the object at `uri`
+#[cfg(feature = "s3")]
+fn open_bucket(uri: &Path) -> crate::Result<(Box<s3::Bucket>, String)> {
+    let uri = uri.to_str().expect("S3 URIs are valid UTF-8, checked by `is_s3_uri`");
+    let (bucket_name, key) = uri
+        .strip_prefix("s3://")
+        .and_then(|rest| rest.split_once('/'))
+        .filter(|(bucket_name, key)| !bucket_name.is_empty() && !key.is_empty())
+        .ok_or_else(|| {
+            FinalError::with_title(format!("Invalid S3 URI '{uri}'"))
+                .detail("Expected the bucket and the key of an object, like 's3://bucket/backup.tar.zst'")
+                .class(ErrorClass::BadArguments)
+        })?;
+
+    let credentials = s3::creds::Credentials::default().map_err(|err| {
+        FinalError::with_title("Could not find the S3 credentials")
+            .detail(err.to_string())
+            .hint("Set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, or AWS_PROFILE")
+            .class(ErrorClass::BadArguments)
+    })?;
+
+    let region_name = std::env::var("AWS_REGION")
+        .or_else(|_| std::env::var("AWS_DEFAULT_REGION"))
+        .unwrap_or_else(|_| "us-east-1".to_string());
+    let custom_endpoint = std::env::var("AWS_ENDPOINT_URL").ok();
+
+    let bucket = match custom_endpoint {
+        Some(endpoint) => {
+            let region = s3::Region::Custom {
+                region: region_name,
+                endpoint,
+            };
+            // Other services don't always support buckets as subdomains
+            s3::Bucket::new(bucket_name, region, credentials).map(|bucket| bucket.with_path_style())
+        }
+        None => {
+            let region = region_name.parse().map_err(|_| {
+                FinalError::with_title(format!("Unknown AWS region '{region_name}'"))
+                    .hint("Set AWS_ENDPOINT_URL to use a service other than AWS")
+                    .class(ErrorClass::BadArguments)
+            })?;
+            s3::Bucket::new(bucket_name, region, credentials)
+        }
+    }
+    .map_err(s3_error(format!("Could not open the bucket '{bucket_name}'")))?;
+
+    Ok((bucket, key.to_string()))
+}
+
+/// URL to download the object at `uri` without credentials, valid for an hour
+#[cfg(feature = "s3")]
+pub fn presigned_url(uri: &Path) -> crate::Result<String> {
+    let (bucket, key) = open_bucket(uri)?;
+    bucket
+        .presign_get(&key, PRESIGN_EXPIRY_SECONDS, None)
+        .map_err(s3_error(format!("Could not sign a request for '{}'", uri.display())))
+}
+
+/// Parts of a multipart upload sent so far, and the data for the last one
+#[cfg(feature = "s3")]
+struct MultipartUpload {
+    bucket: Box<s3::Bucket>,
+    key: String,
+    upload_id: String,
+    buffer: Vec<u8>,
+    parts: Vec<s3::serde_types::Part>,
+    /// Set after a part fails, the upload can only be aborted
+    failed: bool,
+}
+
+#[cfg(feature = "s3")]
+impl MultipartUpload {
+    const CONTENT_TYPE: &'static str = "application/octet-stream";
+
+    fn upload_part(&mut self, chunk: Vec<u8>) -> Result<(), s3::error::S3Error> {
+        let part_number = self.parts.len() as u32 + 1;
+        let part =
+            self.bucket
+                .put_multipart_chunk(chunk, &self.key, part_number, &self.upload_id, Self::CONTENT_TYPE)?;
+        self.parts.push(part);
+        Ok(())
+    }
+
+    fn complete(&mut self) -> Result<(), s3::error::S3Error> {
+        // There's always a last part, even if empty, uploads without parts can't be completed
+        let chunk = std::mem::take(&mut self.buffer);
+        self.upload_part(chunk)?;
+        self.bucket
+            .complete_multipart_upload(&self.key, &self.upload_id, std::mem::take(&mut self.parts))?;
+        Ok(())
+    }
+}
+
+/// Uploads everything written to it as the parts of a multipart upload, which is handed to its
+/// [`Upload`] when dropped, to be completed or aborted depending on how the compression went
+#[cfg(feature = "s3")]
+struct MultipartWriter {
+    upload: Option<MultipartUpload>,
+    handoff: Arc<Mutex<Option<MultipartUpload>>>,
+}
+
+#[cfg(feature = "s3")]
+impl Write for MultipartWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let upload = self.upload.as_mut().expect("upload is only taken when dropped");
+        upload.buffer.extend_from_slice(buf);
+
+        while upload.buffer.len() >= PART_SIZE {
+            let chunk = upload.buffer.drain(..PART_SIZE).collect();
+            if let Err(err) = upload.upload_part(chunk) {
+                upload.failed = true;
+                return Err(std::io::Error::other(err));
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        // Parts have a minimum size, so the last one is only uploaded when finishing
+        Ok(())
+    }
+}
+
+#[cfg(feature = "s3")]
+impl Drop for MultipartWriter {
+    fn drop(&mut self) {
+        *self.handoff.lock().expect("upload lock poisoned") = self.upload.take();
+    }
+}
+
+/// Upload started by [`create_object`], the object only replaces the existing one after
+/// [`Upload::finish`]
+#[derive(Default)]
+pub struct Upload {
+    #[cfg(feature = "s3")]
+    handoff: Arc<Mutex<Option<MultipartUpload>>>,
+}
+
+#[cfg(feature = "s3")]
+impl Upload {
+    fn take(self) -> MultipartUpload {
+        let upload = self.handoff.lock().expect("upload lock poisoned").take();
+        upload.expect("the writer is dropped before finishing the upload")
+    }
+
+    /// Upload the last part and complete the upload, call it after dropping the writer
+    pub fn finish(self, uri: &Path) -> crate::Result<()> {
+        let mut upload = self.take();
+
+        let result = if upload.failed {
+            Err(FinalError::with_title(format!("Could not upload '{}'", uri.display()))
+                .detail("A part of the upload failed")
+                .class(ErrorClass::Io)
+                .into())
+        } else {
+            upload
+                .complete()
+                .map_err(s3_error(format!("Could not upload '{}'", uri.display())))
+        };
+
+        if result.is_err() {
+            let _ = upload.bucket.abort_upload(&upload.key, &upload.upload_id);
+        }
+        result
+    }
+
+    /// Discard the parts uploaded so far, leaving the existing object untouched
+    pub fn abort(self, uri: &Path) -> crate::Result<()> {
+        let upload = self.take();
+        upload
+            .bucket
+            .abort_upload(&upload.key, &upload.upload_id)
+            .map_err(s3_error(format!("Could not abort the upload of '{}'", uri.display())))
+    }
+}
+
+/// Start uploading to the object at `uri`, replacing it when the upload is finished
+#[cfg(feature = "s3")]
+pub fn create_object(uri: &Path) -> crate::Result<(Box<dyn Write + Send>, Upload)> {
+    let (bucket, key) = open_bucket(uri)?;
+    let upload_id = bucket
+        .initiate_multipart_upload(&key, MultipartUpload::CONTENT_TYPE)
+        .map_err(s3_error(format!("Could not start uploading '{}'", uri.display())))?
+        .upload_id;
+
+    let upload = Upload::default();
+    let writer = MultipartWriter {
+        upload: Some(MultipartUpload {
+            bucket,
+            key,
+            upload_id,
+            buffer: Vec::with_capacity(PART_SIZE),
+            parts: vec![],
+            failed: false,
+        }),
+        handoff: Arc::clone(&upload.handoff),
+    };
+    Ok((Box::new(writer), upload))
+}
+
+/// Error returned when S3 URIs are used in builds without the `s3` feature
+#[cfg(not(feature = "s3"))]
+fn no_support() -> Error {
+    Error::UnsupportedFormat {
+        reason: "S3 storage is disabled for this build, it requires the `s3` feature.".into(),
+    }
+}
+
+// Only called to download, with the `http` feature
+#[cfg(all(feature = "http", not(feature = "s3")))]
+pub fn presigned_url(_: &Path) -> crate::Result<String> {
+    Err(no_support())
+}
+
+#[cfg(not(feature = "s3"))]
+pub fn create_object(_: &Path) -> crate::Result<(Box<dyn Write + Send>, Upload)> {
+    Err(no_support())
+}
+
+#[cfg(not(feature = "s3"))]
+impl Upload {
+    pub fn finish(self, _: &Path) -> crate::Result<()> {
+        Err(no_support())
+    }
+
+    pub fn abort(self, _: &Path) -> crate::Result<()> {
+        Err(no_support())
+    }
+}
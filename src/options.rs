@@ -0,0 +1,78 @@
+// Options of the library that are also arguments of the command line. build.rs includes this file
+// for `cli/args.rs`, so it can't have inner attributes like a `//!` doc
+
+/// Unicode normalization form for entry names
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum NormalizationForm {
+    /// Composed characters, used by Linux and Windows
+    Nfc,
+    /// Decomposed characters, used by macOS
+    Nfd,
+    /// Keep names as they are
+    #[default]
+    None,
+}
+
+/// What to do with entries whose path is already taken when unpacking
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ConflictPolicy {
+    /// Ask whether to overwrite each file, answered by `--yes` and `--no`
+    Ask,
+    /// Replace the existing file
+    Overwrite,
+    /// Keep the existing file
+    Skip,
+    /// Unpack under a numbered name, like `notes_1.txt`
+    Rename,
+    /// Replace the existing file only if the entry is newer
+    UpdateNewer,
+}
+
+/// What to do with special files (FIFOs, sockets and devices) when compressing
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum SpecialFiles {
+    /// Leave them out of the archive, with a warning
+    #[default]
+    Skip,
+    /// Store them as special entries, in formats that support it, skipped otherwise
+    Store,
+    /// Stop with an error
+    Fail,
+}
+
+/// Integrity check of xz streams
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum XzCheck {
+    /// No integrity check
+    None,
+    /// CRC32
+    Crc32,
+    /// CRC64, the default of the xz tool
+    #[default]
+    Crc64,
+    /// SHA-256
+    Sha256,
+}
+
+/// Compression of the zip entries matching a glob, set by `--zip-rule`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZipRule {
+    pub glob: String,
+    pub method: ZipMethod,
+    /// Level of the method, from 0 to 9 for deflate
+    pub level: Option<i32>,
+}
+
+/// Compression method of the zip entries matching a [`ZipRule`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ZipMethod {
+    /// Stored as they are
+    Store,
+    /// Deflate, the method of most zip archives
+    Deflate,
+}
@@ -0,0 +1,205 @@
+//! Progress reports and cancellation of the library API.
+//!
+//! [`ProgressSink`] is told how many bytes were processed, which entry is being processed, and the
+//! warnings that would otherwise be printed, and a [`CancellationToken`] stops the operation
+//! between entries, or between reads of the file being compressed or decompressed.
+
+use std::{
+    cell::RefCell,
+    fmt,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use crate::archive::manifest::{ArchiveObserver, StoredEntry};
+
+/// Told about the progress of [`compress`](crate::compress) and [`decompress`](crate::decompress),
+/// from the thread that called them.
+///
+/// Every method does nothing by default.
+pub trait ProgressSink: Send + Sync {
+    /// Total of the bytes processed so far: the bytes of the files stored when compressing, and the
+    /// bytes of the archive read when decompressing
+    fn bytes_processed(&self, _bytes: u64) {}
+
+    /// The entry at `path` inside of the archive was stored when compressing, or is about to be
+    /// unpacked when decompressing
+    fn entry(&self, _path: &Path) {}
+
+    /// Warning that the command prints to stderr, like a file being skipped. Warnings of the
+    /// threads walking the files being compressed are still printed to stderr.
+    fn warning(&self, _message: &str) {}
+}
+
+/// Stops [`compress`](crate::compress) and [`decompress`](crate::decompress) with
+/// [`Error::Cancelled`](crate::Error::Cancelled) once cancelled, from any thread.
+///
+/// Clones share the same state, so a clone can be kept to cancel the operation given the other.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop the operations using this token, as soon as they check it
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+thread_local! {
+    /// Sink of the warnings of the current thread, set by [`Progress::forward_warnings`]
+    static WARNING_SINK: RefCell<Option<Arc<dyn ProgressSink>>> = const { RefCell::new(None) };
+}
+
+/// Send `message` to the sink of the warnings of the current thread, returning `false` if there's
+/// none
+pub(crate) fn forward_warning(message: &str) -> bool {
+    WARNING_SINK.with(|current| match &*current.borrow() {
+        Some(sink) => {
+            sink.warning(message);
+            true
+        }
+        None => false,
+    })
+}
+
+/// Sink and cancellation token of an operation, with the count of bytes processed
+#[derive(Clone, Default)]
+pub struct Progress {
+    sink: Option<Arc<dyn ProgressSink>>,
+    cancellation: CancellationToken,
+    bytes: Arc<AtomicU64>,
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Progress")
+            .field("cancellation", &self.cancellation)
+            .field("bytes", &self.bytes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Progress {
+    pub fn with_sink(self, sink: Arc<dyn ProgressSink>) -> Self {
+        Self {
+            sink: Some(sink),
+            ..self
+        }
+    }
+
+    pub fn with_cancellation(self, cancellation: CancellationToken) -> Self {
+        Self { cancellation, ..self }
+    }
+
+    /// Copy for a new operation, counting bytes from zero
+    pub fn start(&self) -> Self {
+        Self {
+            sink: self.sink.clone(),
+            cancellation: self.cancellation.clone(),
+            bytes: Arc::default(),
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    /// Fail with [`Error::Cancelled`](crate::Error::Cancelled) if the operation was cancelled
+    pub fn check(&self) -> crate::Result<()> {
+        if self.is_cancelled() {
+            return Err(crate::Error::Cancelled);
+        }
+        Ok(())
+    }
+
+    /// Report that the entry at `path` is being processed, failing if the operation was cancelled
+    pub fn entry(&self, path: &Path) -> crate::Result<()> {
+        self.check()?;
+        if let Some(sink) = &self.sink {
+            sink.entry(path);
+        }
+        Ok(())
+    }
+
+    /// Account for `bytes` more being processed, failing if the operation was cancelled
+    pub fn add_bytes(&self, bytes: u64) -> io::Result<()> {
+        if self.is_cancelled() {
+            // Not `Interrupted`, which copying loops retry
+            return Err(io::Error::other("operation cancelled"));
+        }
+        let total = self.bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        if let Some(sink) = &self.sink {
+            sink.bytes_processed(total);
+        }
+        Ok(())
+    }
+
+    /// Run `f`, sending the warnings logged by the current thread meanwhile to the sink instead of
+    /// stderr
+    pub fn forward_warnings<T>(&self, f: impl FnOnce() -> T) -> T {
+        let Some(sink) = &self.sink else {
+            return f();
+        };
+
+        let previous = WARNING_SINK.with(|current| current.replace(Some(Arc::clone(sink))));
+        let result = f();
+        WARNING_SINK.with(|current| *current.borrow_mut() = previous);
+        result
+    }
+
+    /// Wrap `inner`, accounting for every byte read from it
+    pub fn reader<R>(&self, inner: R) -> ProgressReader<R> {
+        ProgressReader {
+            inner,
+            progress: self.clone(),
+        }
+    }
+}
+
+/// Files are reported once stored, the builders are stopped by the errors
+impl ArchiveObserver for Progress {
+    fn entry_stored(&mut self, entry: StoredEntry) -> crate::Result<()> {
+        Progress::entry(self, &entry.name)?;
+        Ok(self.add_bytes(entry.size)?)
+    }
+
+    fn bytes_compressed(&mut self, bytes: u64) -> io::Result<()> {
+        self.add_bytes(bytes)
+    }
+
+    fn wants_content_hash(&self) -> bool {
+        false
+    }
+}
+
+/// Reader accounting for the bytes read from it in a [`Progress`]
+pub struct ProgressReader<R> {
+    inner: R,
+    progress: Progress,
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.add_bytes(read as u64)?;
+        Ok(read)
+    }
+}
+
+impl<S: Seek> Seek for ProgressReader<S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
@@ -0,0 +1,82 @@
+//! Detached Ed25519 signatures of archives.
+//!
+//! Files are signed by their SHA-512 hash, so they don't need to fit in memory, and the signature
+//! is saved hex-encoded in a file next to them, with the `.sig` suffix.
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use ed25519_dalek::{
+    pkcs8::{DecodePrivateKey, DecodePublicKey},
+    Signature, Signer, SigningKey, VerifyingKey,
+};
+use fs_err as fs;
+
+use crate::{
+    error::{ErrorClass, FinalError},
+    utils::{checksum::sha512_from_reader, EscapedPathDisplay},
+};
+
+/// Path of the signature of the file at `path`, like `archive.tar.gz.sig`
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut signature_path = OsString::from(path);
+    signature_path.push(".sig");
+    signature_path.into()
+}
+
+/// Read an Ed25519 private key in the PKCS#8 PEM format
+pub fn read_signing_key(path: &Path) -> crate::Result<SigningKey> {
+    let pem = fs::read_to_string(path)?;
+    SigningKey::from_pkcs8_pem(&pem).map_err(|err| invalid_key_error(path, err.to_string()))
+}
+
+/// Read an Ed25519 public key in the PEM format
+pub fn read_verifying_key(path: &Path) -> crate::Result<VerifyingKey> {
+    let pem = fs::read_to_string(path)?;
+    VerifyingKey::from_public_key_pem(&pem).map_err(|err| invalid_key_error(path, err.to_string()))
+}
+
+fn invalid_key_error(path: &Path, reason: String) -> crate::Error {
+    FinalError::with_title(format!("Invalid key in {}", EscapedPathDisplay::new(path)))
+        .detail(reason)
+        .hint("Keys are Ed25519 keys in the PEM format, they can be generated with:")
+        .hint("  openssl genpkey -algorithm ed25519 -out key.pem")
+        .hint("  openssl pkey -in key.pem -pubout -out pub.pem")
+        .class(ErrorClass::BadArguments)
+        .into()
+}
+
+/// Sign the file at `path`, returning the hex-encoded signature
+pub fn sign_file(path: &Path, key: &SigningKey) -> crate::Result<String> {
+    let hash = sha512_from_reader(&mut fs::File::open(path)?)?;
+    let signature = key.sign(&hash);
+
+    Ok(signature.to_bytes().iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+/// Check if the hex-encoded `signature`, made by [`sign_file`], is a valid signature of the file at `path`
+pub fn verify_file(path: &Path, signature: &str, key: &VerifyingKey) -> crate::Result<bool> {
+    let signature = decode_signature(signature.trim()).ok_or_else(|| {
+        FinalError::with_title("Malformed signature")
+            .detail("Expected an Ed25519 signature, encoded as 128 hexadecimal digits")
+            .class(ErrorClass::BadArguments)
+    })?;
+
+    let hash = sha512_from_reader(&mut fs::File::open(path)?)?;
+    Ok(key.verify_strict(&hash, &signature).is_ok())
+}
+
+fn decode_signature(text: &str) -> Option<Signature> {
+    if text.len() != 128 || !text.is_ascii() {
+        return None;
+    }
+
+    let mut bytes = [0; 64];
+    for (byte, digits) in bytes.iter_mut().zip(text.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+
+    Some(Signature::from_bytes(&bytes))
+}
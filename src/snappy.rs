@@ -0,0 +1,61 @@
+//! Raw snappy, a single block without the framing of the `.sz` format, written by some tools that
+//! use snappy internally instead of the framed format.
+//!
+//! Raw data has no magic number, it's told apart by the stream identifier that framed files start
+//! with. The block can't be split, so it's decoded and encoded in memory.
+
+use std::io::{self, BufRead, Cursor, Read, Write};
+
+/// First chunk of every framed snappy file
+pub const STREAM_IDENTIFIER: [u8; 10] = [0xFF, 0x06, 0x00, 0x00, b's', b'N', b'a', b'P', b'p', b'Y'];
+
+/// Decoder of framed snappy, or of raw snappy if `reader` doesn't start with the stream identifier
+pub fn decoder<R: BufRead + Send + 'static>(mut reader: R) -> io::Result<Box<dyn Read + Send>> {
+    let start = reader.fill_buf()?;
+    let len = start.len().min(STREAM_IDENTIFIER.len());
+    if start[..len] == STREAM_IDENTIFIER[..len] {
+        return Ok(Box::new(snap::read::FrameDecoder::new(reader)));
+    }
+
+    let mut compressed = vec![];
+    reader.read_to_end(&mut compressed)?;
+    let decompressed = snap::raw::Decoder::new().decompress_vec(&compressed)?;
+    Ok(Box::new(Cursor::new(decompressed)))
+}
+
+/// Compresses what is written to it into a single raw snappy block when dropped, like the other
+/// encoders in the chain
+pub struct RawEncoder<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> RawEncoder<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, buffer: vec![] }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let compressed = snap::raw::Encoder::new().compress_vec(&self.buffer)?;
+        self.inner.write_all(&compressed)?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for RawEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // The block is only compressed at the end
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for RawEncoder<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
@@ -0,0 +1,47 @@
+//! Checksum helpers, used to compare file contents without holding them in memory.
+
+use std::io::{self, Read};
+
+use sha2::{Digest, Sha512};
+
+use crate::BUFFER_CAPACITY;
+
+/// Reads `reader` until EOF, returning the amount of bytes read and their CRC32.
+pub fn crc32_from_reader(reader: &mut (impl Read + ?Sized)) -> io::Result<(u64, u32)> {
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0; BUFFER_CAPACITY];
+    let mut size = 0;
+
+    loop {
+        let bytes_read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(bytes_read) => bytes_read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        hasher.update(&buffer[..bytes_read]);
+        size += bytes_read as u64;
+    }
+
+    Ok((size, hasher.finalize()))
+}
+
+/// Reads `reader` until EOF, returning the SHA-512 hash of its contents.
+pub fn sha512_from_reader(reader: &mut (impl Read + ?Sized)) -> io::Result<[u8; 64]> {
+    let mut hasher = Sha512::new();
+    let mut buffer = vec![0; BUFFER_CAPACITY];
+
+    loop {
+        let bytes_read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(bytes_read) => bytes_read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        hasher.update(&buffer[..bytes_read]);
+    }
+
+    let mut hash = [0; 64];
+    hash.copy_from_slice(&hasher.finalize());
+    Ok(hash)
+}
@@ -1,6 +1,19 @@
-use std::path::Path;
+use std::{fs::File, io::Read, path::Path, time::UNIX_EPOCH};
+
+/// Files with the syntax of .gitignore, for what shouldn't go in archives, read even without
+/// `--gitignore`
+pub const OUCH_IGNORE_FILENAME: &str = ".ouchignore";
+
+/// Directories where version control systems keep their data, skipped by `--exclude-vcs`
+const VCS_DIRECTORIES: [&str; 6] = [".git", ".svn", ".hg", ".bzr", "CVS", "_darcs"];
+
+/// File marking the directory it's in as a cache, see <https://bford.info/cachedir/>
+const CACHEDIR_TAG: &str = "CACHEDIR.TAG";
+/// First bytes of valid [`CACHEDIR_TAG`] files
+const CACHEDIR_TAG_SIGNATURE: &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55";
 
 /// Determines which files should be read or ignored during directory walking
+#[derive(Clone)]
 pub struct FileVisibilityPolicy {
     /// Enables reading .ignore files.
     ///
@@ -19,6 +32,52 @@ pub struct FileVisibilityPolicy {
 
     /// Enables reading `.git/info/exclude` files.
     pub read_git_exclude: bool,
+
+    /// Enables reading the global gitignore, like `~/.config/git/ignore`, along with .gitignore files.
+    ///
+    /// This is enabled by default.
+    pub read_global_git_ignore: bool,
+
+    /// If enabled, git's ignore files are only read inside of git repositories.
+    ///
+    /// This is enabled by default.
+    pub require_git: bool,
+
+    /// If enabled, walks through directory entries sorted by file name.
+    ///
+    /// Disabled by default.
+    pub sort_by_name: bool,
+
+    /// How deep to descend into directories, the path given to the walker has depth 0.
+    ///
+    /// Unlimited by default.
+    pub max_depth: Option<usize>,
+
+    /// If enabled, doesn't cross into other file systems, like mount points.
+    ///
+    /// Disabled by default.
+    pub same_file_system: bool,
+
+    /// If enabled, walks into the directories that symlinks point to, otherwise the symlinks are
+    /// yielded like empty directories.
+    ///
+    /// Disabled by default.
+    pub follow_dir_symlinks: bool,
+
+    /// If enabled, skips the directories of version control systems, like `.git`.
+    ///
+    /// Disabled by default.
+    pub exclude_vcs: bool,
+
+    /// If enabled, skips the directories tagged as caches by a `CACHEDIR.TAG` file.
+    ///
+    /// Disabled by default.
+    pub exclude_caches: bool,
+
+    /// Skips the files modified at or after this time, in seconds since the Unix epoch.
+    ///
+    /// Unlimited by default.
+    pub modified_before: Option<u64>,
 }
 
 impl Default for FileVisibilityPolicy {
@@ -28,6 +87,15 @@ impl Default for FileVisibilityPolicy {
             read_hidden: true,
             read_git_ignore: false,
             read_git_exclude: false,
+            read_global_git_ignore: true,
+            require_git: true,
+            sort_by_name: false,
+            max_depth: None,
+            same_file_system: false,
+            follow_dir_symlinks: false,
+            exclude_vcs: false,
+            exclude_caches: false,
+            modified_before: None,
         }
     }
 }
@@ -61,19 +129,140 @@ impl FileVisibilityPolicy {
         }
     }
 
+    #[must_use]
+    /// Enables reading the global gitignore, when .gitignore files are read.
+    pub fn read_global_git_ignore(self, read_global_git_ignore: bool) -> Self {
+        Self {
+            read_global_git_ignore,
+            ..self
+        }
+    }
+
+    #[must_use]
+    /// Only reads git's ignore files inside of git repositories.
+    pub fn require_git(self, require_git: bool) -> Self {
+        Self { require_git, ..self }
+    }
+
     #[must_use]
     /// Enables reading `.git/info/exclude` files.
     pub fn read_hidden(self, read_hidden: bool) -> Self {
         Self { read_hidden, ..self }
     }
 
+    #[must_use]
+    /// Walks through directory entries sorted by file name, so the order doesn't depend on the filesystem.
+    pub fn sort_by_name(self, sort_by_name: bool) -> Self {
+        Self { sort_by_name, ..self }
+    }
+
+    #[must_use]
+    /// Limits how deep to descend into directories, the path given to the walker has depth 0.
+    pub fn max_depth(self, max_depth: Option<usize>) -> Self {
+        Self { max_depth, ..self }
+    }
+
+    #[must_use]
+    /// Doesn't cross into other file systems while walking, like `tar --one-file-system`.
+    pub fn same_file_system(self, same_file_system: bool) -> Self {
+        Self {
+            same_file_system,
+            ..self
+        }
+    }
+
+    #[must_use]
+    /// Walks into the directories that symlinks point to, see [`symlink_loop`] for the symlinks to
+    /// their own parents.
+    pub fn follow_dir_symlinks(self, follow_dir_symlinks: bool) -> Self {
+        Self {
+            follow_dir_symlinks,
+            ..self
+        }
+    }
+
+    #[must_use]
+    /// Skips the directories of version control systems, like `tar --exclude-vcs`.
+    pub fn exclude_vcs(self, exclude_vcs: bool) -> Self {
+        Self { exclude_vcs, ..self }
+    }
+
+    #[must_use]
+    /// Skips the directories tagged as caches, along with their tag, like `tar --exclude-caches-all`.
+    pub fn exclude_caches(self, exclude_caches: bool) -> Self {
+        Self { exclude_caches, ..self }
+    }
+
+    #[must_use]
+    /// Skips the files modified at or after this time, in seconds since the Unix epoch.
+    pub fn modified_before(self, modified_before: Option<u64>) -> Self {
+        Self {
+            modified_before,
+            ..self
+        }
+    }
+
     /// Walks through a directory using [`ignore::Walk`]
     pub fn build_walker(&self, path: impl AsRef<Path>) -> ignore::Walk {
-        ignore::WalkBuilder::new(path)
+        let mut builder = ignore::WalkBuilder::new(path);
+        builder
             .git_exclude(self.read_git_exclude)
             .git_ignore(self.read_git_ignore)
+            // The walker reads it by default, even if .gitignore files aren't
+            .git_global(self.read_git_ignore && self.read_global_git_ignore)
+            .require_git(self.require_git)
             .ignore(self.read_ignore)
             .hidden(self.read_hidden)
-            .build()
+            .max_depth(self.max_depth)
+            .same_file_system(self.same_file_system)
+            .follow_links(self.follow_dir_symlinks)
+            .add_custom_ignore_filename(OUCH_IGNORE_FILENAME);
+
+        if self.sort_by_name {
+            builder.sort_by_file_name(|a, b| a.cmp(b));
+        }
+
+        // The paths given to the walker are never filtered
+        let (exclude_vcs, exclude_caches, modified_before) =
+            (self.exclude_vcs, self.exclude_caches, self.modified_before);
+        if exclude_vcs || exclude_caches || modified_before.is_some() {
+            builder.filter_entry(move |entry| {
+                let is_dir = entry.file_type().is_some_and(|kind| kind.is_dir());
+                let is_vcs = exclude_vcs && VCS_DIRECTORIES.iter().any(|name| entry.file_name() == *name);
+                let is_cache = exclude_caches && is_dir && is_cache_dir(entry.path());
+                // Directories are kept, the files in them may be older
+                let is_too_new = !is_dir && modified_before.is_some_and(|limit| modified_since(entry, limit));
+                !is_vcs && !is_cache && !is_too_new
+            });
+        }
+
+        builder.build()
+    }
+}
+
+/// Whether `entry` was modified at or after `time`, in seconds since the Unix epoch
+fn modified_since(entry: &ignore::DirEntry, time: u64) -> bool {
+    entry
+        .metadata()
+        .ok()
+        .and_then(|metadata| metadata.modified().ok())
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .is_some_and(|modified| modified.as_secs() >= time)
+}
+
+/// Whether `dir` has a [`CACHEDIR_TAG`] file starting with its signature
+fn is_cache_dir(dir: &Path) -> bool {
+    let mut signature = [0; CACHEDIR_TAG_SIGNATURE.len()];
+    File::open(dir.join(CACHEDIR_TAG)).is_ok_and(|mut tag| tag.read_exact(&mut signature).is_ok())
+        && signature == CACHEDIR_TAG_SIGNATURE
+}
+
+/// The symlink found by a walker that points to one of its own parent directories, when following
+/// symlinks. The walker tracks the directories it's in, so walking it would never end
+pub fn symlink_loop(err: &ignore::Error) -> Option<&Path> {
+    match err {
+        ignore::Error::Loop { child, .. } => Some(child),
+        ignore::Error::WithPath { err, .. } | ignore::Error::WithDepth { err, .. } => symlink_loop(err),
+        _ => None,
     }
 }
@@ -1,20 +1,215 @@
 //! Filesystem utility functions.
 
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::{
     env,
-    io::Read,
+    io::{self, Read},
     path::{Path, PathBuf},
+    process,
+    time::{Duration, SystemTime},
 };
 
+use filetime_creation::{set_file_mtime, FileTime};
 use fs_err as fs;
 
 use super::user_wants_to_overwrite;
 use crate::{
+    error::{ErrorClass, FinalError},
     extension::Extension,
-    utils::{logger::info_accessible, EscapedPathDisplay},
+    options::ConflictPolicy,
+    utils::{
+        logger::{info_accessible, warning},
+        EscapedPathDisplay,
+    },
     QuestionPolicy,
 };
 
+/// Start of the names of the temporary directories that archives are unpacked into
+const TEMP_DIR_PREFIX: &str = ".tmp-ouch-";
+
+/// Temporary directories without a process ID in their name, made by older versions, are
+/// considered abandoned after this long
+const UNOWNED_TEMP_DIR_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Setuid, setgid and sticky bits
+const SPECIAL_BITS: u32 = 0o7000;
+
+/// Umask clearing the write permission of the group and others, so unpacked directories and files
+/// are at most 755 and 644, or 755 for executables
+const SAFE_UMASK: u32 = 0o022;
+
+/// How the permissions stored in archives are applied to unpacked files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PermissionsPolicy {
+    /// Bits of the umask cleared from the stored permissions, 0 to restore them as they are stored
+    umask: u32,
+    /// Restore the setuid, setgid and sticky bits, which are cleared otherwise
+    special_bits: bool,
+}
+
+impl PermissionsPolicy {
+    /// Restore the permissions as they are stored, except for the setuid, setgid and sticky bits
+    pub fn preserve() -> Self {
+        Self {
+            umask: 0,
+            special_bits: false,
+        }
+    }
+
+    /// Restore the permissions as they are stored, without the write permission of the group and
+    /// others, nor the setuid, setgid and sticky bits
+    pub fn safe() -> Self {
+        Self {
+            umask: SAFE_UMASK,
+            ..Self::preserve()
+        }
+    }
+
+    /// Apply the umask of the current process.
+    ///
+    /// The umask can only be read by replacing it, so call this before spawning threads that create files.
+    pub fn apply_umask() -> Self {
+        #[cfg(unix)]
+        #[allow(clippy::unnecessary_cast)]
+        // Safety: umask never fails, and the previous value is restored right away
+        let umask = unsafe {
+            let umask = libc::umask(0o022);
+            libc::umask(umask);
+            umask as u32
+        };
+        #[cfg(not(unix))]
+        let umask = 0;
+
+        Self {
+            umask,
+            ..Self::preserve()
+        }
+    }
+
+    /// Also restore the setuid, setgid and sticky bits if `special_bits` is set
+    pub fn with_special_bits(self, special_bits: bool) -> Self {
+        Self { special_bits, ..self }
+    }
+
+    /// Permission bits that should be cleared from unpacked files
+    pub fn mask(self) -> u32 {
+        if self.special_bits {
+            self.umask
+        } else {
+            self.umask | SPECIAL_BITS
+        }
+    }
+
+    /// Warn if the file at `path` is stored with setuid, setgid or sticky bits that aren't restored
+    pub fn warn_special_bits(self, path: &Path, mode: u32) {
+        if !self.special_bits && mode & SPECIAL_BITS != 0 {
+            warning(format!(
+                "Cleared the setuid, setgid or sticky bits of '{}', pass '--preserve-setuid' to keep them",
+                EscapedPathDisplay::new(path)
+            ));
+        }
+    }
+}
+
+/// Set the permissions of an unpacked file to the `mode` stored in the archive, following `policy`
+#[cfg(unix)]
+pub fn set_unpacked_permissions(path: &Path, mode: u32, policy: PermissionsPolicy) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    policy.warn_special_bits(path, mode);
+    fs::set_permissions(path, std::fs::Permissions::from_mode(mode & !policy.mask()))
+}
+
+/// Restore the read-only, hidden, system and archive attributes stored in the archive
+#[cfg(windows)]
+pub fn set_windows_attributes(path: &Path, attributes: u32) -> io::Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+
+    use windows_sys::Win32::Storage::FileSystem::{
+        SetFileAttributesW, FILE_ATTRIBUTE_ARCHIVE, FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_NORMAL,
+        FILE_ATTRIBUTE_READONLY, FILE_ATTRIBUTE_SYSTEM,
+    };
+
+    let attributes =
+        attributes & (FILE_ATTRIBUTE_READONLY | FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM | FILE_ATTRIBUTE_ARCHIVE);
+    let attributes = if attributes == 0 {
+        FILE_ATTRIBUTE_NORMAL
+    } else {
+        attributes
+    };
+    let wide_path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+
+    // Safety: the path is NUL terminated and outlives the call
+    if unsafe { SetFileAttributesW(wide_path.as_ptr(), attributes) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Prefix for the temporary directories created by this process.
+///
+/// Includes the process ID, so other ouch processes can tell if the directory is still in use.
+pub fn temp_dir_prefix() -> String {
+    format!("{TEMP_DIR_PREFIX}{}-", process::id())
+}
+
+/// Find the temporary directories in `dir` left behind by ouch processes that are no longer running
+pub fn find_stale_temp_dirs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut stale_dirs = vec![];
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(suffix) = name.to_str().and_then(|name| name.strip_prefix(TEMP_DIR_PREFIX)) else {
+            continue;
+        };
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        let pid = suffix.split_once('-').and_then(|(pid, _)| pid.parse().ok());
+        let is_stale = match pid.and_then(is_process_running) {
+            Some(is_running) => !is_running,
+            None => entry
+                .metadata()?
+                .modified()?
+                .elapsed()
+                .is_ok_and(|age| age > UNOWNED_TEMP_DIR_MAX_AGE),
+        };
+
+        if is_stale {
+            stale_dirs.push(entry.path());
+        }
+    }
+
+    stale_dirs.sort();
+    Ok(stale_dirs)
+}
+
+/// Check if a process with this ID is running, `None` if that can't be checked
+fn is_process_running(pid: u32) -> Option<bool> {
+    #[cfg(unix)]
+    {
+        let pid = match libc::pid_t::try_from(pid) {
+            Ok(pid) if pid > 0 => pid,
+            _ => return Some(false),
+        };
+
+        // Safety: signal 0 isn't sent, it only checks if the process exists
+        let result = unsafe { libc::kill(pid, 0) };
+        // EPERM means that the process exists, but belongs to another user
+        Some(result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM))
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = pid;
+        None
+    }
+}
+
 pub fn is_path_stdin(path: &Path) -> bool {
     path.as_os_str() == "-"
 }
@@ -34,15 +229,301 @@ pub fn clear_path(path: &Path, question_policy: QuestionPolicy) -> crate::Result
     Ok(true)
 }
 
+/// Find a name for `path` that isn't taken yet by adding a number to its file stem, like
+/// `notes_1.txt` for `notes.txt`
+pub fn rename_for_available_filename(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default();
+
+    (1..)
+        .map(|number| {
+            let mut name = stem.to_owned();
+            name.push(format!("_{number}"));
+            if let Some(extension) = path.extension() {
+                name.push(".");
+                name.push(extension);
+            }
+            path.with_file_name(name)
+        })
+        // Broken symlinks also take the name
+        .find(|candidate| candidate.symlink_metadata().is_err())
+        .expect("some number is available")
+}
+
+/// Check that writing to `path`, inside of `root`, can't reach outside of `root` through symbolic
+/// links, like `link/passwd` after an entry made `link` point to `/etc`
+///
+/// Every directory between `root` and `path` is checked, and `path` itself if `include_last` is set.
+/// Links that stay inside of `root` are allowed, dangling ones are not, writing through them would
+/// create their target. Paths that don't start with `root` are refused too.
+pub fn check_symlinks_stay_inside(root: &Path, path: &Path, include_last: bool) -> crate::Result<()> {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return Err(
+            FinalError::with_title(format!("Refusing to unpack {}", EscapedPathDisplay::new(path)))
+                .detail("The path is outside of the output directory")
+                .class(ErrorClass::CorruptArchive)
+                .into(),
+        );
+    };
+
+    let mut components: Vec<_> = relative.components().collect();
+    if !include_last {
+        components.pop();
+    }
+
+    // Only resolved once a link is found, most paths have none
+    let mut canonical_root = None;
+    let mut current = root.to_path_buf();
+    for component in components {
+        current.push(component);
+        match fs::symlink_metadata(&current) {
+            Ok(metadata) if metadata.is_symlink() => {
+                let canonical_root = match &canonical_root {
+                    Some(canonical_root) => canonical_root,
+                    None => canonical_root.insert(fs::canonicalize(root)?),
+                };
+                match fs::canonicalize(&current) {
+                    Ok(target) if target.starts_with(canonical_root) => current = target,
+                    _ => {
+                        return Err(FinalError::with_title(format!(
+                            "Refusing to unpack {}",
+                            EscapedPathDisplay::new(path)
+                        ))
+                        .detail(format!(
+                            "{} is a symbolic link leading outside of the output directory",
+                            EscapedPathDisplay::new(&current)
+                        ))
+                        .hint("The archive may have been made to overwrite files outside of the output directory")
+                        .class(ErrorClass::CorruptArchive)
+                        .into())
+                    }
+                }
+            }
+            Ok(_) => {}
+            // Nothing below a missing path can be a link
+            Err(_) => break,
+        }
+    }
+
+    Ok(())
+}
+
+/// Where to write a new file at `path`, following `on_conflict` if it's already taken, or `None`
+/// if it should be skipped
+///
+/// `mtime` is the modification time of the new file, compared by [`ConflictPolicy::UpdateNewer`],
+/// which keeps the existing file if it's unknown.
+pub fn resolve_conflict(
+    path: &Path,
+    on_conflict: ConflictPolicy,
+    question_policy: QuestionPolicy,
+    mtime: Option<SystemTime>,
+) -> crate::Result<Option<PathBuf>> {
+    let Ok(metadata) = path.symlink_metadata() else {
+        return Ok(Some(path.to_owned()));
+    };
+
+    let replace = match on_conflict {
+        ConflictPolicy::Ask => user_wants_to_overwrite(path, question_policy)?,
+        ConflictPolicy::Overwrite => true,
+        ConflictPolicy::Skip => false,
+        ConflictPolicy::Rename => return Ok(Some(rename_for_available_filename(path))),
+        ConflictPolicy::UpdateNewer => mtime
+            .zip(metadata.modified().ok())
+            .is_some_and(|(new, existing)| new > existing),
+    };
+
+    if !replace {
+        return Ok(None);
+    }
+
+    // Symlinks are removed without following them
+    if metadata.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+
+    Ok(Some(path.to_owned()))
+}
+
+/// Move the contents of the directory `from` into the directory `to`, resolving each file that
+/// already exists with [`resolve_conflict`], directories that exist in both are merged
+///
+/// Skipped files are left in `from`.
+pub fn merge_into(
+    from: &Path,
+    to: &Path,
+    on_conflict: ConflictPolicy,
+    question_policy: QuestionPolicy,
+) -> crate::Result<()> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let source = entry.path();
+        let target = to.join(entry.file_name());
+        let metadata = entry.metadata()?;
+
+        if metadata.is_dir() && target.is_dir() {
+            merge_into(&source, &target, on_conflict, question_policy)?;
+            continue;
+        }
+
+        if let Some(target) = resolve_conflict(&target, on_conflict, question_policy, metadata.modified().ok())? {
+            move_path(&source, &target)?;
+        }
+    }
+
+    Ok(())
+}
+
 pub fn remove_file_or_dir(path: &Path) -> crate::Result<()> {
-    if path.is_dir() {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+
+    if metadata.is_symlink() {
+        // The link itself, links to directories are directories on Windows
+        fs::remove_file(path).or_else(|_| fs::remove_dir(path))?;
+    } else if metadata.is_dir() {
         fs::remove_dir_all(path)?;
-    } else if path.is_file() {
+    } else if metadata.is_file() {
         fs::remove_file(path)?;
     }
     Ok(())
 }
 
+/// Move the file or directory at `from` to `to`.
+///
+/// Renaming only works inside of a single file system, so fall back to copying and removing,
+/// the copy is synced to the disk before the original is removed.
+pub fn move_path(from: &Path, to: &Path) -> crate::Result<()> {
+    match fs::rename(from, to) {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            copy_recursively(from, to)?;
+
+            #[cfg(unix)]
+            {
+                let parent = to.parent().filter(|parent| !parent.as_os_str().is_empty());
+                sync_directory(parent.unwrap_or(Path::new(".")))?;
+            }
+
+            remove_file_or_dir(from)
+        }
+        result => Ok(result?),
+    }
+}
+
+/// Copy the file or directory at `from` to `to`, keeping symlinks, permissions and modification times
+fn copy_recursively(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(from)?;
+
+    if metadata.is_symlink() {
+        let target = fs::read_link(from)?;
+
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(target, to)?;
+        #[cfg(windows)]
+        if fs::metadata(from).is_ok_and(|metadata| metadata.is_dir()) {
+            std::os::windows::fs::symlink_dir(target, to)?;
+        } else {
+            std::os::windows::fs::symlink_file(target, to)?;
+        }
+
+        return Ok(());
+    }
+
+    if metadata.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursively(&entry.path(), &to.join(entry.file_name()))?;
+        }
+
+        #[cfg(unix)]
+        sync_directory(to)?;
+    } else {
+        let mut output_file = fs::File::create(to)?;
+        io::copy(&mut fs::File::open(from)?, &mut output_file)?;
+        output_file.sync_all()?;
+    }
+
+    // Set after copying the contents, in case they make it read-only
+    fs::set_permissions(to, metadata.permissions())?;
+    set_file_mtime(to, FileTime::from_last_modification_time(&metadata))
+}
+
+/// Sync the entries of a directory to the disk
+#[cfg(unix)]
+fn sync_directory(path: &Path) -> io::Result<()> {
+    fs::File::open(path)?.sync_all()
+}
+
+/// Set when `copy_file_range` turns out to be missing, so it isn't tried again
+#[cfg(target_os = "linux")]
+static COPY_FILE_RANGE_UNAVAILABLE: AtomicBool = AtomicBool::new(false);
+
+/// Copy `len` bytes of `from` starting at `offset` to the current position of `to`, inside the
+/// kernel and sharing the blocks of both files on copy-on-write filesystems like Btrfs and XFS.
+///
+/// Returns `false` without copying anything if it can't be done for these files, or on this
+/// system, for the caller to fall back to reading and writing the bytes itself.
+#[cfg(target_os = "linux")]
+pub fn copy_file_range(from: &std::fs::File, offset: u64, to: &std::fs::File, len: u64) -> io::Result<bool> {
+    use std::os::fd::AsRawFd;
+
+    if COPY_FILE_RANGE_UNAVAILABLE.load(Ordering::Relaxed) {
+        return Ok(false);
+    }
+
+    let Ok(mut offset) = libc::loff_t::try_from(offset) else {
+        return Ok(false);
+    };
+    let mut remaining = len;
+    while remaining > 0 {
+        let chunk = remaining.min(1 << 30) as usize;
+        // SAFETY: both descriptors are open for the duration of the call, and `offset` outlives it
+        let copied = unsafe {
+            libc::copy_file_range(
+                from.as_raw_fd(),
+                &mut offset,
+                to.as_raw_fd(),
+                std::ptr::null_mut(),
+                chunk,
+                0,
+            )
+        };
+
+        if copied > 0 {
+            remaining -= copied as u64;
+            continue;
+        }
+        if copied == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::EINTR) => {}
+            // Kernels older than 4.5, or blocked by a seccomp filter
+            Some(libc::ENOSYS | libc::EPERM) if remaining == len => {
+                COPY_FILE_RANGE_UNAVAILABLE.store(true, Ordering::Relaxed);
+                return Ok(false);
+            }
+            // Not supported between these files, like across filesystems before Linux 5.3
+            Some(libc::EXDEV | libc::EINVAL | libc::EOPNOTSUPP) if remaining == len => return Ok(false),
+            _ => return Err(err),
+        }
+    }
+
+    Ok(true)
+}
+
+/// Copy `len` bytes of `from` starting at `offset` to the current position of `to`, inside the
+/// kernel, which is only done on Linux, so this always returns `false`
+#[cfg(not(target_os = "linux"))]
+pub fn copy_file_range(_from: &std::fs::File, _offset: u64, _to: &std::fs::File, _len: u64) -> io::Result<bool> {
+    Ok(false)
+}
+
 /// Creates a directory at the path, if there is nothing there.
 pub fn create_dir_if_non_existent(path: &Path) -> crate::Result<()> {
     if !path.exists() {
@@ -65,11 +546,11 @@ pub fn cd_into_same_dir_as(filename: &Path) -> crate::Result<PathBuf> {
     Ok(previous_location)
 }
 
-/// Try to detect the file extension by looking for known magic strings
+/// Try to detect the file extension by looking for known magic strings at the start of `buf`
 /// Source: <https://en.wikipedia.org/wiki/List_of_file_signatures>
-pub fn try_infer_extension(path: &Path) -> Option<Extension> {
+pub fn infer_extension(buf: &[u8]) -> Option<Extension> {
     fn is_zip(buf: &[u8]) -> bool {
-        buf.len() >= 3
+        buf.len() >= 4
             && buf[..=1] == [0x50, 0x4B]
             && (buf[2..=3] == [0x3, 0x4] || buf[2..=3] == [0x5, 0x6] || buf[2..=3] == [0x7, 0x8])
     }
@@ -89,14 +570,24 @@ pub fn try_infer_extension(path: &Path) -> Option<Extension> {
         buf.starts_with(&[0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00])
     }
     fn is_lz4(buf: &[u8]) -> bool {
-        buf.starts_with(&[0x04, 0x22, 0x4D, 0x18])
+        buf.starts_with(&crate::lz4::MAGIC) || buf.starts_with(&crate::lz4::LEGACY_MAGIC)
     }
     fn is_sz(buf: &[u8]) -> bool {
-        buf.starts_with(&[0xFF, 0x06, 0x00, 0x00, 0x73, 0x4E, 0x61, 0x50, 0x70, 0x59])
+        buf.starts_with(&crate::snappy::STREAM_IDENTIFIER)
     }
     fn is_zst(buf: &[u8]) -> bool {
         buf.starts_with(&[0x28, 0xB5, 0x2F, 0xFD])
     }
+    /// Length of the skippable frame at the start of `buf`, which can come before the frames of
+    /// zstd and lz4 streams, with any of 16 magic numbers, like the one written first by pzstd
+    fn skippable_frame_len(buf: &[u8]) -> Option<usize> {
+        let magic = u32::from_le_bytes(buf.get(..4)?.try_into().unwrap());
+        if magic & 0xFFFF_FFF0 != 0x184D_2A50 {
+            return None;
+        }
+        let size = u32::from_le_bytes(buf.get(4..8)?.try_into().unwrap());
+        (size as usize).checked_add(8)
+    }
     fn is_rar(buf: &[u8]) -> bool {
         // ref https://www.rarlab.com/technote.htm#rarsign
         // RAR 5.0 8 bytes length signature: 0x52 0x61 0x72 0x21 0x1A 0x07 0x01 0x00
@@ -108,44 +599,112 @@ pub fn try_infer_extension(path: &Path) -> Option<Extension> {
     fn is_sevenz(buf: &[u8]) -> bool {
         buf.starts_with(&[0x37, 0x7A, 0xBC, 0xAF, 0x27, 0x1C])
     }
+    fn is_z(buf: &[u8]) -> bool {
+        buf.starts_with(&crate::lzw::MAGIC)
+    }
+    fn is_lzo(buf: &[u8]) -> bool {
+        buf.starts_with(&crate::lzop::MAGIC)
+    }
+    fn is_lrz(buf: &[u8]) -> bool {
+        buf.starts_with(crate::lrzip::MAGIC)
+    }
+    fn is_squashfs(buf: &[u8]) -> bool {
+        buf.starts_with(crate::archive::squashfs::MAGIC)
+    }
+    fn is_age(buf: &[u8]) -> bool {
+        buf.starts_with(b"age-encryption.org/v1\n")
+    }
 
-    let buf = {
-        let mut buf = [0; 270];
-
-        // Error cause will be ignored, so use std::fs instead of fs_err
-        let result = std::fs::File::open(path).map(|mut file| file.read(&mut buf));
+    use crate::extension::CompressionFormat::*;
 
-        // In case of file open or read failure, could not infer a extension
-        if result.is_err() {
-            return None;
-        }
-        buf
-    };
+    // The frame after the skippable ones tells if it's lz4, zstd is far more common if it's past `buf`
+    if let Some(len) = skippable_frame_len(buf) {
+        return match buf.get(len..).and_then(infer_extension) {
+            Some(extension) if extension.compression_formats == [Lz4] => Some(extension),
+            _ => Some(Extension::new(&[Zstd], "zst")),
+        };
+    }
 
-    use crate::extension::CompressionFormat::*;
-    if is_zip(&buf) {
+    if is_zip(buf) {
         Some(Extension::new(&[Zip], "zip"))
-    } else if is_tar(&buf) {
+    } else if is_tar(buf) {
         Some(Extension::new(&[Tar], "tar"))
-    } else if is_gz(&buf) {
+    } else if is_gz(buf) {
         Some(Extension::new(&[Gzip], "gz"))
-    } else if is_bz2(&buf) {
+    } else if is_bz2(buf) {
         Some(Extension::new(&[Bzip], "bz2"))
-    } else if is_bz3(&buf) {
+    } else if is_bz3(buf) {
         Some(Extension::new(&[Bzip3], "bz3"))
-    } else if is_xz(&buf) {
+    } else if is_xz(buf) {
         Some(Extension::new(&[Lzma], "xz"))
-    } else if is_lz4(&buf) {
+    } else if is_lz4(buf) {
         Some(Extension::new(&[Lz4], "lz4"))
-    } else if is_sz(&buf) {
+    } else if is_sz(buf) {
         Some(Extension::new(&[Snappy], "sz"))
-    } else if is_zst(&buf) {
+    } else if is_zst(buf) {
         Some(Extension::new(&[Zstd], "zst"))
-    } else if is_rar(&buf) {
+    } else if is_rar(buf) {
         Some(Extension::new(&[Rar], "rar"))
-    } else if is_sevenz(&buf) {
+    } else if is_sevenz(buf) {
         Some(Extension::new(&[SevenZip], "7z"))
+    } else if is_z(buf) {
+        Some(Extension::new(&[Lzw], "Z"))
+    } else if is_lzo(buf) {
+        Some(Extension::new(&[Lzop], "lzo"))
+    } else if is_lrz(buf) {
+        Some(Extension::new(&[Lrzip], "lrz"))
+    } else if is_squashfs(buf) {
+        Some(Extension::new(&[Squashfs], "squashfs"))
+    } else if is_age(buf) {
+        Some(Extension::new(&[Age], "age"))
     } else {
-        None
+        // Formats of registered codecs, after the built-in ones
+        crate::codec::extension_from_magic(buf)
+    }
+}
+
+/// Try to detect the file extension of the file at `path` by looking for known magic strings
+pub fn try_infer_extension(path: &Path) -> Option<Extension> {
+    // Error cause will be ignored, so use std::fs instead of fs_err
+    let mut file = std::fs::File::open(path).ok()?;
+
+    let mut buf = [0; 270];
+    // In case of read failure, could not infer a extension
+    let read = file.read(&mut buf).ok()?;
+    infer_extension(&buf[..read])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extension::CompressionFormat::{self, *};
+
+    fn formats(buf: &[u8]) -> Option<&'static [CompressionFormat]> {
+        infer_extension(buf).map(|extension| extension.compression_formats)
+    }
+
+    #[test]
+    fn test_infer_extension() {
+        assert_eq!(formats(b"7z\xBC\xAF\x27\x1C\x00\x04"), Some(&[SevenZip][..]));
+        assert_eq!(formats(b"Rar!\x1A\x07\x00\xCF\x90"), Some(&[Rar][..]));
+        assert_eq!(formats(b"Rar!\x1A\x07\x01\x00\x33\x92"), Some(&[Rar][..]));
+        assert_eq!(formats(b"hsqs\x05\x00\x00\x00"), Some(&[Squashfs][..]));
+        assert_eq!(formats(b"Rar!\x1A\x07"), None);
+        assert_eq!(formats(b""), None);
+    }
+
+    #[test]
+    fn test_infer_extension_after_skippable_frame() {
+        // Skippable frame of pzstd, holding the size of the next frame, then a zstd frame
+        let pzstd = b"\x50\x2A\x4D\x18\x04\x00\x00\x00\x0D\x00\x00\x00\x28\xB5\x2F\xFD";
+        assert_eq!(formats(pzstd), Some(&[Zstd][..]));
+
+        let lz4 = b"\x5F\x2A\x4D\x18\x02\x00\x00\x00ab\x04\x22\x4D\x18\x64\x40";
+        assert_eq!(formats(lz4), Some(&[Lz4][..]));
+
+        // The next frame is past what was read
+        let large = b"\x50\x2A\x4D\x18\x00\x00\x01\x00";
+        assert_eq!(formats(large), Some(&[Zstd][..]));
+        assert_eq!(formats(b"\x50\x2A\x4D\x18\x00"), None);
     }
 }
@@ -1,6 +1,45 @@
-use std::io::{self, stderr, stdout, StderrLock, StdoutLock, Write};
+use std::{
+    ffi::OsString,
+    io::{self, stderr, stdout, Read, Seek, SeekFrom, StderrLock, StdoutLock, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
 
-use crate::utils::logger;
+use fs_err as fs;
+
+use crate::{utils::logger, BUFFER_CAPACITY};
+
+/// Inputs at least this big are read and written with buffers of [`LARGE_INPUT_BUFFER_SIZE`]
+const LARGE_INPUT_SIZE: u64 = 64 * 1024 * 1024;
+const LARGE_INPUT_BUFFER_SIZE: usize = 256 * 1024;
+/// Largest buffer size picked by [`default_buffer_size`]
+const MAX_DEFAULT_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Size of the buffers used to read and write files when `--io-buffer-size` isn't given, `input`
+/// is the metadata of the file being compressed or decompressed.
+///
+/// Big inputs get bigger buffers, and buffers are never smaller than the I/O block size preferred
+/// by the filesystem, which is large on network filesystems.
+pub fn default_buffer_size(input: Option<&std::fs::Metadata>) -> usize {
+    let Some(input) = input else {
+        return BUFFER_CAPACITY;
+    };
+
+    let size = if input.len() >= LARGE_INPUT_SIZE {
+        LARGE_INPUT_BUFFER_SIZE
+    } else {
+        BUFFER_CAPACITY
+    };
+
+    #[cfg(unix)]
+    let size = {
+        use std::os::unix::fs::MetadataExt;
+        size.max(input.blksize() as usize)
+    };
+
+    size.min(MAX_DEFAULT_BUFFER_SIZE)
+}
 
 type StdioOutputLocks = (StdoutLock<'static>, StderrLock<'static>);
 
@@ -14,3 +53,274 @@ pub fn lock_and_flush_output_stdio() -> io::Result<StdioOutputLocks> {
 
     Ok((stdout, stderr))
 }
+
+/// Wraps a reader or writer, sleeping as needed to keep its throughput under `bytes_per_second`
+pub struct Throttled<T> {
+    inner: T,
+    bytes_per_second: u64,
+    transferred: u64,
+    start: Instant,
+}
+
+impl<T> Throttled<T> {
+    pub fn new(inner: T, bytes_per_second: u64) -> Self {
+        Self {
+            inner,
+            bytes_per_second,
+            transferred: 0,
+            start: Instant::now(),
+        }
+    }
+
+    /// Account for `bytes` more being transferred, waiting until the rate allows them
+    fn throttle(&mut self, bytes: usize) {
+        self.transferred += bytes as u64;
+        let expected = Duration::from_secs_f64(self.transferred as f64 / self.bytes_per_second as f64);
+
+        if let Some(wait) = expected.checked_sub(self.start.elapsed()) {
+            thread::sleep(wait);
+        }
+    }
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.throttle(read);
+        Ok(read)
+    }
+}
+
+impl<W: Write> Write for Throttled<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.throttle(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: Seek> Seek for Throttled<S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Bits of the rolling hash of [`Rsyncable`] that must be zero to flush, one point every 4KiB on average
+const RSYNC_HASH_BITS: u32 = 12;
+
+/// Random value of each byte, added to the rolling hash of [`Rsyncable`]
+const GEAR: [u64; 256] = {
+    // splitmix64, the table only has to be random looking and the same everywhere
+    let mut table = [0; 256];
+    let mut state: u64 = 0;
+    let mut index = 0;
+    while index < table.len() {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut value = state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[index] = value ^ (value >> 31);
+        index += 1;
+    }
+    table
+};
+
+/// Wraps an encoder, flushing it at points picked from the data written, like `gzip --rsyncable`.
+/// The points depend only on the last 64 bytes before them, so a change in the input only changes
+/// the output until one of the next points, letting rsync transfer the rest as is.
+pub struct Rsyncable<W> {
+    inner: W,
+    /// Gear hash of the bytes written, each one is shifted out of it after 64 more
+    hash: u64,
+}
+
+impl<W> Rsyncable<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, hash: 0 }
+    }
+}
+
+impl<W: Write> Write for Rsyncable<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut chunk_start = 0;
+        for (index, &byte) in buf.iter().enumerate() {
+            self.hash = (self.hash << 1).wrapping_add(GEAR[byte as usize]);
+
+            if self.hash >> (64 - RSYNC_HASH_BITS) == 0 {
+                self.inner.write_all(&buf[chunk_start..=index])?;
+                self.inner.flush()?;
+                chunk_start = index + 1;
+            }
+        }
+        self.inner.write_all(&buf[chunk_start..])?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// How much of a spooled stream is kept in memory before moving it to a temporary file
+const SPOOL_MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Copy `reader` into memory, or into a temporary file once it's bigger than 64 MiB, for formats
+/// that have to seek in streams that can't, like a zip archive inside of a `.zip.gz`
+pub fn spool(reader: &mut impl Read) -> io::Result<tempfile::SpooledTempFile> {
+    let mut spooled = tempfile::spooled_tempfile(SPOOL_MEMORY_LIMIT);
+    io::copy(reader, &mut spooled)?;
+    spooled.rewind()?;
+    Ok(spooled)
+}
+
+/// Path of the part `number` of the file at `path` split by `--split-size`, like "archive.tar.gz.001"
+pub fn split_part_path(path: &Path, number: usize) -> PathBuf {
+    let mut part = OsString::from(path);
+    part.push(format!(".{number:03}"));
+    PathBuf::from(part)
+}
+
+/// Paths of the existing parts of the file at `path` split by `--split-size`, in order
+pub fn split_part_paths(path: &Path) -> Vec<PathBuf> {
+    (1..)
+        .map(|number| split_part_path(path, number))
+        .take_while(|part| part.is_file())
+        .collect()
+}
+
+/// If `path` is the first part of a split file, like "archive.tar.gz.001", the path of the whole file
+pub fn joined_path(path: &Path) -> Option<PathBuf> {
+    (path.extension()? == "001").then(|| path.with_extension(""))
+}
+
+/// Writes to "<path>.001", "<path>.002" and so on, starting a new part every `part_size` bytes
+pub struct SplitWriter {
+    path: PathBuf,
+    part_size: u64,
+    part: fs::File,
+    part_number: usize,
+    written_to_part: u64,
+}
+
+impl SplitWriter {
+    /// Create the first part right away, so even an empty output has one
+    pub fn new(path: PathBuf, part_size: u64) -> io::Result<Self> {
+        let part = fs::File::create(split_part_path(&path, 1))?;
+        Ok(Self {
+            path,
+            part_size,
+            part,
+            part_number: 1,
+            written_to_part: 0,
+        })
+    }
+}
+
+impl Write for SplitWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.written_to_part == self.part_size {
+            self.part.flush()?;
+            self.part_number += 1;
+            self.part = fs::File::create(split_part_path(&self.path, self.part_number))?;
+            self.written_to_part = 0;
+        }
+
+        let room = (self.part_size - self.written_to_part).min(buf.len() as u64) as usize;
+        let written = self.part.write(&buf[..room])?;
+        self.written_to_part += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.part.flush()
+    }
+}
+
+/// Reads the parts of a file split by `--split-size` as if they were a single file
+pub struct JoinedParts {
+    parts: Vec<fs::File>,
+    /// Position of the start of each part in the joined file
+    starts: Vec<u64>,
+    len: u64,
+    position: u64,
+}
+
+impl JoinedParts {
+    /// Open the file at `path`, and the parts that follow it if it's the first part of a split file
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let paths = match joined_path(path) {
+            Some(joined) => split_part_paths(&joined),
+            None => vec![path.to_path_buf()],
+        };
+
+        let mut parts = Vec::with_capacity(paths.len());
+        let mut starts = Vec::with_capacity(paths.len());
+        let mut len = 0;
+        for path in paths {
+            let part = fs::File::open(path)?;
+            starts.push(len);
+            len += part.metadata()?.len();
+            parts.push(part);
+        }
+
+        Ok(Self {
+            parts,
+            starts,
+            len,
+            position: 0,
+        })
+    }
+
+    /// The part holding the `len` bytes at `position` of the joined file and their position in
+    /// that part, unless they're split across parts
+    pub fn part_at(&self, position: u64, len: u64) -> Option<(&std::fs::File, u64)> {
+        let index = self.starts.partition_point(|&start| start <= position).checked_sub(1)?;
+        let part_end = self.starts.get(index + 1).copied().unwrap_or(self.len);
+        if position.checked_add(len)? > part_end {
+            return None;
+        }
+
+        Some((self.parts[index].file(), position - self.starts[index]))
+    }
+}
+
+impl Read for JoinedParts {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        // Parts can be empty, so take the last one starting at or before the position
+        let index = self.starts.partition_point(|&start| start <= self.position) - 1;
+        let part_end = self.starts.get(index + 1).copied().unwrap_or(self.len);
+        let room = (part_end - self.position).min(buf.len() as u64) as usize;
+
+        let part = &mut self.parts[index];
+        part.seek(SeekFrom::Start(self.position - self.starts[index]))?;
+        let read = part.read(&mut buf[..room])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for JoinedParts {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position = position
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative position"))?;
+        Ok(self.position)
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
-    sync::{mpsc, Arc, Barrier, OnceLock},
+    io::{self, IsTerminal},
+    sync::{mpsc, Arc, Barrier, Mutex, OnceLock, PoisonError},
     thread,
 };
 
@@ -20,6 +21,36 @@ pub fn flush_messages() {
     logger_thread::send_flush_command_and_wait();
 }
 
+/// Gives the line shown under the logs, see [`set_status_line`]
+type StatusLine = Box<dyn Fn() -> String + Send>;
+
+static STATUS_LINE: Mutex<Option<StatusLine>> = Mutex::new(None);
+
+/// Show the line given by `status` under the logs, with a spinner, redrawn every time they're
+/// flushed until [`clear_status_line`].
+///
+/// Only shown when stderr is a terminal, and never in accessibility mode, where it would be read
+/// over and over. Returns whether it's shown.
+pub fn set_status_line(status: impl Fn() -> String + Send + 'static) -> bool {
+    let shown = io::stderr().is_terminal() && !is_running_in_accessible_mode();
+    if shown {
+        *STATUS_LINE.lock().unwrap_or_else(PoisonError::into_inner) = Some(Box::new(status));
+    }
+    shown
+}
+
+/// Stop showing the line set by [`set_status_line`], erasing it
+pub fn clear_status_line() {
+    if STATUS_LINE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+        .is_some()
+    {
+        flush_messages();
+    }
+}
+
 /// An `[INFO]` log to be displayed if we're not running accessibility mode.
 ///
 /// Same as `.info_accessible()`, but only displayed if accessibility mode
@@ -54,6 +85,10 @@ fn info_with_accessibility(contents: String, accessible: bool) {
 
 #[track_caller]
 pub fn warning(contents: String) {
+    if crate::progress::forward_warning(&contents) {
+        return;
+    }
+
     logger_thread::send_print_command(PrintMessage {
         contents,
         // Warnings are important and unlikely to flood, so they should be displayed
@@ -124,66 +159,67 @@ mod logger_thread {
 
     static SENDER: OnceLock<LogSender> = OnceLock::new();
 
-    #[track_caller]
-    fn setup_channel() -> LogReceiver {
-        let (tx, rx) = mpsc::channel();
-        SENDER.set(tx).expect("`setup_channel` should only be called once");
-        rx
-    }
-
-    #[track_caller]
-    fn get_sender() -> &'static LogSender {
-        SENDER.get().expect("No sender, you need to call `setup_channel` first")
-    }
-
+    /// Print warnings directly when there's no logger thread, like when `ouch` is used as a library,
+    /// info messages are dropped then
     #[track_caller]
     pub(super) fn send_print_command(msg: PrintMessage) {
-        get_sender()
-            .send(LoggerCommand::Print(msg))
-            .expect("Failed to send print command");
+        let Some(sender) = SENDER.get() else {
+            if msg.level == MessageLevel::Warning {
+                if let Some(msg) = msg.to_formatted_message() {
+                    eprintln!("{msg}");
+                }
+            }
+            return;
+        };
+
+        // The logger thread is gone after shutting down, nothing is left to print to
+        let _ = sender.send(LoggerCommand::Print(msg));
     }
 
     #[track_caller]
     pub(super) fn send_flush_command_and_wait() {
         let barrier = Arc::new(Barrier::new(2));
+        let command = LoggerCommand::Flush {
+            finished_barrier: barrier.clone(),
+        };
 
-        get_sender()
-            .send(LoggerCommand::Flush {
-                finished_barrier: barrier.clone(),
-            })
-            .expect("Failed to send flush command");
-
-        barrier.wait();
+        if SENDER.get().is_some_and(|sender| sender.send(command).is_ok()) {
+            barrier.wait();
+        }
     }
 
     #[track_caller]
     pub(super) fn send_shutdown_command_and_wait() {
         let barrier = Arc::new(Barrier::new(2));
+        let command = LoggerCommand::FlushAndShutdown {
+            finished_barrier: barrier.clone(),
+        };
 
-        get_sender()
-            .send(LoggerCommand::FlushAndShutdown {
-                finished_barrier: barrier.clone(),
-            })
-            .expect("Failed to send shutdown command");
-
-        barrier.wait();
+        if SENDER.get().is_some_and(|sender| sender.send(command).is_ok()) {
+            barrier.wait();
+        }
     }
 
+    /// Spawn the thread printing the logs, calling it again does nothing
     pub fn spawn_logger_thread() {
-        let log_receiver = setup_channel();
-        thread::spawn(move || run_logger(log_receiver));
+        let (tx, rx) = mpsc::channel();
+        if SENDER.set(tx).is_ok() {
+            thread::spawn(move || run_logger(rx));
+        }
     }
 
     fn run_logger(log_receiver: LogReceiver) {
         const FLUSH_TIMEOUT: Duration = Duration::from_millis(200);
 
         let mut buffer = Vec::<String>::with_capacity(16);
+        let mut status = Status::default();
 
         loop {
             let msg = match log_receiver.recv_timeout(FLUSH_TIMEOUT) {
                 Ok(msg) => msg,
                 Err(RecvTimeoutError::Timeout) => {
-                    flush_logs_to_stderr(&mut buffer);
+                    flush_logs_to_stderr(&mut buffer, &mut status);
+                    status.draw();
                     continue;
                 }
                 Err(RecvTimeoutError::Disconnected) => unreachable!("sender is static"),
@@ -195,17 +231,21 @@ mod logger_thread {
                     if let Some(msg) = msg.to_formatted_message() {
                         buffer.push(msg);
                     }
+                    status.paused = false;
 
                     if buffer.len() == buffer.capacity() {
-                        flush_logs_to_stderr(&mut buffer);
+                        flush_logs_to_stderr(&mut buffer, &mut status);
+                        status.draw();
                     }
                 }
                 LoggerCommand::Flush { finished_barrier } => {
-                    flush_logs_to_stderr(&mut buffer);
+                    // Questions may follow, the status line is drawn again after the next log
+                    flush_logs_to_stderr(&mut buffer, &mut status);
+                    status.paused = true;
                     finished_barrier.wait();
                 }
                 LoggerCommand::FlushAndShutdown { finished_barrier } => {
-                    flush_logs_to_stderr(&mut buffer);
+                    flush_logs_to_stderr(&mut buffer, &mut status);
                     finished_barrier.wait();
                     return;
                 }
@@ -213,11 +253,44 @@ mod logger_thread {
         }
     }
 
-    fn flush_logs_to_stderr(buffer: &mut Vec<String>) {
+    /// Print the logs in `buffer` over the status line, which has to be drawn again after them
+    fn flush_logs_to_stderr(buffer: &mut Vec<String>, status: &mut Status) {
+        status.erase();
         if !buffer.is_empty() {
             let text = buffer.join("\n");
             eprintln!("{text}");
             buffer.clear();
         }
     }
+
+    /// The line set by [`set_status_line`] as drawn under the logs
+    #[derive(Default)]
+    struct Status {
+        drawn: bool,
+        paused: bool,
+        frame: usize,
+    }
+
+    impl Status {
+        fn draw(&mut self) {
+            const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+            if self.paused {
+                return;
+            }
+            let status_line = STATUS_LINE.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some(status_line) = &*status_line {
+                self.erase();
+                eprint!("{} {}", SPINNER[self.frame % SPINNER.len()], status_line());
+                self.frame += 1;
+                self.drawn = true;
+            }
+        }
+
+        fn erase(&mut self) {
+            if std::mem::take(&mut self.drawn) {
+                eprint!("\r\x1b[K");
+            }
+        }
+    }
 }
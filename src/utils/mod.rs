@@ -3,26 +3,40 @@
 //! In here we have the logic for custom formatting, some file and directory utils, and user
 //! stdin interaction helpers.
 
+pub mod checksum;
 pub mod colors;
 mod file_visibility;
 mod formatting;
 mod fs;
 pub mod io;
 pub mod logger;
+pub mod pipeline;
 mod question;
+pub mod sandbox;
+mod sanitize;
 
+#[cfg(unix)]
+pub use self::fs::set_unpacked_permissions;
+#[cfg(windows)]
+pub use self::fs::set_windows_attributes;
 pub use self::{
-    file_visibility::FileVisibilityPolicy,
+    file_visibility::{symlink_loop, FileVisibilityPolicy},
     formatting::{
         nice_directory_display, os_str_to_str, path_to_str, pretty_format_list_of_paths, strip_cur_dir, Bytes,
         EscapedPathDisplay,
     },
     fs::{
-        cd_into_same_dir_as, clear_path, create_dir_if_non_existent, is_path_stdin, remove_file_or_dir,
-        try_infer_extension,
+        cd_into_same_dir_as, check_symlinks_stay_inside, clear_path, copy_file_range, create_dir_if_non_existent,
+        find_stale_temp_dirs, infer_extension, is_path_stdin, merge_into, move_path, remove_file_or_dir,
+        resolve_conflict, temp_dir_prefix, try_infer_extension, PermissionsPolicy,
     },
-    question::{ask_to_create_file, user_wants_to_continue, user_wants_to_overwrite, QuestionAction, QuestionPolicy},
-    utf8::{get_invalid_utf8_paths, is_invalid_utf8},
+    question::{
+        ask_password, ask_to_create_file, check_symlinked_output, set_refuse_symlinked_outputs, user_picks_format,
+        user_wants_to_continue, user_wants_to_overwrite, user_wants_to_remove_temp_dirs, Answer, QuestionAction,
+        QuestionPolicy,
+    },
+    sanitize::{common_prefix, normalize_path, transform_path, unpacked_entry_path, windows_name_problem, Transform},
+    utf8::get_invalid_utf8_paths,
 };
 
 mod utf8 {
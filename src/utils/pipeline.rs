@@ -0,0 +1,123 @@
+//! Copying with the input read in a separate thread, so the codec writing the output doesn't wait
+//! on the disk between its buffers.
+//!
+//! The reader thread fills a ring of [`BUFFER_COUNT`] buffers, handed to the writing thread through
+//! a channel and sent back once written, so reading stays at most that many buffers ahead.
+
+use std::{
+    io::{self, Read, Write},
+    sync::mpsc,
+    thread,
+};
+
+/// Buffers in the ring, how far reading can get ahead of writing
+const BUFFER_COUNT: usize = 4;
+
+/// Copy all of `reader` into `writer`, like [`io::copy`], but reading in a separate thread into
+/// buffers of `buffer_size` bytes.
+///
+/// Returns the number of bytes copied. Errors of the reader are returned once the buffers read
+/// before them are written, and an error of the writer stops the reader thread.
+pub fn copy<R, W>(reader: &mut R, writer: &mut W, buffer_size: usize) -> io::Result<u64>
+where
+    R: Read + Send,
+    W: Write + ?Sized,
+{
+    let (filled_tx, filled_rx) = mpsc::sync_channel::<io::Result<Vec<u8>>>(BUFFER_COUNT);
+    let (empty_tx, empty_rx) = mpsc::sync_channel::<Vec<u8>>(BUFFER_COUNT);
+    for _ in 0..BUFFER_COUNT {
+        let _ = empty_tx.send(Vec::with_capacity(buffer_size));
+    }
+
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            // Ends when the writing side hangs up, dropping its end of both channels
+            while let Ok(mut buffer) = empty_rx.recv() {
+                buffer.resize(buffer_size, 0);
+                let read = loop {
+                    match reader.read(&mut buffer) {
+                        Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                        result => break result,
+                    }
+                };
+
+                match read {
+                    // Dropping `filled_tx` ends the loop on the writing side
+                    Ok(0) => return,
+                    Ok(read) => {
+                        buffer.truncate(read);
+                        if filled_tx.send(Ok(buffer)).is_err() {
+                            return;
+                        }
+                    }
+                    Err(err) => {
+                        let _ = filled_tx.send(Err(err));
+                        return;
+                    }
+                }
+            }
+        });
+
+        let mut copied = 0;
+        for buffer in filled_rx {
+            let buffer = buffer?;
+            writer.write_all(&buffer)?;
+            copied += buffer.len() as u64;
+            let _ = empty_tx.send(buffer);
+        }
+        Ok(copied)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy() {
+        let data: Vec<u8> = (0..BUFFER_COUNT * 3000 + 7).map(|i| (i % 251) as u8).collect();
+        let mut output = vec![];
+
+        let copied = copy(&mut data.as_slice(), &mut output, 1000).unwrap();
+        assert_eq!(copied, data.len() as u64);
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn test_copy_read_error() {
+        let mut reader = (&[1, 2, 3][..]).chain(FailingReader);
+        let mut output = vec![];
+
+        let err = copy(&mut reader, &mut output, 1000).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(output, [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_copy_write_error() {
+        let mut reader = io::repeat(0);
+
+        let err = copy(&mut reader, &mut FailingWriter, 1000).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+    }
+
+    struct FailingReader;
+
+    impl Read for FailingReader {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("read failed"))
+        }
+    }
+
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("write failed"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -5,27 +5,60 @@
 
 use std::{
     borrow::Cow,
-    io::{stdin, BufRead, IsTerminal},
+    io::{self, stdin, BufRead, BufReader, IsTerminal},
     path::Path,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 use fs_err as fs;
 
 use crate::{
     accessible::is_running_in_accessible_mode,
-    error::{Error, FinalError, Result},
-    utils::{self, colors, formatting::path_to_str, io::lock_and_flush_output_stdio, strip_cur_dir},
+    error::{Error, ErrorClass, FinalError, Result},
+    utils::{
+        self, colors, formatting::path_to_str, io::lock_and_flush_output_stdio, logger::warning, strip_cur_dir,
+        EscapedPathDisplay,
+    },
 };
 
+/// Set by `--no-follow-output-symlink`, outputs that are symbolic links are refused
+static REFUSE_SYMLINKED_OUTPUTS: AtomicBool = AtomicBool::new(false);
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-/// Determines if overwrite questions should be skipped or asked to the user
-pub enum QuestionPolicy {
+/// Answer given to a kind of question instead of asking the user
+pub enum Answer {
     /// Ask the user every time
     Ask,
-    /// Set by `--yes`, will say 'Y' to all overwrite questions
-    AlwaysYes,
-    /// Set by `--no`, will say 'N' to all overwrite questions
-    AlwaysNo,
+    /// Say 'Y'
+    Yes,
+    /// Say 'N'
+    No,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+/// Determines if questions should be skipped or asked to the user
+pub struct QuestionPolicy {
+    /// Questions about overwriting or removing files, answered by `--overwrite`
+    pub overwrite: Answer,
+    /// Questions about continuing anyway, like when the format detected from the contents of a
+    /// file differs from its extension, answered by `--assume-format`
+    pub proceed: Answer,
+}
+
+impl QuestionPolicy {
+    /// Ask the user every time
+    pub const ASK: Self = Self::always(Answer::Ask);
+    /// Set by `--yes`, will say 'Y' to all questions
+    pub const ALWAYS_YES: Self = Self::always(Answer::Yes);
+    /// Set by `--no`, will say 'N' to all questions
+    pub const ALWAYS_NO: Self = Self::always(Answer::No);
+
+    const fn always(answer: Answer) -> Self {
+        Self {
+            overwrite: answer,
+            proceed: answer,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -39,10 +72,10 @@ pub enum QuestionAction {
 
 /// Check if QuestionPolicy flags were set, otherwise, ask user if they want to overwrite.
 pub fn user_wants_to_overwrite(path: &Path, question_policy: QuestionPolicy) -> crate::Result<bool> {
-    match question_policy {
-        QuestionPolicy::AlwaysYes => Ok(true),
-        QuestionPolicy::AlwaysNo => Ok(false),
-        QuestionPolicy::Ask => {
+    match question_policy.overwrite {
+        Answer::Yes => Ok(true),
+        Answer::No => Ok(false),
+        Answer::Ask => {
             let path = path_to_str(strip_cur_dir(path));
             let path = Some(&*path);
             let placeholder = Some("FILE");
@@ -53,13 +86,19 @@ pub fn user_wants_to_overwrite(path: &Path, question_policy: QuestionPolicy) ->
 
 /// Create the file if it doesn't exist and if it does then ask to overwrite it.
 /// If the user doesn't want to overwrite then we return [`Ok(None)`]
+///
+/// A symbolic link at `path` is replaced by the new file, nothing is written to its target.
 pub fn ask_to_create_file(path: &Path, question_policy: QuestionPolicy) -> Result<Option<fs::File>> {
-    match fs::OpenOptions::new().write(true).create_new(true).open(path) {
+    let create_new = || fs::OpenOptions::new().write(true).create_new(true).open(path);
+
+    match create_new() {
         Ok(w) => Ok(Some(w)),
         Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            check_symlinked_output(path)?;
             if user_wants_to_overwrite(path, question_policy)? {
                 utils::remove_file_or_dir(path)?;
-                Ok(Some(fs::File::create(path)?))
+                // Not `File::create`, which would follow a link created in the meantime
+                Ok(Some(create_new()?))
             } else {
                 Ok(None)
             }
@@ -68,16 +107,46 @@ pub fn ask_to_create_file(path: &Path, question_policy: QuestionPolicy) -> Resul
     }
 }
 
+/// Refuse outputs that are symbolic links when `refuse` is set, by `--no-follow-output-symlink`
+pub fn set_refuse_symlinked_outputs(refuse: bool) {
+    REFUSE_SYMLINKED_OUTPUTS.store(refuse, Ordering::Relaxed);
+}
+
+/// Warn that the existing output at `path` is a symbolic link, before asking to overwrite it, or
+/// fail if `--no-follow-output-symlink` was passed
+pub fn check_symlinked_output(path: &Path) -> Result<()> {
+    let Ok(target) = fs::read_link(path) else {
+        return Ok(());
+    };
+
+    if REFUSE_SYMLINKED_OUTPUTS.load(Ordering::Relaxed) {
+        return Err(
+            FinalError::with_title(format!("Refusing to write to {}", EscapedPathDisplay::new(path)))
+                .detail(format!("It is a symbolic link to {}", EscapedPathDisplay::new(&target)))
+                .hint("Remove the link or choose another output, or remove '--no-follow-output-symlink'")
+                .class(ErrorClass::Io)
+                .into(),
+        );
+    }
+
+    warning(format!(
+        "{} is a symbolic link to {}, overwriting it replaces the link, not the file it points to",
+        EscapedPathDisplay::new(path),
+        EscapedPathDisplay::new(&target)
+    ));
+    Ok(())
+}
+
 /// Check if QuestionPolicy flags were set, otherwise, ask the user if they want to continue.
 pub fn user_wants_to_continue(
     path: &Path,
     question_policy: QuestionPolicy,
     question_action: QuestionAction,
 ) -> crate::Result<bool> {
-    match question_policy {
-        QuestionPolicy::AlwaysYes => Ok(true),
-        QuestionPolicy::AlwaysNo => Ok(false),
-        QuestionPolicy::Ask => {
+    match question_policy.proceed {
+        Answer::Yes => Ok(true),
+        Answer::No => Ok(false),
+        Answer::Ask => {
             let action = match question_action {
                 QuestionAction::Compression => "compress",
                 QuestionAction::Decompression => "decompress",
@@ -90,6 +159,142 @@ pub fn user_wants_to_continue(
     }
 }
 
+/// Ask the user which of `choices` to compress `path` with, by number or name, returning `None`
+/// if questions are answered by `--yes` or `--no`, or can't be asked without a terminal.
+pub fn user_picks_format(
+    path: &Path,
+    choices: &[&'static str],
+    question_policy: QuestionPolicy,
+) -> crate::Result<Option<&'static str>> {
+    if question_policy.proceed != Answer::Ask || !stdin().is_terminal() {
+        return Ok(None);
+    }
+
+    let path = path_to_str(strip_cur_dir(path));
+    let _locks = lock_and_flush_output_stdio()?;
+    let mut stdin_lock = stdin().lock();
+
+    // Ask the same question to end while no valid answers are given
+    loop {
+        eprintln!("'{path}' has no extension, which format should it be compressed with?");
+        for (number, choice) in choices.iter().enumerate() {
+            eprintln!("  {}: {choice}", number + 1);
+        }
+
+        let mut answer = String::new();
+        if stdin_lock.read_line(&mut answer)? == 0 {
+            return Err(FinalError::with_title("Unexpected EOF when asking question.")
+                .detail("Expected the number or the name of a format, but found EOF instead.")
+                .hint("If using Ouch in scripting, consider using `--format` or `--default-format`.")
+                .into());
+        }
+
+        let answer = answer.trim();
+        let picked = match answer.parse::<usize>() {
+            Ok(number) => number.checked_sub(1).and_then(|index| choices.get(index)),
+            Err(_) => choices.iter().find(|choice| choice.eq_ignore_ascii_case(answer)),
+        };
+        if let Some(choice) = picked {
+            return Ok(Some(choice));
+        }
+    }
+}
+
+/// Check if QuestionPolicy flags were set, otherwise, ask the user if they want to remove the
+/// temporary directories that were just reported.
+pub fn user_wants_to_remove_temp_dirs(question_policy: QuestionPolicy) -> crate::Result<bool> {
+    match question_policy.overwrite {
+        Answer::Yes => Ok(true),
+        Answer::No => Ok(false),
+        Answer::Ask => Confirmation::new("Do you want to remove them?", None).ask(None),
+    }
+}
+
+/// Ask the user for a password in the terminal, without echoing what is typed.
+pub fn ask_password() -> crate::Result<String> {
+    let _locks = lock_and_flush_output_stdio()?;
+
+    let mut password = read_line_without_echo("Password: ").map_err(|err| {
+        FinalError::with_title("Could not ask for the password")
+            .detail(format!("Error: {err}."))
+            .hint("If not running in a terminal, consider using `--password-file`.")
+    })?;
+
+    let trimmed_len = password.trim_end_matches(['\n', '\r']).len();
+    password.truncate(trimmed_len);
+    Ok(password)
+}
+
+/// Print `prompt` and read a line from the terminal, with echo disabled while reading it
+#[cfg(unix)]
+fn read_line_without_echo(prompt: &str) -> io::Result<String> {
+    use std::{io::Write, mem::MaybeUninit, os::fd::AsRawFd};
+
+    // Use the terminal even if stdin or stderr are redirected
+    let tty = fs::OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    let fd = tty.file().as_raw_fd();
+
+    let original = unsafe {
+        let mut termios = MaybeUninit::uninit();
+        if libc::tcgetattr(fd, termios.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        termios.assume_init()
+    };
+
+    let mut hidden = original;
+    hidden.c_lflag &= !libc::ECHO;
+    // Still echo the newline, so the output continues in the next line
+    hidden.c_lflag |= libc::ECHONL;
+
+    (&tty).write_all(prompt.as_bytes())?;
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &hidden) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut line = String::new();
+    let result = BufReader::new(&tty).read_line(&mut line);
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+
+    match result? {
+        0 => Err(io::ErrorKind::UnexpectedEof.into()),
+        _ => Ok(line),
+    }
+}
+
+/// Print `prompt` and read a line from the console, with echo disabled while reading it
+#[cfg(windows)]
+fn read_line_without_echo(prompt: &str) -> io::Result<String> {
+    use std::os::windows::io::AsRawHandle;
+
+    use windows_sys::Win32::System::Console::{GetConsoleMode, SetConsoleMode, ENABLE_ECHO_INPUT};
+
+    // Use the console even if stdin is redirected
+    let console = fs::OpenOptions::new().read(true).write(true).open("CONIN$")?;
+    let handle = console.file().as_raw_handle();
+
+    let mut original = 0;
+    if unsafe { GetConsoleMode(handle, &mut original) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    eprint!("{prompt}");
+    if unsafe { SetConsoleMode(handle, original & !ENABLE_ECHO_INPUT) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut line = String::new();
+    let result = BufReader::new(&console).read_line(&mut line);
+    unsafe { SetConsoleMode(handle, original) };
+    // The newline typed by the user wasn't echoed
+    eprintln!();
+
+    match result? {
+        0 => Err(io::ErrorKind::UnexpectedEof.into()),
+        _ => Ok(line),
+    }
+}
+
 /// Confirmation dialog for end user with [Y/n] question.
 ///
 /// If the placeholder is found in the prompt text, it will be replaced to form the final message.
@@ -0,0 +1,167 @@
+//! Sandbox of `--sandbox`, restricting what the process can access while it unpacks untrusted
+//! archives.
+//!
+//! On Linux, Landlock limits the files the process can open from then on to reading the inputs and
+//! writing beneath the directories it unpacks into, so a bug in a decoder can't reach the rest of
+//! the system. It only applies to the calling thread and the threads it starts afterwards.
+
+use std::path::Path;
+
+use crate::error::{CowStr, ErrorClass, FinalError};
+
+/// Files and directories that stay accessible in the sandbox
+#[derive(Debug, Default)]
+pub struct SandboxRules<'a> {
+    /// Files that can be read, like the inputs
+    pub readable: Vec<&'a Path>,
+    /// Directories beneath which anything can be read, created, written and removed
+    pub writable: Vec<&'a Path>,
+}
+
+fn unavailable(detail: impl Into<CowStr>) -> FinalError {
+    FinalError::with_title("Cannot unpack in a sandbox")
+        .detail(detail)
+        .hint("Remove '--sandbox' to unpack without it")
+}
+
+/// Restrict the current thread, and the threads it starts from now on, to `rules`.
+///
+/// Fails if the system can't sandbox the process, instead of going on without the sandbox.
+#[cfg(target_os = "linux")]
+pub fn enter(rules: &SandboxRules) -> crate::Result<()> {
+    landlock::restrict_self(rules).map_err(|err| match err.raw_os_error() {
+        Some(libc::ENOSYS | libc::EOPNOTSUPP) => unavailable("Landlock isn't enabled in this kernel").into(),
+        _ => unavailable(err.to_string()).class(ErrorClass::Io).into(),
+    })
+}
+
+/// Restrict the current thread, and the threads it starts from now on, to `rules`.
+///
+/// Fails if the system can't sandbox the process, instead of going on without the sandbox.
+#[cfg(not(target_os = "linux"))]
+pub fn enter(_rules: &SandboxRules) -> crate::Result<()> {
+    Err(unavailable("Sandboxing is only supported on Linux").into())
+}
+
+/// Raw Landlock syscalls, see landlock(7)
+#[cfg(target_os = "linux")]
+mod landlock {
+    use std::{
+        fs::{self, File},
+        io,
+        os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+        path::Path,
+    };
+
+    use super::SandboxRules;
+
+    const CREATE_RULESET_VERSION: u32 = 1 << 0;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+    /// Every access of the first version of Landlock, from executing files to making symlinks
+    const ACCESS_FS_V1: u64 = (1 << 13) - 1;
+    /// Linking and renaming files across directories, since the second version
+    const ACCESS_FS_REFER: u64 = 1 << 13;
+    /// Truncating files, since the third version
+    const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    fn check(result: libc::c_long) -> io::Result<libc::c_long> {
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(result)
+    }
+
+    /// Accesses denied unless allowed by a rule, the ones the running kernel knows about
+    fn handled_access() -> io::Result<u64> {
+        // SAFETY: querying the version takes no attributes
+        let version = check(unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0,
+                CREATE_RULESET_VERSION,
+            )
+        })?;
+
+        Ok(match version {
+            1 => ACCESS_FS_V1,
+            2 => ACCESS_FS_V1 | ACCESS_FS_REFER,
+            _ => ACCESS_FS_V1 | ACCESS_FS_REFER | ACCESS_FS_TRUNCATE,
+        })
+    }
+
+    fn add_rule(ruleset: &File, path: &Path, allowed_access: u64) -> io::Result<()> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_PATH | libc::O_CLOEXEC)
+            .open(path)?;
+        let attr = PathBeneathAttr {
+            allowed_access,
+            parent_fd: file.as_raw_fd(),
+        };
+
+        // SAFETY: `attr` outlives the call, and the kernel only reads it
+        check(unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset.as_raw_fd(),
+                RULE_PATH_BENEATH,
+                &attr as *const PathBeneathAttr,
+                0,
+            )
+        })?;
+        Ok(())
+    }
+
+    pub fn restrict_self(rules: &SandboxRules) -> io::Result<()> {
+        let handled_access_fs = handled_access()?;
+        let attr = RulesetAttr { handled_access_fs };
+
+        // SAFETY: `attr` outlives the call, and the kernel only reads it
+        let fd = check(unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        })?;
+        // SAFETY: the syscall returned a new file descriptor, closed when dropped
+        let ruleset = unsafe { <File as std::os::fd::FromRawFd>::from_raw_fd(fd as i32) };
+
+        for path in &rules.readable {
+            add_rule(&ruleset, path, ACCESS_FS_READ_FILE)?;
+        }
+        for path in &rules.writable {
+            add_rule(&ruleset, path, handled_access_fs)?;
+        }
+        // Passwords are asked in the terminal, if there's one
+        let _ = add_rule(
+            &ruleset,
+            Path::new("/dev/tty"),
+            ACCESS_FS_READ_FILE | ACCESS_FS_WRITE_FILE,
+        );
+
+        // Required to restrict a process without privileges, it can't gain them with setuid
+        // programs afterwards
+        // SAFETY: only sets a flag of the process
+        check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) }.into())?;
+        // SAFETY: only restricts the calling thread
+        check(unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,448 @@
+//! Adjustments to entry names, like Unicode normalization, the rules given to `--transform` and
+//! escaping names that can't be used as file names on Windows.
+
+use std::{
+    borrow::Cow,
+    ffi::OsStr,
+    path::{Component, Path, PathBuf},
+};
+
+use regex::{Regex, RegexBuilder};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{
+    error::{ErrorClass, FinalError},
+    options::NormalizationForm,
+};
+
+/// Device names reserved by Windows, with or without an extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2",
+    "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters that aren't allowed in Windows file names
+const INVALID_CHARACTERS: [char; 7] = ['<', '>', ':', '"', '|', '?', '*'];
+
+/// Apply the Unicode normalization `form` to `path`, paths that aren't valid UTF-8 are kept as they are
+pub fn normalize_path(path: &Path, form: NormalizationForm) -> Cow<'_, Path> {
+    let Some(text) = path.to_str() else {
+        return Cow::Borrowed(path);
+    };
+
+    let normalized: String = match form {
+        NormalizationForm::Nfc => text.nfc().collect(),
+        NormalizationForm::Nfd => text.nfd().collect(),
+        NormalizationForm::None => return Cow::Borrowed(path),
+    };
+
+    if normalized == text {
+        Cow::Borrowed(path)
+    } else {
+        Cow::Owned(normalized.into())
+    }
+}
+
+/// Describes why `name` can't be used as a file name on Windows, if it can't
+pub fn windows_name_problem(name: &OsStr) -> Option<&'static str> {
+    let name = name.to_string_lossy();
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        Some("is a reserved device name")
+    } else if name.ends_with(['.', ' ']) {
+        Some("ends with a dot or a space")
+    } else if name.contains(INVALID_CHARACTERS) || name.contains(|ch: char| ch.is_ascii_control()) {
+        Some("contains characters that are not allowed")
+    } else {
+        None
+    }
+}
+
+/// Escape the components of `path` that can't be used as file names on Windows.
+///
+/// Reserved names get a `_` prefix, while trailing dots and spaces, and invalid characters, are
+/// replaced by `_`, so `aux.txt` becomes `_aux.txt` and `notes.` becomes `notes_`.
+#[cfg(any(windows, test))]
+pub fn windows_compatible_path(path: &Path) -> Cow<'_, Path> {
+    if !path.components().any(|component| match component {
+        Component::Normal(name) => windows_name_problem(name).is_some(),
+        _ => false,
+    }) {
+        return Cow::Borrowed(path);
+    }
+
+    let escaped = path
+        .components()
+        .map(|component| match component {
+            Component::Normal(name) if windows_name_problem(name).is_some() => {
+                Cow::Owned(escape_windows_name(&name.to_string_lossy()).into())
+            }
+            component => Cow::Borrowed(component.as_os_str()),
+        })
+        .collect::<PathBuf>();
+
+    Cow::Owned(escaped)
+}
+
+/// A sed-like substitution given to `--transform`, like `s/^old-name/new-name/`, applied to the
+/// names of unpacked entries
+#[derive(Debug, Clone)]
+pub struct Transform {
+    regex: Regex,
+    /// Replacement in the syntax of [`Regex::replace`]
+    replacement: String,
+    /// Replace every match instead of only the first one, the `g` flag
+    global: bool,
+}
+
+impl Transform {
+    /// Parse an `s/REGEX/REPLACEMENT/FLAGS` expression, any character can be used instead of `/`.
+    ///
+    /// Like in sed, `&` and `\1` to `\9` in the replacement are replaced by the match and its
+    /// groups, and the flags are `g` to replace every match and `i` to ignore case.
+    pub fn parse(expression: &str) -> crate::Result<Self> {
+        let invalid = |reason: String| {
+            FinalError::with_title(format!("Invalid transform expression '{expression}'"))
+                .detail(reason)
+                .hint("Expressions look like 's/^old-name/new-name/', with the optional flags:")
+                .hint("  g  to replace every match instead of the first one")
+                .hint("  i  to ignore case")
+                .class(ErrorClass::BadArguments)
+        };
+
+        let mut chars = expression.chars();
+        let delimiter = match (chars.next(), chars.next()) {
+            (Some('s'), Some(delimiter)) if delimiter != '\\' && !delimiter.is_alphanumeric() => delimiter,
+            _ => return Err(invalid("Expected 's' followed by a delimiter, like 's/'".into()).into()),
+        };
+
+        // Regex, replacement and flags, escaped delimiters lose their backslash
+        let mut parts = vec![String::new()];
+        let mut escaped = false;
+        for ch in chars {
+            if !escaped && ch == delimiter && parts.len() < 3 {
+                parts.push(String::new());
+                continue;
+            }
+
+            let part = parts.last_mut().expect("has at least one part");
+            if escaped {
+                if ch != delimiter {
+                    part.push('\\');
+                }
+                part.push(ch);
+                escaped = false;
+            } else if ch == '\\' {
+                escaped = true;
+            } else {
+                part.push(ch);
+            }
+        }
+
+        let [pattern, replacement, flags] = <[String; 3]>::try_from(parts)
+            .map_err(|_| invalid(format!("Missing the closing '{delimiter}' delimiter")))?;
+
+        let mut global = false;
+        let mut case_insensitive = false;
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => case_insensitive = true,
+                _ => return Err(invalid(format!("Unknown flag '{flag}'")).into()),
+            }
+        }
+
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(case_insensitive)
+            .build()
+            .map_err(|err| invalid(err.to_string()))?;
+
+        Ok(Self {
+            regex,
+            replacement: sed_replacement(&replacement),
+            global,
+        })
+    }
+
+    /// Remove the directory `prefix` from the start of names, entries named like `prefix` itself
+    /// are renamed to nothing, so they're skipped. `None` if `prefix` isn't valid UTF-8
+    pub fn strip_prefix(prefix: &Path) -> Option<Self> {
+        // Tar archives made with `tar -C dir .` have names starting with "./"
+        let pattern = format!(r"^(?:\.?/)*{}(?:/|$)", regex::escape(prefix.to_str()?));
+        Some(Self {
+            regex: Regex::new(&pattern).expect("escaped prefix is a valid regex"),
+            replacement: String::new(),
+            global: false,
+        })
+    }
+
+    fn apply<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        if self.global {
+            self.regex.replace_all(name, self.replacement.as_str())
+        } else {
+            self.regex.replace(name, self.replacement.as_str())
+        }
+    }
+}
+
+/// Convert a sed replacement, where `&` and `\N` are the match and its groups, to the syntax of
+/// [`Regex::replace`]
+fn sed_replacement(replacement: &str) -> String {
+    let mut converted = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars();
+
+    while let Some(ch) = chars.next() {
+        let literal = match ch {
+            '&' => {
+                converted.push_str("${0}");
+                continue;
+            }
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => {
+                    converted.push_str(&format!("${{{digit}}}"));
+                    continue;
+                }
+                Some(escaped) => escaped,
+                None => '\\',
+            },
+            ch => ch,
+        };
+
+        if literal == '$' {
+            converted.push_str("$$");
+        } else {
+            converted.push(literal);
+        }
+    }
+
+    converted
+}
+
+/// Apply `transforms` to `path` in order, returns `None` if the entry should be skipped because
+/// it was renamed to nothing, or to a path outside of the output directory.
+///
+/// Paths that aren't valid UTF-8 are kept as they are.
+pub fn transform_path<'a>(path: &'a Path, transforms: &[Transform]) -> Option<Cow<'a, Path>> {
+    let Some(name) = path.to_str().filter(|_| !transforms.is_empty()) else {
+        return Some(Cow::Borrowed(path));
+    };
+
+    let mut transformed = name.to_owned();
+    for transform in transforms {
+        transformed = transform.apply(&transformed).into_owned();
+    }
+
+    if transformed == name {
+        return Some(Cow::Borrowed(path));
+    }
+
+    let transformed = PathBuf::from(transformed);
+    if transformed
+        .components()
+        .any(|component| component == Component::ParentDir)
+    {
+        super::logger::warning(format!(
+            "Skipping {}, it was transformed to {} which is outside of the output directory",
+            super::EscapedPathDisplay::new(path),
+            super::EscapedPathDisplay::new(&transformed),
+        ));
+        return None;
+    }
+
+    // Like for archive entries, leading slashes don't make the path absolute
+    let transformed: PathBuf = transformed
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect();
+
+    (!transformed.as_os_str().is_empty()).then_some(Cow::Owned(transformed))
+}
+
+/// The directory at the root of an archive with every other entry of `entries` inside of it, like
+/// "project-1.2.3" in the archives of source releases, given the path of each entry and whether
+/// it's a directory. `None` if there isn't a single one, or it's empty
+pub fn common_prefix<'a>(entries: impl IntoIterator<Item = (&'a Path, bool)>) -> Option<PathBuf> {
+    let mut prefix = None;
+    let mut has_contents = false;
+
+    for (path, is_dir) in entries {
+        let mut components = path.components().filter_map(|component| match component {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        });
+        // Like the "./" entry of `tar -C dir .`
+        let Some(first) = components.next() else {
+            continue;
+        };
+        let is_nested = components.next().is_some();
+
+        if (!is_nested && !is_dir) || *prefix.get_or_insert(first) != first {
+            return None;
+        }
+        has_contents |= is_nested;
+    }
+
+    prefix.filter(|_| has_contents).map(PathBuf::from)
+}
+
+/// Name to unpack an entry at `path` with, applying the `--transform` rules in `transforms` and the
+/// Unicode normalization `form`, returns `None` if the entry should be skipped.
+///
+/// On Windows, names that can't be used there are escaped with [`windows_compatible_path`].
+pub fn unpacked_entry_path<'a>(
+    path: &'a Path,
+    form: NormalizationForm,
+    transforms: &[Transform],
+) -> Option<Cow<'a, Path>> {
+    let path = match transform_path(path, transforms)? {
+        Cow::Borrowed(path) => normalize_path(path, form),
+        Cow::Owned(path) => Cow::Owned(normalize_path(&path, form).into_owned()),
+    };
+
+    #[cfg(windows)]
+    if let Cow::Owned(escaped_path) = windows_compatible_path(&path) {
+        super::logger::warning(format!(
+            "Unpacking {} as {}, the original name can't be used on Windows",
+            super::EscapedPathDisplay::new(&path),
+            super::EscapedPathDisplay::new(&escaped_path),
+        ));
+        return Some(Cow::Owned(escaped_path));
+    }
+
+    Some(path)
+}
+
+#[cfg(any(windows, test))]
+fn escape_windows_name(name: &str) -> String {
+    let mut escaped: String = name
+        .chars()
+        .map(|ch| {
+            if INVALID_CHARACTERS.contains(&ch) || ch.is_ascii_control() {
+                '_'
+            } else {
+                ch
+            }
+        })
+        .collect();
+
+    let kept_len = escaped.trim_end_matches(['.', ' ']).len();
+    let trailing_len = escaped.len() - kept_len;
+    escaped.truncate(kept_len);
+    escaped.push_str(&"_".repeat(trailing_len));
+
+    let stem = escaped.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        escaped.insert(0, '_');
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_path() {
+        let composed = Path::new("caf\u{e9}/r\u{e9}sum\u{e9}.txt");
+        let decomposed = Path::new("cafe\u{301}/re\u{301}sume\u{301}.txt");
+
+        assert_eq!(normalize_path(decomposed, NormalizationForm::Nfc), composed);
+        assert_eq!(normalize_path(composed, NormalizationForm::Nfd), decomposed);
+        assert_eq!(normalize_path(composed, NormalizationForm::Nfc), composed);
+        assert_eq!(normalize_path(decomposed, NormalizationForm::None), decomposed);
+    }
+
+    #[test]
+    fn test_windows_name_problem() {
+        assert!(windows_name_problem(OsStr::new("aux.txt")).is_some());
+        assert!(windows_name_problem(OsStr::new("Con")).is_some());
+        assert!(windows_name_problem(OsStr::new("COM1.tar.gz")).is_some());
+        assert!(windows_name_problem(OsStr::new("notes.")).is_some());
+        assert!(windows_name_problem(OsStr::new("what?")).is_some());
+        assert!(windows_name_problem(OsStr::new("auxiliary.txt")).is_none());
+        assert!(windows_name_problem(OsStr::new("COM10")).is_none());
+        assert!(windows_name_problem(OsStr::new("file.txt")).is_none());
+    }
+
+    #[test]
+    fn test_windows_compatible_path() {
+        assert_eq!(
+            windows_compatible_path(Path::new("dir/file.txt")),
+            Path::new("dir/file.txt")
+        );
+        assert_eq!(windows_compatible_path(Path::new("aux.txt")), Path::new("_aux.txt"));
+        assert_eq!(
+            windows_compatible_path(Path::new("con/notes. ")),
+            Path::new("_con/notes__")
+        );
+        assert_eq!(windows_compatible_path(Path::new("a:b/c*d")), Path::new("a_b/c_d"));
+    }
+    #[test]
+    fn test_common_prefix() {
+        let prefix =
+            |entries: &[(&str, bool)]| common_prefix(entries.iter().map(|&(path, is_dir)| (Path::new(path), is_dir)));
+
+        assert_eq!(
+            prefix(&[
+                ("project/", true),
+                ("project/src/main.rs", false),
+                ("project/README", false)
+            ]),
+            Some("project".into())
+        );
+        assert_eq!(prefix(&[("./", true), ("./project/a", false)]), Some("project".into()));
+        assert_eq!(prefix(&[("project/a", false), ("other/b", false)]), None);
+        assert_eq!(prefix(&[("project/a", false), ("README", false)]), None);
+        assert_eq!(prefix(&[("file.txt", false)]), None);
+        assert_eq!(prefix(&[("empty/", true)]), None);
+        assert_eq!(prefix(&[]), None);
+
+        let strip = Transform::strip_prefix(Path::new("project-1.2")).unwrap();
+        assert_eq!(
+            transform_path(Path::new("./project-1.2/src/a.rs"), std::slice::from_ref(&strip)),
+            Some(Path::new("src/a.rs").into())
+        );
+        assert_eq!(
+            transform_path(Path::new("project-1.2/"), std::slice::from_ref(&strip)),
+            None
+        );
+        assert_eq!(
+            transform_path(Path::new("project-132/a"), &[strip]),
+            Some(Path::new("project-132/a").into())
+        );
+    }
+
+    #[test]
+    fn test_transform_path() {
+        let transform = |expressions: &[&str], path: &str| {
+            let transforms: Vec<_> = expressions.iter().map(|e| Transform::parse(e).unwrap()).collect();
+            transform_path(Path::new(path), &transforms).map(Cow::into_owned)
+        };
+
+        assert_eq!(transform(&[r"s/^folder\///"], "folder/a.txt"), Some("a.txt".into()));
+        assert_eq!(transform(&[r"s|\.txt$|.md|"], "dir/a.txt"), Some("dir/a.md".into()));
+        assert_eq!(transform(&["s/a/b/g"], "aaa"), Some("bbb".into()));
+        assert_eq!(transform(&["s/A/b/"], "aaa"), Some("aaa".into()));
+        assert_eq!(transform(&["s/A/b/i"], "aaa"), Some("baa".into()));
+        assert_eq!(transform(&[r"s/\(x\)/[&]/"], "(x)"), Some("[(x)]".into()));
+        assert_eq!(transform(&[r"s/(\w+)-(\w+)/\2-\1/"], "a-b"), Some("b-a".into()));
+        assert_eq!(transform(&["s/^/$HOME-/"], "a"), Some("$HOME-a".into()));
+        assert_eq!(transform(&["s/^/prefix\\//", "s/x$/y/"], "x"), Some("prefix/y".into()));
+        assert_eq!(transform(&["s/^/\\//"], "a"), Some("a".into()));
+        assert_eq!(transform(&["s/^folder//"], "folder"), None);
+        assert_eq!(transform(&["s/^/..\\//"], "a"), None);
+
+        assert!(Transform::parse("s/a/b").is_err());
+        assert!(Transform::parse("y/a/b/").is_err());
+        assert!(Transform::parse("s/a/b/x").is_err());
+        assert!(Transform::parse("s/(/b/").is_err());
+    }
+}
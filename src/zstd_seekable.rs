@@ -0,0 +1,285 @@
+//! The [zstd seekable format](https://github.com/facebook/zstd/blob/dev/contrib/seekable_format/zstd_seekable_compression_format.md),
+//! written by `--seekable`.
+//!
+//! The data is split into frames compressed independently, followed by a skippable frame with the
+//! sizes of each one, so readers can jump to any position decompressing a single frame. Decoders
+//! that don't know the format read it as a regular `.zst` file.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use rayon::prelude::*;
+
+/// Magic number of skippable frames holding a seek table
+const SKIPPABLE_MAGIC: u32 = 0x184D2A5E;
+/// Magic number at the end of the seek table
+const SEEKABLE_MAGIC: u32 = 0x8F92EAB1;
+/// Size of the footer of the seek table: the number of frames, the descriptor and the magic number
+const FOOTER_SIZE: u64 = 9;
+/// Descriptor bit set when the entries of the seek table have a checksum
+const CHECKSUM_FLAG: u8 = 1 << 7;
+
+/// Uncompressed size of each frame, smaller frames make seeking cheaper but compress worse
+const FRAME_SIZE: usize = 1024 * 1024;
+
+/// Compresses what is written to it into independent frames, writing the seek table when dropped,
+/// like the other encoders in the chain
+pub struct SeekableEncoder<W: Write> {
+    inner: W,
+    level: i32,
+    checksum: bool,
+    buffer: Vec<u8>,
+    /// Compressed and uncompressed sizes of the frames written so far
+    frames: Vec<(u32, u32)>,
+}
+
+impl<W: Write> SeekableEncoder<W> {
+    pub fn new(inner: W, level: i32, checksum: bool) -> Self {
+        Self {
+            inner,
+            level,
+            checksum,
+            buffer: vec![],
+            frames: vec![],
+        }
+    }
+
+    /// Frames are compressed in parallel, a few at a time, then written in order
+    fn batch_size() -> usize {
+        FRAME_SIZE * rayon::current_num_threads()
+    }
+
+    /// Compress and write the frames in the buffer, the last one may be smaller than [`FRAME_SIZE`]
+    /// only when `finishing`
+    fn write_frames(&mut self, finishing: bool) -> io::Result<()> {
+        let full_frames_len = self.buffer.len() / FRAME_SIZE * FRAME_SIZE;
+        let len = if finishing { self.buffer.len() } else { full_frames_len };
+
+        let compressed: Vec<Vec<u8>> = self.buffer[..len]
+            .par_chunks(FRAME_SIZE)
+            .map(|frame| {
+                let mut compressor = zstd::bulk::Compressor::new(self.level)?;
+                compressor.set_parameter(zstd::stream::raw::CParameter::ChecksumFlag(self.checksum))?;
+                compressor.compress(frame)
+            })
+            .collect::<io::Result<_>>()?;
+
+        for (frame, uncompressed) in compressed.iter().zip(self.buffer[..len].chunks(FRAME_SIZE)) {
+            self.inner.write_all(frame)?;
+            self.frames.push((frame.len() as u32, uncompressed.len() as u32));
+        }
+        self.buffer.drain(..len);
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.write_frames(true)?;
+
+        let mut table = vec![];
+        table.extend_from_slice(&SKIPPABLE_MAGIC.to_le_bytes());
+        table.extend_from_slice(&(self.frames.len() as u32 * 8 + FOOTER_SIZE as u32).to_le_bytes());
+        for (compressed, uncompressed) in &self.frames {
+            table.extend_from_slice(&compressed.to_le_bytes());
+            table.extend_from_slice(&uncompressed.to_le_bytes());
+        }
+        table.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        // Frames carry their own checksums, the table doesn't repeat them
+        table.push(0);
+        table.extend_from_slice(&SEEKABLE_MAGIC.to_le_bytes());
+
+        self.inner.write_all(&table)?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for SeekableEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= Self::batch_size() {
+            self.write_frames(false)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Frames can only be cut at their size, so only what was already compressed is flushed
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for SeekableEncoder<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Frame of a seekable file
+struct Frame {
+    /// Position of the frame in the compressed file
+    compressed_start: u64,
+    compressed_size: u32,
+    /// Position of the data of the frame in the decompressed stream
+    start: u64,
+    size: u32,
+}
+
+/// Reads the decompressed data of a seekable file, decompressing only the frames that are read
+pub struct SeekableDecoder<R> {
+    inner: R,
+    frames: Vec<Frame>,
+    len: u64,
+    position: u64,
+    /// Index and data of the last decompressed frame
+    current: Option<(usize, Vec<u8>)>,
+}
+
+impl<R: Read + Seek> SeekableDecoder<R> {
+    /// Read the seek table at the end of `inner`, `None` if it isn't a seekable file
+    pub fn new(mut inner: R) -> io::Result<Option<Self>> {
+        let file_len = inner.seek(SeekFrom::End(0))?;
+        if file_len < FOOTER_SIZE + 8 {
+            return Ok(None);
+        }
+
+        let mut footer = [0; FOOTER_SIZE as usize];
+        inner.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))?;
+        inner.read_exact(&mut footer)?;
+        let frame_count = u32::from_le_bytes(footer[..4].try_into().unwrap()) as u64;
+        let descriptor = footer[4];
+        if u32::from_le_bytes(footer[5..].try_into().unwrap()) != SEEKABLE_MAGIC {
+            return Ok(None);
+        }
+
+        let entry_size = if descriptor & CHECKSUM_FLAG != 0 { 12 } else { 8 };
+        let table_size = frame_count * entry_size + FOOTER_SIZE;
+        let Some(table_start) = file_len.checked_sub(table_size + 8) else {
+            return Ok(None);
+        };
+
+        let mut table = vec![0; (table_size + 8) as usize];
+        inner.seek(SeekFrom::Start(table_start))?;
+        inner.read_exact(&mut table)?;
+        let skippable_magic = u32::from_le_bytes(table[..4].try_into().unwrap());
+        let skippable_size = u32::from_le_bytes(table[4..8].try_into().unwrap()) as u64;
+        if skippable_magic != SKIPPABLE_MAGIC || skippable_size != table_size {
+            return Ok(None);
+        }
+
+        let mut frames = Vec::with_capacity(frame_count as usize);
+        let (mut compressed_start, mut start) = (0, 0);
+        for entry in table[8..].chunks_exact(entry_size as usize).take(frame_count as usize) {
+            let compressed_size = u32::from_le_bytes(entry[..4].try_into().unwrap());
+            let size = u32::from_le_bytes(entry[4..8].try_into().unwrap());
+            frames.push(Frame {
+                compressed_start,
+                compressed_size,
+                start,
+                size,
+            });
+            compressed_start += compressed_size as u64;
+            start += size as u64;
+        }
+
+        if compressed_start != table_start {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the seek table doesn't match the frames of the zstd file",
+            ));
+        }
+
+        Ok(Some(Self {
+            inner,
+            frames,
+            len: start,
+            position: 0,
+            current: None,
+        }))
+    }
+
+    /// Decompress the frame at `index`, unless it's the current one
+    fn load_frame(&mut self, index: usize) -> io::Result<&[u8]> {
+        if !matches!(&self.current, Some((current, _)) if *current == index) {
+            let frame = &self.frames[index];
+            let mut compressed = vec![0; frame.compressed_size as usize];
+            self.inner.seek(SeekFrom::Start(frame.compressed_start))?;
+            self.inner.read_exact(&mut compressed)?;
+
+            let data = zstd::bulk::decompress(&compressed, frame.size as usize)?;
+            if data.len() != frame.size as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "a frame of the zstd file is smaller than its size in the seek table",
+                ));
+            }
+            self.current = Some((index, data));
+        }
+
+        Ok(&self.current.as_ref().expect("frame was just loaded").1)
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        // Frames can be empty, so take the last one starting at or before the position
+        let index = self.frames.partition_point(|frame| frame.start <= self.position) - 1;
+        let offset = (self.position - self.frames[index].start) as usize;
+        let data = &self.load_frame(index)?[offset..];
+
+        let read = data.len().min(buf.len());
+        buf[..read].copy_from_slice(&data[..read]);
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableDecoder<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position = position
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative position"))?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_seekable_round_trip() {
+        let data: Vec<u8> = (0..FRAME_SIZE * 3 + 100).map(|i| (i % 251) as u8).collect();
+
+        let mut compressed = vec![];
+        SeekableEncoder::new(&mut compressed, 3, true).write_all(&data).unwrap();
+
+        // Regular decoders skip the seek table
+        assert_eq!(zstd::decode_all(compressed.as_slice()).unwrap(), data);
+
+        let mut decoder = SeekableDecoder::new(Cursor::new(&compressed)).unwrap().unwrap();
+        assert_eq!(decoder.frames.len(), 4);
+
+        let mut buf = vec![0; 200];
+        decoder.seek(SeekFrom::Start(FRAME_SIZE as u64 * 2 - 100)).unwrap();
+        decoder.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[FRAME_SIZE * 2 - 100..FRAME_SIZE * 2 + 100]);
+
+        let mut rest = vec![];
+        decoder.seek(SeekFrom::End(-50)).unwrap();
+        decoder.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, data[data.len() - 50..]);
+
+        let regular = zstd::encode_all(data.as_slice(), 3).unwrap();
+        assert!(SeekableDecoder::new(Cursor::new(regular)).unwrap().is_none());
+    }
+}
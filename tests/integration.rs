@@ -1,7 +1,11 @@
 #[macro_use]
 mod utils;
 
-use std::{iter::once, path::PathBuf};
+use std::{
+    iter::once,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
 use fs_err as fs;
 use parse_display::Display;
@@ -27,6 +31,8 @@ enum DirectoryExtension {
     Tlzma,
     Tsz,
     Txz,
+    #[cfg(feature = "lzo")]
+    Tzo,
     Tzst,
     Zip,
 }
@@ -41,6 +47,8 @@ enum FileExtension {
     Gz,
     Lz4,
     Lzma,
+    #[cfg(feature = "lzo")]
+    Lzo,
     Sz,
     Xz,
     Zst,
@@ -83,6 +91,19 @@ fn create_random_files(dir: impl Into<PathBuf>, depth: u8, rng: &mut SmallRng) {
     }
 }
 
+/// Create random nested directories without any files in them
+fn create_empty_directories(dir: impl Into<PathBuf>, depth: u8, rng: &mut SmallRng) {
+    if depth == 0 {
+        return;
+    }
+
+    let dir = &dir.into();
+
+    for _ in 0..rng.gen_range(1..=3u32) {
+        create_empty_directories(tempfile::tempdir_in(dir).unwrap().into_path(), depth - 1, rng);
+    }
+}
+
 /// Compress and decompress a single empty file
 #[proptest(cases = 200)]
 fn single_empty_file(ext: Extension, #[any(size_range(0..8).lift())] exts: Vec<FileExtension>) {
@@ -164,7 +185,8 @@ fn single_file_stdin(
         .success();
 
     match ext {
-        Extension::Directory(_) => {}
+        // The original filename is stored in gzip headers
+        Extension::Directory(_) | Extension::File(FileExtension::Gz) => {}
         // We don't know the original filename, so we create a file named stdin-output
         // Change the top-level "before" directory to match
         Extension::File(_) => fs::rename(before_file, before_file.with_file_name("stdin-output")).unwrap(),
@@ -193,62 +215,3226 @@ fn multiple_files(
     assert_same_directory(before, after, !matches!(ext, DirectoryExtension::Zip));
 }
 
-#[cfg(feature = "unrar")]
+/// Compress and decompress empty directories, next to a file or alone, they shouldn't be dropped
+#[proptest(cases = 25)]
+fn empty_directories(
+    ext: DirectoryExtension,
+    #[any(size_range(0..1).lift())] extra_extensions: Vec<FileExtension>,
+    #[strategy(1u8..4)] depth: u8,
+    with_file: bool,
+) {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before = &dir.join("before");
+    let before_dir = &before.join("dir");
+    fs::create_dir_all(before_dir).unwrap();
+    let archive = &dir.join(format!("archive.{}", merge_extensions(&ext, extra_extensions)));
+    let after = &dir.join("after");
+    create_empty_directories(before_dir, depth, &mut SmallRng::from_entropy());
+    if with_file {
+        fs::write(before_dir.join("file"), "contents").unwrap();
+    }
+    ouch!("-A", "c", before_dir, archive);
+    ouch!("-A", "d", archive, "-d", after);
+    assert_same_directory(before, after, !matches!(ext, DirectoryExtension::Zip));
+}
+
+/// Compress the same files created in a different order and with different timestamps,
+/// checking that --reproducible outputs are byte-identical
 #[test]
-fn unpack_rar() -> Result<(), Box<dyn std::error::Error>> {
-    fn test_unpack_rar_single(input: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
-        let dir = tempdir()?;
-        let dirpath = dir.path();
-        let unpacked_path = &dirpath.join("testfile.txt");
-        ouch!("-A", "d", input, "-d", dirpath);
-        let content = fs::read_to_string(unpacked_path)?;
-        assert_eq!(content, "Testing 123\n");
+fn compress_reproducible() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
 
-        Ok(())
+    for (parent, names, mtime) in [
+        ("first", ["a", "b", "c"], 1_000_000_000),
+        ("second", ["c", "b", "a"], 1_500_000_000),
+    ] {
+        let inner = dir.join(parent).join("dir");
+        fs::create_dir_all(inner.join("sub")).unwrap();
+        for name in names {
+            fs::write(inner.join(name), name).unwrap();
+            filetime_creation::set_file_mtime(inner.join(name), filetime_creation::FileTime::from_unix_time(mtime, 0))
+                .unwrap();
+        }
     }
 
-    let mut datadir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR")?);
-    datadir.push("tests/data");
-    ["testfile.rar3.rar.gz", "testfile.rar5.rar"]
-        .iter()
-        .try_for_each(|path| test_unpack_rar_single(&datadir.join(path)))?;
+    for ext in ["tar.gz", "zip", "tar.zst"] {
+        let first = &dir.join(format!("first.{ext}"));
+        let second = &dir.join(format!("second.{ext}"));
+        let other_mtime = &dir.join(format!("other_mtime.{ext}"));
+        ouch!("-A", "c", dir.join("first/dir"), first, "--reproducible");
+        ouch!("-A", "c", dir.join("second/dir"), second, "--reproducible");
+        ouch!("-A", "c", dir.join("second/dir"), other_mtime, "--mtime", "@1700000000");
+        assert_eq!(fs::read(first).unwrap(), fs::read(second).unwrap());
+        assert_ne!(fs::read(first).unwrap(), fs::read(other_mtime).unwrap());
+    }
+}
 
-    Ok(())
+/// Decompress with --verify, checking the unpacked files against the archive
+#[proptest(cases = 25)]
+fn decompress_and_verify(ext: DirectoryExtension, #[strategy(0u8..3)] depth: u8) {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before = &dir.join("before");
+    let before_dir = &before.join("dir");
+    fs::create_dir_all(before_dir).unwrap();
+    let archive = &dir.join(format!("archive.{ext}"));
+    let after = &dir.join("after");
+    create_random_files(before_dir, depth, &mut SmallRng::from_entropy());
+    ouch!("-A", "c", before_dir, archive);
+    ouch!("-A", "d", archive, "-d", after, "--verify");
+    assert_same_directory(before, after, !matches!(ext, DirectoryExtension::Zip));
 }
 
-#[cfg(feature = "unrar")]
+/// --verify checks single file formats too, and the outputs renamed because of a conflict
 #[test]
-fn unpack_rar_stdin() -> Result<(), Box<dyn std::error::Error>> {
-    fn test_unpack_rar_single(input: &std::path::Path, format: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let dir = tempdir()?;
-        let dirpath = dir.path();
-        let unpacked_path = &dirpath.join("testfile.txt");
-        crate::utils::cargo_bin()
-            .args([
-                "-A",
-                "-y",
-                "d",
-                "-",
-                "-d",
-                dirpath.to_str().unwrap(),
-                "--format",
-                format,
-            ])
-            .pipe_stdin(input)
-            .unwrap()
+fn verify_single_files_and_renamed_outputs() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file.txt");
+    fs::write(file, "new").unwrap();
+
+    for format in ["tar", "zip", "7z", "gz", "zst"] {
+        let archive = &dir.join(format!("file.txt.{format}"));
+        ouch!("-A", "c", file, archive);
+
+        let out = &dir.join(format!("out-{format}"));
+        fs::create_dir(out).unwrap();
+        fs::write(out.join("file.txt"), "old").unwrap();
+        let output = crate::utils::cargo_bin()
+            .args(["-A", "d", "--verify", "--on-conflict", "rename"])
+            .arg(archive)
+            .arg("-d")
+            .arg(out)
             .assert()
             .success();
-        let content = fs::read_to_string(unpacked_path)?;
-        assert_eq!(content, "Testing 123\n");
+        let stderr = String::from_utf8_lossy(&output.get_output().stderr);
+        assert!(stderr.contains("Verified"), "{format}: {stderr}");
+        assert_eq!(fs::read_to_string(out.join("file.txt")).unwrap(), "old");
+        assert_eq!(fs::read_to_string(out.join("file_1.txt")).unwrap(), "new");
+    }
+}
 
-        Ok(())
+/// Decompressing a file with trailing garbage, or a truncated one, should fail instead of writing partial output
+#[test]
+fn decompress_corrupted_streams() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file");
+    fs::write(file, "some repeated contents\n".repeat(10_000)).unwrap();
+
+    for (ext, check_trailing_data) in [
+        ("gz", true),
+        ("bz2", true),
+        ("lz4", false),
+        ("xz", true),
+        ("lzma", true),
+        ("sz", true),
+        ("zst", true),
+        #[cfg(feature = "lzo")]
+        ("lzo", true),
+    ] {
+        let compressed = &dir.join(format!("file.{ext}"));
+        ouch!("-A", "c", file, compressed);
+        let contents = fs::read(compressed).unwrap();
+
+        let truncated = &dir.join(format!("truncated.{ext}"));
+        fs::write(truncated, &contents[..contents.len() / 2]).unwrap();
+        crate::utils::cargo_bin()
+            .args(["-A", "-y", "d", truncated.to_str().unwrap(), "-d"])
+            .arg(dir.join(format!("truncated_{ext}")))
+            .assert()
+            .failure();
+
+        if check_trailing_data {
+            let trailing = &dir.join(format!("trailing.{ext}"));
+            fs::write(trailing, [contents.as_slice(), b"garbage"].concat()).unwrap();
+            crate::utils::cargo_bin()
+                .args(["-A", "-y", "d", trailing.to_str().unwrap(), "-d"])
+                .arg(dir.join(format!("trailing_{ext}")))
+                .assert()
+                .failure();
+        }
     }
+}
 
-    let mut datadir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR")?);
-    datadir.push("tests/data");
-    [("testfile.rar3.rar.gz", "rar.gz"), ("testfile.rar5.rar", "rar")]
-        .iter()
-        .try_for_each(|(path, format)| test_unpack_rar_single(&datadir.join(path), format))?;
+/// `--overwrite` only answers the questions about replacing files
+#[test]
+fn overwrite_does_not_accept_format_mismatch() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file");
+    fs::write(file, "some contents").unwrap();
 
-    Ok(())
+    let compressed = &dir.join("file.zst");
+    ouch!("-A", "c", file, compressed);
+    let out = &dir.join("out");
+    fs::create_dir(out).unwrap();
+    fs::write(out.join("file"), "old contents").unwrap();
+    crate::utils::cargo_bin()
+        .args(["-A", "d", "--overwrite"])
+        .arg(compressed)
+        .arg("-d")
+        .arg(out)
+        .assert()
+        .success();
+    assert_eq!(fs::read(out.join("file")).unwrap(), b"some contents");
+
+    // The contents are zstd, the question about continuing anyway is answered 'no' without a terminal
+    let misnamed = &dir.join("misnamed.gz");
+    fs::copy(compressed, misnamed).unwrap();
+    crate::utils::cargo_bin()
+        .args(["-A", "d", "--overwrite"])
+        .arg(misnamed)
+        .arg("-d")
+        .arg(out)
+        .assert()
+        .success();
+    assert!(!out.join("misnamed").exists());
+}
+
+/// `--ignore-extension` detects the whole chain of formats from the contents
+#[test]
+fn decompress_ignoring_extension() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "some contents").unwrap();
+
+    let archive = &dir.join("archive.tar.zst.gz");
+    ouch!("-A", "c", input, archive);
+    let misnamed = &dir.join("archive.zip");
+    fs::rename(archive, misnamed).unwrap();
+
+    ouch!("-A", "l", "--ignore-extension", misnamed);
+    let out = &dir.join("out");
+    ouch!("-A", "d", "--ignore-extension", misnamed, "-d", out);
+    assert_eq!(fs::read(out.join("input/file")).unwrap(), b"some contents");
+
+    crate::utils::cargo_bin()
+        .args(["-A", "-y", "d", "--ignore-extension", "-"])
+        .assert()
+        .code(2);
+}
+
+/// `--explain` prints the formats, outputs and levels before doing the work
+#[test]
+fn explain_compression_and_decompression() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "some contents").unwrap();
+
+    let archive = &dir.join("archive.tar.zst");
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "--explain", "c", "--level", "7"])
+        .arg(input)
+        .arg(archive)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("  Formats: tar.zst"));
+    assert!(stderr.contains("  Levels: zstd 7"));
+    assert!(stderr.contains("  Threads: "));
+
+    let out = &dir.join("out");
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "d", "--explain"])
+        .arg(archive)
+        .arg("-d")
+        .arg(out)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("  Formats: tar.zst"));
+    assert!(stderr.contains("if the archive has a single entry at its root"));
+    assert!(stderr.contains("  Temporary directory: "));
+    assert_eq!(fs::read(out.join("input/file")).unwrap(), b"some contents");
+}
+
+/// Failing files don't stop the other ones from being decompressed, unless `--fail-fast` is passed
+#[test]
+fn decompress_partial_failure() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file");
+    fs::write(file, "some repeated contents\n".repeat(10_000)).unwrap();
+
+    let compressed = &dir.join("file.gz");
+    ouch!("-A", "c", file, compressed);
+    let contents = fs::read(compressed).unwrap();
+    let truncated = &dir.join("truncated.gz");
+    fs::write(truncated, &contents[..contents.len() / 2]).unwrap();
+
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "-y", "--error-format", "json", "d"])
+        .args([truncated, compressed])
+        .arg("-d")
+        .arg(dir.join("output"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(6));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let error: serde_json::Value = serde_json::from_str(stderr.lines().last().unwrap()).unwrap();
+    assert_eq!(error["class"], "partial-failure");
+    assert_eq!(error["details"].as_array().unwrap().len(), 1);
+    assert_eq!(fs::read(dir.join("output/file")).unwrap(), fs::read(file).unwrap());
+
+    crate::utils::cargo_bin()
+        .args(["-A", "-y", "d", "--fail-fast"])
+        .args([truncated, compressed])
+        .arg("-d")
+        .arg(dir.join("fail_fast"))
+        .assert()
+        .code(4);
+
+    // Every file failing keeps the class of the failures
+    let truncated_copy = &dir.join("truncated_copy.gz");
+    fs::copy(truncated, truncated_copy).unwrap();
+    crate::utils::cargo_bin()
+        .args(["-A", "-y", "d"])
+        .args([truncated, truncated_copy])
+        .arg("-d")
+        .arg(dir.join("all_failed"))
+        .assert()
+        .code(4);
+}
+
+/// Failures exit with the code of their class, and `--error-format json` prints them as JSON
+#[test]
+fn error_format_json_and_exit_codes() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file");
+    fs::write(file, "some repeated contents\n".repeat(10_000)).unwrap();
+
+    let compressed = &dir.join("file.gz");
+    ouch!("-A", "c", file, compressed);
+    let contents = fs::read(compressed).unwrap();
+    let truncated = &dir.join("truncated.gz");
+    fs::write(truncated, &contents[..contents.len() / 2]).unwrap();
+
+    for (input, expected_class, expected_exit_code) in [
+        (truncated, "corrupt-archive", 4),
+        (file, "unsupported-format", 3),
+        (&dir.join("missing.gz"), "io", 5),
+    ] {
+        let output = crate::utils::cargo_bin()
+            .args(["-A", "-y", "--error-format", "json", "d"])
+            .arg(input)
+            .arg("-d")
+            .arg(dir.join("output"))
+            .output()
+            .unwrap();
+
+        assert_eq!(output.status.code(), Some(expected_exit_code));
+        // Info messages may come before the error, which is printed last and in a single line
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let error: serde_json::Value = serde_json::from_str(stderr.lines().last().unwrap()).unwrap();
+        assert_eq!(error["class"], expected_class);
+        assert_eq!(error["exit_code"], expected_exit_code);
+        assert!(error["code"].is_string());
+        assert!(error["title"].is_string());
+    }
+
+    // Errors are still printed as text by default
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "-y", "d"])
+        .arg(truncated)
+        .arg("-d")
+        .arg(dir.join("output"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Failed to decompress"));
+}
+
+/// Decompress files made of multiple concatenated gzip members or zstd frames
+#[test]
+fn decompress_concatenated_streams() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+
+    for ext in ["gz", "zst"] {
+        let mut concatenated = vec![];
+        for (i, contents) in ["first part\n", "second part\n"].into_iter().enumerate() {
+            let part = &dir.join(format!("part{i}"));
+            fs::write(part, contents).unwrap();
+            let compressed = &dir.join(format!("part{i}.{ext}"));
+            ouch!("-A", "c", part, compressed);
+            concatenated.extend(fs::read(compressed).unwrap());
+        }
+
+        let archive = &dir.join(format!("joined.{ext}"));
+        fs::write(archive, concatenated).unwrap();
+        let after = &dir.join(format!("after_{ext}"));
+        ouch!("-A", "d", archive, "-d", after);
+
+        assert_eq!(
+            fs::read_to_string(after.join("joined")).unwrap(),
+            "first part\nsecond part\n"
+        );
+    }
+}
+
+/// Compare a directory against an archive built from it, before and after changing the directory
+#[proptest(cases = 25)]
+fn diff_directory_against_archive(ext: DirectoryExtension, #[strategy(0u8..3)] depth: u8) {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before_dir = &dir.join("dir");
+    fs::create_dir(before_dir).unwrap();
+    let archive = &dir.join(format!("archive.{ext}"));
+    create_random_files(before_dir, depth, &mut SmallRng::from_entropy());
+    ouch!("-A", "c", before_dir, archive);
+
+    let output = ouch!("diff", before_dir, archive, "--json");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim(),
+        r#"{"added":[],"modified":[],"removed":[]}"#
+    );
+
+    fs::write(before_dir.join("new_file"), "new").unwrap();
+    let output = ouch!("diff", archive, before_dir, "--json");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim(),
+        r#"{"added":["dir/new_file"],"modified":[],"removed":[]}"#
+    );
+}
+
+#[test]
+fn compare_directory_with_archive() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("same.txt"), "same").unwrap();
+    fs::write(input.join("edited.txt"), "before").unwrap();
+    fs::write(input.join("removed.txt"), "removed").unwrap();
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", input, archive);
+        ouch!("-A", "compare", input, archive);
+    }
+
+    fs::write(input.join("edited.txt"), "after!").unwrap();
+    fs::remove_file(input.join("removed.txt")).unwrap();
+    fs::write(input.join("added.txt"), "added").unwrap();
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let output = crate::utils::cargo_bin()
+            .args(["-A", "compare"])
+            .arg(input)
+            .arg(dir.join(format!("archive.{format}")))
+            .assert()
+            .failure()
+            .get_output()
+            .clone();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains("- input/added.txt (only in the directory)"));
+        assert!(stdout.contains("+ input/removed.txt (only in the archive)"));
+        assert!(stdout.contains("~ input/edited.txt (contents differ)"));
+        assert!(!stdout.contains("same.txt"));
+    }
+}
+
+#[test]
+fn list_with_a_format_for_each_input() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    for name in ["a", "b"] {
+        fs::create_dir(dir.join(name)).unwrap();
+        fs::write(dir.join(name).join("file.txt"), name).unwrap();
+    }
+
+    // Named without their extensions
+    let tar = &dir.join("first");
+    let zip = &dir.join("second");
+    ouch!("-A", "c", dir.join("a"), tar, "--format", "tar.gz");
+    ouch!("-A", "c", dir.join("b"), zip, "--format", "zip");
+
+    ouch!("-A", "l", tar, zip, "--format", "tar.gz", "--format", "zip");
+
+    let out = &dir.join("out");
+    ouch!("-A", "d", tar, zip, "-d", out, "--format", "tar.gz", "--format", "zip");
+    assert_eq!(fs::read_to_string(out.join("a/file.txt")).unwrap(), "a");
+    assert_eq!(fs::read_to_string(out.join("b/file.txt")).unwrap(), "b");
+
+    // Two formats for three inputs
+    crate::utils::cargo_bin()
+        .args(["-A", "l", "--format", "tar.gz", "--format", "zip"])
+        .args([tar, zip, tar])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn compress_and_decompress_with_custom_extension() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file.txt"), "contents").unwrap();
+
+    let archive = &dir.join("input.pkg");
+    ouch!("-A", "c", input, archive, "--map", "pkg=tar.zst");
+    assert!(fs::read(archive).unwrap().starts_with(&[0x28, 0xb5, 0x2f, 0xfd]));
+
+    crate::utils::cargo_bin()
+        .args(["-A", "l"])
+        .arg(archive)
+        .env("OUCH_EXTENSION_MAP", "pkg=tar.zst")
+        .assert()
+        .success();
+
+    let out = &dir.join("out");
+    ouch!("-A", "d", archive, "-d", out, "--map", "pkg=tar.zst");
+    assert_eq!(fs::read_to_string(out.join("input/file.txt")).unwrap(), "contents");
+
+    crate::utils::cargo_bin()
+        .args(["-A", "l", "--map", "pkg=tar.nope"])
+        .arg(archive)
+        .assert()
+        .failure()
+        .code(2);
+}
+
+/// Check that --rate-limit slows down both compression and decompression
+#[test]
+fn compress_and_decompress_with_rate_limit() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file");
+    let mut contents = vec![0; 50_000];
+    SmallRng::from_entropy().fill(contents.as_mut_slice());
+    fs::write(file, &contents).unwrap();
+
+    // Random contents don't compress, so around 50 KB are written and then read back
+    let archive = &dir.join("file.gz");
+    let start = Instant::now();
+    ouch!("-A", "c", file, archive, "--rate-limit", "100KB/s");
+    assert!(start.elapsed() >= Duration::from_millis(400));
+
+    let after = &dir.join("after");
+    let start = Instant::now();
+    ouch!("-A", "d", archive, "-d", after, "--rate-limit", "100KB/s");
+    assert!(start.elapsed() >= Duration::from_millis(400));
+
+    assert_eq!(fs::read(after.join("file")).unwrap(), contents);
+}
+
+/// Check that small and large --io-buffer-size values don't change the output
+#[test]
+fn compress_and_decompress_with_io_buffer_size() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file");
+    let mut contents = vec![0; 100_000];
+    SmallRng::from_entropy().fill(contents.as_mut_slice());
+    fs::write(file, &contents).unwrap();
+
+    for (format, buffer_size) in [("gz", "1KiB"), ("zst", "4MiB"), ("tar", "1KiB"), ("tar.xz", "4MiB")] {
+        let archive = &dir.join(format!("file.{format}"));
+        let after = &dir.join(format!("after_{format}"));
+        ouch!("-A", "c", file, archive, "--io-buffer-size", buffer_size);
+        ouch!("-A", "d", archive, "-d", after, "--io-buffer-size", buffer_size);
+        assert_eq!(fs::read(after.join("file")).unwrap(), contents, "format: {format}");
+    }
+}
+
+/// Check that --normalize applies to entry names when compressing and when decompressing
+#[test]
+fn normalize_entry_names() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before_dir = &dir.join("dir");
+    fs::create_dir(before_dir).unwrap();
+    // "café" with a decomposed "é", like macOS names files
+    fs::write(before_dir.join("cafe\u{301}"), "coffee").unwrap();
+
+    for format in ["tar", "zip", "7z"] {
+        let archive = &dir.join(format!("normalized.{format}"));
+        let after = &dir.join(format!("after_normalized_{format}"));
+        ouch!("-A", "c", before_dir, archive, "--normalize", "nfc");
+        ouch!("-A", "d", archive, "-d", after);
+        assert_eq!(
+            fs::read_to_string(after.join("dir/caf\u{e9}")).unwrap(),
+            "coffee",
+            "format: {format}"
+        );
+
+        let archive = &dir.join(format!("original.{format}"));
+        let after = &dir.join(format!("after_original_{format}"));
+        ouch!("-A", "c", before_dir, archive);
+        ouch!("-A", "d", archive, "-d", after, "--normalize", "nfc");
+        assert_eq!(
+            fs::read_to_string(after.join("dir/caf\u{e9}")).unwrap(),
+            "coffee",
+            "format: {format}"
+        );
+    }
+}
+
+/// Check that --windows-compat warns about names that can't be used on Windows
+#[test]
+fn compress_warns_about_windows_names() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before_dir = &dir.join("dir");
+    fs::create_dir(before_dir).unwrap();
+    for name in ["aux.txt", "notes.", "readme.md"] {
+        fs::write(before_dir.join(name), name).unwrap();
+    }
+
+    let output = ouch!("-q", "c", before_dir, dir.join("archive.tar"), "--windows-compat");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("`dir/aux.txt` is a reserved device name"));
+    assert!(stderr.contains("`dir/notes.` ends with a dot or a space"));
+    assert!(!stderr.contains("readme.md"));
+}
+
+/// Check that `ouch clean` only removes the temporary directories of processes that are gone
+#[cfg(unix)]
+#[test]
+fn clean_stale_temp_dirs() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    // No process can have this ID, the maximum on Linux is 2^22
+    let stale = &dir.join(".tmp-ouch-2147483647-abc123");
+    let in_use = &dir.join(format!(".tmp-ouch-{}-abc123", std::process::id()));
+    let unrelated = &dir.join(".tmp-other-2147483647-abc123");
+    for path in [stale, in_use, unrelated] {
+        fs::create_dir(path).unwrap();
+    }
+
+    ouch!("-A", "clean", dir);
+
+    assert!(!stale.exists());
+    assert!(in_use.exists());
+    assert!(unrelated.exists());
+}
+
+/// Check that --temp-dir unpacks archives somewhere else before moving them into place
+#[test]
+fn decompress_with_temp_dir() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before_dir = &dir.join("dir");
+    fs::create_dir(before_dir).unwrap();
+    create_random_files(before_dir, 2, &mut SmallRng::from_entropy());
+    let temp_dir = &dir.join("temp");
+
+    for format in ["tar.gz", "zip"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        let after = &dir.join(format!("after_{format}"));
+        ouch!("-A", "c", before_dir, archive);
+        ouch!("-A", "d", archive, "-d", after, "--temp-dir", temp_dir);
+
+        assert_same_directory(before_dir, after.join("dir"), false);
+        assert_eq!(fs::read_dir(temp_dir).unwrap().count(), 0);
+    }
+}
+
+/// Check that unpacked files are copied into place when the temporary directory is in another file system
+#[cfg(target_os = "linux")]
+#[test]
+fn decompress_with_temp_dir_in_another_file_system() {
+    // Usually a tmpfs, while the test directory isn't
+    let Ok(temp_dir) = tempfile::tempdir_in("/dev/shm") else {
+        return;
+    };
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before_dir = &dir.join("dir");
+    fs::create_dir(before_dir).unwrap();
+    create_random_files(before_dir, 2, &mut SmallRng::from_entropy());
+    let archive = &dir.join("archive.tar");
+    let after = &dir.join("after");
+
+    ouch!("-A", "c", before_dir, archive);
+    ouch!("-A", "d", archive, "-d", after, "--temp-dir", temp_dir.path());
+
+    assert_same_directory(before_dir, after.join("dir"), true);
+    assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+}
+
+/// Check that 7z archives keep the permissions and modification time of files
+#[cfg(unix)]
+#[test]
+fn sevenz_preserves_permissions_and_mtime() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before_dir = &dir.join("dir");
+    fs::create_dir(before_dir).unwrap();
+    let file = &before_dir.join("script.sh");
+    fs::write(file, "#!/bin/sh").unwrap();
+    fs::set_permissions(file, std::fs::Permissions::from_mode(0o751)).unwrap();
+    filetime_creation::set_file_mtime(file, filetime_creation::FileTime::from_unix_time(1_000_000_000, 0)).unwrap();
+
+    let archive = &dir.join("archive.7z");
+    let after = &dir.join("after");
+    ouch!("-A", "c", before_dir, archive);
+    ouch!("-A", "d", archive, "-d", after);
+
+    let metadata = fs::metadata(after.join("dir/script.sh")).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o751);
+    assert_eq!(
+        filetime_creation::FileTime::from_last_modification_time(&metadata).unix_seconds(),
+        1_000_000_000
+    );
+}
+
+/// Check that 7z archives restore the mode and modification time of directories after the files
+/// inside of them, a read-only directory doesn't refuse its files
+#[cfg(unix)]
+#[test]
+fn sevenz_restores_directories_after_their_files() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before_dir = &dir.join("dir");
+    let read_only = &before_dir.join("read-only");
+    fs::create_dir_all(read_only).unwrap();
+    fs::write(read_only.join("file"), "some contents").unwrap();
+    fs::set_permissions(read_only, std::fs::Permissions::from_mode(0o555)).unwrap();
+    filetime_creation::set_file_mtime(read_only, filetime_creation::FileTime::from_unix_time(1_000_000_000, 0))
+        .unwrap();
+
+    let archive = &dir.join("archive.7z");
+    let after = &dir.join("after");
+    ouch!("-A", "c", before_dir, archive);
+    ouch!("-A", "d", archive, "-d", after);
+
+    let unpacked = &after.join("dir/read-only");
+    assert_eq!(fs::read(unpacked.join("file")).unwrap(), b"some contents");
+    let metadata = fs::metadata(unpacked).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o555);
+    assert_eq!(
+        filetime_creation::FileTime::from_last_modification_time(&metadata).unix_seconds(),
+        1_000_000_000
+    );
+
+    // So the temporary directory can be removed
+    for path in [read_only, unpacked] {
+        fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+}
+
+/// The directory at the root of the archive gets its stored mtime and mode once moved to the
+/// output, even though unpacking the files inside of it changed them
+#[cfg(unix)]
+#[test]
+fn top_level_directory_keeps_mtime_and_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let project = &dir.join("project");
+    fs::create_dir(project).unwrap();
+    fs::write(project.join("file"), "some contents").unwrap();
+    fs::set_permissions(project, std::fs::Permissions::from_mode(0o750)).unwrap();
+    filetime_creation::set_file_mtime(project, filetime_creation::FileTime::from_unix_time(1_000_000_000, 0)).unwrap();
+
+    for format in ["tar.gz", "zip"] {
+        let archive = &dir.join(format!("project.{format}"));
+        let out = &dir.join(format!("out-{format}"));
+        ouch!("-A", "c", project, archive);
+        ouch!("-A", "d", archive, "-d", out);
+
+        let metadata = fs::metadata(out.join("project")).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o750, "{format}");
+        assert_eq!(
+            filetime_creation::FileTime::from_last_modification_time(&metadata).unix_seconds(),
+            1_000_000_000,
+            "{format}"
+        );
+    }
+}
+
+/// Directories keep their stored mtime, even though the files unpacked after them changed it
+#[test]
+fn unpacked_directories_keep_mtime() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let nested = &dir.join("project/src/nested");
+    fs::create_dir_all(nested).unwrap();
+    fs::write(nested.join("file"), "some contents").unwrap();
+    for (path, mtime) in [(nested, 1_000_000_000), (&nested.join(".."), 1_100_000_000)] {
+        filetime_creation::set_file_mtime(path, filetime_creation::FileTime::from_unix_time(mtime, 0)).unwrap();
+    }
+
+    for format in ["tar.gz", "zip"] {
+        let archive = &dir.join(format!("project.{format}"));
+        let out = &dir.join(format!("out-{format}"));
+        ouch!("-A", "c", dir.join("project"), archive);
+        ouch!("-A", "d", archive, "-d", out);
+
+        for (path, mtime) in [("project/src/nested", 1_000_000_000), ("project/src", 1_100_000_000)] {
+            let metadata = fs::metadata(out.join(path)).unwrap();
+            assert_eq!(
+                filetime_creation::FileTime::from_last_modification_time(&metadata).unix_seconds(),
+                mtime,
+                "{path} in {format}"
+            );
+        }
+    }
+}
+
+/// Check that --no-same-permissions applies the umask to unpacked files
+#[cfg(target_os = "linux")]
+#[test]
+fn decompress_applying_umask() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let status = fs::read_to_string("/proc/self/status").unwrap();
+    let umask = status
+        .lines()
+        .find_map(|line| line.strip_prefix("Umask:"))
+        .map(|umask| u32::from_str_radix(umask.trim(), 8).unwrap())
+        .unwrap();
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before_dir = &dir.join("dir");
+    fs::create_dir(before_dir).unwrap();
+    let file = &before_dir.join("file");
+    fs::write(file, "contents").unwrap();
+    fs::set_permissions(file, std::fs::Permissions::from_mode(0o777)).unwrap();
+
+    for format in ["tar", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        let after = &dir.join(format!("after_{format}"));
+        ouch!("-A", "c", before_dir, archive);
+        ouch!("-A", "d", archive, "-d", after, "--no-same-permissions");
+
+        let mode = fs::metadata(after.join("dir/file")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o777 & !umask, "format: {format}");
+    }
+}
+
+/// Decompress an encrypted zip with the password passed in the command line or in a file
+#[test]
+fn decompress_with_password() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let archive = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("tests/data/testfile.encrypted.zip");
+    let password_file = &dir.join("password");
+    fs::write(password_file, "ouch\n").unwrap();
+
+    ouch!("-A", "d", &archive, "-d", dir.join("flag"), "--password", "ouch");
+    ouch!(
+        "-A",
+        "d",
+        &archive,
+        "-d",
+        dir.join("file"),
+        "--password-file",
+        password_file
+    );
+
+    for output in ["flag", "file"] {
+        let content = fs::read_to_string(dir.join(output).join("testfile.txt")).unwrap();
+        assert_eq!(content, "Testing 123\n");
+    }
+
+    fs::write(password_file, "wrong\n").unwrap();
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "d", archive.to_str().unwrap(), "-d"])
+        .arg(dir.join("wrong"))
+        .arg("--password-file")
+        .arg(password_file)
+        .assert()
+        .code(2);
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr);
+    assert!(stderr.contains("Wrong password"), "{stderr}");
+
+    // With more than one password, each archive uses the password in the same position
+    let other_archive = &dir.join("other.zip");
+    fs::copy(&archive, other_archive).unwrap();
+    for (passwords, should_succeed) in [
+        (&["ouch", "ouch"][..], true),
+        (&["ouch", "wrong"], false),
+        (&["ouch", "ouch", "ouch"], false),
+    ] {
+        let output = dir.join(format!("multiple-{}", passwords.join("-")));
+        let mut command = crate::utils::cargo_bin();
+        command
+            .args(["-A", "-y", "d"])
+            .arg(&archive)
+            .arg(other_archive)
+            .arg("-d")
+            .arg(&output);
+        for password in passwords {
+            command.args(["--password", password]);
+        }
+
+        if should_succeed {
+            command.assert().success();
+            assert_eq!(
+                fs::read_to_string(output.join("testfile.txt")).unwrap(),
+                "Testing 123\n"
+            );
+        } else {
+            command.assert().failure();
+        }
+    }
+}
+
+/// Encrypt archives to an age recipient and decrypt them with its identity
+#[cfg(feature = "age")]
+#[test]
+fn encrypt_and_decrypt_age() {
+    use age::secrecy::ExposeSecret;
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before = &dir.join("before");
+    let before_dir = &before.join("dir");
+    fs::create_dir_all(before_dir).unwrap();
+    create_random_files(before_dir, 2, &mut SmallRng::from_entropy());
+
+    let identity = age::x25519::Identity::generate();
+    let identity_file = &dir.join("key.txt");
+    fs::write(identity_file, identity.to_string().expose_secret()).unwrap();
+    let recipient = format!("age:{}", identity.to_public());
+    let other_identity_file = &dir.join("other-key.txt");
+    let other_identity = age::x25519::Identity::generate();
+    fs::write(other_identity_file, other_identity.to_string().expose_secret()).unwrap();
+
+    for ext in ["tar.age", "tar.zst.age", "zip.age"] {
+        let archive = &dir.join(format!("archive.{ext}"));
+        let after = &dir.join(format!("after-{ext}"));
+        ouch!("-A", "c", before_dir, archive, "--encrypt", &recipient);
+        ouch!("-A", "d", archive, "-d", after, "--decrypt", identity_file);
+        assert_same_directory(before, after, ext.starts_with("tar"));
+
+        // Another identity can't decrypt it
+        crate::utils::cargo_bin()
+            .args(["-A", "-y", "d"])
+            .arg(archive)
+            .arg("-d")
+            .arg(dir.join("wrong"))
+            .arg("--decrypt")
+            .arg(other_identity_file)
+            .assert()
+            .failure();
+    }
+}
+
+/// `--encrypt` and the `.age` extension must be used together
+#[test]
+fn encrypt_requires_age_extension() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file");
+    fs::write(file, "contents").unwrap();
+
+    for (output, encrypt_args) in [
+        ("file.gz", &["--encrypt", "age:age1recipient"][..]),
+        ("file.gz.age", &[]),
+    ] {
+        let output = crate::utils::cargo_bin()
+            .args(["-A", "-y", "c"])
+            .arg(file)
+            .arg(dir.join(output))
+            .args(encrypt_args)
+            .output()
+            .unwrap();
+
+        assert_eq!(output.status.code(), Some(2));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Cannot encrypt to"));
+    }
+}
+
+/// Sign an archive while compressing it, then verify the signature
+#[test]
+fn sign_and_verify() {
+    use ed25519_dalek::{
+        pkcs8::{spki::der::pem::LineEnding, EncodePrivateKey, EncodePublicKey},
+        SigningKey,
+    };
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file");
+    fs::write(file, "some contents").unwrap();
+
+    let [key, public_key, other_public_key] = ["key.pem", "pub.pem", "other-pub.pem"].map(|name| dir.join(name));
+    let signing_key = SigningKey::from_bytes(&[7; 32]);
+    let other_key = SigningKey::from_bytes(&[8; 32]);
+    fs::write(&key, signing_key.to_pkcs8_pem(LineEnding::LF).unwrap().as_bytes()).unwrap();
+    for (path, key) in [(&public_key, &signing_key), (&other_public_key, &other_key)] {
+        fs::write(path, key.verifying_key().to_public_key_pem(LineEnding::LF).unwrap()).unwrap();
+    }
+
+    let archive = &dir.join("file.tar.gz");
+    ouch!("-A", "c", file, archive, "--sign-key", &key);
+    assert!(dir.join("file.tar.gz.sig").exists());
+    ouch!("-A", "verify", archive, "--pubkey", &public_key);
+
+    // Another key, or a modified archive, fail to verify
+    let copy = &dir.join("copy.tar.gz");
+    fs::copy(archive, copy).unwrap();
+    let mut contents = fs::read(archive).unwrap();
+    contents.push(0);
+    let modified = &dir.join("modified.tar.gz");
+    fs::write(modified, contents).unwrap();
+
+    for (path, public_key) in [(copy, &other_public_key), (modified, &public_key)] {
+        crate::utils::cargo_bin()
+            .args(["-A", "verify"])
+            .arg(path)
+            .arg("--sig")
+            .arg(dir.join("file.tar.gz.sig"))
+            .arg("--pubkey")
+            .arg(public_key)
+            .assert()
+            .code(4);
+    }
+}
+
+#[test]
+fn compress_with_codec_integrity_options() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file");
+    fs::write(file, "some contents").unwrap();
+
+    let zst = &dir.join("file.zst");
+    ouch!("-A", "c", file, zst, "--checksum");
+    // Content_Checksum_flag of the frame header descriptor
+    assert_ne!(fs::read(zst).unwrap()[4] & 0b100, 0);
+
+    let xz = &dir.join("file.xz");
+    ouch!("-A", "c", file, xz, "--xz-check", "sha256");
+    // Check ID in the stream flags
+    assert_eq!(fs::read(xz).unwrap()[7], 0x0A);
+
+    let gz = &dir.join("file.gz");
+    ouch!(
+        "-A",
+        "c",
+        file,
+        gz,
+        "--gzip-name",
+        "name.txt",
+        "--gzip-comment",
+        "a comment"
+    );
+    let gz_contents = fs::read(gz).unwrap();
+    // FNAME and FCOMMENT flags, followed by both zero-terminated fields
+    assert_eq!(gz_contents[3], 0x08 | 0x10);
+    assert!(gz_contents[10..].starts_with(b"name.txt\0a comment\0"));
+
+    for (archive, out) in [(zst, "out-zst"), (xz, "out-xz"), (gz, "out-gz")] {
+        ouch!("-A", "d", archive, "-d", dir.join(out));
+        assert_eq!(fs::read(dir.join(out).join("file")).unwrap(), b"some contents");
+    }
+}
+
+/// .lzma files are written in the legacy format of LZMA Utils, and xz written to .lzma by older
+/// versions can still be read
+#[test]
+fn compress_legacy_lzma() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file");
+    fs::write(file, "some contents").unwrap();
+
+    let lzma = &dir.join("file.lzma");
+    ouch!("-A", "c", file, lzma, "--xz-extreme");
+    // Properties of the default preset, lc=3 lp=0 pb=2, and a dictionary of 8MiB
+    assert_eq!(fs::read(lzma).unwrap()[..5], [0x5D, 0x00, 0x00, 0x80, 0x00]);
+    ouch!("-A", "d", lzma, "-d", dir.join("out"));
+    assert_eq!(fs::read(dir.join("out").join("file")).unwrap(), b"some contents");
+
+    let xz = &dir.join("file.xz");
+    ouch!("-A", "c", file, xz);
+    let old_lzma = &dir.join("old.lzma");
+    fs::rename(xz, old_lzma).unwrap();
+    ouch!("-A", "d", old_lzma, "-d", dir.join("out-old"));
+    assert_eq!(fs::read(dir.join("out-old").join("old")).unwrap(), b"some contents");
+
+    // Archives too, instead of xz with the .lzma extension
+    for extension in ["tlzma", "tar.lzma"] {
+        let archive = &dir.join(format!("archive.{extension}"));
+        ouch!("-A", "c", file, archive);
+        assert_eq!(fs::read(archive).unwrap()[..3], [0x5D, 0x00, 0x00]);
+        let out = &dir.join(format!("out-{extension}"));
+        ouch!("-A", "d", archive, "-d", out);
+        assert_eq!(fs::read(out.join("file")).unwrap(), b"some contents");
+    }
+}
+
+/// `--snappy-raw` writes a single block without the stream identifier, which is still decompressed
+#[test]
+fn snappy_raw_round_trip() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file");
+    let contents = "some repeated contents\n".repeat(1000);
+    fs::write(file, &contents).unwrap();
+
+    let framed = &dir.join("framed.sz");
+    let raw = &dir.join("raw.sz");
+    ouch!("-A", "c", file, framed);
+    ouch!("-A", "c", file, raw, "--snappy-raw");
+    assert!(fs::read(framed).unwrap().starts_with(b"\xFF\x06\x00\x00sNaPpY"));
+    // Starts with the decompressed length instead, as a varint
+    assert!(fs::read(raw).unwrap().starts_with(&[0xD8, 0xB3, 0x01]));
+
+    for archive in [framed, raw] {
+        let out = dir.join("out");
+        ouch!("-A", "d", archive, "-d", &out);
+        let name = archive.file_stem().unwrap();
+        assert_eq!(fs::read_to_string(out.join(name)).unwrap(), contents);
+    }
+}
+
+/// lz4 frames of single files store their size, and legacy frames are decompressed
+#[test]
+fn lz4_content_size_and_legacy_frames() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file");
+    let contents = "some repeated contents\n".repeat(1000);
+    fs::write(file, &contents).unwrap();
+
+    let lz4 = &dir.join("file.lz4");
+    ouch!("-A", "c", file, lz4);
+    let header = fs::read(lz4).unwrap();
+    // Content size flag, and the size after the block descriptor
+    assert_ne!(header[4] & 0b1000, 0);
+    assert_eq!(header[6..14], (contents.len() as u64).to_le_bytes());
+
+    let block = lz4_flex::block::compress(contents.as_bytes());
+    let legacy = &dir.join("legacy.lz4");
+    let legacy_contents = [
+        &[0x02, 0x21, 0x4C, 0x18][..],
+        &(block.len() as u32).to_le_bytes(),
+        &block,
+    ]
+    .concat();
+    fs::write(legacy, legacy_contents).unwrap();
+    ouch!("-A", "d", legacy, "-d", dir.join("out"));
+    assert_eq!(fs::read_to_string(dir.join("out").join("legacy")).unwrap(), contents);
+}
+
+/// With `--rsyncable`, a change at the start of the input leaves the end of the output as it was
+#[test]
+fn rsyncable_output_resyncs_after_changes() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let mut state = 1u64;
+    let words: Vec<String> = (0..100_000)
+        .map(|_| {
+            state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            format!("word{}", (state >> 33) % 5000)
+        })
+        .collect();
+    let contents = words.join(" ");
+    let changed = format!("X{}", &contents[1..]);
+
+    for ext in ["gz", "zst"] {
+        let mut outputs = vec![];
+        for (name, contents) in [("before", &contents), ("after", &changed)] {
+            let file = &dir.join(name);
+            fs::write(file, contents).unwrap();
+            let compressed = &dir.join(format!("{name}.{ext}"));
+            ouch!("-A", "c", file, compressed, "--rsyncable");
+
+            ouch!("-A", "d", compressed, "-d", dir.join(format!("{name}-{ext}")));
+            assert_eq!(
+                &fs::read_to_string(dir.join(format!("{name}-{ext}")).join(name)).unwrap(),
+                contents
+            );
+            outputs.push(fs::read(compressed).unwrap());
+        }
+
+        if ext == "gz" {
+            // The last 8 bytes are the CRC and size of the contents
+            let [before, after] =
+                [&outputs[0], &outputs[1]].map(|output| &output[output.len() - 100_008..output.len() - 8]);
+            assert_eq!(before, after);
+        }
+    }
+}
+
+/// xz is compressed with every thread of `--threads`, unless `--memory` only leaves room for one
+#[test]
+fn xz_threads_and_memory_limit() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file");
+    let contents = "some repeated contents\n".repeat(100_000);
+    fs::write(file, &contents).unwrap();
+
+    let [single, limited, threaded] = ["single.xz", "limited.xz", "threaded.xz"].map(|name| dir.join(name));
+    ouch!("-A", "-c", "1", "c", file, &single);
+    ouch!("-A", "-c", "4", "c", file, &limited, "--memory", "1");
+    ouch!("-A", "-c", "4", "c", file, &threaded);
+    assert_eq!(fs::read(&single).unwrap(), fs::read(&limited).unwrap());
+
+    for (archive, out) in [(&single, "out-single"), (&threaded, "out-threaded")] {
+        ouch!("-A", "d", archive, "-d", dir.join(out));
+        // Single files are named after the archive
+        let unpacked = dir.join(out).join(archive.file_stem().unwrap());
+        assert_eq!(fs::read_to_string(unpacked).unwrap(), contents);
+    }
+}
+
+#[test]
+fn gzip_header_name_and_mtime() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("notes.txt");
+    fs::write(file, "some notes").unwrap();
+    let mtime = std::time::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    std::fs::File::options()
+        .write(true)
+        .open(file)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+
+    let archive = &dir.join("archive.gz");
+    ouch!("-A", "c", file, archive);
+    assert_eq!(fs::read(archive).unwrap()[4..8], 1_000_000_000u32.to_le_bytes());
+
+    // The name is only taken from the header for stdin, which has none
+    let after = &dir.join("after");
+    crate::utils::cargo_bin()
+        .args(["-A", "-y", "d", "-", "-d", after.to_str().unwrap(), "--format", "gz"])
+        .pipe_stdin(archive)
+        .unwrap()
+        .assert()
+        .success();
+    let unpacked = &after.join("notes.txt");
+    assert_eq!(fs::read(unpacked).unwrap(), b"some notes");
+    assert_eq!(fs::metadata(unpacked).unwrap().modified().unwrap(), mtime);
+}
+
+#[test]
+fn compress_with_archive_comment() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let folder = &dir.join("folder");
+    fs::create_dir(folder).unwrap();
+    fs::write(folder.join("a.txt"), "some text").unwrap();
+
+    for format in ["zip", "tar.gz"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", folder, archive, "--comment", "release v1.2");
+
+        let output = crate::utils::cargo_bin().args(["info"]).arg(archive).output().unwrap();
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).contains("release v1.2"));
+
+        // The PAX global header of tar isn't a file
+        let output = crate::utils::cargo_bin()
+            .args(["list", "--json"])
+            .arg(archive)
+            .output()
+            .unwrap();
+        let listing: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        let paths: Vec<_> = listing["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["path"].as_str().unwrap().trim_end_matches('/').to_owned())
+            .collect();
+        assert_eq!(paths, ["folder", "folder/a.txt"]);
+        if format == "zip" {
+            assert_eq!(listing["comment"], "release v1.2");
+        }
+
+        let out = &dir.join(format!("out-{format}"));
+        ouch!("-A", "d", archive, "-d", out);
+        assert_eq!(fs::read_dir(out).unwrap().count(), 1);
+        assert_eq!(fs::read_to_string(out.join("folder/a.txt")).unwrap(), "some text");
+    }
+
+    crate::utils::cargo_bin()
+        .args(["-A", "c"])
+        .arg(folder)
+        .arg(dir.join("archive.7z"))
+        .args(["--comment", "release v1.2"])
+        .assert()
+        .failure()
+        .code(2);
+}
+
+#[test]
+fn info_shows_formats_and_entries() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let folder = &dir.join("folder");
+    fs::create_dir(folder).unwrap();
+    fs::write(folder.join("a.txt"), "some text").unwrap();
+    fs::write(folder.join("b.txt"), "more text").unwrap();
+
+    let archive = &dir.join("archive.tar.zst");
+    ouch!("-A", "c", folder, archive, "--checksum");
+
+    let output = crate::utils::cargo_bin().args(["info"]).arg(archive).output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    for line in [
+        "Formats: tar.zst",
+        "content checksum",
+        "Files: 2",
+        "Directories: 1",
+        "Unpacked size: 18.00 B",
+        "Encrypted: no",
+    ] {
+        assert!(stdout.contains(line), "{line:?} not in {stdout}");
+    }
+
+    let encrypted =
+        PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("tests/data/testfile.encrypted.zip");
+    let output = crate::utils::cargo_bin().arg("info").arg(encrypted).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Encrypted entries: 1"), "{stdout}");
+}
+
+/// `list --long` shows the compression method of the entries and whether they're encrypted
+#[test]
+fn list_long_shows_method_and_encryption() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file.txt");
+    fs::write(file, "some contents").unwrap();
+
+    let archive = &dir.join("archive.zip");
+    ouch!("-A", "c", file, archive);
+    let output = ouch!("list", "-l", archive);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("file.txt (Deflated)"), "{stdout}");
+
+    let encrypted =
+        PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("tests/data/testfile.encrypted.zip");
+    let output = ouch!("list", "--long", &encrypted, "--password", "ouch");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("encrypted)"), "{stdout}");
+
+    let output = ouch!("list", "--json", &encrypted, "--password", "ouch");
+    let listing: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(listing["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|entry| entry["encrypted"] == true));
+}
+
+/// The entries of encrypted archives are listed from their headers, and a wrong password fails
+/// as a bad argument rather than as a corrupted archive
+#[test]
+fn list_encrypted_archive() {
+    let encrypted =
+        PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("tests/data/testfile.encrypted.zip");
+
+    for password in [&[][..], &["--password", "ouch"]] {
+        let output = crate::utils::cargo_bin()
+            .arg("list")
+            .arg(&encrypted)
+            .args(password)
+            .assert()
+            .success();
+        let stdout = String::from_utf8(output.get_output().stdout.clone()).unwrap();
+        assert!(stdout.contains("testfile.txt"), "{stdout}");
+    }
+
+    crate::utils::cargo_bin()
+        .arg("list")
+        .arg(&encrypted)
+        .args(["--password", "wrong"])
+        .assert()
+        .code(2);
+}
+
+/// With `--dir`, the output is named after the input, with the extension given to `--format`
+#[test]
+fn compress_into_dir() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let project = &dir.join("project");
+    fs::create_dir(project).unwrap();
+    fs::write(project.join("file"), "some contents").unwrap();
+
+    let dist = &dir.join("dist");
+    ouch!("-A", "c", project, "-d", dist, "--format", "tar.zst");
+    let archive = &dist.join("project.tar.zst");
+    assert!(archive.exists());
+
+    let out = &dir.join("out");
+    ouch!("-A", "d", archive, "-d", out);
+    assert_same_directory(project, out.join("project"), false);
+
+    crate::utils::cargo_bin()
+        .args(["-A", "c"])
+        .args([project, archive])
+        .args(["-d", "dist", "--format", "zip"])
+        .assert()
+        .code(2);
+}
+
+/// Outputs without extensions are compressed with `OUCH_DEFAULT_FORMAT`, and fail without it
+/// since there's no terminal to ask which format to use
+#[test]
+fn compress_with_default_format() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let project = &dir.join("project");
+    fs::create_dir(project).unwrap();
+    fs::write(project.join("file"), "some contents").unwrap();
+
+    let backup = &dir.join("backup");
+    crate::utils::cargo_bin()
+        .args(["-A", "c"])
+        .args([project, backup])
+        .env("OUCH_DEFAULT_FORMAT", "tar.gz")
+        .assert()
+        .success();
+
+    let out = &dir.join("out");
+    ouch!("-A", "d", backup, "-d", out, "--format", "tar.gz");
+    assert_same_directory(project, out.join("project"), false);
+
+    crate::utils::cargo_bin()
+        .args(["-A", "c", "--no"])
+        .args([project, &dir.join("other")])
+        .env_remove("OUCH_DEFAULT_FORMAT")
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn compress_files_from_list() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    for name in ["a", "b", "c"] {
+        fs::write(dir.join(name), name).unwrap();
+    }
+
+    let list = &dir.join("list.txt");
+    fs::write(list, "a\r\n\nb\n").unwrap();
+    crate::utils::cargo_bin()
+        .current_dir(dir)
+        .args(["-A", "c", "--files-from", "list.txt", "c", "lines.tar"])
+        .assert()
+        .success();
+
+    // NUL separated, from stdin
+    crate::utils::cargo_bin()
+        .current_dir(dir)
+        .args(["-A", "c", "-T", "-", "--null", "nul.tar"])
+        .write_stdin("a\0b\0c\0")
+        .assert()
+        .success();
+
+    for archive in ["lines.tar", "nul.tar"] {
+        let output = &dir.join(format!("{archive}-output"));
+        ouch!("-A", "d", dir.join(archive), "-d", output);
+        for name in ["a", "b", "c"] {
+            assert_eq!(
+                fs::read_to_string(output.join(archive.trim_end_matches(".tar")).join(name)).unwrap(),
+                name
+            );
+        }
+    }
+}
+
+#[test]
+fn list_chained_zip_and_7z() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let folder = &dir.join("folder");
+    fs::create_dir(folder).unwrap();
+    fs::write(folder.join("a.txt"), "a").unwrap();
+
+    for format in ["zip.gz", "7z.xz"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", folder, archive);
+
+        let output = crate::utils::cargo_bin().args(["list"]).arg(archive).output().unwrap();
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).contains("folder/a.txt"));
+    }
+}
+
+/// The archive comment is listed, `--json` also has the comments of the entries
+#[test]
+fn list_zip_comments() {
+    let archive = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("tests/data/testfile.comments.zip");
+
+    let output = crate::utils::cargo_bin().args(["list"]).arg(&archive).output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Comment: Archive comment"));
+
+    let output = crate::utils::cargo_bin()
+        .args(["list", "--json"])
+        .arg(&archive)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let listing: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(listing["comment"], "Archive comment");
+    assert_eq!(listing["entries"][0]["path"], "testfile.txt");
+    assert_eq!(listing["entries"][0]["type"], "file");
+    assert_eq!(listing["entries"][0]["comment"], "Entry comment");
+}
+
+/// Zip archive storing `entries` under raw names, without the flag marking them as UTF-8
+fn zip_with_raw_names(entries: &[(&[u8], &[u8])]) -> Vec<u8> {
+    let mut archive = vec![];
+    let mut central_directory = vec![];
+    for (name, contents) in entries {
+        let offset = archive.len() as u32;
+        // Version 2.0, no flags, stored, 1980-01-01
+        let common = [
+            &20u16.to_le_bytes()[..],
+            &0u16.to_le_bytes(),
+            &0u16.to_le_bytes(),
+            &0u16.to_le_bytes(),
+            &33u16.to_le_bytes(),
+            &crc32fast::hash(contents).to_le_bytes(),
+            &(contents.len() as u32).to_le_bytes(),
+            &(contents.len() as u32).to_le_bytes(),
+            &(name.len() as u16).to_le_bytes(),
+            &0u16.to_le_bytes(),
+        ]
+        .concat();
+
+        archive.extend([&0x04034b50u32.to_le_bytes()[..], &common, name, contents].concat());
+        central_directory.extend(
+            [
+                &0x02014b50u32.to_le_bytes()[..],
+                &20u16.to_le_bytes(),
+                &common,
+                // No comment, disk 0, no attributes
+                &[0; 10],
+                &offset.to_le_bytes(),
+                name,
+            ]
+            .concat(),
+        );
+    }
+
+    let offset = archive.len() as u32;
+    let count = (entries.len() as u16).to_le_bytes();
+    archive.extend(&central_directory);
+    archive.extend(
+        [
+            &0x06054b50u32.to_le_bytes()[..],
+            &[0; 4],
+            &count,
+            &count,
+            &(central_directory.len() as u32).to_le_bytes(),
+            &offset.to_le_bytes(),
+            &[0; 2],
+        ]
+        .concat(),
+    );
+    archive
+}
+
+/// Names that aren't flagged as UTF-8 are decoded with `--encoding`, instead of CP437
+#[test]
+fn list_and_decompress_zip_with_encoding() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let archive = &dir.join("archive.zip");
+    // "日本語.txt" and "ソ" in Shift-JIS, the second byte of "ソ" being a backslash
+    fs::write(
+        archive,
+        zip_with_raw_names(&[(b"\x93\xfa\x96\x7b\x8c\xea.txt", b"a"), (b"\x83\x5c", b"b")]),
+    )
+    .unwrap();
+
+    let output = crate::utils::cargo_bin()
+        .args(["list", "--json", "--encoding", "shift_jis"])
+        .arg(archive)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let listing: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(listing["entries"][0]["path"], "日本語.txt");
+    assert_eq!(listing["entries"][1]["path"], "ソ");
+    assert_eq!(listing["entries"][1]["type"], "file");
+
+    let out = &dir.join("out");
+    ouch!("-A", "d", archive, "-d", out, "--encoding", "Shift_JIS");
+    assert_eq!(fs::read(out.join("archive/日本語.txt")).unwrap(), b"a");
+    assert_eq!(fs::read(out.join("archive/ソ")).unwrap(), b"b");
+
+    crate::utils::cargo_bin()
+        .args(["list", "--encoding", "klingon"])
+        .arg(archive)
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn list_and_decompress_print0() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let folder = &dir.join("folder");
+    fs::create_dir(folder).unwrap();
+    fs::write(folder.join("with space.txt"), "a").unwrap();
+    fs::write(folder.join("other.txt"), "b").unwrap();
+
+    let archive = &dir.join("archive.zip");
+    ouch!("-A", "c", folder, archive);
+
+    let output = crate::utils::cargo_bin()
+        .args(["list", "--print0"])
+        .arg(archive)
+        .output()
+        .unwrap();
+    let mut listed: Vec<&[u8]> = output.stdout.split(|&byte| byte == 0).collect();
+    // Every path ends with a NUL
+    assert_eq!(listed.pop(), Some(&b""[..]));
+    listed.sort();
+    assert_eq!(listed, [&b"folder/"[..], b"folder/other.txt", b"folder/with space.txt"]);
+
+    let after = &dir.join("after");
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "d", "--print0"])
+        .arg(archive)
+        .arg("-d")
+        .arg(after)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let mut unpacked: Vec<PathBuf> = output
+        .stdout
+        .split(|&byte| byte == 0)
+        .filter(|path| !path.is_empty())
+        .map(|path| PathBuf::from(std::str::from_utf8(path).unwrap()))
+        .collect();
+    unpacked.sort();
+    let folder = &after.join("folder");
+    assert_eq!(
+        unpacked,
+        [folder.clone(), folder.join("other.txt"), folder.join("with space.txt")]
+    );
+}
+
+#[test]
+fn decompress_with_transform() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let folder = &dir.join("folder");
+    fs::create_dir_all(folder.join("sub")).unwrap();
+    fs::write(folder.join("a.txt"), "a").unwrap();
+    fs::write(folder.join("sub/b.txt"), "b").unwrap();
+    fs::write(folder.join("c.log"), "c").unwrap();
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", folder, archive);
+
+        let after = &dir.join(format!("after-{format}"));
+        crate::utils::cargo_bin()
+            .args([
+                "-A",
+                "d",
+                "--verify",
+                "--transform",
+                "s,^folder/*,,",
+                "--transform",
+                r"s/\.txt$/.md/",
+            ])
+            .arg(archive)
+            .arg("-d")
+            .arg(after)
+            .assert()
+            .success();
+
+        let unpacked = &after.join("archive");
+        assert_eq!(fs::read_to_string(unpacked.join("a.md")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(unpacked.join("sub/b.md")).unwrap(), "b");
+        assert_eq!(fs::read_to_string(unpacked.join("c.log")).unwrap(), "c");
+        assert!(!unpacked.join("folder").exists());
+    }
+
+    crate::utils::cargo_bin()
+        .args(["-A", "d", "--transform", "s/a/b"])
+        .arg(dir.join("archive.zip"))
+        .assert()
+        .failure();
+}
+
+#[test]
+fn decompress_flattened() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let folder = &dir.join("folder");
+    fs::create_dir_all(folder.join("a/b")).unwrap();
+    fs::write(folder.join("notes.txt"), "1").unwrap();
+    fs::write(folder.join("a/notes.txt"), "2").unwrap();
+    fs::write(folder.join("a/b/other.txt"), "3").unwrap();
+
+    for format in ["tar", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", folder, archive);
+
+        let after = &dir.join(format!("after-{format}"));
+        ouch!("-A", "d", "--keep-directory-structure=false", archive, "-d", after);
+
+        let unpacked = &after.join("archive");
+        let mut names: Vec<_> = fs::read_dir(unpacked)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["notes.txt", "notes_1.txt", "other.txt"]);
+
+        let mut contents: Vec<_> = ["notes.txt", "notes_1.txt"]
+            .map(|name| fs::read_to_string(unpacked.join(name)).unwrap())
+            .to_vec();
+        contents.sort();
+        assert_eq!(contents, ["1", "2"]);
+        assert_eq!(fs::read_to_string(unpacked.join("other.txt")).unwrap(), "3");
+    }
+}
+
+#[test]
+fn decompress_on_conflict() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let folder = &dir.join("folder");
+    fs::create_dir(folder).unwrap();
+    fs::write(folder.join("a.txt"), "new").unwrap();
+    fs::write(folder.join("b.txt"), "b").unwrap();
+
+    let archive = &dir.join("archive.tar");
+    ouch!("-A", "c", folder, archive);
+
+    // Modification times of the existing file, compared to the one in the archive
+    let now = filetime_creation::FileTime::now().unix_seconds();
+    let (older, newer) = (now - 24 * 60 * 60, now + 24 * 60 * 60);
+    let cases = [
+        ("skip", None, "old"),
+        ("overwrite", None, "new"),
+        ("rename", None, "old"),
+        ("update-newer", Some(older), "new"),
+        ("update-newer", Some(newer), "old"),
+    ];
+
+    for (index, (policy, existing_mtime, expected)) in cases.into_iter().enumerate() {
+        let out = &dir.join(format!("out-{index}"));
+        fs::create_dir_all(out.join("folder")).unwrap();
+        fs::write(out.join("folder/a.txt"), "old").unwrap();
+        if let Some(mtime) = existing_mtime {
+            let mtime = filetime_creation::FileTime::from_unix_time(mtime, 0);
+            filetime_creation::set_file_mtime(out.join("folder/a.txt"), mtime).unwrap();
+        }
+        fs::write(out.join("folder/keep.txt"), "keep").unwrap();
+
+        crate::utils::cargo_bin()
+            .args(["d", "--on-conflict", policy])
+            .arg(archive)
+            .arg("-d")
+            .arg(out)
+            .assert()
+            .success();
+
+        let out = &out.join("folder");
+        assert_eq!(fs::read_to_string(out.join("a.txt")).unwrap(), expected, "{policy}");
+        assert_eq!(fs::read_to_string(out.join("b.txt")).unwrap(), "b");
+        assert_eq!(fs::read_to_string(out.join("keep.txt")).unwrap(), "keep");
+        assert_eq!(out.join("a_1.txt").exists(), policy == "rename");
+    }
+}
+
+#[test]
+fn decompress_strip_common_prefix() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let folder = &dir.join("project-1.2.3");
+    fs::create_dir_all(folder.join("src")).unwrap();
+    fs::write(folder.join("README"), "readme").unwrap();
+    fs::write(folder.join("src/main.rs"), "main").unwrap();
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", folder, archive);
+
+        let out = &dir.join(format!("out-{format}"));
+        ouch!("-A", "d", archive, "-d", out, "--strip-common-prefix", "--verify");
+        assert_eq!(fs::read_to_string(out.join("README")).unwrap(), "readme");
+        assert_eq!(fs::read_to_string(out.join("src/main.rs")).unwrap(), "main");
+        assert!(!out.join("project-1.2.3").exists());
+        assert!(!out.join("archive").exists());
+    }
+
+    // Without a single directory at the root, the archive is unpacked as usual
+    let other = &dir.join("other.txt");
+    fs::write(other, "other").unwrap();
+    let archive = &dir.join("two-roots.tar");
+    ouch!("-A", "c", folder, other, archive);
+    let out = &dir.join("out-two-roots");
+    ouch!("-A", "d", archive, "-d", out, "--strip-common-prefix");
+    assert!(out.join("two-roots/project-1.2.3/README").exists());
+    assert!(out.join("two-roots/other.txt").exists());
+}
+
+#[test]
+fn decompress_in_place() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let one = &dir.join("one.txt");
+    let two = &dir.join("two.txt");
+    fs::write(one, "new").unwrap();
+    fs::write(two, "two").unwrap();
+
+    let archive = &dir.join("archive.tar.gz");
+    ouch!("-A", "c", one, two, archive);
+
+    for (answer, expected) in [("--no", "old"), ("--yes", "new")] {
+        let out = &dir.join(format!("out{answer}"));
+        fs::create_dir(out).unwrap();
+        fs::write(out.join("one.txt"), "old").unwrap();
+        fs::write(out.join("keep.txt"), "keep").unwrap();
+
+        crate::utils::cargo_bin()
+            .args(["d", "--in-place", answer])
+            .arg(archive)
+            .arg("-d")
+            .arg(out)
+            .assert()
+            .success();
+
+        // Unpacked next to the existing files, not in a directory named after the archive
+        let mut names: Vec<_> = fs::read_dir(out)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["keep.txt", "one.txt", "two.txt"]);
+        assert_eq!(fs::read_to_string(out.join("one.txt")).unwrap(), expected);
+        assert_eq!(fs::read_to_string(out.join("two.txt")).unwrap(), "two");
+    }
+}
+
+#[test]
+fn decompress_skip_identical() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let one = &dir.join("one.txt");
+    let two = &dir.join("two.txt");
+    fs::write(one, "one").unwrap();
+    fs::write(two, "two").unwrap();
+
+    let archive = &dir.join("archive.zip");
+    ouch!("-A", "c", one, two, archive);
+
+    let out = &dir.join("out");
+    fs::create_dir(out).unwrap();
+    ouch!("-A", "d", "--in-place", archive, "-d", out);
+
+    // Same size and modification time, but not the same contents
+    let unpacked = out.join("one.txt");
+    let mtime = filetime_creation::FileTime::from_last_modification_time(&fs::metadata(&unpacked).unwrap());
+    fs::write(&unpacked, "ONE").unwrap();
+    filetime_creation::set_file_mtime(&unpacked, mtime).unwrap();
+    fs::write(out.join("two.txt"), "TWO!").unwrap();
+
+    for (checksum, expected) in [(None, "ONE"), (Some("--checksum"), "one")] {
+        crate::utils::cargo_bin()
+            .args(["d", "--in-place", "--skip-identical", "--on-conflict", "overwrite"])
+            .args(checksum)
+            .arg(archive)
+            .arg("-d")
+            .arg(out)
+            .assert()
+            .success();
+
+        assert_eq!(fs::read_to_string(&unpacked).unwrap(), expected);
+        assert_eq!(fs::read_to_string(out.join("two.txt")).unwrap(), "two");
+        fs::write(out.join("two.txt"), "TWO!").unwrap();
+    }
+
+    // Only files that already exist can be skipped
+    crate::utils::cargo_bin()
+        .args(["d", "--skip-identical"])
+        .arg(archive)
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn compress_stdin_as_named_entry() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+
+    for format in ["tar.zst", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        crate::utils::cargo_bin()
+            .args(["-A", "c", "-"])
+            .arg(archive)
+            .args(["--entry-name", "logs/output.txt"])
+            .write_stdin("piped data")
+            .assert()
+            .success();
+
+        let after = &dir.join(format!("after-{format}"));
+        ouch!("-A", "d", archive, "-d", after);
+        assert_eq!(fs::read_to_string(after.join("logs/output.txt")).unwrap(), "piped data");
+        // Only the file is stored, without entries for its parent directories
+        let listed = crate::utils::cargo_bin().args(["list"]).arg(archive).output().unwrap();
+        assert!(!String::from_utf8_lossy(&listed.stdout).contains("logs/\n"));
+    }
+
+    // Single file formats don't need a name
+    let compressed = &dir.join("output.gz");
+    crate::utils::cargo_bin()
+        .args(["-A", "c", "-"])
+        .arg(compressed)
+        .write_stdin("piped data")
+        .assert()
+        .success();
+    ouch!("-A", "d", compressed, "-d", dir);
+    assert_eq!(fs::read_to_string(dir.join("output")).unwrap(), "piped data");
+
+    crate::utils::cargo_bin()
+        .args(["-A", "c", "-"])
+        .arg(dir.join("missing-name.tar"))
+        .write_stdin("piped data")
+        .assert()
+        .failure();
+    crate::utils::cargo_bin()
+        .args(["-A", "c", "-"])
+        .arg(dir.join("outside.tar"))
+        .args(["--entry-name", "../outside.txt"])
+        .write_stdin("piped data")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn compress_concat() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let a = &dir.join("a.log");
+    let b = &dir.join("b.log");
+    fs::write(a, "first\n").unwrap();
+    fs::write(b, "second\n").unwrap();
+
+    for format in ["gz", "zst", "xz"] {
+        let compressed = &dir.join(format!("merged.log.{format}"));
+        crate::utils::cargo_bin()
+            .args(["-A", "c", "--concat"])
+            .arg(a)
+            .arg("-")
+            .arg(b)
+            .arg(compressed)
+            .write_stdin("piped\n")
+            .assert()
+            .success();
+
+        let after = &dir.join(format!("after-{format}"));
+        ouch!("-A", "d", compressed, "-d", after);
+        assert_eq!(
+            fs::read_to_string(after.join("merged.log")).unwrap(),
+            "first\npiped\nsecond\n"
+        );
+    }
+
+    // Multiple files need an archive format otherwise, and archives can't join them
+    for (concat, output) in [(None, "merged.log.gz"), (Some("--concat"), "merged.tar.gz")] {
+        crate::utils::cargo_bin()
+            .args(["-A", "c"])
+            .args(concat)
+            .arg(a)
+            .arg(b)
+            .arg(dir.join(output))
+            .assert()
+            .code(2);
+    }
+}
+
+#[test]
+fn split_output() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    // Random data doesn't compress, so the archive spans a few parts
+    for i in 0..8 {
+        let mut contents = vec![0; 4096];
+        SmallRng::from_entropy().fill(contents.as_mut_slice());
+        fs::write(input.join(format!("file{i}")), contents).unwrap();
+    }
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", input, archive, "--split-size", "10KiB");
+        assert!(!archive.exists());
+
+        let parts: Vec<_> = (1..)
+            .map(|number| dir.join(format!("archive.{format}.{number:03}")))
+            .take_while(|part| part.exists())
+            .collect();
+        assert!(parts.len() > 1);
+        assert!(parts[..parts.len() - 1]
+            .iter()
+            .all(|part| fs::metadata(part).unwrap().len() == 10 * 1024));
+
+        let after = &dir.join(format!("after-{format}"));
+        ouch!("-A", "d", &parts[0], "-d", after);
+        assert_same_directory(input, after.join("input"), false);
+    }
+}
+
+#[test]
+fn seekable_zstd() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    // Bigger than a frame, so the archive has a few of them
+    for i in 0..3 {
+        let mut contents = vec![0; 768 * 1024];
+        SmallRng::from_entropy().fill(contents.as_mut_slice());
+        fs::write(input.join(format!("file{i}")), contents).unwrap();
+    }
+
+    let archive = &dir.join("archive.tar.zst");
+    ouch!("-A", "c", input, archive, "--seekable", "--checksum");
+
+    let listed = crate::utils::cargo_bin().args(["list"]).arg(archive).output().unwrap();
+    let listed = String::from_utf8_lossy(&listed.stdout);
+    assert!((0..3).all(|i| listed.contains(&format!("input/file{i}"))));
+
+    let after = &dir.join("after");
+    ouch!("-A", "d", archive, "-d", after);
+    assert_same_directory(input, after.join("input"), false);
+}
+
+#[test]
+fn tar_index() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("keep.txt"), "keep").unwrap();
+    fs::write(input.join("skip.txt"), "skip").unwrap();
+
+    for (format, flags) in [("tar", &[][..]), ("tar.zst", &["--seekable"][..])] {
+        let archive = &dir.join(format!("archive.{format}"));
+        crate::utils::cargo_bin()
+            .args(["-A", "-y", "c", "--index"])
+            .args(flags)
+            .arg(input)
+            .arg(archive)
+            .assert()
+            .success();
+        assert!(dir.join(format!("archive.{format}.idx")).exists());
+
+        let listed = crate::utils::cargo_bin().args(["list"]).arg(archive).output().unwrap();
+        let listed = String::from_utf8_lossy(&listed.stdout);
+        assert!(listed.contains("input/keep.txt") && listed.contains("input/skip.txt"));
+
+        let after = &dir.join(format!("after-{format}"));
+        crate::utils::cargo_bin()
+            .args(["-A", "d", "--transform", "s/^.*skip.txt$//"])
+            .arg(archive)
+            .arg("-d")
+            .arg(after)
+            .assert()
+            .success();
+        assert_eq!(fs::read_to_string(after.join("input/keep.txt")).unwrap(), "keep");
+        assert!(!after.join("input/skip.txt").exists());
+    }
+
+    crate::utils::cargo_bin()
+        .args(["-A", "-y", "c", "--index"])
+        .arg(input)
+        .arg(dir.join("archive.zip"))
+        .assert()
+        .failure();
+}
+
+#[test]
+fn compress_dedup() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("copies")).unwrap();
+    let mut contents = vec![0; 64 * 1024];
+    SmallRng::from_entropy().fill(contents.as_mut_slice());
+    fs::write(input.join("original"), &contents).unwrap();
+    fs::write(input.join("copies/first"), &contents).unwrap();
+    fs::write(input.join("copies/second"), &contents).unwrap();
+    fs::write(input.join("other"), "other").unwrap();
+
+    let plain = &dir.join("plain.tar");
+    let deduped = &dir.join("deduped.tar");
+    ouch!("-A", "c", input, plain);
+    ouch!("-A", "c", input, deduped, "--dedup");
+    assert!(fs::metadata(deduped).unwrap().len() < fs::metadata(plain).unwrap().len() / 2);
+
+    let after = &dir.join("after");
+    ouch!("-A", "d", deduped, "-d", after, "--verify");
+    assert_same_directory(input, after.join("input"), false);
+
+    crate::utils::cargo_bin()
+        .args(["-A", "-y", "c", "--dedup"])
+        .arg(input)
+        .arg(dir.join("archive.zip"))
+        .assert()
+        .failure();
+}
+
+/// The status line is only drawn on terminals, without it the output is the same
+#[test]
+fn compress_max_depth() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("a/b")).unwrap();
+    fs::write(input.join("top.txt"), "top").unwrap();
+    fs::write(input.join("a/middle.txt"), "middle").unwrap();
+    fs::write(input.join("a/b/bottom.txt"), "bottom").unwrap();
+
+    for format in ["tar", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", input, archive, "--max-depth", "2");
+
+        let out = &dir.join(format!("out-{format}"));
+        ouch!("-A", "d", archive, "-d", out);
+        assert!(out.join("input/top.txt").exists());
+        assert!(out.join("input/a/middle.txt").exists());
+        assert!(out.join("input/a/b").is_dir());
+        assert!(!out.join("input/a/b/bottom.txt").exists());
+    }
+}
+
+#[test]
+fn compress_exclude_vcs_and_caches() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join(".git/objects")).unwrap();
+    fs::create_dir_all(input.join("sub/.hg")).unwrap();
+    fs::create_dir_all(input.join("cache")).unwrap();
+    fs::create_dir_all(input.join("fake-cache")).unwrap();
+    fs::write(input.join(".git/objects/blob"), "blob").unwrap();
+    fs::write(input.join("sub/.hg/store"), "store").unwrap();
+    fs::write(input.join("sub/code.rs"), "code").unwrap();
+    let tag = "Signature: 8a477f597d28d172789f06886806bc55\n# This file is a cache directory tag\n";
+    fs::write(input.join("cache/CACHEDIR.TAG"), tag).unwrap();
+    fs::write(input.join("cache/cached.bin"), "cached").unwrap();
+    fs::write(input.join("fake-cache/CACHEDIR.TAG"), "not a signature").unwrap();
+
+    for format in ["tar", "zip"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", input, archive, "--exclude-vcs", "--exclude-caches");
+
+        let out = &dir.join(format!("out-{format}"));
+        ouch!("-A", "d", archive, "-d", out);
+        assert!(out.join("input/sub/code.rs").exists());
+        assert!(out.join("input/fake-cache/CACHEDIR.TAG").exists());
+        assert!(!out.join("input/.git").exists());
+        assert!(!out.join("input/sub/.hg").exists());
+        assert!(!out.join("input/cache").exists());
+    }
+
+    // The input files themselves are always compressed
+    let archive = &dir.join("git.tar");
+    ouch!("-A", "c", input.join(".git"), archive, "--exclude-vcs");
+    let stdout = String::from_utf8(ouch!("list", archive).stdout).unwrap();
+    assert!(stdout.contains("blob"), "{stdout}");
+}
+
+#[test]
+fn compress_respects_ouchignore() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("logs")).unwrap();
+    fs::write(input.join(".ouchignore"), "*.log\n").unwrap();
+    fs::write(input.join("notes.txt"), "notes").unwrap();
+    fs::write(input.join("debug.log"), "debug").unwrap();
+    fs::write(input.join("logs/.ouchignore"), "*.txt\n").unwrap();
+    fs::write(input.join("logs/kept.csv"), "kept").unwrap();
+    fs::write(input.join("logs/skipped.txt"), "skipped").unwrap();
+
+    for format in ["tar", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", input, archive);
+
+        let out = &dir.join(format!("out-{format}"));
+        ouch!("-A", "d", archive, "-d", out);
+        assert!(out.join("input/notes.txt").exists());
+        assert!(out.join("input/logs/kept.csv").exists());
+        assert!(!out.join("input/debug.log").exists());
+        assert!(!out.join("input/logs/skipped.txt").exists());
+    }
+}
+
+#[test]
+fn compress_gitignore_outside_of_repositories() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join(".gitignore"), "*.log\n").unwrap();
+    fs::write(input.join("notes.txt"), "notes").unwrap();
+    fs::write(input.join("debug.log"), "debug").unwrap();
+    fs::write(input.join("cache.tmp"), "cache").unwrap();
+    // The global gitignore
+    let config = &dir.join("config");
+    fs::create_dir_all(config.join("git")).unwrap();
+    fs::write(config.join("git/ignore"), "*.tmp\n").unwrap();
+
+    let compress = |name: &str, flags: &[&str]| {
+        let archive = dir.join(format!("{name}.tar"));
+        crate::utils::cargo_bin()
+            .env("HOME", dir)
+            .env("XDG_CONFIG_HOME", config)
+            .args(["-A", "c"])
+            .arg(input)
+            .arg(&archive)
+            .args(flags)
+            .assert()
+            .success();
+        let out = dir.join(name);
+        ouch!("-A", "d", archive, "-d", &out);
+        ["debug.log", "cache.tmp"].map(|file| out.join("input").join(file).exists())
+    };
+
+    // Not a git repository, and the global gitignore isn't read without --gitignore
+    assert_eq!(compress("none", &[]), [true, true]);
+    assert_eq!(compress("gitignore", &["-g"]), [true, true]);
+    assert_eq!(compress("no-require-git", &["-g", "--no-require-git"]), [false, false]);
+    assert_eq!(
+        compress("no-global", &["-g", "--no-require-git", "--no-global-gitignore"]),
+        [false, true]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn compress_follow_dir_symlinks() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(dir.join("outside")).unwrap();
+    fs::write(dir.join("outside/file.txt"), "outside").unwrap();
+    fs::create_dir(input).unwrap();
+    std::os::unix::fs::symlink("../outside", input.join("linked")).unwrap();
+    // Following it would never end
+    std::os::unix::fs::symlink(".", input.join("itself")).unwrap();
+
+    for format in ["tar", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", input, archive);
+        let out = &dir.join(format!("out-{format}"));
+        ouch!("-A", "d", archive, "-d", out);
+        assert!(out.join("input/linked").is_dir());
+        assert!(!out.join("input/linked/file.txt").exists());
+
+        let archive = &dir.join(format!("followed.{format}"));
+        ouch!("-A", "c", input, archive, "--follow-dir-symlinks");
+        let out = &dir.join(format!("followed-{format}"));
+        ouch!("-A", "d", archive, "-d", out);
+        assert_eq!(
+            fs::read_to_string(out.join("input/linked/file.txt")).unwrap(),
+            "outside"
+        );
+        assert!(!out.join("input/itself").exists());
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn output_symlink_is_replaced_not_followed() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file.txt");
+    fs::write(file, "contents").unwrap();
+    let important = &dir.join("important");
+    fs::write(important, "important").unwrap();
+
+    let output = &dir.join("file.txt.gz");
+    std::os::unix::fs::symlink(important, output).unwrap();
+    crate::utils::cargo_bin()
+        .args(["-A", "-y", "c", "--no-follow-output-symlink"])
+        .args([file, output])
+        .assert()
+        .code(5);
+    assert!(output.is_symlink());
+
+    ouch!("-A", "-y", "c", file, output);
+    assert!(!output.is_symlink());
+    assert_eq!(fs::read_to_string(important).unwrap(), "important");
+
+    // Dangling links aren't followed either
+    let missing = &dir.join("missing");
+    let output = &dir.join("dangling.gz");
+    std::os::unix::fs::symlink(missing, output).unwrap();
+    ouch!("-A", "-y", "c", file, output);
+    assert!(!output.is_symlink());
+    assert!(!missing.exists());
+}
+
+#[test]
+fn compress_manifest() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("sub")).unwrap();
+    fs::write(input.join("a.txt"), "abc").unwrap();
+    fs::write(input.join("sub/b.txt"), "").unwrap();
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        let manifest = &dir.join(format!("manifest-{format}.json"));
+        ouch!("-A", "c", input, archive, "--manifest", manifest);
+
+        let manifest: serde_json::Value = serde_json::from_str(&fs::read_to_string(manifest).unwrap()).unwrap();
+        let mut entries = manifest["entries"].as_array().unwrap().clone();
+        entries.sort_by_key(|entry| entry["path"].as_str().unwrap().to_owned());
+        let paths: Vec<_> = entries.iter().map(|entry| entry["path"].as_str().unwrap()).collect();
+        assert_eq!(paths, ["input", "input/a.txt", "input/sub", "input/sub/b.txt"]);
+
+        let file = &entries[1];
+        assert_eq!(file["type"], "file");
+        assert_eq!(file["size"], 3);
+        // SHA-512 of "abc"
+        assert!(file["sha512"].as_str().unwrap().starts_with("ddaf35a193617aba"));
+        assert_eq!(entries[0]["type"], "directory");
+        assert!(entries[0]["sha512"].is_null());
+    }
+
+    crate::utils::cargo_bin()
+        .args(["-A", "-y", "c", "--manifest"])
+        .arg(dir.join("manifest.json"))
+        .arg(input.join("a.txt"))
+        .arg(dir.join("a.txt.gz"))
+        .assert()
+        .failure();
+}
+
+#[test]
+fn compress_newer_than() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("sub")).unwrap();
+    for name in ["old.txt", "changed.txt", "sub/old.txt"] {
+        fs::write(input.join(name), name).unwrap();
+        filetime_creation::set_file_mtime(
+            input.join(name),
+            filetime_creation::FileTime::from_unix_time(1_000_000_000, 0),
+        )
+        .unwrap();
+    }
+
+    for format in ["tar.zst", "zip", "7z"] {
+        let full = &dir.join(format!("full.{format}"));
+        ouch!("-A", "c", input, full);
+
+        let mtime = filetime_creation::FileTime::from_unix_time(1_100_000_000, 0);
+        filetime_creation::set_file_mtime(input.join("changed.txt"), mtime).unwrap();
+        fs::write(input.join("sub/added.txt"), "added").unwrap();
+
+        let incremental = &dir.join(format!("incremental.{format}"));
+        ouch!("-A", "c", input, incremental, "--newer-than", full);
+
+        // Next to the manifest, the entries are unpacked into a directory named after the archive
+        let out = &dir.join(format!("out-{format}")).join("incremental");
+        ouch!("-A", "d", incremental, "-d", out.parent().unwrap());
+        let mut unpacked: Vec<_> = fs::read_dir(out.join("input"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        unpacked.sort();
+        assert_eq!(unpacked, ["changed.txt", "sub"]);
+        assert_eq!(fs::read_to_string(out.join("input/sub/added.txt")).unwrap(), "added");
+        assert!(!out.join("input/sub/old.txt").exists());
+        let manifest = fs::read_to_string(out.join(".ouch-incremental")).unwrap();
+        assert_eq!(manifest, format!("base: {}\n", full.display()));
+
+        fs::remove_file(input.join("sub/added.txt")).unwrap();
+        filetime_creation::set_file_mtime(
+            input.join("changed.txt"),
+            filetime_creation::FileTime::from_unix_time(1_000_000_000, 0),
+        )
+        .unwrap();
+    }
+
+    let by_mtime = &dir.join("by-mtime.tar");
+    filetime_creation::set_file_mtime(
+        input.join("changed.txt"),
+        filetime_creation::FileTime::from_unix_time(1_100_000_000, 0),
+    )
+    .unwrap();
+    ouch!("-A", "c", input, by_mtime, "--newer-mtime", "@1050000000");
+    let out = &dir.join("out-by-mtime").join("by-mtime");
+    ouch!("-A", "d", by_mtime, "-d", out.parent().unwrap());
+    assert!(out.join("input/changed.txt").exists());
+    assert!(!out.join("input/old.txt").exists());
+    assert_eq!(
+        fs::read_to_string(out.join(".ouch-incremental")).unwrap(),
+        "newer-mtime: @1050000000\n"
+    );
+}
+
+#[test]
+fn compress_mtime_range() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("sub")).unwrap();
+    for (name, mtime) in [
+        ("old.txt", 1_000_000_000),
+        ("sub/middle.txt", 1_100_000_000),
+        ("new.txt", 1_200_000_000),
+    ] {
+        fs::write(input.join(name), name).unwrap();
+        filetime_creation::set_file_mtime(input.join(name), filetime_creation::FileTime::from_unix_time(mtime, 0))
+            .unwrap();
+    }
+
+    let archive = &dir.join("archive.tar");
+    ouch!(
+        "-A",
+        "c",
+        input,
+        archive,
+        "--newer-mtime",
+        "2004-01-01",
+        "--older-mtime",
+        "2005-01-01T00:00:00+01:00"
+    );
+    // Next to the manifest, the entries are unpacked into a directory named after the archive
+    let out = &dir.join("out").join("archive");
+    ouch!("-A", "d", archive, "-d", out.parent().unwrap());
+    assert!(out.join("input/sub/middle.txt").exists());
+    assert!(!out.join("input/old.txt").exists());
+    assert!(!out.join("input/new.txt").exists());
+
+    let archive = &dir.join("archive.zip");
+    ouch!("-A", "c", input, archive, "--older-mtime", "1 day ago");
+    let out = &dir.join("out-zip");
+    ouch!("-A", "d", archive, "-d", out);
+    assert!(out.join("input/old.txt").exists());
+    assert!(out.join("input/new.txt").exists());
+    // Only --newer-mtime and --newer-than make incremental archives
+    assert!(!out.join(".ouch-incremental").exists());
+
+    crate::utils::cargo_bin()
+        .args(["c", "--older-mtime", "next week"])
+        .arg(input)
+        .arg(dir.join("invalid.tar"))
+        .assert()
+        .code(2);
+}
+
+/// The settings of a run, kept in globals, are replaced by the next one in the same process
+#[cfg(feature = "cli")]
+#[test]
+fn run_twice_in_one_process() {
+    use std::ffi::OsStr;
+
+    use clap::Parser;
+
+    let run = |args: &[&dyn AsRef<OsStr>]| {
+        let args = once(OsStr::new("ouch")).chain(args.iter().map(|arg| arg.as_ref()));
+        ouch::cli::run(ouch::cli::CliArgs::parse_from(args)).unwrap();
+    };
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    let file = &input.join("file.txt");
+    fs::write(file, "contents").unwrap();
+    filetime_creation::set_file_mtime(file, filetime_creation::FileTime::from_unix_time(1_000_000_000, 0)).unwrap();
+    let identity = &dir.join("key.txt");
+    fs::write(identity, "").unwrap();
+
+    let first = &dir.join("first.pkg");
+    run(&[
+        &"-q",
+        &"-c2",
+        &"--map=pkg=tar.gz",
+        &"--decrypt",
+        identity,
+        &"c",
+        input,
+        first,
+        &"--newer-mtime=@1050000000",
+    ]);
+    assert!(fs::read(first).unwrap().starts_with(&[0x1F, 0x8B]));
+    let out = &dir.join("out-first");
+    run(&[&"-q", &"--map=pkg=tar.gz", &"d", first, &"-d", out]);
+    assert!(out.join("first/.ouch-incremental").exists());
+    assert!(!out.join("first/input/file.txt").exists());
+
+    // Without the extension mapped to tar.gz, the baseline, or the identity file of the first run
+    let second = &dir.join("second.pkg");
+    run(&[&"-q", &"-c2", &"--map=pkg=zip", &"c", input, second]);
+    assert!(fs::read(second).unwrap().starts_with(b"PK"));
+    let out = &dir.join("out-second");
+    run(&[&"-q", &"--map=pkg=zip", &"d", second, &"-d", out]);
+    assert_eq!(fs::read_to_string(out.join("input/file.txt")).unwrap(), "contents");
+}
+
+#[cfg(unix)]
+#[test]
+fn compress_special_files() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file.txt"), "data").unwrap();
+    assert!(std::process::Command::new("mkfifo")
+        .arg(input.join("fifo"))
+        .status()
+        .unwrap()
+        .success());
+
+    // Skipped by default, without blocking on the FIFO
+    for format in ["tar", "zip", "7z"] {
+        let archive = &dir.join(format!("skipped.{format}"));
+        ouch!("-A", "c", input, archive);
+        let listed = crate::utils::cargo_bin().args(["list"]).arg(archive).output().unwrap();
+        let listed = String::from_utf8_lossy(&listed.stdout);
+        assert!(listed.contains("file.txt"));
+        assert!(!listed.contains("fifo"));
+    }
+
+    let archive = &dir.join("stored.tar");
+    crate::utils::cargo_bin()
+        .args(["-A", "c", "--special-files", "store"])
+        .arg(input)
+        .arg(archive)
+        .assert()
+        .success();
+    let listed = crate::utils::cargo_bin().args(["list"]).arg(archive).output().unwrap();
+    assert!(String::from_utf8_lossy(&listed.stdout).contains("fifo"));
+
+    crate::utils::cargo_bin()
+        .args(["-A", "c", "--special-files", "fail"])
+        .arg(input)
+        .arg(dir.join("failed.tar"))
+        .assert()
+        .failure();
+}
+
+#[cfg(feature = "unrar")]
+#[test]
+fn unpack_rar() -> Result<(), Box<dyn std::error::Error>> {
+    fn test_unpack_rar_single(input: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempdir()?;
+        let dirpath = dir.path();
+        let unpacked_path = &dirpath.join("testfile.txt");
+        ouch!("-A", "d", input, "-d", dirpath);
+        let content = fs::read_to_string(unpacked_path)?;
+        assert_eq!(content, "Testing 123\n");
+
+        Ok(())
+    }
+
+    let mut datadir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR")?);
+    datadir.push("tests/data");
+    ["testfile.rar3.rar.gz", "testfile.rar5.rar"]
+        .iter()
+        .try_for_each(|path| test_unpack_rar_single(&datadir.join(path)))?;
+
+    Ok(())
+}
+
+#[cfg(feature = "unrar")]
+#[test]
+fn unpack_rar_stdin() -> Result<(), Box<dyn std::error::Error>> {
+    fn test_unpack_rar_single(input: &std::path::Path, format: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempdir()?;
+        let dirpath = dir.path();
+        let unpacked_path = &dirpath.join("testfile.txt");
+        crate::utils::cargo_bin()
+            .args([
+                "-A",
+                "-y",
+                "d",
+                "-",
+                "-d",
+                dirpath.to_str().unwrap(),
+                "--format",
+                format,
+            ])
+            .pipe_stdin(input)
+            .unwrap()
+            .assert()
+            .success();
+        let content = fs::read_to_string(unpacked_path)?;
+        assert_eq!(content, "Testing 123\n");
+
+        Ok(())
+    }
+
+    let mut datadir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR")?);
+    datadir.push("tests/data");
+    [("testfile.rar3.rar.gz", "rar.gz"), ("testfile.rar5.rar", "rar")]
+        .iter()
+        .try_for_each(|(path, format)| test_unpack_rar_single(&datadir.join(path), format))?;
+
+    Ok(())
+}
+
+#[test]
+fn unpack_tar_z() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let archive = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("tests/data/testfile.tar.Z");
+
+    ouch!("-A", "d", &archive, "-d", dir.join("tar.Z"));
+    assert_eq!(
+        fs::read_to_string(dir.join("tar.Z/testfile.txt")).unwrap(),
+        "Testing 123\n"
+    );
+
+    // Same archive with the `taz` alias
+    let taz = &dir.join("testfile.taz");
+    fs::copy(&archive, taz).unwrap();
+    ouch!("-A", "d", taz, "-d", dir.join("taz"));
+    assert_eq!(
+        fs::read_to_string(dir.join("taz/testfile.txt")).unwrap(),
+        "Testing 123\n"
+    );
+
+    // Only decompression is supported
+    crate::utils::cargo_bin()
+        .args(["-A", "c"])
+        .arg(dir.join("taz/testfile.txt"))
+        .arg(dir.join("testfile.txt.Z"))
+        .assert()
+        .failure()
+        .code(3);
+}
+
+/// Zip entries compressed with zstd (method 93) and xz (method 95)
+#[test]
+fn unpack_zip_with_zstd_and_xz_entries() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let archive = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("tests/data/testfile.zstd-xz.zip");
+
+    ouch!("-A", "d", &archive, "-d", dir.join("out"));
+    assert_eq!(
+        fs::read_to_string(dir.join("out/testfile.zstd-xz/zstd.txt")).unwrap(),
+        "Compressed with zstd\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("out/testfile.zstd-xz/xz.txt")).unwrap(),
+        "Compressed with xz\n"
+    );
+    ouch!("-A", "l", &archive);
+
+    // Set the method of the first entry in the central directory to PPMd (98)
+    let mut bytes = fs::read(&archive).unwrap();
+    let end_of_central_directory = bytes.len() - 22;
+    let offset_bytes = &bytes[end_of_central_directory + 16..end_of_central_directory + 20];
+    let central_directory = u32::from_le_bytes(offset_bytes.try_into().unwrap()) as usize;
+    bytes[central_directory + 10..central_directory + 12].copy_from_slice(&98u16.to_le_bytes());
+    let ppmd = &dir.join("ppmd.zip");
+    fs::write(ppmd, bytes).unwrap();
+
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "d"])
+        .arg(ppmd)
+        .arg("-d")
+        .arg(dir.join("ppmd"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Compression method 98 is not supported"));
+}
+
+/// `ouch bench` prints a row for each format and level, and only takes single compression formats
+#[test]
+fn bench_formats_and_levels() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file.txt"), "contents ".repeat(10_000)).unwrap();
+
+    let output = crate::utils::cargo_bin()
+        .args(["bench", "--formats", "gz,zst", "--levels", "1,3"])
+        .arg(input)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let rows: Vec<Vec<&str>> = stdout
+        .lines()
+        .skip(2)
+        .map(|line| line.split_whitespace().take(2).collect())
+        .collect();
+    assert_eq!(rows, [["gz", "1"], ["gz", "3"], ["zst", "1"], ["zst", "3"]]);
+
+    crate::utils::cargo_bin()
+        .args(["bench", "--formats", "tgz"])
+        .arg(input)
+        .assert()
+        .code(2);
+}
+
+/// Entries can't be written through links made by earlier entries of the same archive
+#[cfg(unix)]
+#[test]
+fn unpack_through_symlink_entry_is_refused() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let outside = &dir.join("outside");
+    fs::create_dir(outside).unwrap();
+
+    let archive = &dir.join("evil.tar");
+    let mut builder = tar::Builder::new(fs::File::create(archive).unwrap());
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Symlink);
+    header.set_size(0);
+    builder.append_link(&mut header, "evil/link", outside).unwrap();
+    let mut header = tar::Header::new_gnu();
+    header.set_size(5);
+    header.set_mode(0o644);
+    builder
+        .append_data(&mut header, "evil/link/passwd", &b"owned"[..])
+        .unwrap();
+    builder.finish().unwrap();
+
+    for extra_args in [&[][..], &["--in-place"], &["--on-conflict", "rename"]] {
+        let out = &dir.join(format!("out{}", extra_args.len()));
+        crate::utils::cargo_bin()
+            .args(["-A", "-y", "d"])
+            .arg(archive)
+            .arg("-d")
+            .arg(out)
+            .args(extra_args)
+            .assert()
+            .code(4);
+        assert!(!outside.join("passwd").exists());
+    }
+
+    // Links that stay inside of the output directory can still be written through
+    let archive = &dir.join("inside.tar");
+    let mut builder = tar::Builder::new(fs::File::create(archive).unwrap());
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Directory);
+    header.set_size(0);
+    header.set_mode(0o755);
+    builder
+        .append_data(&mut header, "inside/real", std::io::empty())
+        .unwrap();
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Symlink);
+    header.set_size(0);
+    builder.append_link(&mut header, "inside/link", "real").unwrap();
+    let mut header = tar::Header::new_gnu();
+    header.set_size(2);
+    header.set_mode(0o644);
+    builder
+        .append_data(&mut header, "inside/link/file", &b"ok"[..])
+        .unwrap();
+    builder.finish().unwrap();
+
+    let out = &dir.join("inside_out");
+    ouch!("-A", "d", archive, "-d", out);
+    assert_eq!(fs::read_to_string(out.join("inside/real/file")).unwrap(), "ok");
+}
+
+/// Hard links renamed by `--transform` can't point to files through links leading outside
+#[cfg(unix)]
+#[test]
+fn hard_link_through_symlink_entry_is_refused() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let outside = &dir.join("outside");
+    fs::create_dir(outside).unwrap();
+    fs::write(outside.join("secret"), "secret").unwrap();
+
+    let archive = &dir.join("evil.tar");
+    let mut builder = tar::Builder::new(fs::File::create(archive).unwrap());
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Symlink);
+    header.set_size(0);
+    builder.append_link(&mut header, "evil/link", outside).unwrap();
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Link);
+    header.set_size(0);
+    builder
+        .append_link(&mut header, "evil/copy", "evil/link/secret")
+        .unwrap();
+    builder.finish().unwrap();
+
+    let out = &dir.join("out");
+    crate::utils::cargo_bin()
+        .args(["-A", "-y", "d", "--transform", "s/copy$/renamed/"])
+        .arg(archive)
+        .arg("-d")
+        .arg(out)
+        .assert()
+        .code(4);
+    assert!(!out.join("evil/renamed").exists());
+}
+
+/// 7z entries with names leaving the output directory are skipped, sevenz_rust joins them as is
+#[test]
+fn sevenz_entries_outside_of_output_are_skipped() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file.txt");
+    fs::write(file, "owned").unwrap();
+    let absolute = dir.join("absolute.txt");
+
+    let archive = &dir.join("evil.7z");
+    let mut writer = sevenz_rust::SevenZWriter::create(archive).unwrap();
+    for name in ["../escaped.txt", absolute.to_str().unwrap(), "inside.txt"] {
+        let entry = sevenz_rust::SevenZArchiveEntry::from_path(file, name.to_owned());
+        writer
+            .push_archive_entry(entry, Some(fs::File::open(file).unwrap()))
+            .unwrap();
+    }
+    writer.finish().unwrap();
+
+    let out = &dir.join("out");
+    ouch!("-A", "d", archive, "-d", out);
+    assert_eq!(fs::read_to_string(out.join("inside.txt")).unwrap(), "owned");
+    assert!(!dir.join("escaped.txt").exists());
+    assert!(!out.join("escaped.txt").exists());
+    assert!(!absolute.exists());
+}
+
+/// Setuid bits are cleared with a warning unless `--preserve-setuid` is passed
+#[cfg(unix)]
+#[test]
+fn unpack_clears_setuid_bits() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file");
+    fs::write(file, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(file, std::fs::Permissions::from_mode(0o4755)).unwrap();
+
+    for format in ["tar", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", file, archive);
+
+        let out = &dir.join(format!("cleared_{format}"));
+        let output = crate::utils::cargo_bin()
+            .args(["-A", "-y", "d"])
+            .arg(archive)
+            .arg("-d")
+            .arg(out)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("--preserve-setuid"));
+        let mode = fs::metadata(out.join("file")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o755, "{format}");
+
+        let out = &dir.join(format!("preserved_{format}"));
+        ouch!("-A", "d", archive, "-d", out, "--preserve-setuid");
+        let mode = fs::metadata(out.join("file")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o4755, "{format}");
+    }
+}
+
+/// Group and world writable entries lose those permissions unless `--umask-safe=false` is passed
+#[cfg(unix)]
+#[test]
+fn unpack_clamps_writable_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before_dir = &dir.join("dir");
+    fs::create_dir(before_dir).unwrap();
+    let file = &before_dir.join("file");
+    fs::write(file, "contents").unwrap();
+    fs::set_permissions(file, std::fs::Permissions::from_mode(0o4777)).unwrap();
+    fs::set_permissions(before_dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+
+    let archive = &dir.join("archive.tar");
+    ouch!("-A", "c", before_dir, archive);
+    let mode = |path: &std::path::Path| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+
+    let clamped = &dir.join("clamped");
+    ouch!("-A", "d", archive, "-d", clamped);
+    assert_eq!(mode(&clamped.join("dir")), 0o755);
+    assert_eq!(mode(&clamped.join("dir/file")), 0o755);
+
+    let stored = &dir.join("stored");
+    ouch!(
+        "-A",
+        "d",
+        archive,
+        "-d",
+        stored,
+        "--umask-safe=false",
+        "--preserve-setuid"
+    );
+    assert_eq!(mode(&stored.join("dir")), 0o777);
+    assert_eq!(mode(&stored.join("dir/file")), 0o4777);
+
+    // The last of `--preserve-setuid` and `--no-special-permissions` wins
+    let overridden = &dir.join("overridden");
+    ouch!(
+        "-A",
+        "d",
+        archive,
+        "-d",
+        overridden,
+        "--umask-safe=false",
+        "--preserve-setuid",
+        "--no-special-permissions"
+    );
+    assert_eq!(mode(&overridden.join("dir/file")), 0o777);
+}
+
+/// Archives unpack in the sandbox, on kernels with Landlock enabled
+#[cfg(target_os = "linux")]
+#[test]
+fn decompress_in_sandbox() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "some contents").unwrap();
+
+    let archive = &dir.join("archive.tar.gz");
+    ouch!("-A", "c", input, archive);
+
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "-y", "d", "--sandbox"])
+        .arg(archive)
+        .arg("-d")
+        .arg(dir.join("output"))
+        .output()
+        .unwrap();
+    if String::from_utf8_lossy(&output.stderr).contains("Landlock isn't enabled") {
+        return;
+    }
+    assert!(output.status.success());
+    assert_eq!(fs::read(dir.join("output/input/file")).unwrap(), b"some contents");
+
+    // Removing the input would need access to its directory
+    crate::utils::cargo_bin()
+        .args(["-A", "-y", "d", "--sandbox", "--remove"])
+        .arg(archive)
+        .assert()
+        .code(2);
+}
+
+/// Stored zip entries are copied straight from the archive, and still checked against their CRC32
+#[test]
+fn unpack_zip_with_stored_entries() {
+    use std::io::Write;
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let archive = &dir.join("stored.zip");
+    let contents: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+
+    let mut writer = zip::ZipWriter::new(fs::File::create(archive).unwrap());
+    let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    writer.start_file("a.bin", options).unwrap();
+    writer.write_all(&contents).unwrap();
+    writer.start_file("b.txt", options).unwrap();
+    writer.write_all(b"stored").unwrap();
+    writer.finish().unwrap();
+
+    ouch!("-A", "d", archive, "-d", dir.join("out"));
+    assert_eq!(fs::read(dir.join("out/stored/a.bin")).unwrap(), contents);
+    assert_eq!(fs::read_to_string(dir.join("out/stored/b.txt")).unwrap(), "stored");
+
+    // Change a byte of the contents of the first entry
+    let mut bytes = fs::read(archive).unwrap();
+    let position = bytes.windows(5).position(|window| window == b"a.bin").unwrap() + 5 + 1000;
+    bytes[position] ^= 0xff;
+    let corrupted = &dir.join("corrupted.zip");
+    fs::write(corrupted, bytes).unwrap();
+
+    crate::utils::cargo_bin()
+        .args(["-A", "d"])
+        .arg(corrupted)
+        .arg("-d")
+        .arg(dir.join("corrupted"))
+        .assert()
+        .failure();
+}
+
+/// Files of plain tar archives are copied straight from them too, keeping their permissions and
+/// modification time
+#[cfg(unix)]
+#[test]
+fn unpack_tar_with_copied_files() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before_dir = &dir.join("dir");
+    fs::create_dir(before_dir).unwrap();
+    let contents: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let file = &before_dir.join("a.bin");
+    fs::write(file, &contents).unwrap();
+    fs::set_permissions(file, std::fs::Permissions::from_mode(0o751)).unwrap();
+    filetime_creation::set_file_mtime(file, filetime_creation::FileTime::from_unix_time(1_000_000_000, 0)).unwrap();
+    fs::write(before_dir.join("b.txt"), "stored").unwrap();
+
+    let archive = &dir.join("archive.tar");
+    let after = &dir.join("after");
+    ouch!("-A", "c", before_dir, archive);
+    ouch!("-A", "d", archive, "-d", after);
+
+    assert_eq!(fs::read(after.join("dir/a.bin")).unwrap(), contents);
+    assert_eq!(fs::read_to_string(after.join("dir/b.txt")).unwrap(), "stored");
+    let metadata = fs::metadata(after.join("dir/a.bin")).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o751);
+    assert_eq!(
+        filetime_creation::FileTime::from_last_modification_time(&metadata).unix_seconds(),
+        1_000_000_000
+    );
+}
+
+/// `--filter-cmd` runs a program instead of the built-in codec of the outermost format
+#[cfg(unix)]
+#[test]
+fn compress_and_decompress_with_filter_cmd() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file.txt"), "contents ".repeat(10_000)).unwrap();
+
+    let archive = &dir.join("archive.tar.gz");
+    ouch!("-A", "c", input, archive, "--filter-cmd", "gzip -9");
+
+    // Read by the built-in decoder, and through the program
+    ouch!("-A", "d", archive, "-d", dir.join("builtin"));
+    assert_same_directory(input, dir.join("builtin/input"), false);
+    ouch!("-A", "d", archive, "-d", dir.join("filtered"), "--filter-cmd", "gzip");
+    assert_same_directory(input, dir.join("filtered/input"), false);
+
+    // Failures of the program fail the command, removing the output
+    let failed = &dir.join("failed.tar.gz");
+    crate::utils::cargo_bin()
+        .args(["-A", "c", "--filter-cmd", "false"])
+        .arg(input)
+        .arg(failed)
+        .assert()
+        .failure();
+    assert!(!failed.exists());
+
+    // There's no compression format to take the place of
+    crate::utils::cargo_bin()
+        .args(["-A", "c", "--filter-cmd", "gzip"])
+        .arg(input)
+        .arg(dir.join("archive.tar"))
+        .assert()
+        .code(2);
+}
+
+/// `.lzo` needs the `lzo` feature, builds without it fail naming it
+#[cfg(not(feature = "lzo"))]
+#[test]
+fn lzo_without_feature() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file");
+    fs::write(file, "contents").unwrap();
+
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "c"])
+        .arg(file)
+        .arg(dir.join("file.lzo"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("`lzo` feature"));
+}
+
+/// `.lrz` files are recognised, but can't be unpacked
+#[test]
+fn lrzip_is_recognised() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let archive = &dir.join("backup.tar.lrz");
+    fs::write(archive, [b"LRZI\x00\x06".as_slice(), &[0; 32]].concat()).unwrap();
+
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "info"])
+        .arg(archive)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("format version 0.6"));
+
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "d"])
+        .arg(archive)
+        .arg("-d")
+        .arg(dir.join("out"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("lrzip -d"));
+}
+
+/// SquashFS images are recognised by their extension or contents, but can't be listed or unpacked
+#[test]
+fn squashfs_is_recognised() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let image = &dir.join("rootfs.sqfs");
+    fs::write(image, [b"hsqs\x05\0\0\0".as_slice(), &[0; 200]].concat()).unwrap();
+
+    let output = crate::utils::cargo_bin().args(["-A", "l"]).arg(image).output().unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("SquashFS images are not supported"));
+
+    let misnamed = &dir.join("rootfs.img");
+    fs::rename(image, misnamed).unwrap();
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "d", "--ignore-extension"])
+        .arg(misnamed)
+        .arg("-d")
+        .arg(dir.join("out"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unsquashfs"));
+}
+
+/// Shell completions and the man page are printed by their subcommands
+#[test]
+fn print_completions_and_manpage() {
+    for shell in ["bash", "elvish", "fish", "powershell", "zsh"] {
+        let output = crate::utils::cargo_bin().args(["completions", shell]).output().unwrap();
+        assert!(output.status.success());
+        assert!(String::from_utf8(output.stdout).unwrap().contains("decompress"));
+    }
+
+    let output = crate::utils::cargo_bin().arg("manpage").output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().contains(".TH ouch 1"));
+}
+
+/// Big files that look already compressed are stored as they are in zip archives with
+/// `--auto-store`, and compressing them again in other formats is warned about
+#[test]
+fn incompressible_contents_are_detected() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("photos");
+    fs::create_dir(input).unwrap();
+    let mut contents = vec![0; 2 * 1024 * 1024];
+    SmallRng::seed_from_u64(0).fill(contents.as_mut_slice());
+    fs::write(input.join("photo.jpg"), contents).unwrap();
+    fs::write(input.join("notes.txt"), "some notes").unwrap();
+
+    let output = ouch!("-A", "c", input, dir.join("photos.tar.gz"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("look already compressed") && stderr.contains("--fast"),
+        "{stderr}"
+    );
+
+    let output = ouch!("-A", "c", input, dir.join("photos.zip"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--auto-store"), "{stderr}");
+
+    let archive = &dir.join("stored.zip");
+    let output = ouch!("-A", "c", input, archive, "--auto-store");
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("look already compressed"));
+    let output = ouch!("list", "-l", archive);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("photo.jpg (Stored)"), "{stdout}");
+    assert!(stdout.contains("notes.txt (Deflated)"), "{stdout}");
+}
+
+/// `--zip-rule` picks the method of the zip entries matching a glob, the first matching rule wins
+#[test]
+fn zip_rules_pick_the_method_of_entries() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("mixed");
+    fs::create_dir_all(input.join("nested")).unwrap();
+    fs::write(input.join("nested/photo.jpg"), "not really a photo").unwrap();
+    fs::write(input.join("notes.txt"), "some notes").unwrap();
+    fs::write(input.join("other.txt"), "other notes").unwrap();
+
+    let archive = &dir.join("mixed.zip");
+    ouch!(
+        "-A",
+        "c",
+        input,
+        archive,
+        "--zip-rule",
+        "*.jpg=store",
+        "--zip-rule",
+        "other.txt=store",
+        "--zip-rule",
+        "*.txt=deflate:9"
+    );
+    let output = ouch!("list", "-l", archive);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("photo.jpg (Stored)"), "{stdout}");
+    assert!(stdout.contains("other.txt (Stored)"), "{stdout}");
+    assert!(stdout.contains("notes.txt (Deflated)"), "{stdout}");
+
+    crate::utils::cargo_bin()
+        .args(["c", "--zip-rule", "*.jpg=brotli"])
+        .arg(input)
+        .arg(dir.join("invalid.zip"))
+        .assert()
+        .code(2);
+}
+
+/// `--estimate` prints the projected output without writing it
+#[test]
+fn estimate_doesnt_write_the_output() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("a.txt"), "some repeated contents\n".repeat(10_000)).unwrap();
+    fs::write(input.join("b.txt"), "other repeated contents\n".repeat(10_000)).unwrap();
+
+    for output in ["out.tar.gz", "out.zip", "out.txt.zst"] {
+        let output = &dir.join(output);
+        let input = if output.ends_with("out.txt.zst") {
+            &input.join("a.txt")
+        } else {
+            input
+        };
+
+        let stdout = String::from_utf8(ouch!("-A", "c", input, output, "--estimate").stdout).unwrap();
+        assert!(stdout.contains("Estimated size: "), "{stdout}");
+        assert!(stdout.contains("Estimated time: "), "{stdout}");
+        assert!(!output.exists());
+    }
+
+    crate::utils::cargo_bin()
+        .args(["c", "-", "out.gz", "--estimate"])
+        .current_dir(dir)
+        .assert()
+        .code(2);
 }
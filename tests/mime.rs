@@ -17,8 +17,7 @@ fn sanity_check_through_mime() {
     write_random_content(test_file, &mut SmallRng::from_entropy());
 
     let formats = [
-        "7z", "tar", "zip", "tar.gz", "tgz", "tbz", "tbz2", "txz", "tlzma", "tzst", "tar.bz", "tar.bz2", "tar.lzma",
-        "tar.xz", "tar.zst",
+        "7z", "tar", "zip", "tar.gz", "tgz", "tbz", "tbz2", "txz", "tzst", "tar.bz", "tar.bz2", "tar.xz", "tar.zst",
     ];
 
     let expected_mimes = [
@@ -30,12 +29,10 @@ fn sanity_check_through_mime() {
         "application/x-bzip2",
         "application/x-bzip2",
         "application/x-xz",
-        "application/x-xz",
         "application/zstd",
         "application/x-bzip2",
         "application/x-bzip2",
         "application/x-xz",
-        "application/x-xz",
         "application/zstd",
     ];
 